@@ -1,12 +1,17 @@
 let
-  pkgs = import ./nixpkgs.nix { overlays = [ (import ./overlay.nix) ]; };
+  pkgs = import ./nixpkgs.nix {
+    overlays = map (x: import (./overlays + "/${x}"))
+      (builtins.sort builtins.lessThan
+        (builtins.attrNames (builtins.readDir ./overlays)));
+  };
   packages = with pkgs; [
     cacert
-    cfssl
     cni-plugins
     conmon
     conntrack-tools
+    containerd
     cri-o
+    curl
     cri-tools
     etcd
     iproute
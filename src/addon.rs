@@ -0,0 +1,344 @@
+use crate::{
+    aggregation::Aggregation,
+    certmanager::CertManager,
+    cloudprovider::CloudProvider,
+    cni::Cni,
+    config::Config,
+    coredns::CoreDns,
+    dashboard::Dashboard,
+    ingress::Ingress,
+    kubectl::Kubectl,
+    logging::Logging,
+    metrics::MetricsServer,
+    network::Network,
+    observability::Observability,
+    proxy::Proxy,
+    rbac::Rbac,
+    registryauth::RegistryAuth,
+    runtimeclass::RuntimeClasses,
+    spec::Spec,
+    status::{AddonState, Status},
+};
+use anyhow::{bail, Context, Result};
+use log::{debug, info};
+use std::{
+    fs::read_dir,
+    path::{Path, PathBuf},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// A workload which gets applied to the cluster after the control plane is
+/// up and running
+pub trait Addon {
+    /// The name of the addon, which is used to track its state
+    fn name(&self) -> String;
+
+    /// Apply the addon to the cluster, which has to be idempotent
+    fn apply(&self, config: &Config, network: &Network, kubectl: &Kubectl) -> Result<()>;
+}
+
+/// All YAML manifests of a user provided directory
+pub struct ManifestDir<'a> {
+    dir: &'a Path,
+    selectors: &'a [String],
+}
+
+impl<'a> ManifestDir<'a> {
+    /// The maximum time to wait for the pods of a single selector
+    const TIMEOUT: Duration = Duration::from_secs(300);
+
+    /// Create a new manifest directory addon, which waits for the pods
+    /// matching the `[NAMESPACE/]SELECTOR` selectors to be ready
+    pub fn new(dir: &'a Path, selectors: &'a [String]) -> Self {
+        Self { dir, selectors }
+    }
+
+    /// Retrieve all YAML manifests of the directory in alphabetical order
+    fn manifests(&self) -> Result<Vec<PathBuf>> {
+        let mut manifests = vec![];
+        for entry in read_dir(self.dir)
+            .with_context(|| format!("Unable to read manifest dir '{}'", self.dir.display()))?
+        {
+            let path = entry?.path();
+            let extension = path.extension().and_then(|x| x.to_str());
+            if path.is_file() && matches!(extension, Some("yml" | "yaml")) {
+                manifests.push(path);
+            }
+        }
+        manifests.sort();
+        Ok(manifests)
+    }
+
+    /// Split the selector into its namespace and label selector
+    fn split_selector(selector: &str) -> Result<(&str, &str)> {
+        let (namespace, labels) = selector.split_once('/').unwrap_or(("default", selector));
+        if namespace.is_empty() || labels.is_empty() {
+            bail!(
+                "Invalid selector, expected format [NAMESPACE/]SELECTOR: {}",
+                selector
+            )
+        }
+        Ok((namespace, labels))
+    }
+
+    /// Wait until pods matching the selector exist and all of them are ready
+    fn wait(kubectl: &Kubectl, selector: &str) -> Result<()> {
+        let (namespace, labels) = Self::split_selector(selector)?;
+        info!("Waiting for pods '{}' to be ready", selector);
+
+        let now = Instant::now();
+        while now.elapsed() < Self::TIMEOUT {
+            let output =
+                kubectl.execute(&["get", "pods", "-n", namespace, "-l", labels, "-o=name"])?;
+            if output.stdout.is_empty() {
+                debug!("No pods found for '{}' yet", selector);
+                sleep(Duration::from_secs(2));
+                continue;
+            }
+            kubectl
+                .execute(&[
+                    "wait",
+                    "--for=condition=Ready",
+                    "pods",
+                    "-n",
+                    namespace,
+                    "-l",
+                    labels,
+                    &format!(
+                        "--timeout={}s",
+                        Self::TIMEOUT.saturating_sub(now.elapsed()).as_secs()
+                    ),
+                ])
+                .with_context(|| format!("Pods '{}' did not become ready", selector))?;
+            return Ok(());
+        }
+        bail!("No pods found for '{}'", selector)
+    }
+}
+
+impl Addon for ManifestDir<'_> {
+    fn name(&self) -> String {
+        format!(
+            "manifests-{}",
+            self.dir
+                .file_name()
+                .map(|x| x.to_string_lossy())
+                .unwrap_or_default()
+        )
+    }
+
+    fn apply(&self, _: &Config, _: &Network, kubectl: &Kubectl) -> Result<()> {
+        for selector in self.selectors {
+            Self::split_selector(selector)?;
+        }
+        for manifest in self.manifests()? {
+            info!("Applying manifest '{}'", manifest.display());
+            kubectl
+                .apply(&manifest)
+                .with_context(|| format!("Unable to apply manifest '{}'", manifest.display()))?;
+        }
+        for selector in self.selectors {
+            Self::wait(kubectl, selector)?;
+        }
+        Ok(())
+    }
+}
+
+/// All workloads applied to the running cluster, which are the optional
+/// addons as well as the required ones
+pub struct Addons<'a> {
+    config: &'a Config,
+    network: &'a Network,
+    kubectl: &'a Kubectl,
+    registry_auth: &'a RegistryAuth,
+}
+
+impl<'a> Addons<'a> {
+    /// Create a new instance for the provided configuration
+    pub fn new(
+        config: &'a Config,
+        network: &'a Network,
+        kubectl: &'a Kubectl,
+        registry_auth: &'a RegistryAuth,
+    ) -> Self {
+        Self {
+            config,
+            network,
+            kubectl,
+            registry_auth,
+        }
+    }
+
+    /// Apply all workloads to the running cluster, which is idempotent
+    pub fn apply(&self) -> Result<()> {
+        info!("Applying cluster addons");
+        self.apply_registry_auth()?;
+        if let Some(spec) = Spec::load(self.config)? {
+            if spec.has_labels() {
+                self.apply_addon_of(&spec)?;
+            }
+        }
+        if self.config.fake_cloud_controller_manager() {
+            Self::apply_addon("cloud-controller-manager", || {
+                CloudProvider::initialize(self.config, self.network, self.kubectl)
+            })?;
+        }
+        if Cni::is_managed(self.config) {
+            self.apply_addon_of(&Cni)?;
+        }
+        if self.config.self_hosted() && !self.config.no_proxy() {
+            // CoreDNS requires a working service network
+            Self::apply_addon("kube-proxy", || {
+                Proxy::apply(self.config, self.network, self.kubectl)
+            })?;
+        }
+        self.apply_addon_of(&CoreDns)?;
+        if !self.config.runtime_classes().is_empty() {
+            self.apply_addon_of(&RuntimeClasses)?;
+        }
+        if Self::enabled(self.config, Ingress::NAME) {
+            self.apply_addon_of(&Ingress)?;
+        }
+        Self::apply_addon("aggregation", || {
+            Aggregation::verify(self.config, self.kubectl)
+        })?;
+        Self::apply_addon("rbac", || {
+            Rbac::verify(self.config, self.network, self.kubectl)
+        })?;
+        if Self::enabled(self.config, CertManager::NAME) {
+            self.apply_addon_of(&CertManager)?;
+        }
+        if Self::enabled(self.config, MetricsServer::NAME) {
+            self.apply_addon_of(&MetricsServer)?;
+        }
+        if Self::enabled(self.config, Logging::NAME) {
+            self.apply_addon_of(&Logging)?;
+        }
+        if Self::enabled(self.config, Observability::NAME) {
+            self.apply_addon_of(&Observability)?;
+        }
+        if Self::enabled(self.config, Dashboard::NAME) {
+            self.apply_addon_of(&Dashboard)?;
+        }
+        self.apply_manifests()
+    }
+
+    /// Returns true if the optional addon got enabled via `--addons`
+    pub fn enabled(config: &Config, name: &str) -> bool {
+        config.addons().iter().any(|x| x == name)
+    }
+
+    /// Apply an addon by using the configuration
+    fn apply_addon_of(&self, addon: &dyn Addon) -> Result<()> {
+        Self::apply_addon(&addon.name(), || {
+            addon.apply(self.config, self.network, self.kubectl)
+        })
+    }
+
+    /// Apply the registry image pull secret if enabled
+    pub fn apply_registry_auth(&self) -> Result<()> {
+        if self.config.pull_secret() {
+            Self::apply_addon("registry-auth", || {
+                self.registry_auth.apply(self.config, self.kubectl)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Apply all additional manifests
+    pub fn apply_manifests(&self) -> Result<()> {
+        for manifest in self.config.manifests() {
+            let name = format!(
+                "manifest-{}",
+                manifest
+                    .file_name()
+                    .map(|x| x.to_string_lossy())
+                    .unwrap_or_default()
+            );
+            Self::apply_addon(&name, || {
+                info!("Applying manifest '{}'", manifest.display());
+                self.kubectl
+                    .apply(manifest)
+                    .with_context(|| format!("Unable to apply manifest '{}'", manifest.display()))
+            })?;
+        }
+        if let Some(dir) = self.config.apply_manifests() {
+            self.apply_addon_of(&ManifestDir::new(dir, self.config.wait_for()))?;
+        }
+        Ok(())
+    }
+
+    /// Apply a single addon and track its state
+    fn apply_addon<F>(name: &str, f: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        Status::set_addon(name, AddonState::Pending);
+        let result = f();
+        Status::set_addon(
+            name,
+            if result.is_ok() {
+                AddonState::Applied
+            } else {
+                AddonState::Failed
+            },
+        );
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn manifests_success() -> Result<()> {
+        let d = tempdir()?;
+        for name in &["b.yaml", "a.yml", "c.json", "README.md"] {
+            write(d.path().join(name), "")?;
+        }
+        create_dir_all(d.path().join("d.yml"))?;
+
+        let addon = ManifestDir::new(d.path(), &[]);
+        assert_eq!(
+            addon.manifests()?,
+            vec![d.path().join("a.yml"), d.path().join("b.yaml")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn manifests_failure() {
+        let addon = ManifestDir::new(Path::new("/does/not/exist"), &[]);
+        assert!(addon.manifests().is_err());
+    }
+
+    #[test]
+    fn name_success() {
+        let addon = ManifestDir::new(Path::new("/some/workloads"), &[]);
+        assert_eq!(addon.name(), "manifests-workloads");
+    }
+
+    #[test]
+    fn split_selector_success() -> Result<()> {
+        assert_eq!(
+            ManifestDir::split_selector("app=web")?,
+            ("default", "app=web")
+        );
+        assert_eq!(
+            ManifestDir::split_selector("shop/app=web,tier=db")?,
+            ("shop", "app=web,tier=db")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn split_selector_failure() {
+        for selector in &["", "/app=web", "shop/"] {
+            assert!(ManifestDir::split_selector(selector).is_err());
+        }
+    }
+}
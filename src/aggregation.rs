@@ -0,0 +1,133 @@
+use crate::{config::Config, kubectl::Kubectl};
+use anyhow::{bail, Context, Result};
+use log::{debug, info};
+use serde_json::Value;
+use std::{
+    fs::{self, create_dir_all},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// A smoke check of the API aggregation layer
+pub struct Aggregation;
+
+impl Aggregation {
+    const GROUP: &'static str = "smoke.kubernix.io";
+    const VERSION: &'static str = "v1alpha1";
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// Register a trivial APIService which is backed by the API server itself
+    /// and verify that the aggregator is able to authenticate against it
+    pub fn verify(config: &Config, kubectl: &Kubectl) -> Result<()> {
+        info!("Verifying API aggregation");
+        let dir = config.root().join("aggregation");
+        create_dir_all(&dir)?;
+
+        let name = format!("{}.{}", Self::VERSION, Self::GROUP);
+        let file = dir.join("apiservice.yml");
+        fs::write(
+            &file,
+            format!(
+                include_str!("assets/aggregation.yml"),
+                name = name,
+                group = Self::GROUP,
+                version = Self::VERSION,
+            ),
+        )?;
+        kubectl
+            .apply(&file)
+            .context("Unable to register smoke check APIService")?;
+
+        // Never keep the APIService, since it breaks the discovery of clients
+        let result = Self::wait_discovery(&name, kubectl);
+        kubectl
+            .execute(&["delete", "apiservice", &name, "--ignore-not-found"])
+            .context("Unable to remove smoke check APIService")?;
+        result?;
+
+        info!("API aggregation verified");
+        Ok(())
+    }
+
+    fn wait_discovery(name: &str, kubectl: &Kubectl) -> Result<()> {
+        let now = Instant::now();
+        while now.elapsed() < Self::TIMEOUT {
+            let output = kubectl.execute(&["get", "apiservice", name, "-o=json"])?;
+            match Self::discovery_result(&output.stdout) {
+                Some(true) => return Ok(()),
+                Some(false) => bail!(
+                    "API aggregation is not able to authenticate, \
+                     the front proxy certificates may be misconfigured"
+                ),
+                None => debug!("APIService {} not checked yet", name),
+            }
+            sleep(Duration::from_secs(2));
+        }
+        bail!(
+            "Timed out waiting for the APIService {} to be checked",
+            name
+        )
+    }
+
+    /// Returns true if the aggregator reached the API server, false if the
+    /// request got rejected and `None` if the APIService is not checked yet.
+    /// The API server does not serve the smoke check group, which means that
+    /// a `404` proves a successfully authenticated request.
+    fn discovery_result(apiservice: &[u8]) -> Option<bool> {
+        let value = serde_json::from_slice::<Value>(apiservice).ok()?;
+        let condition = value["status"]["conditions"]
+            .as_array()?
+            .iter()
+            .find(|x| x["type"] == "Available")?
+            .clone();
+        if condition["status"] == "True" {
+            return Some(true);
+        }
+        let message = condition["message"].as_str().unwrap_or_default();
+        if message.contains("401") || message.contains("403") {
+            Some(false)
+        } else if message.contains("404") {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apiservice(status: &str, message: &str) -> Vec<u8> {
+        format!(
+            r#"{{"status":{{"conditions":[{{"type":"Available","status":"{}","message":"{}"}}]}}}}"#,
+            status, message
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn discovery_result_success() {
+        assert_eq!(
+            Aggregation::discovery_result(&apiservice("True", "all checks passed")),
+            Some(true)
+        );
+        assert_eq!(
+            Aggregation::discovery_result(&apiservice("False", "bad status from url: 404")),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn discovery_result_failure() {
+        assert_eq!(
+            Aggregation::discovery_result(&apiservice("False", "bad status from url: 401")),
+            Some(false)
+        );
+        assert_eq!(
+            Aggregation::discovery_result(&apiservice("False", "endpoints not found")),
+            None
+        );
+        assert_eq!(Aggregation::discovery_result(b"invalid"), None);
+    }
+}
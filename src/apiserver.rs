@@ -1,16 +1,19 @@
 use crate::{
+    audit::Audit,
     config::Config,
+    controlplane::ControlPlane,
     encryptionconfig::EncryptionConfig,
+    kine::Kine,
     kubectl::Kubectl,
     network::Network,
     pki::Pki,
-    process::{Process, ProcessState, Stoppable},
+    process::{Process, ProcessState, Readiness, Stoppable},
 };
 use anyhow::{Context, Result};
-use log::debug;
+use log::{debug, warn};
 use std::{
-    fs::{self, create_dir_all},
-    path::Path,
+    fs::{self, canonicalize, create_dir_all},
+    path::{Path, PathBuf},
 };
 
 pub struct ApiServer {
@@ -18,6 +21,8 @@ pub struct ApiServer {
 }
 
 impl ApiServer {
+    const DIR: &'static str = "apiserver";
+
     pub fn start(
         config: &Config,
         network: &Network,
@@ -25,57 +30,159 @@ impl ApiServer {
         encryptionconfig: &EncryptionConfig,
         kubectl: &Kubectl,
     ) -> ProcessState {
-        let dir = config.root().join("apiserver");
+        let dir = config.root().join(Self::DIR);
         create_dir_all(&dir)?;
 
-        let mut process = Process::start(
+        // Multiple nodes share the host network and are reachable via their
+        // node IP, whereas the single node is reachable via its hostname
+        let kubelet_address_types = if config.multi_node() {
+            "InternalIP,Hostname,InternalDNS,ExternalDNS,ExternalIP"
+        } else {
+            "Hostname,InternalDNS,InternalIP,ExternalDNS,ExternalIP"
+        };
+
+        // Tokens minted elsewhere can be verified via additional public keys
+        let mut service_account_keys = vec![];
+        for key in config.service_account_keys() {
+            let key = canonicalize(key).with_context(|| {
+                format!("Unable to find service account key '{}'", key.display())
+            })?;
+            service_account_keys.push(format!("--service-account-key-file={}", key.display()));
+        }
+
+        // Without a policy, no audit events are recorded at all. A provided
+        // policy gets copied into the run root, which is the only directory
+        // available to the control plane container.
+        let audit_policy = dir.join("audit-policy.yml");
+        match config.audit_policy() {
+            Some(policy) => {
+                if !config.record().is_empty() {
+                    warn!("Recording resources depends on the provided audit policy")
+                }
+                fs::copy(policy, &audit_policy).with_context(|| {
+                    format!("Unable to copy audit policy '{}'", policy.display())
+                })?;
+            }
+            None => fs::write(&audit_policy, Audit::policy(config.record())?)?,
+        }
+        let audit_policy = format!("--audit-policy-file={}", audit_policy.display());
+
+        // kine serves the etcd API via a local socket without TLS
+        let etcd = if config.single_binary() {
+            vec![format!("--etcd-servers={}", Kine::endpoint(config)?)]
+        } else {
+            vec![
+                format!("--etcd-cafile={}", pki.etcd_ca().cert().display()),
+                format!(
+                    "--etcd-certfile={}",
+                    pki.apiserver_etcd_client().cert().display()
+                ),
+                format!(
+                    "--etcd-keyfile={}",
+                    pki.apiserver_etcd_client().key().display()
+                ),
+                format!("--etcd-servers=https://{}", network.etcd_client()),
+            ]
+        };
+
+        let args: &[&str] = &[
+            "--allow-privileged=true",
+            "--audit-log-maxage=30",
+            "--audit-log-maxbackup=3",
+            "--audit-log-maxsize=100",
+            &format!("--audit-log-path={}", Self::audit_log(config).display()),
+            &audit_policy,
+            "--authorization-mode=Node,RBAC",
+            "--bind-address=0.0.0.0",
+            &format!("--client-ca-file={}", pki.ca().cert().display()),
+            "--enable-aggregator-routing=true",
+            "--event-ttl=1h",
+            &format!(
+                "--encryption-provider-config={}",
+                encryptionconfig.path().display()
+            ),
+            &format!(
+                "--kubelet-certificate-authority={}",
+                pki.ca().cert().display()
+            ),
+            &format!(
+                "--kubelet-client-certificate={}",
+                pki.apiserver().cert().display()
+            ),
+            &format!("--kubelet-client-key={}", pki.apiserver().key().display()),
+            &format!(
+                "--kubelet-preferred-address-types={}",
+                kubelet_address_types
+            ),
+            &format!(
+                "--proxy-client-cert-file={}",
+                pki.front_proxy_client().cert().display()
+            ),
+            &format!(
+                "--proxy-client-key-file={}",
+                pki.front_proxy_client().key().display()
+            ),
+            &format!(
+                "--requestheader-allowed-names={}",
+                pki.front_proxy_client().name()
+            ),
+            &format!(
+                "--requestheader-client-ca-file={}",
+                pki.front_proxy_ca().cert().display()
+            ),
+            "--requestheader-extra-headers-prefix=X-Remote-Extra-",
+            "--requestheader-group-headers=X-Remote-Group",
+            "--requestheader-username-headers=X-Remote-User",
+            &config.runtime_config_arg()?,
+            &format!(
+                "--service-account-key-file={}",
+                pki.service_account().cert().display()
+            ),
+            "--service-account-issuer=https://kubernetes.default.svc.cluster.local",
+            &format!(
+                "--service-account-signing-key-file={}",
+                pki.service_account().key().display()
+            ),
+            &format!("--secure-port={}", config.api_port()),
+            &format!("--service-cluster-ip-range={}", network.service_cidr()),
+            &format!("--tls-cert-file={}", pki.apiserver().cert().display()),
+            &format!("--tls-private-key-file={}", pki.apiserver().key().display()),
+            "--v=2",
+        ];
+        let cloud_provider = config.cloud_provider_arg();
+        let feature_gates = config.feature_gates_arg()?;
+        let mut process = ControlPlane::exec(
+            config,
             &dir,
             "API Server",
             "kube-apiserver",
-            &[
-                "--allow-privileged=true",
-                "--audit-log-maxage=30",
-                "--audit-log-maxbackup=3",
-                "--audit-log-maxsize=100",
-                &format!("--audit-log-path={}", dir.join("audit.log").display()),
-                "--authorization-mode=Node,RBAC",
-                "--bind-address=0.0.0.0",
-                &format!("--client-ca-file={}", pki.ca().cert().display()),
-                &format!("--etcd-cafile={}", pki.ca().cert().display()),
-                &format!("--etcd-certfile={}", pki.apiserver().cert().display()),
-                &format!("--etcd-keyfile={}", pki.apiserver().key().display()),
-                &format!("--etcd-servers=https://{}", network.etcd_client()),
-                "--event-ttl=1h",
-                &format!(
-                    "--encryption-provider-config={}",
-                    encryptionconfig.path().display()
-                ),
-                &format!(
-                    "--kubelet-certificate-authority={}",
-                    pki.ca().cert().display()
-                ),
-                &format!(
-                    "--kubelet-client-certificate={}",
-                    pki.apiserver().cert().display()
-                ),
-                &format!("--kubelet-client-key={}", pki.apiserver().key().display()),
-                "--runtime-config=api/all=true",
-                &format!(
-                    "--service-account-key-file={}",
-                    pki.service_account().cert().display()
-                ),
-                &format!("--service-cluster-ip-range={}", network.service_cidr()),
-                &format!("--tls-cert-file={}", pki.apiserver().cert().display()),
-                &format!("--tls-private-key-file={}", pki.apiserver().key().display()),
-                "--v=2",
-            ],
+            &args
+                .iter()
+                .copied()
+                .chain(etcd.iter().map(String::as_str))
+                .chain(service_account_keys.iter().map(String::as_str))
+                .chain(cloud_provider.as_deref())
+                .chain(feature_gates.as_deref())
+                .chain(config.apiserver_args().iter().map(String::as_str))
+                .collect::<Vec<_>>(),
+            &config.component_env_vars("apiserver"),
         )?;
 
-        process.wait_ready("sending update to cc")?;
+        process.wait_ready(Readiness::readyz(config.api_port(), pki))?;
+        process.describe(
+            "kube-apiserver",
+            &config.api_server(),
+            Some(kubectl.kubeconfig()),
+        );
         Self::setup_rbac(&dir, kubectl)?;
         Ok(Box::new(Self { process }))
     }
 
+    /// Retrieve the path to the audit log
+    pub fn audit_log(config: &Config) -> PathBuf {
+        config.root().join(Self::DIR).join("audit.log")
+    }
+
     fn setup_rbac(dir: &Path, kubectl: &Kubectl) -> Result<()> {
         debug!("Creating API Server RBAC rule for kubelet");
         let file = dir.join("rbac.yml");
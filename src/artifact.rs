@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{self, read_dir},
+    path::{Path, PathBuf},
+};
+
+/// Generated files inside the run root which depend on the configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Artifact {
+    /// The nix environment including overlays and packages
+    Nix,
+
+    /// All certificates
+    Pki,
+
+    /// The kubeconfigs, which embed the certificates
+    KubeConfigs,
+
+    /// The persisted command lines of all processes
+    RunFiles,
+
+    /// The CRI-O and CNI configurations of every node
+    CrioConfigs,
+
+    /// The kubelet configurations of every node
+    KubeletConfigs,
+
+    /// The kube-proxy configurations of every node
+    ProxyConfig,
+
+    /// The configurations of the default and secondary scheduler
+    SchedulerConfigs,
+
+    /// The CoreDNS manifest
+    CoreDns,
+
+    /// The metrics-server manifest
+    MetricsServer,
+
+    /// The ingress-nginx manifest
+    Ingress,
+
+    /// The Kubernetes Dashboard manifest
+    Dashboard,
+
+    /// The cert-manager manifest
+    CertManager,
+}
+
+impl Display for Artifact {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Artifact::Nix => "nix environment",
+            Artifact::Pki => "PKI",
+            Artifact::KubeConfigs => "kubeconfigs",
+            Artifact::RunFiles => "process run files",
+            Artifact::CrioConfigs => "CRI runtime and CNI configs",
+            Artifact::KubeletConfigs => "kubelet configs",
+            Artifact::ProxyConfig => "kube-proxy configs",
+            Artifact::SchedulerConfigs => "scheduler configs",
+            Artifact::CoreDns => "CoreDNS manifest",
+            Artifact::MetricsServer => "metrics-server manifest",
+            Artifact::Ingress => "ingress-nginx manifest",
+            Artifact::Dashboard => "Kubernetes Dashboard manifest",
+            Artifact::CertManager => "cert-manager manifest",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Artifact {
+    /// Retrieve all artifacts which have to be regenerated if the provided
+    /// configuration fields change
+    pub fn affected_by(fields: &[String]) -> Vec<Artifact> {
+        use Artifact::*;
+        let mut artifacts = fields
+            .iter()
+            .flat_map(|field| match field.as_str() {
+                "cidr" | "cluster-prefix" | "service-prefix" | "node-prefix" => vec![
+                    Pki,
+                    KubeConfigs,
+                    RunFiles,
+                    CrioConfigs,
+                    KubeletConfigs,
+                    ProxyConfig,
+                    CoreDns,
+                ],
+                "nodes" => vec![Pki, KubeConfigs, RunFiles, CrioConfigs, KubeletConfigs],
+                "overlays" | "packages" | "fake-nodes" => vec![Nix],
+                "single-binary" => vec![Nix, RunFiles],
+                "api-port" => vec![KubeConfigs, RunFiles, ProxyConfig],
+                "kubelet-port" => vec![KubeletConfigs],
+                "feature-gates" => vec![RunFiles, KubeletConfigs, ProxyConfig],
+                "control-plane-container" => vec![RunFiles],
+                "oci-runtime" | "runtime-classes" | "cni" => vec![Nix, CrioConfigs],
+                "cloud-provider"
+                | "container-runtime"
+                | "etcd-quota-backend-bytes"
+                | "etcd-heartbeat-interval"
+                | "etcd-election-timeout"
+                | "etcd-snapshot-count"
+                | "etcd-port"
+                | "record"
+                | "runtime-config"
+                | "audit-policy"
+                | "apiserver-args"
+                | "controller-manager-args"
+                | "scheduler-args"
+                | "kubelet-args"
+                | "etcd-args"
+                | "component-env"
+                | "http-proxy"
+                | "https-proxy"
+                | "proxy-exclusions"
+                | "service-account-keys" => vec![RunFiles],
+                "socket-dir" | "cri" | "node-runtimes" => vec![RunFiles, CrioConfigs],
+                "image-gc-high-threshold"
+                | "image-gc-low-threshold"
+                | "container-log-max-size"
+                | "container-log-max-files" => vec![KubeletConfigs],
+                "insecure-registries" | "auth-file" | "storage-quota" | "log-size-max" => {
+                    vec![CrioConfigs]
+                }
+                "scheduler-extenders" => vec![SchedulerConfigs],
+                "proxy-mode" => vec![Nix, ProxyConfig],
+                "spec" => vec![KubeletConfigs, ProxyConfig, SchedulerConfigs],
+                "secondary-scheduler" => vec![RunFiles, SchedulerConfigs],
+                "image-mirror" | "images" => {
+                    vec![
+                        CrioConfigs,
+                        ProxyConfig,
+                        CoreDns,
+                        MetricsServer,
+                        Ingress,
+                        Dashboard,
+                        CertManager,
+                    ]
+                }
+                _ => vec![],
+            })
+            .collect::<Vec<_>>();
+        artifacts.sort();
+        artifacts.dedup();
+        artifacts
+    }
+
+    /// Remove the artifact from the run root to let it be regenerated on the
+    /// next bootstrap
+    pub fn remove(self, root: &Path) -> Result<()> {
+        for path in self.paths(root)? {
+            debug!("Removing {} artifact '{}'", self, path.display());
+            if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            }
+            .with_context(|| format!("Unable to remove '{}'", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve all existing paths of the artifact
+    fn paths(self, root: &Path) -> Result<Vec<PathBuf>> {
+        let paths = match self {
+            Artifact::Nix => vec![root.join("nix")],
+            Artifact::Pki => vec![root.join("pki")],
+            Artifact::KubeConfigs => vec![root.join("kubeconfig")],
+            Artifact::RunFiles => {
+                let mut paths = vec![];
+                for dir in Self::subdirs(root)? {
+                    paths.push(dir.join("run.yml"));
+                    paths.extend(Self::subdirs(&dir)?.iter().map(|x| x.join("run.yml")));
+                }
+                paths
+            }
+            Artifact::CrioConfigs => {
+                let mut paths = Self::subdirs(&root.join("crio"))?
+                    .iter()
+                    .flat_map(|x| vec![x.join("crio.conf.d"), x.join("cni")])
+                    .collect::<Vec<_>>();
+                paths.extend(
+                    Self::subdirs(&root.join("containerd"))?
+                        .iter()
+                        .flat_map(|x| vec![x.join("config.toml"), x.join("cni")]),
+                );
+                paths
+            }
+            Artifact::KubeletConfigs => Self::subdirs(&root.join("kubelet"))?
+                .iter()
+                .map(|x| x.join("config.yml"))
+                .collect(),
+            Artifact::ProxyConfig => {
+                let mut paths = vec![root.join("proxy").join("kube-proxy.yml")];
+                paths.extend(
+                    Self::subdirs(&root.join("proxy"))?
+                        .iter()
+                        .map(|x| x.join("config.yml")),
+                );
+                paths
+            }
+            Artifact::SchedulerConfigs => vec![
+                root.join("scheduler").join("config.yml"),
+                root.join("secondary-scheduler").join("config.yml"),
+            ],
+            Artifact::CoreDns => vec![root.join("coredns").join("coredns.yml")],
+            Artifact::MetricsServer => {
+                vec![root.join("metrics-server").join("metrics-server.yml")]
+            }
+            Artifact::Ingress => vec![root.join("ingress").join("ingress.yml")],
+            Artifact::Dashboard => vec![root.join("dashboard").join("dashboard.yml")],
+            Artifact::CertManager => {
+                vec![root.join("cert-manager").join("cert-manager.yml")]
+            }
+        };
+        Ok(paths.into_iter().filter(|x| x.exists()).collect())
+    }
+
+    /// Retrieve all subdirectories, whereas symlinks are not followed
+    fn subdirs(dir: &Path) -> Result<Vec<PathBuf>> {
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut dirs = vec![];
+        for entry in read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+            }
+        }
+        Ok(dirs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::create_dir_all;
+    use tempfile::tempdir;
+
+    #[test]
+    fn affected_by_success() {
+        assert_eq!(
+            Artifact::affected_by(&["packages".into(), "overlays".into()]),
+            vec![Artifact::Nix]
+        );
+        assert_eq!(
+            Artifact::affected_by(&["image-gc-high-threshold".into(), "images".into()]),
+            vec![
+                Artifact::CrioConfigs,
+                Artifact::KubeletConfigs,
+                Artifact::ProxyConfig,
+                Artifact::CoreDns,
+                Artifact::MetricsServer,
+                Artifact::Ingress,
+                Artifact::Dashboard,
+                Artifact::CertManager
+            ]
+        );
+        assert!(Artifact::affected_by(&["log-level".into()]).is_empty());
+    }
+
+    #[test]
+    fn remove_success() -> Result<()> {
+        let d = tempdir()?;
+        let node = d.path().join("crio").join("node-0");
+        create_dir_all(node.join("cni"))?;
+        create_dir_all(node.join("containers"))?;
+        fs::write(node.join("run.yml"), "")?;
+
+        Artifact::CrioConfigs.remove(d.path())?;
+        Artifact::RunFiles.remove(d.path())?;
+        assert!(!node.join("cni").exists());
+        assert!(!node.join("run.yml").exists());
+        assert!(node.join("containers").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde_json::{json, Value};
+use std::fmt::Write;
+
+/// The API server audit policy and the replay of its recorded entries
+pub struct Audit;
+
+impl Audit {
+    /// Verbs which modify resources and get recorded
+    const VERBS: &'static [&'static str] = &["create", "update", "patch", "delete"];
+
+    /// Metadata fields which are set by the API server and prevent a replay
+    const SERVER_FIELDS: &'static [&'static str] = &[
+        "creationTimestamp",
+        "generation",
+        "managedFields",
+        "resourceVersion",
+        "selfLink",
+        "uid",
+    ];
+
+    /// Render the default audit policy, which records all modifications of
+    /// the provided resources (`RESOURCE[.GROUP]`) at `RequestResponse` level
+    /// and all other requests except frequent ones at `Metadata` level
+    pub fn policy(resources: &[String]) -> Result<String> {
+        let mut rules = vec![];
+        let mut groups = vec![];
+        for resource in resources {
+            let (name, group) = resource.split_once('.').unwrap_or((resource, ""));
+            if name.is_empty() {
+                bail!("Invalid resource '{}', expected RESOURCE[.GROUP]", resource)
+            }
+            groups.push(json!({ "group": group, "resources": [name] }));
+        }
+        if !groups.is_empty() {
+            rules.push(
+                json!({ "level": "RequestResponse", "verbs": Self::VERBS, "resources": groups }),
+            );
+        }
+        rules.extend(vec![
+            json!({
+                "level": "None",
+                "nonResourceURLs": ["/healthz*", "/livez*", "/readyz*", "/version"],
+            }),
+            json!({
+                "level": "None",
+                "resources": [
+                    { "group": "", "resources": ["events"] },
+                    { "group": "coordination.k8s.io", "resources": ["leases"] },
+                ],
+            }),
+            json!({ "level": "Metadata" }),
+        ]);
+        Ok(serde_yaml::to_string(&json!({
+            "apiVersion": "audit.k8s.io/v1",
+            "kind": "Policy",
+            "omitStages": ["RequestReceived"],
+            "rules": rules,
+        }))?)
+    }
+
+    /// Convert the recorded audit log into a kubectl script, whereas only the
+    /// provided users are considered if not empty. Otherwise system
+    /// components except service accounts outside of `kube-system` are
+    /// skipped.
+    pub fn replay(log: &str, users: &[String]) -> Result<String> {
+        let mut script = "#!/usr/bin/env bash\nset -euo pipefail\n".to_owned();
+        for (i, line) in log
+            .lines()
+            .enumerate()
+            .filter(|(_, x)| !x.trim().is_empty())
+        {
+            let event: Value = serde_json::from_str(line)
+                .with_context(|| format!("Unable to parse audit entry in line {}", i + 1))?;
+            // Entries below `RequestResponse` level belong to resources
+            // which are not recorded
+            if event["level"] != "RequestResponse"
+                || event["stage"] != "ResponseComplete"
+                || !Self::succeeded(&event)
+            {
+                continue;
+            }
+            let user = event["user"]["username"].as_str().unwrap_or_default();
+            let verb = event["verb"].as_str().unwrap_or_default();
+            if !Self::selected(user, users) {
+                continue;
+            }
+            match Self::command(&event)? {
+                Some(command) => write!(script, "\n# {} by {}\n{}", verb, user, command)?,
+                None => debug!("Skipping audit entry in line {}", i + 1),
+            }
+        }
+        Ok(script)
+    }
+
+    /// Returns true if the request was successful
+    fn succeeded(event: &Value) -> bool {
+        matches!(event["responseStatus"]["code"].as_u64(), Some(200..=299))
+    }
+
+    /// Returns true if the entries of the user should be replayed
+    fn selected(user: &str, users: &[String]) -> bool {
+        if !users.is_empty() {
+            return users.iter().any(|x| x == user);
+        }
+        !user.starts_with("system:")
+            || (user.starts_with("system:serviceaccount:")
+                && !user.starts_with("system:serviceaccount:kube-system:"))
+    }
+
+    /// Retrieve the kubectl command to reproduce the audit entry
+    fn command(event: &Value) -> Result<Option<String>> {
+        let object_ref = &event["objectRef"];
+        if object_ref["subresource"].is_string() {
+            return Ok(None);
+        }
+        match event["verb"].as_str().unwrap_or_default() {
+            "create" | "update" | "patch" => {
+                let mut object = event["responseObject"].clone();
+                if !object.is_object() {
+                    return Ok(None);
+                }
+                Self::clean(&mut object);
+                Ok(Some(format!(
+                    "kubectl apply -f - <<'EOF'\n{}\nEOF\n",
+                    serde_json::to_string_pretty(&object)?
+                )))
+            }
+            "delete" => {
+                let name = match object_ref["name"].as_str() {
+                    Some(name) => name,
+                    None => return Ok(None),
+                };
+                let resource = object_ref["resource"].as_str().unwrap_or_default();
+                let mut command = match object_ref["apiGroup"].as_str() {
+                    Some(group) if !group.is_empty() => {
+                        format!("kubectl delete {}.{} {}", resource, group, name)
+                    }
+                    _ => format!("kubectl delete {} {}", resource, name),
+                };
+                if let Some(namespace) = object_ref["namespace"].as_str() {
+                    write!(command, " --namespace={}", namespace)?;
+                }
+                command.push('\n');
+                Ok(Some(command))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Remove the server populated fields and the status of the object
+    fn clean(object: &mut Value) {
+        if let Some(object) = object.as_object_mut() {
+            object.remove("status");
+        }
+        if let Some(metadata) = object["metadata"].as_object_mut() {
+            for field in Self::SERVER_FIELDS {
+                metadata.remove(*field);
+            }
+        }
+        if let Some(annotations) = object["metadata"]["annotations"].as_object_mut() {
+            annotations.remove("kubectl.kubernetes.io/last-applied-configuration");
+            annotations.remove("deployment.kubernetes.io/revision");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = r#"
+{"level":"RequestResponse","stage":"ResponseComplete","verb":"create","user":{"username":"admin"},"objectRef":{"resource":"configmaps","namespace":"default","name":"test","apiVersion":"v1"},"responseStatus":{"code":201},"responseObject":{"kind":"ConfigMap","apiVersion":"v1","metadata":{"name":"test","namespace":"default","uid":"1","resourceVersion":"2"},"data":{"key":"value"}}}
+{"level":"RequestResponse","stage":"ResponseComplete","verb":"update","user":{"username":"system:serviceaccount:kube-system:deployment-controller"},"objectRef":{"resource":"deployments","namespace":"default","name":"test","apiGroup":"apps"},"responseStatus":{"code":200},"responseObject":{"kind":"Deployment"}}
+{"level":"RequestResponse","stage":"ResponseComplete","verb":"delete","user":{"username":"admin"},"objectRef":{"resource":"deployments","namespace":"default","name":"test","apiGroup":"apps"},"responseStatus":{"code":200}}
+{"level":"RequestResponse","stage":"ResponseComplete","verb":"delete","user":{"username":"admin"},"objectRef":{"resource":"configmaps","namespace":"default","name":"other"},"responseStatus":{"code":404}}
+{"level":"Metadata","stage":"ResponseComplete","verb":"delete","user":{"username":"admin"},"objectRef":{"resource":"secrets","namespace":"default","name":"test"},"responseStatus":{"code":200}}
+"#;
+
+    #[test]
+    fn policy_success() -> Result<()> {
+        let policy = Audit::policy(&["configmaps".into(), "deployments.apps".into()])?;
+        assert!(policy.contains("RequestResponse"));
+        assert!(policy.contains("group: apps"));
+        assert!(policy.contains("- configmaps"));
+
+        let policy: Value = serde_yaml::from_str(&Audit::policy(&[])?)?;
+        let rules = policy["rules"].as_array().context("no rules")?;
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[2]["level"], "Metadata");
+        Ok(())
+    }
+
+    #[test]
+    fn policy_failure() {
+        assert!(Audit::policy(&[".apps".into()]).is_err());
+    }
+
+    #[test]
+    fn replay_success() -> Result<()> {
+        let script = Audit::replay(LOG, &[])?;
+        assert!(script.contains("kubectl apply -f - <<'EOF'"));
+        assert!(script.contains(r#""key": "value""#));
+        assert!(!script.contains("resourceVersion"));
+        assert!(!script.contains("Deployment"));
+        assert!(script.contains("kubectl delete deployments.apps test --namespace=default"));
+        assert!(!script.contains("other"));
+        assert!(!script.contains("secrets"));
+        Ok(())
+    }
+
+    #[test]
+    fn replay_success_users() -> Result<()> {
+        let script = Audit::replay(LOG, &["nobody".into()])?;
+        assert!(!script.contains("kubectl"));
+        Ok(())
+    }
+
+    #[test]
+    fn replay_failure() {
+        assert!(Audit::replay("invalid", &[]).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{addon::Addon, config::Config, kubectl::Kubectl, network::Network, pki::Pki};
+use anyhow::{bail, Context, Result};
+use base64::encode;
+use log::{debug, info};
+use std::{
+    fs::{self, create_dir_all, read},
+    path::Path,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// The cert-manager addon, which provides a cluster issuer signing
+/// certificates via an intermediate CA of the cluster CA
+pub struct CertManager;
+
+impl CertManager {
+    /// The name of the addon
+    pub const NAME: &'static str = "cert-manager";
+
+    /// The name and label of the CA injector
+    const CAINJECTOR: &'static str = "cert-manager-cainjector";
+
+    /// The name and label of the admission webhook
+    const WEBHOOK: &'static str = "cert-manager-webhook";
+
+    const CONTROLLER_IMAGE: &'static str = "quay.io/jetstack/cert-manager-controller:v1.12.3";
+    const CAINJECTOR_IMAGE: &'static str = "quay.io/jetstack/cert-manager-cainjector:v1.12.3";
+    const WEBHOOK_IMAGE: &'static str = "quay.io/jetstack/cert-manager-webhook:v1.12.3";
+
+    /// The custom resource definitions of the cert-manager release
+    const CRDS: &'static str = include_str!("assets/cert-manager-crds.yml");
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// The name of the cluster issuer backed by the intermediate CA
+    pub const ISSUER: &'static str = "kubernix-ca";
+
+    /// Render the cluster issuer together with the secret containing the
+    /// provided intermediate CA certificate and key, whereas the certificate
+    /// gets chained with the cluster CA
+    fn issuer(cert: &Path, key: &Path, ca: &Path) -> Result<String> {
+        let data = |path: &Path| -> Result<Vec<u8>> {
+            read(path).with_context(|| format!("Unable to read '{}'", path.display()))
+        };
+        Ok(format!(
+            include_str!("assets/cert-manager-issuer.yml"),
+            secret = Self::ISSUER,
+            issuer = Self::ISSUER,
+            cert = encode([data(cert)?, data(ca)?].concat()),
+            key = encode(data(key)?),
+            ca = encode(data(ca)?),
+        ))
+    }
+
+    /// Wait until the webhook accepts the issuer, which requires the CA
+    /// injected into the webhook configurations
+    fn wait_webhook(file: &Path, kubectl: &Kubectl) -> Result<()> {
+        let now = Instant::now();
+        while now.elapsed() < Self::TIMEOUT {
+            match kubectl.dry_run(file) {
+                Ok(()) => return Ok(()),
+                Err(e) => debug!("cert-manager webhook not ready yet: {}", e),
+            }
+            sleep(Duration::from_secs(2));
+        }
+        bail!("Timed out waiting for the cert-manager webhook")
+    }
+}
+
+impl Addon for CertManager {
+    fn name(&self) -> String {
+        CertManager::NAME.into()
+    }
+
+    fn apply(&self, config: &Config, network: &Network, kubectl: &Kubectl) -> Result<()> {
+        info!("Deploying cert-manager and waiting to be ready");
+
+        let dir = config.root().join(CertManager::NAME);
+        create_dir_all(&dir)?;
+
+        let file = dir.join("cert-manager.yml");
+        if !file.exists() {
+            let yml = CertManager::CRDS.to_owned()
+                + &format!(
+                    include_str!("assets/cert-manager.yml"),
+                    controller_image =
+                        config.image("cert-manager", CertManager::CONTROLLER_IMAGE)?,
+                    cainjector_image =
+                        config.image(CertManager::CAINJECTOR, CertManager::CAINJECTOR_IMAGE)?,
+                    webhook_image =
+                        config.image(CertManager::WEBHOOK, CertManager::WEBHOOK_IMAGE)?,
+                );
+            fs::write(&file, yml)?;
+        }
+        kubectl
+            .apply(&file)
+            .context("Unable to deploy cert-manager")?;
+        kubectl.wait_ready(CertManager::NAME)?;
+        kubectl.wait_ready(CertManager::CAINJECTOR)?;
+        kubectl.wait_ready(CertManager::WEBHOOK)?;
+
+        // The issuer requires the established custom resource definitions and
+        // embeds the current CAs, which is why it gets always rendered again
+        let pki = Pki::new(config, network)?;
+        let issuer_ca = Pki::setup_issuer_ca(config)?;
+        let file = dir.join("issuer.yml");
+        fs::write(
+            &file,
+            CertManager::issuer(issuer_ca.cert(), issuer_ca.key(), pki.ca().cert())?,
+        )?;
+        CertManager::wait_webhook(&file, kubectl)?;
+        kubectl
+            .apply(&file)
+            .context("Unable to create the cert-manager cluster issuer")?;
+        info!(
+            "cert-manager deployed, certificates can be issued via the '{}' cluster issuer",
+            CertManager::ISSUER
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn crds_success() -> Result<()> {
+        let docs = CertManager::CRDS
+            .split("---\n")
+            .filter(|x| !x.trim().is_empty())
+            .map(serde_yaml::from_str::<serde_yaml::Value>)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(docs.len(), 6);
+        assert_eq!(
+            docs[3]["metadata"]["name"].as_str(),
+            Some("clusterissuers.cert-manager.io")
+        );
+        assert_eq!(docs[3]["spec"]["scope"].as_str(), Some("Cluster"));
+        for doc in docs {
+            let schema = &doc["spec"]["versions"][0]["schema"]["openAPIV3Schema"];
+            assert!(schema["properties"]["spec"]["properties"].is_mapping());
+            assert!(schema["x-kubernetes-preserve-unknown-fields"].is_null());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn issuer_success() -> Result<()> {
+        let d = tempdir()?;
+        let (cert, key, ca) = (
+            d.path().join("issuer-ca.pem"),
+            d.path().join("issuer-ca-key.pem"),
+            d.path().join("ca.pem"),
+        );
+        fs::write(&cert, "cert")?;
+        fs::write(&key, "key")?;
+        fs::write(&ca, "ca")?;
+        let docs = CertManager::issuer(&cert, &key, &ca)?
+            .split("\n---\n")
+            .map(serde_yaml::from_str::<serde_yaml::Value>)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(docs[0]["data"]["tls.crt"].as_str(), Some("Y2VydGNh"));
+        assert_eq!(docs[0]["data"]["tls.key"].as_str(), Some("a2V5"));
+        assert_eq!(docs[0]["data"]["ca.crt"].as_str(), Some("Y2E="));
+        assert_eq!(
+            docs[1]["spec"]["ca"]["secretName"].as_str(),
+            Some(CertManager::ISSUER)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn issuer_failure() {
+        let path = Path::new("/invalid");
+        assert!(CertManager::issuer(path, path, path).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use crate::kubectl::Kubectl;
+use anyhow::{bail, Context, Result};
+use console::style;
+use std::{
+    ffi::OsStr,
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+
+/// The validation of manifests against the running API server
+pub struct Check;
+
+impl Check {
+    /// File extensions which are considered as manifests
+    const EXTENSIONS: &'static [&'static str] = &["yaml", "yml", "json"];
+
+    /// Validate all manifests of the provided file or directory via a server
+    /// side dry run, which covers the schema as well as the admission
+    pub fn run(kubectl: &Kubectl, path: &Path) -> Result<()> {
+        let manifests = Self::manifests(path)?;
+        if manifests.is_empty() {
+            bail!("No manifests found in '{}'", path.display())
+        }
+
+        let mut failed = 0;
+        for manifest in &manifests {
+            match kubectl.dry_run(manifest) {
+                Ok(()) => println!("{}  {}", style("ok").green(), manifest.display()),
+                Err(e) => {
+                    failed += 1;
+                    println!("{}  {}\n{}", style("fail").red(), manifest.display(), e);
+                }
+            }
+        }
+        if failed > 0 {
+            bail!(
+                "{} of {} manifests failed the validation",
+                failed,
+                manifests.len()
+            )
+        }
+        Ok(())
+    }
+
+    /// Retrieve all manifests of the file or recursively of the directory in
+    /// sorted order
+    fn manifests(path: &Path) -> Result<Vec<PathBuf>> {
+        if path.is_file() {
+            return Ok(vec![path.into()]);
+        }
+        let mut manifests = vec![];
+        for entry in
+            read_dir(path).with_context(|| format!("Unable to read '{}'", path.display()))?
+        {
+            let entry = entry?.path();
+            if entry.is_dir() {
+                manifests.extend(Self::manifests(&entry)?);
+            } else if Self::EXTENSIONS
+                .iter()
+                .any(|x| entry.extension() == Some(OsStr::new(x)))
+            {
+                manifests.push(entry);
+            }
+        }
+        manifests.sort();
+        Ok(manifests)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, create_dir_all};
+    use tempfile::tempdir;
+
+    #[test]
+    fn manifests_success() -> Result<()> {
+        let d = tempdir()?;
+        create_dir_all(d.path().join("sub"))?;
+        for file in &["b.yaml", "a.json", "sub/c.yml", "README.md"] {
+            fs::write(d.path().join(file), "")?;
+        }
+        assert_eq!(
+            Check::manifests(d.path())?,
+            vec![
+                d.path().join("a.json"),
+                d.path().join("b.yaml"),
+                d.path().join("sub/c.yml"),
+            ]
+        );
+        assert_eq!(
+            Check::manifests(&d.path().join("README.md"))?,
+            vec![d.path().join("README.md")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn manifests_failure() {
+        assert!(Check::manifests(Path::new("/does/not/exist")).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{config::Config, kubectl::Kubectl, network::Network, node::Node};
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde_json::json;
+
+/// A fake cloud controller manager for the external cloud provider
+pub struct CloudProvider;
+
+impl CloudProvider {
+    /// The taint the kubelet registers nodes with if the cloud provider is external
+    const UNINITIALIZED_TAINT: &'static str = "node.cloudprovider.kubernetes.io/uninitialized";
+
+    /// Initialize all nodes like a cloud controller manager would
+    pub fn initialize(config: &Config, network: &Network, kubectl: &Kubectl) -> Result<()> {
+        info!("Initializing nodes via fake cloud controller manager");
+        for number in 0..config.nodes() {
+            let node = Node::name(config, network, number);
+            Self::initialize_node(kubectl, &node, number)
+                .with_context(|| format!("Unable to initialize node {}", node))?;
+        }
+        info!("All nodes initialized");
+        Ok(())
+    }
+
+    fn initialize_node(kubectl: &Kubectl, node: &str, number: u8) -> Result<()> {
+        debug!("Initializing node {}", node);
+
+        // The provider ID is immutable once set
+        if Self::jsonpath(kubectl, node, "{.spec.providerID}")?.is_empty() {
+            kubectl.execute(&[
+                "patch",
+                "node",
+                node,
+                "--type=merge",
+                &format!(
+                    "-p={}",
+                    json!({ "spec": { "providerID": Self::provider_id(node) } })
+                ),
+            ])?;
+        }
+
+        let mut args = vec![
+            "label".to_owned(),
+            "node".into(),
+            node.into(),
+            "--overwrite".into(),
+        ];
+        args.extend(Self::labels(number));
+        kubectl.execute(&args.iter().map(String::as_str).collect::<Vec<_>>())?;
+
+        let taints = Self::jsonpath(kubectl, node, "{.spec.taints[*].key}")?;
+        if taints
+            .split_whitespace()
+            .any(|x| x == Self::UNINITIALIZED_TAINT)
+        {
+            kubectl.execute(&[
+                "taint",
+                "node",
+                node,
+                &format!("{}:NoSchedule-", Self::UNINITIALIZED_TAINT),
+            ])?;
+        }
+
+        debug!("Node {} initialized", node);
+        Ok(())
+    }
+
+    /// Retrieve a jsonpath expression of the provided node
+    fn jsonpath(kubectl: &Kubectl, node: &str, path: &str) -> Result<String> {
+        let output = kubectl.execute(&["get", "node", node, &format!("-o=jsonpath={}", path)])?;
+        Ok(String::from_utf8(output.stdout)?.trim().into())
+    }
+
+    fn provider_id(node: &str) -> String {
+        format!("kubernix://{}", node)
+    }
+
+    /// The topology labels of the node, whereas every node is an own zone
+    fn labels(number: u8) -> Vec<String> {
+        vec![
+            "node.kubernetes.io/instance-type=kubernix".into(),
+            "topology.kubernetes.io/region=kubernix".into(),
+            format!("topology.kubernetes.io/zone=kubernix-{}", number),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provider_id_success() {
+        assert_eq!(CloudProvider::provider_id("node-0"), "kubernix://node-0");
+    }
+
+    #[test]
+    fn labels_success() {
+        assert!(CloudProvider::labels(2).contains(&"topology.kubernetes.io/zone=kubernix-2".into()));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    crio::CriSocket, kubeconfig::KubeConfig, kubelet::Kubelet, network::Network, node::Node,
+    pki::Pki, Config,
+};
+use anyhow::{Context, Result};
+use getset::{CopyGetters, Getters};
+use ipnetwork::Ipv4Network;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, read_to_string},
+    path::{Path, PathBuf},
+};
+
+/// The facts about a bootstrapped cluster, which are written into the run
+/// root to be consumed by external tools
+#[derive(Clone, Debug, Deserialize, Getters, PartialEq, Serialize)]
+pub struct ClusterInfo {
+    #[get = "pub"]
+    /// The endpoint of the API server
+    api_server: String,
+
+    #[get = "pub"]
+    /// The certificate authority of the cluster
+    ca: PathBuf,
+
+    #[get = "pub"]
+    /// The kubeconfig of the cluster administrator
+    admin_kubeconfig: PathBuf,
+
+    #[get = "pub"]
+    /// The kubeconfig of the controller manager
+    controller_manager_kubeconfig: PathBuf,
+
+    #[get = "pub"]
+    /// The kubeconfig of the scheduler
+    scheduler_kubeconfig: PathBuf,
+
+    #[get = "pub"]
+    /// The kubeconfig of the proxy
+    proxy_kubeconfig: PathBuf,
+
+    #[get = "pub"]
+    /// The CIDR of all pods
+    cluster_cidr: Ipv4Network,
+
+    #[get = "pub"]
+    /// The CIDR of all services
+    service_cidr: Ipv4Network,
+
+    #[get = "pub"]
+    /// All nodes of the cluster
+    nodes: Vec<NodeInfo>,
+}
+
+/// The facts about a single node of the cluster
+#[derive(Clone, CopyGetters, Debug, Deserialize, Getters, PartialEq, Serialize)]
+pub struct NodeInfo {
+    #[get = "pub"]
+    /// The name of the node
+    name: String,
+
+    #[get = "pub"]
+    /// The CIDR of the pods on the node
+    pod_cidr: Ipv4Network,
+
+    #[get = "pub"]
+    /// The CRI socket of the container runtime
+    cri_socket: PathBuf,
+
+    #[get = "pub"]
+    /// The kubeconfig of the kubelet
+    kubeconfig: PathBuf,
+
+    #[get_copy = "pub"]
+    #[serde(default)]
+    /// The secure port of the kubelet
+    kubelet_port: u16,
+
+    #[get_copy = "pub"]
+    #[serde(default)]
+    /// The port of the kubelet healthz endpoint
+    healthz_port: u16,
+}
+
+impl ClusterInfo {
+    const FILENAME: &'static str = "cluster-info.json";
+
+    /// Collect the cluster facts from the bootstrapped components
+    pub fn new(
+        config: &Config,
+        network: &Network,
+        pki: &Pki,
+        kubeconfig: &KubeConfig,
+    ) -> Result<ClusterInfo> {
+        let mut nodes = vec![];
+        for (number, (pod_cidr, kubelet)) in network
+            .crio_cidrs()
+            .iter()
+            .zip(kubeconfig.kubelets())
+            .enumerate()
+        {
+            let number = number as u8;
+            nodes.push(NodeInfo {
+                name: Node::name(config, network, number),
+                pod_cidr: *pod_cidr,
+                cri_socket: CriSocket::of(config, network, number)?.path().into(),
+                kubeconfig: kubelet.clone(),
+                kubelet_port: Kubelet::port(config, number),
+                healthz_port: Kubelet::healthz_port(config, number),
+            });
+        }
+        Ok(ClusterInfo {
+            api_server: config.api_server(),
+            ca: pki.ca().cert().clone(),
+            admin_kubeconfig: kubeconfig.admin().clone(),
+            controller_manager_kubeconfig: kubeconfig.controller_manager().clone(),
+            scheduler_kubeconfig: kubeconfig.scheduler().clone(),
+            proxy_kubeconfig: kubeconfig.proxy().clone(),
+            cluster_cidr: *network.cluster_cidr(),
+            service_cidr: *network.service_cidr(),
+            nodes,
+        })
+    }
+
+    /// Load the cluster facts from the run root
+    pub fn load(config: &Config) -> Result<ClusterInfo> {
+        Self::load_root(config.root())
+    }
+
+    /// Load the cluster facts from the provided run root
+    pub fn load_root(root: &Path) -> Result<ClusterInfo> {
+        let file = Self::path(root);
+        serde_json::from_str(&read_to_string(&file).with_context(|| {
+            format!(
+                "Unable to read cluster info '{}', is the cluster running?",
+                file.display()
+            )
+        })?)
+        .with_context(|| format!("Unable to parse cluster info '{}'", file.display()))
+    }
+
+    /// Write the cluster facts into the run root
+    pub fn write(&self, config: &Config) -> Result<()> {
+        let file = Self::path(config.root());
+        fs::write(&file, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Unable to write cluster info '{}'", file.display()))
+    }
+
+    /// Render the environment variables addressing every node
+    pub fn env(&self) -> String {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                format!(
+                    "export KUBERNIX_NODE_{i}_NAME={}\n\
+                     export KUBERNIX_NODE_{i}_CRI_SOCKET=unix://{}\n\
+                     export KUBERNIX_NODE_{i}_KUBELET_PORT={}\n\
+                     export KUBERNIX_NODE_{i}_HEALTHZ_PORT={}\n",
+                    node.name,
+                    node.cri_socket.display(),
+                    node.kubelet_port,
+                    node.healthz_port,
+                    i = i,
+                )
+            })
+            .collect()
+    }
+
+    /// Retrieve the path to the cluster info file
+    pub fn path(root: &Path) -> PathBuf {
+        root.join(Self::FILENAME)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::test_config, network::tests::test_network};
+
+    #[test]
+    fn write_load_success() -> Result<()> {
+        let c = test_config()?;
+        let network = test_network()?;
+        let info = ClusterInfo {
+            api_server: "https://127.0.0.1:6443".into(),
+            ca: "ca.pem".into(),
+            admin_kubeconfig: "admin.kubeconfig".into(),
+            controller_manager_kubeconfig: "controller-manager.kubeconfig".into(),
+            scheduler_kubeconfig: "scheduler.kubeconfig".into(),
+            proxy_kubeconfig: "proxy.kubeconfig".into(),
+            cluster_cidr: *network.cluster_cidr(),
+            service_cidr: *network.service_cidr(),
+            nodes: vec![NodeInfo {
+                name: "node-0".into(),
+                pod_cidr: network.crio_cidrs()[0],
+                cri_socket: "crio.sock".into(),
+                kubeconfig: "kubelet.kubeconfig".into(),
+                kubelet_port: 11250,
+                healthz_port: 12250,
+            }],
+        };
+        info.write(&c)?;
+        assert_eq!(ClusterInfo::load(&c)?, info);
+
+        let env = info.env();
+        assert!(env.contains("export KUBERNIX_NODE_0_NAME=node-0\n"));
+        assert!(env.contains("export KUBERNIX_NODE_0_CRI_SOCKET=unix://crio.sock\n"));
+        assert!(env.contains("export KUBERNIX_NODE_0_HEALTHZ_PORT=12250\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn load_failure() -> Result<()> {
+        let c = test_config()?;
+        assert!(ClusterInfo::load(&c).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+use crate::{
+    addon::Addon,
+    config::{CniProvider, Config},
+    kubectl::Kubectl,
+    network::Network,
+    pki::Pki,
+    system::System,
+    token::Token,
+};
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde_json::{json, to_string_pretty, Value};
+use std::{
+    fs::{self, create_dir_all, read_dir},
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
+
+/// The pod network of the cluster
+pub struct Cni;
+
+impl Cni {
+    /// The name of the addon
+    pub const NAME: &'static str = "cni";
+
+    const FLANNEL_IMAGE: &'static str = "docker.io/flannel/flannel:v0.22.0";
+    const CALICO_IMAGE: &'static str = "docker.io/calico/node:v3.26.1";
+    const CILIUM_IMAGE: &'static str = "quay.io/cilium/cilium:v1.14.0";
+
+    /// The name of the service account and cluster role of the Calico CNI
+    /// plugin, which follows the upstream manifests
+    const CALICO_CNI: &'static str = "calico-cni-plugin";
+
+    /// The expiration of the Calico CNI plugin token in seconds
+    const CALICO_TOKEN_DURATION: u64 = 7 * 24 * 60 * 60;
+    const CILIUM_OPERATOR_IMAGE: &'static str = "quay.io/cilium/operator-generic:v1.14.0";
+
+    /// The custom resources of Calico by their kind, plural and scope
+    const CALICO_CRDS: &'static [(&'static str, &'static str, &'static str)] = &[
+        ("BGPConfiguration", "bgpconfigurations", "Cluster"),
+        ("BGPFilter", "bgpfilters", "Cluster"),
+        ("BGPPeer", "bgppeers", "Cluster"),
+        ("BlockAffinity", "blockaffinities", "Cluster"),
+        ("CalicoNodeStatus", "caliconodestatuses", "Cluster"),
+        ("ClusterInformation", "clusterinformations", "Cluster"),
+        ("FelixConfiguration", "felixconfigurations", "Cluster"),
+        ("GlobalNetworkPolicy", "globalnetworkpolicies", "Cluster"),
+        ("GlobalNetworkSet", "globalnetworksets", "Cluster"),
+        ("HostEndpoint", "hostendpoints", "Cluster"),
+        ("IPAMBlock", "ipamblocks", "Cluster"),
+        ("IPAMConfig", "ipamconfigs", "Cluster"),
+        ("IPAMHandle", "ipamhandles", "Cluster"),
+        ("IPPool", "ippools", "Cluster"),
+        ("IPReservation", "ipreservations", "Cluster"),
+        (
+            "KubeControllersConfiguration",
+            "kubecontrollersconfigurations",
+            "Cluster",
+        ),
+        ("NetworkPolicy", "networkpolicies", "Namespaced"),
+        ("NetworkSet", "networksets", "Namespaced"),
+    ];
+
+    /// The nix package which provides the CNI plugins of the provider,
+    /// whereas the Cilium plugin gets installed by its agent
+    pub fn package(provider: CniProvider) -> Option<&'static str> {
+        match provider {
+            CniProvider::Bridge | CniProvider::Cilium => None,
+            CniProvider::Flannel => Some("cni-plugin-flannel"),
+            CniProvider::Calico => Some("calico-cni-plugin"),
+        }
+    }
+
+    /// The CNI plugins of the provider, which are not part of the default ones
+    fn plugins(provider: CniProvider) -> &'static [&'static str] {
+        match provider {
+            CniProvider::Bridge | CniProvider::Cilium => &[],
+            CniProvider::Flannel => &["flannel"],
+            CniProvider::Calico => &["calico", "calico-ipam"],
+        }
+    }
+
+    /// Verify that the provider supports the configured cluster. All nodes
+    /// share the network namespace of the host, which means that the agents
+    /// of the providers would interfere with each other.
+    pub fn preflight(config: &Config) -> Result<()> {
+        if Self::is_managed(config) && config.multi_node() {
+            bail!(
+                "The {} CNI provider requires a single node, \
+                 since all nodes share the network namespace of the host",
+                config.cni()
+            )
+        }
+        if Self::is_managed(config) && config.node_prefix() < config.cluster_prefix() {
+            bail!(
+                "The node prefix /{} must not be shorter than the cluster prefix /{}, \
+                 since the node CIDRs get allocated from the cluster CIDR",
+                config.node_prefix(),
+                config.cluster_prefix()
+            )
+        }
+        Ok(())
+    }
+
+    /// Returns true if the pod network is managed by a provider other than
+    /// the bridge, which gets deployed as addon and requires the pod CIDRs
+    /// of the nodes to be allocated by the controller manager
+    pub fn is_managed(config: &Config) -> bool {
+        config.cni() != CniProvider::Bridge
+    }
+
+    /// Retrieve the directory of the CNI plugin binaries. Providers other
+    /// than the bridge use a directory within the run root, which links the
+    /// default plugins next to the ones of the provider.
+    pub fn plugin_dir(config: &Config) -> Result<PathBuf> {
+        let loopback = System::find_executable("loopback")?;
+        let default = loopback.parent().context("Unable to find CNI plugin dir")?;
+        if !Self::is_managed(config) {
+            return Ok(default.into());
+        }
+
+        let dir = config.root().join(Self::NAME).join("bin");
+        create_dir_all(&dir)?;
+        for entry in read_dir(default)? {
+            let entry = entry?;
+            System::symlink(&entry.path(), &dir.join(entry.file_name()))?;
+        }
+        for plugin in Self::plugins(config.cni()) {
+            System::symlink(&System::find_executable(plugin)?, &dir.join(plugin))?;
+        }
+        Ok(dir)
+    }
+
+    /// Write the CNI configuration of the node into the provided network
+    /// directory of its CRI runtime
+    pub fn write_config(
+        config: &Config,
+        network: &Network,
+        node: u8,
+        node_name: &str,
+        dir: &Path,
+    ) -> Result<()> {
+        let name = Self::network_name(config, node_name);
+        let (file, value) = match config.cni() {
+            CniProvider::Bridge => {
+                let cidr = network
+                    .crio_cidrs()
+                    .get(node as usize)
+                    .with_context(|| format!("Unable to find CIDR for {}", node_name))?;
+                (
+                    "10-bridge.json",
+                    json!({
+                        "cniVersion": "0.3.1",
+                        "name": name,
+                        "type": "bridge",
+                        "bridge": Network::interface(config, node),
+                        "isGateway": true,
+                        "ipMasq": true,
+                        "hairpinMode": true,
+                        "ipam": {
+                            "type": "host-local",
+                            "routes": [{ "dst": "0.0.0.0/0" }],
+                            "ranges": [[{ "subnet": cidr }]]
+                        }
+                    }),
+                )
+            }
+            CniProvider::Flannel => (
+                "10-flannel.conflist",
+                Self::conflist(
+                    &name,
+                    json!({
+                        "type": "flannel",
+                        "delegate": {
+                            "hairpinMode": true,
+                            "isDefaultGateway": true
+                        }
+                    }),
+                ),
+            ),
+            CniProvider::Calico => (
+                "10-calico.conflist",
+                Self::conflist(
+                    &name,
+                    json!({
+                        "type": "calico",
+                        "datastore_type": "kubernetes",
+                        "nodename": node_name,
+                        "ipam": {
+                            "type": "host-local",
+                            "subnet": "usePodCidr"
+                        },
+                        "policy": { "type": "k8s" },
+                        "kubernetes": {
+                            "kubeconfig": Self::calico_kubeconfig(config)
+                        }
+                    }),
+                ),
+            ),
+            CniProvider::Cilium => (
+                "05-cilium.conflist",
+                Self::conflist(&name, json!({ "type": "cilium-cni" })),
+            ),
+        };
+        fs::write(dir.join(file), to_string_pretty(&value)?)?;
+        Ok(())
+    }
+
+    /// Retrieve the CNI network name of the provided node, which identifies
+    /// its masquerading rules on the host and is therefore unique per cluster
+    pub fn network_name(config: &Config, node_name: &str) -> String {
+        format!("{}-{}", config.cluster_name(), node_name)
+    }
+
+    /// Create a configuration list of the provided plugin, chained with the
+    /// port mapping plugin to support host ports
+    fn conflist(name: &str, plugin: Value) -> Value {
+        json!({
+            "cniVersion": "0.3.1",
+            "name": name,
+            "plugins": [
+                plugin,
+                {
+                    "type": "portmap",
+                    "capabilities": { "portMappings": true }
+                }
+            ]
+        })
+    }
+
+    /// The kubeconfig of the Calico CNI plugin, which authenticates as its
+    /// dedicated service account
+    fn calico_kubeconfig(config: &Config) -> PathBuf {
+        config.root().join(Self::NAME).join("calico-cni.kubeconfig")
+    }
+
+    /// Request a token for the service account of the Calico CNI plugin and
+    /// write its kubeconfig. The token does not get refreshed like done by
+    /// calico-node upstream, which is why a new one is requested on every
+    /// start of the cluster.
+    fn write_calico_kubeconfig(
+        config: &Config,
+        network: &Network,
+        kubectl: &Kubectl,
+    ) -> Result<()> {
+        let pki = Pki::new(config, network)?;
+        let kubeconfig = Token::new(
+            Self::CALICO_CNI,
+            "kube-system",
+            Self::CALICO_CNI,
+            Self::CALICO_TOKEN_DURATION,
+        )
+        .kubeconfig(config, kubectl, pki.ca().cert(), &config.api_server())
+        .context("Unable to create Calico CNI plugin kubeconfig")?;
+        System::write_private(&Self::calico_kubeconfig(config), &kubeconfig)
+    }
+
+    /// Render the custom resource definitions of Calico
+    fn calico_crds() -> String {
+        Self::CALICO_CRDS
+            .iter()
+            .map(|(kind, plural, scope)| {
+                format!(
+                    include_str!("assets/calico-crd.yml"),
+                    kind = kind,
+                    plural = plural,
+                    singular = kind.to_lowercase(),
+                    scope = scope,
+                )
+            })
+            .collect()
+    }
+}
+
+impl Addon for Cni {
+    fn name(&self) -> String {
+        Cni::NAME.into()
+    }
+
+    fn apply(&self, config: &Config, network: &Network, kubectl: &Kubectl) -> Result<()> {
+        let provider = config.cni();
+        info!("Deploying {} and waiting to be ready", provider);
+
+        let dir = config.root().join(Cni::NAME);
+        create_dir_all(&dir)?;
+
+        let yml = match provider {
+            CniProvider::Bridge => return Ok(()),
+            CniProvider::Flannel => format!(
+                include_str!("assets/flannel.yml"),
+                cidr = network.cluster_cidr(),
+                image = config.image("flannel", Cni::FLANNEL_IMAGE)?,
+            ),
+            CniProvider::Calico => {
+                Cni::calico_crds()
+                    + &format!(
+                        include_str!("assets/calico.yml"),
+                        image = config.image("calico", Cni::CALICO_IMAGE)?,
+                    )
+            }
+            CniProvider::Cilium => format!(
+                include_str!("assets/cilium.yml"),
+                cidr = network.cluster_cidr(),
+                kube_proxy_replacement = config.no_proxy(),
+                api_host = Ipv4Addr::LOCALHOST,
+                api_port = config.api_port(),
+                plugin_dir = Cni::plugin_dir(config)?.display(),
+                image = config.image("cilium", Cni::CILIUM_IMAGE)?,
+                operator_image = config.image("cilium-operator", Cni::CILIUM_OPERATOR_IMAGE)?,
+            ),
+        };
+        let file = dir.join(format!("{}.yml", provider));
+
+        if !file.exists() {
+            fs::write(&file, yml)?;
+        }
+
+        kubectl
+            .apply(&file)
+            .with_context(|| format!("Unable to deploy {}", provider))?;
+        if provider == CniProvider::Calico {
+            Cni::write_calico_kubeconfig(config, network, kubectl)?;
+        }
+        kubectl.wait_ready(&provider.to_string())?;
+        info!("{} deployed", provider);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{test_config, test_config_named, test_config_with};
+
+    #[test]
+    fn calico_crds_success() -> Result<()> {
+        let crds = Cni::calico_crds();
+        let docs = crds
+            .split("\n---\n")
+            .filter(|x| !x.trim().is_empty())
+            .map(serde_yaml::from_str::<serde_yaml::Value>)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(docs.len(), Cni::CALICO_CRDS.len());
+        assert_eq!(
+            docs[3]["metadata"]["name"].as_str(),
+            Some("blockaffinities.crd.projectcalico.org")
+        );
+        assert_eq!(
+            docs[3]["spec"]["names"]["singular"].as_str(),
+            Some("blockaffinity")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn preflight_success() -> Result<()> {
+        let c = test_config()?;
+        Cni::preflight(&c)?;
+        assert!(!Cni::is_managed(&c));
+
+        let c = test_config_with(|c| {
+            c.set_cni(CniProvider::Cilium);
+        })?;
+        Cni::preflight(&c)?;
+        assert!(Cni::is_managed(&c));
+        Ok(())
+    }
+
+    #[test]
+    fn network_name_success() -> Result<()> {
+        let c = test_config()?;
+        assert_eq!(Cni::network_name(&c, "node-0"), "kubernix-node-0");
+
+        let c = test_config_named("dev")?;
+        assert_eq!(Cni::network_name(&c, "node-0"), "kubernix-dev-node-0");
+        Ok(())
+    }
+
+    #[test]
+    fn preflight_failure() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_nodes(2).set_cni(CniProvider::Calico);
+        })?;
+        assert!(Cni::preflight(&c).is_err());
+        Ok(())
+    }
+}
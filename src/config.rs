@@ -1,17 +1,27 @@
 //! Configuration related structures
-use crate::{podman::Podman, system::System};
-use anyhow::{Context, Result};
-use clap::{AppSettings, Clap};
+use crate::{
+    artifact::Artifact, configfile::ConfigFile, graph::Unit, logger::LogSink, podman::Podman,
+    spec::Spec, system::System,
+};
+use anyhow::{bail, Context, Error, Result};
+use clap::{AppSettings, Clap, FromArgMatches, IntoApp};
+use console::{set_colors_enabled, set_colors_enabled_stderr, style, user_attended_stderr, Term};
 use getset::{CopyGetters, Getters};
 use ipnetwork::Ipv4Network;
-use log::LevelFilter;
-use serde::{Deserialize, Serialize};
+use log::{warn, LevelFilter};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
+    collections::BTreeMap,
+    env::{set_var, var_os},
+    fmt,
     fs::{self, canonicalize, create_dir_all, read_to_string},
-    path::PathBuf,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
 #[derive(Clap, CopyGetters, Getters, Deserialize, Serialize)]
+#[cfg_attr(test, derive(getset::Setters))]
 #[serde(rename_all = "kebab-case")]
 #[clap(
     after_help("More info at: https://github.com/saschagrunert/kubernix"),
@@ -22,9 +32,48 @@ use std::{
 pub struct Config {
     #[get = "pub"]
     #[clap(subcommand)]
+    #[serde(skip)]
     /// All available subcommands
     subcommand: Option<SubCommand>,
 
+    #[get = "pub"]
+    #[clap(skip)]
+    #[serde(skip)]
+    /// The sink receiving all log messages, for example if kubernix is
+    /// embedded in another application
+    log_sink: Option<LogSink>,
+
+    #[clap(skip)]
+    #[serde(skip)]
+    /// The fields which have been explicitly requested via the command line
+    /// or the environment
+    requested: Vec<String>,
+
+    #[get_copy = "pub"]
+    #[clap(env("KUBERNIX_YES"), long("yes"), takes_value(false))]
+    #[serde(skip)]
+    /// Apply configuration changes to an existing run root without asking
+    yes: bool,
+
+    #[get_copy = "pub"]
+    #[clap(env("KUBERNIX_FORCE"), long("force"), takes_value(false))]
+    #[serde(skip)]
+    /// Remove conflicting network interfaces of previous runs without asking
+    force: bool,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("auto"),
+        env("KUBERNIX_COLOR"),
+        long("color"),
+        possible_values(&["auto", "always", "never"]),
+        value_name("WHEN")
+    )]
+    #[serde(skip)]
+    /// Colorize the output and animate the progress bar, whereas `auto` does
+    /// this only for interactive terminals
+    color: ColorMode,
+
     #[get = "pub"]
     #[clap(
         default_value("kubernix-run"),
@@ -37,6 +86,13 @@ pub struct Config {
     /// Path where all the runtime data is stored
     root: PathBuf,
 
+    #[get = "pub"]
+    #[clap(env("KUBERNIX_NAME"), global(true), long("name"), value_name("NAME"))]
+    #[serde(default)]
+    /// The name of the cluster, which gets appended to the run root and
+    /// prefixes the node containers to let multiple clusters coexist
+    name: Option<String>,
+
     #[get_copy = "pub"]
     #[clap(
         default_value("info"),
@@ -60,15 +116,97 @@ pub struct Config {
     /// The CIDR used for the cluster
     cidr: Ipv4Network,
 
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("24"),
+        env("KUBERNIX_CLUSTER_PREFIX"),
+        long("cluster-prefix"),
+        value_name("PREFIX")
+    )]
+    #[serde(default = "Config::default_prefix")]
+    /// The prefix length of the cluster CIDR, which is carved from `--cidr`
+    cluster_prefix: u8,
+
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("24"),
+        env("KUBERNIX_SERVICE_PREFIX"),
+        long("service-prefix"),
+        value_name("PREFIX")
+    )]
+    #[serde(default = "Config::default_prefix")]
+    /// The prefix length of the service CIDR, which is carved from `--cidr`
+    service_prefix: u8,
+
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("24"),
+        env("KUBERNIX_NODE_PREFIX"),
+        long("node-prefix"),
+        value_name("PREFIX")
+    )]
+    #[serde(default = "Config::default_prefix")]
+    /// The prefix length of the pod CIDR of every node, which is carved from
+    /// `--cidr`
+    node_prefix: u8,
+
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("6443"),
+        env("KUBERNIX_API_PORT"),
+        long("api-port"),
+        value_name("PORT")
+    )]
+    #[serde(default = "Config::default_api_port")]
+    /// The secure port of the API server
+    api_port: u16,
+
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("2379"),
+        env("KUBERNIX_ETCD_PORT"),
+        long("etcd-port"),
+        value_name("PORT")
+    )]
+    #[serde(default = "Config::default_etcd_port")]
+    /// The client port of etcd, whereas the peer port is the next one
+    etcd_port: u16,
+
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("11250"),
+        env("KUBERNIX_KUBELET_PORT"),
+        long("kubelet-port"),
+        value_name("PORT")
+    )]
+    #[serde(default = "Config::default_kubelet_port")]
+    /// The secure port of the kubelet on the first node, whereas every node
+    /// uses the next one and the healthz ports start 1000 ports above
+    kubelet_port: u16,
+
     #[get = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
     #[clap(
-        env("KUBERNIX_OVERLAY"),
+        env("KUBERNIX_OVERLAYS"),
         long("overlay"),
+        multiple(true),
         short('o'),
         value_name("PATH")
     )]
-    /// The Nix package overlay to be used
-    overlay: Option<PathBuf>,
+    #[serde(
+        alias = "overlay",
+        default,
+        deserialize_with = "Config::deserialize_overlays"
+    )]
+    /// The Nix package overlays or directories containing overlays to be used,
+    /// which are applied in order
+    overlays: Vec<PathBuf>,
 
     #[get = "pub"]
     #[clap(
@@ -81,12 +219,33 @@ pub struct Config {
     /// Additional dependencies to be added to the environment
     packages: Vec<String>,
 
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_TARGET"),
+        long("target"),
+        value_name("ssh://[USER@]HOST[:PORT]")
+    )]
+    #[serde(skip)]
+    /// Bootstrap the cluster on a remote machine via SSH and forward its API server to localhost
+    target: Option<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_NIX_WRAPPER"),
+        long("nix-wrapper"),
+        value_name("COMMAND")
+    )]
+    #[serde(default)]
+    /// A whitespace separated command to run nix through, for example `nsenter -t 1 -m --`
+    nix_wrapper: Option<String>,
+
     #[get = "pub"]
     #[clap(env("KUBERNIX_SHELL"), long("shell"), short('s'), value_name("SHELL"))]
     /// The shell executable to be used, defaults to $SHELL, fallback is `sh`
     shell: Option<String>,
 
     #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
     #[clap(
         default_value("1"),
         env("KUBERNIX_NODES"),
@@ -97,6 +256,19 @@ pub struct Config {
     /// The number of nodes to be registered
     nodes: u8,
 
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("0"),
+        env("KUBERNIX_FAKE_NODES"),
+        long("fake-nodes"),
+        value_name("NODES")
+    )]
+    #[serde(default)]
+    /// The number of fake nodes simulated by kwok in addition to the real
+    /// ones, which do not run any workloads
+    fake_nodes: u8,
+
     #[get = "pub"]
     #[clap(
         env("KUBERNIX_CONTAINER_RUNTIME"),
@@ -109,6 +281,70 @@ pub struct Config {
     /// The container runtime to be used for the nodes, irrelevant if `nodes` equals to `1`
     container_runtime: String,
 
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("crio"),
+        env("KUBERNIX_CRI"),
+        long("cri"),
+        possible_values(&["crio", "containerd"]),
+        value_name("RUNTIME")
+    )]
+    #[serde(default)]
+    /// The CRI runtime of all nodes without an explicit node runtime
+    cri: NodeRuntime,
+
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("bridge"),
+        env("KUBERNIX_CNI"),
+        long("cni"),
+        possible_values(&["bridge", "flannel", "calico", "cilium"]),
+        value_name("PROVIDER")
+    )]
+    #[serde(default)]
+    /// The CNI provider of the pod network, whereas all providers except
+    /// `bridge` require a single node
+    cni: CniProvider,
+
+    #[get = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        env("KUBERNIX_NODE_RUNTIMES"),
+        long("node-runtime"),
+        multiple(true),
+        value_name("NODE=RUNTIME")
+    )]
+    #[serde(default)]
+    /// The CRI runtimes (`crio` or `containerd`) of individual nodes by their
+    /// index, whereas all other nodes use the `--cri` runtime
+    node_runtimes: Vec<String>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("runc"),
+        env("KUBERNIX_OCI_RUNTIME"),
+        long("oci-runtime"),
+        possible_values(OciRuntime::ALL),
+        value_name("RUNTIME")
+    )]
+    #[serde(default)]
+    /// The default low-level OCI runtime of the CRI runtimes
+    oci_runtime: OciRuntime,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_RUNTIME_CLASSES"),
+        long("runtime-class"),
+        multiple(true),
+        possible_values(OciRuntime::ALL),
+        value_name("RUNTIME")
+    )]
+    #[serde(default)]
+    /// Additional OCI runtimes, which can be selected by pods via their
+    /// RuntimeClass of the same name
+    runtime_classes: Vec<OciRuntime>,
+
     #[get = "pub"]
     #[clap(
         conflicts_with("shell"),
@@ -119,152 +355,2835 @@ pub struct Config {
     )]
     /// Do not spawn an interactive shell after bootstrap
     no_shell: bool,
-}
 
-/// Possible subcommands
-#[derive(Clap, Deserialize, Serialize)]
-pub enum SubCommand {
-    /// Spawn an additional shell session
-    #[clap(name("shell"))]
-    Shell,
-}
+    #[get_copy = "pub"]
+    #[clap(
+        env("KUBERNIX_BACKGROUND_ADDONS"),
+        long("background-addons"),
+        takes_value(false)
+    )]
+    #[serde(default)]
+    /// Apply the cluster addons in background after the control plane is ready
+    background_addons: bool,
 
-impl Default for Config {
-    fn default() -> Self {
-        let mut config = Self::parse();
-        if config.shell.is_none() {
-            config.shell = System::shell().ok();
-        }
-        config
-    }
-}
+    #[get_copy = "pub"]
+    #[clap(env("KUBERNIX_SELF_HOSTED"), long("self-hosted"), takes_value(false))]
+    #[serde(default)]
+    /// Run kube-proxy as DaemonSet inside the cluster instead of a process on the host
+    self_hosted: bool,
 
-impl Config {
-    const FILENAME: &'static str = "kubernix.toml";
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("iptables"),
+        env("KUBERNIX_PROXY_MODE"),
+        long("proxy-mode"),
+        possible_values(&["iptables", "ipvs", "nftables"]),
+        value_name("MODE")
+    )]
+    #[serde(default)]
+    /// The mode of kube-proxy, whereas `nftables` requires kube-proxy v1.29
+    /// or later
+    proxy_mode: ProxyMode,
 
-    /// Make the configs root path absolute
-    pub fn canonicalize_root(&mut self) -> Result<()> {
-        self.create_root_dir()?;
-        self.root =
-            canonicalize(self.root()).context("Unable to canonicalize config root directory")?;
-        Ok(())
-    }
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(env("KUBERNIX_NO_PROXY"), long("no-proxy"), takes_value(false))]
+    #[serde(default)]
+    /// Skip kube-proxy entirely, for example to test CNIs which replace it
+    no_proxy: bool,
 
-    /// Write the current configuration to the internal set root path
-    pub fn to_file(&self) -> Result<()> {
-        self.create_root_dir()?;
-        fs::write(self.root().join(Self::FILENAME), toml::to_string(&self)?)
-            .context("Unable to write configuration to file")?;
-        Ok(())
-    }
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_CLOUD_PROVIDER"),
+        long("cloud-provider"),
+        possible_values(&["external"]),
+        value_name("PROVIDER")
+    )]
+    #[serde(default)]
+    /// The cloud provider of the cluster, whereas `external` expects a cloud controller manager
+    /// to initialize the nodes
+    cloud_provider: Option<String>,
 
-    /// Read the configuration from the internal set root path
-    /// If not existing, write the current configuration to the path.
-    pub fn try_load_file(&mut self) -> Result<()> {
-        let file = self.root().join(Self::FILENAME);
-        if file.exists() {
-            *self = toml::from_str(&read_to_string(&file).with_context(|| {
-                format!(
-                    "Unable to read expected configuration file '{}'",
-                    file.display(),
-                )
-            })?)
-            .with_context(|| format!("Unable to load config file '{}'", file.display()))?;
-        } else {
-            self.to_file()?;
-        }
-        Ok(())
-    }
+    #[get_copy = "pub"]
+    #[clap(
+        env("KUBERNIX_FAKE_CLOUD_CONTROLLER_MANAGER"),
+        long("fake-cloud-controller-manager"),
+        requires("cloud-provider"),
+        takes_value(false)
+    )]
+    #[serde(default)]
+    /// Initialize the nodes of the external cloud provider like a cloud controller manager would
+    fake_cloud_controller_manager: bool,
 
-    /// Return the set shell as result type
-    pub fn shell_ok(&self) -> Result<String> {
-        let shell = self.shell.as_ref().context("No shell set")?;
-        Ok(shell.into())
-    }
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_SCHEDULER_EXTENDERS"),
+        long("scheduler-extender"),
+        multiple(true),
+        value_name("URL")
+    )]
+    #[serde(default)]
+    /// HTTP(S) scheduler extenders to be called for filtering and prioritizing nodes
+    scheduler_extenders: Vec<String>,
 
-    /// Returns true if multi node support is enabled
-    pub fn multi_node(&self) -> bool {
-        self.nodes() > 1
-    }
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_SECONDARY_SCHEDULER"),
+        long("secondary-scheduler"),
+        value_name("BINARY")
+    )]
+    #[serde(default)]
+    /// A kube-scheduler compatible binary from the nix environment to be run as secondary
+    /// scheduler, which serves pods requesting its binary name as scheduler name
+    secondary_scheduler: Option<String>,
 
-    fn create_root_dir(&self) -> Result<()> {
-        create_dir_all(self.root()).context("Unable to create root directory")
-    }
-}
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_SERVICE_ACCOUNT_KEYS"),
+        long("service-account-key"),
+        multiple(true),
+        value_name("PATH")
+    )]
+    #[serde(default)]
+    /// Additional public keys to verify service account tokens, for example
+    /// the ones of another cluster
+    service_account_keys: Vec<PathBuf>,
 
-#[cfg(test)]
-pub mod tests {
-    use super::*;
-    use std::path::Path;
-    use tempfile::tempdir;
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("ecdsa-p256"),
+        env("KUBERNIX_PKI_KEY_TYPE"),
+        long("pki-key-type"),
+        possible_values(&["rsa2048", "rsa4096", "ecdsa-p256", "ed25519"]),
+        value_name("TYPE")
+    )]
+    #[serde(default)]
+    /// The key type of the generated certificates, which only applies to a
+    /// newly created PKI
+    pki_key_type: PkiKeyType,
 
-    pub fn test_config() -> Result<Config> {
-        let mut c = Config::default();
-        c.root = tempdir()?.into_path();
-        c.canonicalize_root()?;
-        Ok(c)
-    }
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(env("KUBERNIX_SEED"), long("seed"), value_name("SEED"))]
+    #[serde(default)]
+    /// INSECURE: Generate the encryption key and the PKI deterministically
+    /// from the seed, which is only meant for tests
+    seed: Option<u64>,
 
-    pub fn test_config_wrong_root() -> Result<Config> {
-        let mut c = test_config()?;
-        c.root = Path::new("/").join("proc");
-        Ok(c)
-    }
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("aescbc"),
+        env("KUBERNIX_ENCRYPTION_PROVIDER"),
+        long("encryption-provider"),
+        possible_values(&["aescbc", "secretbox", "kms", "identity"]),
+        value_name("PROVIDER")
+    )]
+    #[serde(default)]
+    /// The provider encrypting secrets at rest, whereas the previous one is
+    /// kept for reading existing secrets if it changes
+    encryption_provider: EncryptionProvider,
 
-    pub fn test_config_wrong_cidr() -> Result<Config> {
-        let mut c = test_config()?;
-        c.cidr = "10.0.0.1/25".parse()?;
-        Ok(c)
-    }
+    #[get = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        env("KUBERNIX_KMS_ENDPOINT"),
+        long("kms-endpoint"),
+        value_name("SOCKET")
+    )]
+    /// The UNIX socket of the KMS v2 plugin used by the kms encryption
+    /// provider
+    kms_endpoint: Option<PathBuf>,
 
-    #[test]
-    fn canonicalize_root_success() -> Result<()> {
-        let mut c = Config::default();
-        c.root = tempdir()?.into_path();
-        c.canonicalize_root()
-    }
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("365"),
+        env("KUBERNIX_CERT_VALIDITY"),
+        long("cert-validity"),
+        value_name("DAYS")
+    )]
+    #[serde(default = "Config::default_cert_validity")]
+    /// The days the generated certificates are valid, which can be renewed
+    /// via `kubernix renew-certs`
+    cert_validity: u16,
 
-    #[test]
-    fn canonicalize_root_failure() {
-        let mut c = Config::default();
-        c.root = Path::new("/").join("proc").join("invalid");
-        assert!(c.canonicalize_root().is_err())
-    }
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_RECORD"),
+        long("record"),
+        multiple(true),
+        value_name("RESOURCE[.GROUP]")
+    )]
+    #[serde(default)]
+    /// Resources whose modifications are recorded in the API server audit log
+    /// to be replayed via `kubernix replay --audit`
+    record: Vec<String>,
 
-    #[test]
-    fn to_file_success() -> Result<()> {
-        let mut c = Config::default();
-        c.root = tempdir()?.into_path();
-        c.to_file()
-    }
+    #[get = "pub"]
+    #[clap(env("KUBERNIX_AUDIT_POLICY"), long("audit-policy"), value_name("PATH"))]
+    /// The audit policy of the API server to be used instead of the default
+    /// one, which records all requests at metadata level
+    audit_policy: Option<PathBuf>,
 
-    #[test]
-    fn to_file_failure() {
-        let mut c = Config::default();
-        c.root = Path::new("/").join("proc").join("invalid");
-        assert!(c.to_file().is_err())
-    }
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_RUNTIME_CONFIG"),
+        long("runtime-config"),
+        multiple(true),
+        value_name("API=BOOL")
+    )]
+    #[serde(default)]
+    /// API groups and versions to be enabled or disabled on top of all
+    /// default APIs, like `batch/v2alpha1=true` or `policy/v1beta1=false`
+    runtime_config: Vec<String>,
 
-    #[test]
-    fn try_load_file_success() -> Result<()> {
-        let mut c = Config::default();
-        c.root = tempdir()?.into_path();
-        fs::write(
-            c.root.join(Config::FILENAME),
-            r#"
-cidr = "1.1.1.1/16"
-container-runtime = "podman"
-log-level = "DEBUG"
-no-shell = false
-nodes = 1
-packages = []
-root = "root"
-            "#,
-        )?;
-        c.try_load_file()?;
-        assert_eq!(c.root(), Path::new("root"));
-        assert_eq!(c.log_level(), LevelFilter::Debug);
-        assert_eq!(&c.cidr().to_string(), "1.1.1.1/16");
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_FEATURE_GATES"),
+        long("feature-gates"),
+        multiple(true),
+        value_name("GATE=BOOL")
+    )]
+    #[serde(default)]
+    /// Kubernetes feature gates of all components, like `MemoryQoS=true`,
+    /// whereas multiple ones can be separated by commas as well
+    feature_gates: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        allow_hyphen_values(true),
+        env("KUBERNIX_APISERVER_ARGS"),
+        long("apiserver-arg"),
+        multiple(true),
+        number_of_values(1),
+        value_name("ARG")
+    )]
+    #[serde(default)]
+    /// Additional arguments appended to the ones of the API server, like `--v=4`
+    apiserver_args: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        allow_hyphen_values(true),
+        env("KUBERNIX_CONTROLLER_MANAGER_ARGS"),
+        long("controller-manager-arg"),
+        multiple(true),
+        number_of_values(1),
+        value_name("ARG")
+    )]
+    #[serde(default)]
+    /// Additional arguments appended to the ones of the controller manager, like `--v=4`
+    controller_manager_args: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        allow_hyphen_values(true),
+        env("KUBERNIX_SCHEDULER_ARGS"),
+        long("scheduler-arg"),
+        multiple(true),
+        number_of_values(1),
+        value_name("ARG")
+    )]
+    #[serde(default)]
+    /// Additional arguments appended to the ones of the scheduler, like `--v=4`
+    scheduler_args: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        allow_hyphen_values(true),
+        env("KUBERNIX_KUBELET_ARGS"),
+        long("kubelet-arg"),
+        multiple(true),
+        number_of_values(1),
+        value_name("ARG")
+    )]
+    #[serde(default)]
+    /// Additional arguments appended to the ones of the kubelets, like `--v=4`
+    kubelet_args: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        allow_hyphen_values(true),
+        env("KUBERNIX_ETCD_ARGS"),
+        long("etcd-arg"),
+        multiple(true),
+        number_of_values(1),
+        value_name("ARG")
+    )]
+    #[serde(default)]
+    /// Additional arguments appended to the ones of etcd, like `--log-level=debug`
+    etcd_args: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_MANIFESTS"),
+        long("manifest"),
+        multiple(true),
+        value_name("PATH")
+    )]
+    #[serde(default)]
+    /// Additional Kubernetes manifests to be applied as cluster addons
+    manifests: Vec<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_APPLY_MANIFESTS"),
+        long("apply-manifests"),
+        value_name("DIR")
+    )]
+    #[serde(default)]
+    /// A directory whose YAML manifests get applied as cluster addon in
+    /// alphabetical order
+    apply_manifests: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_WAIT_FOR"),
+        long("wait-for"),
+        multiple(true),
+        value_name("[NAMESPACE/]SELECTOR")
+    )]
+    #[serde(default)]
+    /// Label selectors of pods which have to be ready after applying the
+    /// `--apply-manifests` directory
+    wait_for: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(env("KUBERNIX_SPEC"), long("spec"), value_name("PATH"))]
+    #[serde(default)]
+    /// A declarative cluster spec file describing the nodes, addons,
+    /// manifests and component patches
+    spec: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        env("KUBERNIX_ADDONS"),
+        long("addons"),
+        multiple(true),
+        possible_values(Config::ADDONS),
+        value_name("ADDON")
+    )]
+    #[serde(default)]
+    /// Optional cluster addons to be deployed after CoreDNS
+    addons: Vec<String>,
+
+    #[get = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(env("KUBERNIX_HTTP_PROXY"), long("http-proxy"), value_name("URL"))]
+    #[serde(default)]
+    /// The proxy for outgoing HTTP requests, for example of nix and the
+    /// container runtimes when pulling images
+    http_proxy: Option<String>,
+
+    #[get = "pub"]
+    #[clap(env("KUBERNIX_HTTPS_PROXY"), long("https-proxy"), value_name("URL"))]
+    #[serde(default)]
+    /// The proxy for outgoing HTTPS requests, which falls back to the HTTP proxy
+    https_proxy: Option<String>,
+
+    #[get = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        env("KUBERNIX_PROXY_EXCLUSIONS"),
+        long("proxy-exclude"),
+        multiple(true),
+        value_name("HOST")
+    )]
+    #[serde(default)]
+    /// Additional hosts, domains or CIDRs which are not accessed via the
+    /// proxy, whereas the cluster internal ones are always excluded
+    proxy_exclusions: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_INSECURE_REGISTRIES"),
+        long("insecure-registry"),
+        multiple(true),
+        value_name("REGISTRY")
+    )]
+    #[serde(default)]
+    /// Registries (`host:port`) to be accessed without TLS verification
+    insecure_registries: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(env("KUBERNIX_AUTH_FILE"), long("auth-file"), value_name("PATH"))]
+    /// Registry credentials file in containers-auth.json(5) format used for image pulls
+    auth_file: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_REGISTRY_LOGINS"),
+        long("registry-login"),
+        multiple(true),
+        value_name("REGISTRY=USER:PASSWORD")
+    )]
+    #[serde(skip)]
+    /// Additional registry credentials used for image pulls
+    registry_logins: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_IMAGE_MIRROR"),
+        long("image-mirror"),
+        value_name("PREFIX")
+    )]
+    #[serde(default)]
+    /// The registry prefix which replaces the registry of all default images
+    image_mirror: Option<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_IMAGES"),
+        long("image"),
+        multiple(true),
+        value_name("NAME=IMAGE")
+    )]
+    #[serde(default)]
+    /// Image overrides for the addons (`coredns`, `dashboard`, `ingress`,
+    /// `metrics-server`), the pause image (`pause`) and the node base image
+    /// (`nix`)
+    images: Vec<String>,
+
+    #[get_copy = "pub"]
+    #[clap(env("KUBERNIX_PULL_SECRET"), long("pull-secret"), takes_value(false))]
+    #[serde(default)]
+    /// Provide the registry credentials as image pull secret in the default namespace
+    pull_secret: bool,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("85"),
+        env("KUBERNIX_IMAGE_GC_HIGH_THRESHOLD"),
+        long("image-gc-high-threshold"),
+        value_name("PERCENT")
+    )]
+    #[serde(default = "Config::default_image_gc_high_threshold")]
+    /// The disk usage percentage after which the kubelet image garbage collection always runs
+    image_gc_high_threshold: u8,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("80"),
+        env("KUBERNIX_IMAGE_GC_LOW_THRESHOLD"),
+        long("image-gc-low-threshold"),
+        value_name("PERCENT")
+    )]
+    #[serde(default = "Config::default_image_gc_low_threshold")]
+    /// The disk usage percentage to which the kubelet image garbage collection frees space
+    image_gc_low_threshold: u8,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_STORAGE_QUOTA"),
+        long("storage-quota"),
+        value_name("SIZE")
+    )]
+    /// The maximum size of a single container storage layer (e.g. `10G`), overlay driver only
+    storage_quota: Option<String>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        allow_hyphen_values(true),
+        default_value("-1"),
+        env("KUBERNIX_LOG_SIZE_MAX"),
+        long("log-size-max"),
+        value_name("BYTES")
+    )]
+    #[serde(default = "Config::default_log_size_max")]
+    /// The maximum size of a single container log file before conmon truncates it, negative
+    /// values disable the limit
+    log_size_max: i64,
+
+    #[get = "pub"]
+    #[clap(
+        default_value("10Mi"),
+        env("KUBERNIX_CONTAINER_LOG_MAX_SIZE"),
+        long("container-log-max-size"),
+        value_name("SIZE")
+    )]
+    #[serde(default = "Config::default_container_log_max_size")]
+    /// The size at which the kubelet rotates a container log file
+    container_log_max_size: String,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("5"),
+        env("KUBERNIX_CONTAINER_LOG_MAX_FILES"),
+        long("container-log-max-files"),
+        value_name("COUNT")
+    )]
+    #[serde(default = "Config::default_container_log_max_files")]
+    /// The maximum number of rotated log files the kubelet keeps per container
+    container_log_max_files: u8,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("2147483648"),
+        env("KUBERNIX_ETCD_QUOTA_BACKEND_BYTES"),
+        long("etcd-quota-backend-bytes"),
+        value_name("BYTES")
+    )]
+    #[serde(default = "Config::default_etcd_quota_backend_bytes")]
+    /// The size of the etcd backend database after which etcd raises a space quota alarm
+    etcd_quota_backend_bytes: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("100"),
+        env("KUBERNIX_ETCD_HEARTBEAT_INTERVAL"),
+        long("etcd-heartbeat-interval"),
+        value_name("MILLISECONDS")
+    )]
+    #[serde(default = "Config::default_etcd_heartbeat_interval")]
+    /// The etcd heartbeat interval
+    etcd_heartbeat_interval: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("1000"),
+        env("KUBERNIX_ETCD_ELECTION_TIMEOUT"),
+        long("etcd-election-timeout"),
+        value_name("MILLISECONDS")
+    )]
+    #[serde(default = "Config::default_etcd_election_timeout")]
+    /// The etcd election timeout, which has to be at least five times the heartbeat interval
+    etcd_election_timeout: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("100000"),
+        env("KUBERNIX_ETCD_SNAPSHOT_COUNT"),
+        long("etcd-snapshot-count"),
+        value_name("COUNT")
+    )]
+    #[serde(default = "Config::default_etcd_snapshot_count")]
+    /// The number of committed etcd transactions which trigger a snapshot to disk
+    etcd_snapshot_count: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        env("KUBERNIX_SINGLE_BINARY"),
+        long("single-binary"),
+        takes_value(false)
+    )]
+    #[serde(default)]
+    /// Replace etcd by kine, which stores the cluster state in a sqlite database within the run root
+    single_binary: bool,
+
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        env("KUBERNIX_CONTROL_PLANE_CONTAINER"),
+        long("control-plane-container"),
+        takes_value(false)
+    )]
+    #[serde(default)]
+    /// Run etcd, the API server, controller manager and scheduler inside a
+    /// dedicated container, like the nodes on multi node clusters
+    control_plane_container: bool,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("never"),
+        env("KUBERNIX_RESTART_POLICY"),
+        long("restart-policy"),
+        possible_values(&["never", "on-failure", "always"]),
+        value_name("POLICY")
+    )]
+    #[serde(default)]
+    /// Restart crashed component processes, either only if they exited with
+    /// a failure or on every unexpected exit
+    restart_policy: RestartPolicy,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("5"),
+        env("KUBERNIX_MAX_RESTARTS"),
+        long("max-restarts"),
+        value_name("COUNT")
+    )]
+    #[serde(default = "Config::default_max_restarts")]
+    /// The maximum number of automatic restarts per component process
+    max_restarts: u32,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("120"),
+        env("KUBERNIX_READINESS_TIMEOUT"),
+        long("readiness-timeout"),
+        value_name("SECONDS")
+    )]
+    #[serde(default = "Config::default_readiness_timeout")]
+    /// The seconds every component process has to become ready, which can
+    /// be overridden per component via `component-timeout`
+    readiness_timeout: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("KUBERNIX_READINESS_RETRIES"),
+        long("readiness-retries"),
+        value_name("COUNT")
+    )]
+    #[serde(default)]
+    /// The number of times a component process gets started again if it
+    /// does not become ready in time
+    readiness_retries: u32,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("10"),
+        env("KUBERNIX_STOP_GRACE_PERIOD"),
+        long("stop-grace-period"),
+        value_name("SECONDS")
+    )]
+    #[serde(default = "Config::default_stop_grace_period")]
+    /// The seconds a component process gets to exit on SIGTERM before its
+    /// process group gets killed
+    stop_grace_period: u64,
+
+    #[get_copy = "pub"]
+    #[clap(env("KUBERNIX_EPHEMERAL"), long("ephemeral"), takes_value(false))]
+    #[serde(default)]
+    /// Mount a tmpfs at the run root, which discards all cluster data on exit
+    ephemeral: bool,
+
+    #[get = "pub"]
+    #[clap(
+        default_value("4G"),
+        env("KUBERNIX_EPHEMERAL_SIZE"),
+        long("ephemeral-size"),
+        value_name("SIZE")
+    )]
+    #[serde(default = "Config::default_ephemeral_size")]
+    /// The maximum size of the ephemeral run root tmpfs
+    ephemeral_size: String,
+
+    #[clap(env("KUBERNIX_SOCKET_DIR"), long("socket-dir"), value_name("PATH"))]
+    #[serde(default)]
+    /// The directory where all UNIX sockets are created, defaults to
+    /// `/run/kubernix/<hash>`
+    socket_dir: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("7"),
+        env("KUBERNIX_GC_LOG_RETENTION"),
+        long("gc-log-retention"),
+        value_name("DAYS")
+    )]
+    #[serde(default = "Config::default_gc_log_retention")]
+    /// The days rotated pod logs and audit log backups are kept before
+    /// `kubernix gc` removes them
+    gc_log_retention: u16,
+
+    #[get_copy = "pub"]
+    #[cfg_attr(test, set = "pub(crate)")]
+    #[clap(
+        default_value("1"),
+        env("KUBERNIX_GC_ETCD_SNAPSHOTS"),
+        long("gc-etcd-snapshots"),
+        value_name("COUNT")
+    )]
+    #[serde(default = "Config::default_gc_etcd_snapshots")]
+    /// The number of newest etcd snapshots kept by `kubernix gc`
+    gc_etcd_snapshots: u8,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_COMPONENT_TIMEOUTS"),
+        long("component-timeout"),
+        multiple(true),
+        value_name("COMPONENT=SECONDS")
+    )]
+    // Tables have to be serialized after all values, which is why this
+    // and the following field have to be the last ones
+    #[serde(
+        default,
+        deserialize_with = "Config::deserialize_component_timeouts",
+        serialize_with = "Config::serialize_component_timeouts"
+    )]
+    /// The readiness timeouts of individual components in seconds, like
+    /// `apiserver=300`, which are stored as `[component-timeouts]` table
+    component_timeouts: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("KUBERNIX_COMPONENT_ENV"),
+        long("component-env"),
+        multiple(true),
+        value_name("COMPONENT:NAME=VALUE")
+    )]
+    #[serde(
+        default,
+        deserialize_with = "Config::deserialize_component_env",
+        serialize_with = "Config::serialize_component_env"
+    )]
+    /// Additional environment variables of the component processes, like
+    /// `apiserver:GOGC=50`, which are stored as `[component-env]` table
+    component_env: Vec<String>,
+}
+
+/// Possible subcommands
+#[derive(Clap, Deserialize, Serialize)]
+pub enum SubCommand {
+    /// Spawn an additional shell session
+    #[clap(name("shell"))]
+    Shell,
+
+    /// Manage the container images of the nodes
+    #[clap(name("images"))]
+    Images(Images),
+
+    /// Manage the nodes of the running cluster
+    #[clap(name("node"))]
+    Node(NodeCommand),
+
+    /// Create short-lived credentials for the running cluster
+    #[clap(name("token"))]
+    Token(TokenCommand),
+
+    /// Run single phases of the bootstrap
+    #[clap(name("phase"))]
+    Phase(PhaseCommand),
+
+    /// Build a container image via podman and load it into the CRI-O storage
+    /// of every node
+    #[clap(name("build-and-load"))]
+    BuildAndLoad {
+        #[clap(value_name("IMAGE"))]
+        /// The name of the image, which can be used by pods afterwards
+        image: String,
+
+        #[clap(default_value("."), value_name("CONTEXT"))]
+        /// The build context directory
+        context: PathBuf,
+
+        #[clap(long("file"), value_name("PATH"))]
+        /// The Dockerfile to be used instead of the one within the context
+        file: Option<PathBuf>,
+    },
+
+    /// Run kubectl by using the admin kubeconfig of the cluster
+    #[clap(
+        name("kubectl"),
+        setting(AppSettings::AllowLeadingHyphen),
+        setting(AppSettings::TrailingVarArg)
+    )]
+    Kubectl {
+        #[clap(multiple(true), value_name("ARGS"))]
+        /// Arguments passed to kubectl
+        args: Vec<String>,
+    },
+
+    /// Restart a component of the running cluster and all components which
+    /// depend on it from their run files
+    #[clap(name("restart"))]
+    Restart {
+        #[clap(possible_values(Unit::COMPONENTS), value_name("COMPONENT"))]
+        /// The component to be restarted
+        component: String,
+
+        #[clap(default_value("0"), long("node"), value_name("NODE"))]
+        /// The number of the node for the crio, kubelet and proxy components
+        node: u8,
+
+        #[clap(default_value("0"), long("downtime"), value_name("SECONDS"))]
+        /// The seconds to wait until the components get started again
+        downtime: u64,
+    },
+
+    /// Validate the edited configuration file of a component and restart it
+    /// together with all components which depend on it
+    #[clap(name("reload"))]
+    Reload {
+        #[clap(possible_values(ConfigFile::COMPONENTS), value_name("COMPONENT"))]
+        /// The component to be reloaded
+        component: String,
+
+        #[clap(default_value("0"), long("node"), value_name("NODE"))]
+        /// The number of the node for the crio, kubelet and proxy components
+        node: u8,
+    },
+
+    /// Renew all certificates against the existing certificate authorities and
+    /// restart the components of the running cluster
+    #[clap(name("renew-certs"))]
+    RenewCerts,
+
+    /// Prepend a new encryption key, rewrite all secrets of the running
+    /// cluster and prune the previous keys and providers afterwards
+    #[clap(name("rotate-encryption-key"))]
+    RotateEncryptionKey,
+
+    /// Remove rotated logs, old etcd snapshots and unused container images
+    /// according to the retention settings
+    #[clap(name("gc"))]
+    Gc {
+        #[clap(long("dry-run"), takes_value(false))]
+        /// Only print the files which would be removed
+        dry_run: bool,
+    },
+
+    /// Gracefully stop the running cluster and wait for its cleanup
+    #[clap(name("stop"))]
+    Stop {
+        #[clap(default_value("120"), long("timeout"), value_name("SECONDS"))]
+        /// The time to wait for the cleanup of the cluster
+        timeout: u64,
+    },
+
+    /// Remove the state left behind by a killed kubernix instance, like
+    /// mounts, node containers and hosts file entries
+    #[clap(name("cleanup"))]
+    Cleanup {
+        #[clap(long("remove-root"), takes_value(false))]
+        /// Delete the run root afterwards, including all logs and configs
+        remove_root: bool,
+    },
+
+    /// Validate manifests against the running API server via a server side
+    /// dry run
+    #[clap(name("check"))]
+    Check {
+        #[clap(value_name("PATH"))]
+        /// The manifest file or directory, which is searched recursively for
+        /// .yaml, .yml and .json files
+        path: PathBuf,
+    },
+
+    /// Expose the API server on localhost for the invoking user
+    #[clap(name("proxy"))]
+    Proxy {
+        #[clap(default_value("8001"), long("port"), value_name("PORT"))]
+        /// The localhost port to serve the API on
+        port: u16,
+    },
+
+    /// Show the status of the cluster
+    #[clap(name("status"))]
+    Status {
+        #[clap(
+            default_value("text"),
+            long("output"),
+            possible_values(&["text", "json"]),
+            short('o'),
+            value_name("FORMAT")
+        )]
+        /// The output format
+        output: OutputFormat,
+    },
+
+    /// List the log files of the cluster or print the log of a component
+    #[clap(name("logs"))]
+    Logs {
+        #[clap(long("pods"), takes_value(false))]
+        /// List the container logs of all pods across the nodes
+        pods: bool,
+
+        #[clap(
+            conflicts_with_all(&["pods", "filter"]),
+            long("component"),
+            short('c'),
+            value_name("COMPONENT")
+        )]
+        /// Print the log of the component with the provided name, like etcd,
+        /// apiserver or kubelet-1
+        component: Option<String>,
+
+        #[clap(long("follow"), requires("component"), short('f'), takes_value(false))]
+        /// Keep printing new lines of the component log
+        follow: bool,
+
+        #[clap(long("since"), requires("component"), value_name("DURATION"))]
+        /// Only print lines of the component log newer than the duration,
+        /// like 30s, 10m or 2h
+        since: Option<String>,
+
+        #[clap(value_name("FILTER"))]
+        /// Only list logs whose name contains the filter
+        filter: Option<String>,
+    },
+
+    /// Re-execute the recorded commands of the last bootstrap or convert the
+    /// recorded API server audit log into a kubectl script
+    #[clap(name("replay"))]
+    Replay {
+        #[clap(long("audit"), conflicts_with("until"))]
+        /// Convert the API server audit log instead of re-executing commands
+        audit: bool,
+
+        #[clap(long("until"), value_name("PHASE"))]
+        /// Stop after the last command of the bootstrap phase, for example
+        /// `certificates` or `processes`, which is required if running as
+        /// root
+        until: Option<String>,
+
+        #[clap(long("user"), multiple(true), requires("audit"), value_name("USER"))]
+        /// Only replay the requests of the users, defaults to all non system
+        /// users and service accounts outside of kube-system
+        users: Vec<String>,
+
+        #[clap(value_name("FILE"))]
+        /// The recorded commands or audit log, defaults to the one of the
+        /// current run root
+        file: Option<PathBuf>,
+    },
+
+    /// Print the environment of the cluster as export lines for eval
+    #[clap(name("env"))]
+    Env {
+        #[clap(
+            default_value("bash"),
+            long("shell"),
+            possible_values(&["bash", "fish", "zsh"]),
+            value_name("SHELL")
+        )]
+        /// The shell syntax of the export lines
+        shell: EnvShell,
+    },
+
+    /// List all clusters next to the current run root
+    #[clap(name("list"))]
+    List {
+        #[clap(
+            default_value("text"),
+            long("output"),
+            possible_values(&["text", "json"]),
+            short('o'),
+            value_name("FORMAT")
+        )]
+        /// The output format
+        output: OutputFormat,
+    },
+
+    /// Show the version of kubernix
+    #[clap(name("version"))]
+    Version {
+        #[clap(
+            default_value("text"),
+            long("output"),
+            possible_values(&["text", "json"]),
+            short('o'),
+            value_name("FORMAT")
+        )]
+        /// The output format
+        output: OutputFormat,
+    },
+}
+
+/// The output formats of the inspection subcommands
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum OutputFormat {
+    /// Human readable text
+    Text,
+
+    /// Structured JSON
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => bail!("Unknown output format: {}", s),
+        }
+    }
+}
+
+/// The shells supported by the env subcommand
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum EnvShell {
+    /// GNU Bourne-Again SHell
+    Bash,
+
+    /// Friendly interactive shell
+    Fish,
+
+    /// Z shell
+    Zsh,
+}
+
+impl FromStr for EnvShell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bash" => Ok(EnvShell::Bash),
+            "fish" => Ok(EnvShell::Fish),
+            "zsh" => Ok(EnvShell::Zsh),
+            _ => bail!("Unknown shell: {}", s),
+        }
+    }
+}
+
+/// The individually runnable phases of the bootstrap
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum BootstrapPhase {
+    /// Kernel modules, sysctls and host entries
+    System,
+
+    /// The container image of the nodes and the control plane container
+    Image,
+
+    /// The validated network with stale interfaces removed
+    Network,
+
+    /// The certificate authorities and certificates
+    Pki,
+
+    /// The kubeconfigs together with the encryption config, cluster info
+    /// and registry credentials
+    KubeConfig,
+
+    /// The component processes of the running cluster
+    Processes,
+
+    /// The addons and manifests of the running cluster
+    Addons,
+}
+
+impl BootstrapPhase {
+    /// The names of all phases in bootstrap order
+    pub const NAMES: &'static [&'static str] = &[
+        "system",
+        "image",
+        "network",
+        "pki",
+        "kubeconfig",
+        "processes",
+        "addons",
+    ];
+}
+
+impl FromStr for BootstrapPhase {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "system" => Ok(BootstrapPhase::System),
+            "image" => Ok(BootstrapPhase::Image),
+            "network" => Ok(BootstrapPhase::Network),
+            "pki" => Ok(BootstrapPhase::Pki),
+            "kubeconfig" => Ok(BootstrapPhase::KubeConfig),
+            "processes" => Ok(BootstrapPhase::Processes),
+            "addons" => Ok(BootstrapPhase::Addons),
+            _ => bail!("Unknown bootstrap phase: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for BootstrapPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BootstrapPhase::System => "system",
+            BootstrapPhase::Image => "image",
+            BootstrapPhase::Network => "network",
+            BootstrapPhase::Pki => "pki",
+            BootstrapPhase::KubeConfig => "kubeconfig",
+            BootstrapPhase::Processes => "processes",
+            BootstrapPhase::Addons => "addons",
+        })
+    }
+}
+
+/// The policy for colored output and the animated progress bar
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum ColorMode {
+    /// Only if the output is an interactive terminal
+    Auto,
+
+    /// Independently of the output
+    Always,
+
+    /// Plain output without any progress bar
+    Never,
+}
+
+impl Default for ColorMode {
+    fn default() -> Self {
+        ColorMode::Auto
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => bail!("Unknown color mode: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColorMode::Auto => "auto",
+            ColorMode::Always => "always",
+            ColorMode::Never => "never",
+        })
+    }
+}
+
+impl ColorMode {
+    /// Apply the policy to all colored output, whereas `auto` keeps the
+    /// terminal detection of stdout and stderr
+    pub fn apply(self) {
+        let enabled = match self {
+            ColorMode::Auto => return,
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        };
+        set_colors_enabled(enabled);
+        set_colors_enabled_stderr(enabled);
+    }
+
+    /// Returns true if the progress bar should be animated, which is only
+    /// visible on an interactive terminal anyway
+    pub fn animated(self) -> bool {
+        self != ColorMode::Never && user_attended_stderr()
+    }
+}
+
+/// The CRI runtime of a node
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NodeRuntime {
+    /// CRI-O
+    Crio,
+
+    /// containerd
+    Containerd,
+}
+
+impl Default for NodeRuntime {
+    fn default() -> Self {
+        NodeRuntime::Crio
+    }
+}
+
+impl FromStr for NodeRuntime {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "crio" => Ok(NodeRuntime::Crio),
+            "containerd" => Ok(NodeRuntime::Containerd),
+            _ => bail!("Unknown node runtime: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for NodeRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NodeRuntime::Crio => "crio",
+            NodeRuntime::Containerd => "containerd",
+        })
+    }
+}
+
+/// The low-level OCI runtime of the containers
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OciRuntime {
+    /// The reference implementation
+    Runc,
+
+    /// The lightweight runtime written in C
+    Crun,
+
+    /// Kata Containers, which run inside lightweight VMs
+    Kata,
+
+    /// gVisor, which runs containers on an application kernel
+    Gvisor,
+}
+
+impl Default for OciRuntime {
+    fn default() -> Self {
+        OciRuntime::Runc
+    }
+}
+
+impl OciRuntime {
+    /// The names of all available runtimes
+    pub const ALL: &'static [&'static str] = &["runc", "crun", "kata", "gvisor"];
+
+    /// The name of the runtime
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OciRuntime::Runc => "runc",
+            OciRuntime::Crun => "crun",
+            OciRuntime::Kata => "kata",
+            OciRuntime::Gvisor => "gvisor",
+        }
+    }
+}
+
+impl FromStr for OciRuntime {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "runc" => Ok(OciRuntime::Runc),
+            "crun" => Ok(OciRuntime::Crun),
+            "kata" => Ok(OciRuntime::Kata),
+            "gvisor" => Ok(OciRuntime::Gvisor),
+            _ => bail!("Unknown OCI runtime: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for OciRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The service proxy implementation of kube-proxy
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProxyMode {
+    /// iptables rules
+    Iptables,
+
+    /// IP Virtual Server load balancing
+    Ipvs,
+
+    /// nftables rules
+    Nftables,
+}
+
+impl Default for ProxyMode {
+    fn default() -> Self {
+        ProxyMode::Iptables
+    }
+}
+
+impl FromStr for ProxyMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "iptables" => Ok(ProxyMode::Iptables),
+            "ipvs" => Ok(ProxyMode::Ipvs),
+            "nftables" => Ok(ProxyMode::Nftables),
+            _ => bail!("Unknown proxy mode: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for ProxyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ProxyMode::Iptables => "iptables",
+            ProxyMode::Ipvs => "ipvs",
+            ProxyMode::Nftables => "nftables",
+        })
+    }
+}
+
+/// The policy to restart crashed component processes
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Keep crashed processes stopped
+    Never,
+
+    /// Restart processes exiting with a failure
+    OnFailure,
+
+    /// Restart processes on every unexpected exit
+    Always,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::Never
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "never" => Ok(RestartPolicy::Never),
+            "on-failure" => Ok(RestartPolicy::OnFailure),
+            "always" => Ok(RestartPolicy::Always),
+            _ => bail!("Unknown restart policy: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RestartPolicy::Never => "never",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Always => "always",
+        })
+    }
+}
+
+/// The provider of the pod network
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CniProvider {
+    /// The CNI bridge plugin with host-local IPAM
+    Bridge,
+
+    /// flannel
+    Flannel,
+
+    /// Calico in policy-only mode
+    Calico,
+
+    /// Cilium
+    Cilium,
+}
+
+impl Default for CniProvider {
+    fn default() -> Self {
+        CniProvider::Bridge
+    }
+}
+
+impl FromStr for CniProvider {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bridge" => Ok(CniProvider::Bridge),
+            "flannel" => Ok(CniProvider::Flannel),
+            "calico" => Ok(CniProvider::Calico),
+            "cilium" => Ok(CniProvider::Cilium),
+            _ => bail!("Unknown CNI provider: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for CniProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CniProvider::Bridge => "bridge",
+            CniProvider::Flannel => "flannel",
+            CniProvider::Calico => "calico",
+            CniProvider::Cilium => "cilium",
+        })
+    }
+}
+
+/// The key type of the generated certificates
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PkiKeyType {
+    /// RSA with 2048 bits
+    Rsa2048,
+
+    /// RSA with 4096 bits
+    Rsa4096,
+
+    /// ECDSA on the P-256 curve
+    EcdsaP256,
+
+    /// EdDSA on Curve25519
+    Ed25519,
+}
+
+impl Default for PkiKeyType {
+    fn default() -> Self {
+        PkiKeyType::EcdsaP256
+    }
+}
+
+impl FromStr for PkiKeyType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rsa2048" => Ok(PkiKeyType::Rsa2048),
+            "rsa4096" => Ok(PkiKeyType::Rsa4096),
+            "ecdsa-p256" => Ok(PkiKeyType::EcdsaP256),
+            "ed25519" => Ok(PkiKeyType::Ed25519),
+            _ => bail!("Unknown PKI key type: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for PkiKeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PkiKeyType::Rsa2048 => "rsa2048",
+            PkiKeyType::Rsa4096 => "rsa4096",
+            PkiKeyType::EcdsaP256 => "ecdsa-p256",
+            PkiKeyType::Ed25519 => "ed25519",
+        })
+    }
+}
+
+/// The provider encrypting secrets at rest
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EncryptionProvider {
+    /// AES-CBC with PKCS#7 padding
+    Aescbc,
+
+    /// XSalsa20 and Poly1305
+    Secretbox,
+
+    /// Envelope encryption via a KMS v2 plugin
+    Kms,
+
+    /// No encryption at all
+    Identity,
+}
+
+impl Default for EncryptionProvider {
+    fn default() -> Self {
+        EncryptionProvider::Aescbc
+    }
+}
+
+impl FromStr for EncryptionProvider {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "aescbc" => Ok(EncryptionProvider::Aescbc),
+            "secretbox" => Ok(EncryptionProvider::Secretbox),
+            "kms" => Ok(EncryptionProvider::Kms),
+            "identity" => Ok(EncryptionProvider::Identity),
+            _ => bail!("Unknown encryption provider: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for EncryptionProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EncryptionProvider::Aescbc => "aescbc",
+            EncryptionProvider::Secretbox => "secretbox",
+            EncryptionProvider::Kms => "kms",
+            EncryptionProvider::Identity => "identity",
+        })
+    }
+}
+
+/// The images subcommand
+#[derive(Clap, Deserialize, Serialize)]
+pub struct Images {
+    #[clap(subcommand)]
+    /// All available images subcommands
+    pub subcommand: ImagesSubCommand,
+}
+
+/// Possible images subcommands
+#[derive(Clap, Deserialize, Serialize)]
+pub enum ImagesSubCommand {
+    /// Remove all unused container images on every node
+    #[clap(name("prune"))]
+    Prune,
+}
+
+/// The node subcommand
+#[derive(Clap, Deserialize, Serialize)]
+pub struct NodeCommand {
+    #[clap(subcommand)]
+    /// All available node subcommands
+    pub subcommand: NodeSubCommand,
+}
+
+/// Possible node subcommands
+#[derive(Clap, Deserialize, Serialize)]
+pub enum NodeSubCommand {
+    /// Stop the kubelet and CRI-O of the node and start them again after the
+    /// downtime by keeping the node identity
+    #[clap(name("restart"))]
+    Restart {
+        #[clap(value_name("NODE"))]
+        /// The number of the node to be restarted, starting at zero
+        node: u8,
+
+        #[clap(default_value("30"), long("downtime"), value_name("SECONDS"))]
+        /// The seconds to wait until the node gets started again
+        downtime: u64,
+    },
+}
+
+/// The phase subcommand
+#[derive(Clap, Deserialize, Serialize)]
+pub struct PhaseCommand {
+    #[clap(subcommand)]
+    /// All available phase subcommands
+    pub subcommand: PhaseSubCommand,
+}
+
+/// Possible phase subcommands
+#[derive(Clap, Deserialize, Serialize)]
+pub enum PhaseSubCommand {
+    /// Run a single phase of the bootstrap, which keeps all existing
+    /// artifacts. The processes and addons phases apply to the running
+    /// cluster.
+    #[clap(name("run"))]
+    Run {
+        #[clap(possible_values(BootstrapPhase::NAMES), value_name("PHASE"))]
+        /// The phase to be run
+        phase: BootstrapPhase,
+    },
+}
+
+/// The token subcommand
+#[derive(Clap, Deserialize, Serialize)]
+pub struct TokenCommand {
+    #[clap(subcommand)]
+    /// All available token subcommands
+    pub subcommand: TokenSubCommand,
+}
+
+/// Possible token subcommands
+#[derive(Clap, Deserialize, Serialize)]
+pub enum TokenSubCommand {
+    /// Print a kubeconfig which authenticates via a short-lived service
+    /// account token instead of the admin client certificate
+    #[clap(name("kubeconfig"))]
+    Kubeconfig {
+        #[clap(
+            default_value("kubernix-user"),
+            long("service-account"),
+            value_name("NAME")
+        )]
+        /// The service account to be created if it does not exist
+        service_account: String,
+
+        #[clap(default_value("default"), long("namespace"), value_name("NAMESPACE"))]
+        /// The namespace of the service account
+        namespace: String,
+
+        #[clap(default_value("view"), long("cluster-role"), value_name("ROLE"))]
+        /// The cluster role bound to the service account
+        cluster_role: String,
+
+        #[clap(default_value("3600"), long("duration"), value_name("SECONDS"))]
+        /// The seconds until the token expires, which has to be at least 600
+        duration: u64,
+
+        #[clap(long("server"), value_name("URL"))]
+        /// The API server address used within the kubeconfig, which defaults
+        /// to the local API server port and can be changed to share the
+        /// cluster with other machines
+        server: Option<String>,
+    },
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        // Former versions supported only a single overlay
+        if var_os("KUBERNIX_OVERLAYS").is_none() {
+            if let Some(overlay) = var_os("KUBERNIX_OVERLAY") {
+                set_var("KUBERNIX_OVERLAYS", overlay);
+            }
+        }
+        let matches = Self::into_app().get_matches();
+        let mut config = Self::from_arg_matches(&matches);
+        config.requested = Self::into_app()
+            .get_arguments()
+            .map(|x| x.get_name())
+            .filter(|x| {
+                matches.occurrences_of(x) > 0
+                    || var_os(format!("KUBERNIX_{}", x.to_uppercase().replace('-', "_"))).is_some()
+            })
+            .map(|x| x.into())
+            .collect();
+        if config.shell.is_none() {
+            config.shell = System::shell().ok();
+        }
+        if let Some(name) = &config.name {
+            config.root = Self::named_root(&config.root, name);
+        }
+        config
+    }
+}
+
+impl Config {
+    /// The name of the configuration file inside the run root
+    pub const FILENAME: &'static str = "kubernix.toml";
+
+    /// The names of all optional addons
+    pub const ADDONS: &'static [&'static str] = &[
+        "cert-manager",
+        "dashboard",
+        "ingress",
+        "logging",
+        "metrics-server",
+        "observability",
+    ];
+
+    /// Retrieve the run root of the named cluster, which is a sibling of the
+    /// provided root
+    fn named_root(root: &Path, name: &str) -> PathBuf {
+        match root.file_name() {
+            Some(x) => root.with_file_name(format!("{}-{}", x.to_string_lossy(), name)),
+            None => root.join(name),
+        }
+    }
+
+    /// Verify that the cluster name is usable within container names
+    pub fn validate_name(&self) -> Result<()> {
+        if let Some(name) = self.name() {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || x == '-')
+                || name.starts_with('-')
+                || name.ends_with('-')
+            {
+                bail!(
+                    "Invalid cluster name '{}', expected lowercase alphanumeric characters or '-'",
+                    name
+                )
+            }
+        }
+        Ok(())
+    }
+
+    /// Retrieve the name of the cluster, which prefixes its containers and
+    /// names the kubeconfig context
+    pub fn cluster_name(&self) -> String {
+        match self.name() {
+            Some(name) => format!("kubernix-{}", name),
+            None => "kubernix".into(),
+        }
+    }
+
+    /// Retrieve the run roots of all clusters next to the unnamed one by
+    /// their names, whereas the unnamed cluster has no name
+    pub fn clusters(&self) -> Result<Vec<(Option<String>, PathBuf)>> {
+        let root = match self.name() {
+            Some(name) => {
+                let named = self.root().to_string_lossy();
+                PathBuf::from(named.strip_suffix(&format!("-{}", name)).unwrap_or(&named))
+            }
+            None => self.root().clone(),
+        };
+        let base = root
+            .file_name()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let parent = match root.parent() {
+            Some(x) if !x.as_os_str().is_empty() => x.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let mut clusters = vec![];
+        if !parent.is_dir() {
+            return Ok(clusters);
+        }
+        for entry in fs::read_dir(&parent)
+            .with_context(|| format!("Unable to read directory '{}'", parent.display()))?
+        {
+            let path = entry?.path();
+            if !path.join(Self::FILENAME).exists() {
+                continue;
+            }
+            let file_name = path
+                .file_name()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if file_name == base {
+                clusters.push((None, path));
+            } else if let Some(name) = file_name.strip_prefix(&format!("{}-", base)) {
+                clusters.push((Some(name.into()), path));
+            }
+        }
+        clusters.sort();
+        Ok(clusters)
+    }
+
+    /// Make the configs root path absolute
+    pub fn canonicalize_root(&mut self) -> Result<()> {
+        self.create_root_dir()?;
+        self.root =
+            canonicalize(self.root()).context("Unable to canonicalize config root directory")?;
+        Ok(())
+    }
+
+    /// Translate the cluster spec file into the configuration, whereas the
+    /// fields defined by the spec are treated as explicitly requested
+    pub fn apply_spec(&mut self) -> Result<()> {
+        let path = match &self.spec {
+            Some(path) => canonicalize(path)
+                .with_context(|| format!("Unable to find spec file '{}'", path.display()))?,
+            None => return Ok(()),
+        };
+        let spec = Spec::from_file(&path)?;
+        self.spec = Some(path);
+
+        let mut fields = vec!["spec"];
+        if !spec.nodes().is_empty() {
+            self.nodes = spec.nodes().len() as u8;
+            self.node_runtimes = spec.node_runtimes();
+            fields.extend(&["nodes", "node-runtimes"]);
+            if !self.multi_node()
+                && spec
+                    .nodes()
+                    .iter()
+                    .any(|x| x.resources().cpus().is_some() || x.resources().memory().is_some())
+            {
+                warn!(
+                    "Node resources are only applied to the node containers of multi node clusters"
+                )
+            }
+        }
+        if !spec.addons().is_empty() {
+            self.addons = spec.addons().iter().map(|x| x.name().clone()).collect();
+            self.images.extend(spec.images());
+            fields.extend(&["addons", "images"]);
+        }
+        if !spec.manifests().is_empty() {
+            self.manifests.extend(spec.manifests().iter().cloned());
+            fields.push("manifests");
+        }
+        for field in fields {
+            if !self.requested.iter().any(|x| x == field) {
+                self.requested.push(field.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the current configuration to the internal set root path
+    pub fn to_file(&self) -> Result<()> {
+        self.create_root_dir()?;
+        fs::write(self.root().join(Self::FILENAME), toml::to_string(&self)?)
+            .context("Unable to write configuration to file")?;
+        Ok(())
+    }
+
+    /// Read the configuration from the internal set root path
+    /// If not existing, write the current configuration to the path.
+    /// Explicitly requested changes to an existing configuration get applied
+    /// after confirmation, whereas all affected artifacts will be removed.
+    pub fn try_load_file(&mut self) -> Result<()> {
+        let file = self.root().join(Self::FILENAME);
+        if file.exists() {
+            let mut stored: Config = toml::from_str(&read_to_string(&file).with_context(|| {
+                format!(
+                    "Unable to read expected configuration file '{}'",
+                    file.display(),
+                )
+            })?)
+            .with_context(|| format!("Unable to load config file '{}'", file.display()))?;
+
+            let changed = stored
+                .changed_fields(self)?
+                .into_iter()
+                .filter(|x| self.requested.contains(x))
+                .collect::<Vec<_>>();
+            if !changed.is_empty() {
+                self.confirm_changes(&stored, &changed)?;
+                stored = stored.merge(self, &changed)?;
+                for artifact in Artifact::affected_by(&changed) {
+                    artifact.remove(self.root())?;
+                }
+                fs::write(&file, toml::to_string(&stored)?)
+                    .context("Unable to write configuration to file")?;
+            }
+
+            stored.subcommand = self.subcommand.take();
+            stored.log_sink = self.log_sink.take();
+            stored.registry_logins = self.registry_logins.clone();
+            stored.yes = self.yes;
+            stored.force = self.force;
+            stored.color = self.color;
+            *self = stored;
+        } else {
+            self.to_file()?;
+        }
+        Ok(())
+    }
+
+    /// Show the diff between the stored and the requested configuration and
+    /// ask the user to proceed
+    fn confirm_changes(&self, stored: &Config, changed: &[String]) -> Result<()> {
+        let (old, new) = (toml::Value::try_from(stored)?, toml::Value::try_from(self)?);
+        let value = |v: &toml::Value, k: &str| v.get(k).map(|x| x.to_string()).unwrap_or_default();
+
+        let term = Term::stderr();
+        term.write_line(&format!(
+            "The configuration differs from the stored one in '{}':",
+            self.root().display()
+        ))?;
+        for field in changed {
+            term.write_line(
+                &style(format!("- {} = {}", field, value(&old, field)))
+                    .red()
+                    .to_string(),
+            )?;
+            term.write_line(
+                &style(format!("+ {} = {}", field, value(&new, field)))
+                    .green()
+                    .to_string(),
+            )?;
+        }
+        let artifacts = Artifact::affected_by(changed);
+        if !artifacts.is_empty() {
+            term.write_line("The following artifacts will be regenerated:")?;
+            for artifact in &artifacts {
+                term.write_line(&format!("  {}", artifact))?;
+            }
+        }
+
+        if self.yes() {
+            return Ok(());
+        }
+        if !term.is_term() {
+            bail!("Configuration changes require confirmation, use --yes to apply them")
+        }
+        term.write_str("Apply the changes? [y/N] ")?;
+        match term.read_line()?.trim() {
+            "y" | "Y" | "yes" => Ok(()),
+            _ => bail!("Configuration changes declined"),
+        }
+    }
+
+    /// Take the provided fields from the other configuration
+    fn merge(&self, other: &Config, fields: &[String]) -> Result<Config> {
+        let mut value = toml::Value::try_from(self)?;
+        let other = toml::Value::try_from(other)?;
+        let table = value.as_table_mut().context("Configuration is no table")?;
+        for field in fields {
+            match other.get(field) {
+                Some(v) => table.insert(field.clone(), v.clone()),
+                None => table.remove(field),
+            };
+        }
+        Ok(value.try_into()?)
+    }
+
+    /// Read the configuration file again, whereas the runtime related fields
+    /// like the root path are taken from the current configuration
+    pub fn reload(&self) -> Result<Config> {
+        let file = self.root().join(Self::FILENAME);
+        let mut config: Config = toml::from_str(&read_to_string(&file).with_context(|| {
+            format!(
+                "Unable to read expected configuration file '{}'",
+                file.display(),
+            )
+        })?)
+        .with_context(|| format!("Unable to load config file '{}'", file.display()))?;
+        config.root = self.root.clone();
+        config.log_sink = self.log_sink.clone();
+        config.registry_logins = self.registry_logins.clone();
+        config.shell = config.shell.or_else(|| self.shell.clone());
+        Ok(config)
+    }
+
+    /// Retrieve the names of all fields which differ from the other configuration
+    pub fn changed_fields(&self, other: &Config) -> Result<Vec<String>> {
+        let (a, b) = (toml::Value::try_from(self)?, toml::Value::try_from(other)?);
+        let (a, b) = (
+            a.as_table().context("Configuration is no table")?,
+            b.as_table().context("Configuration is no table")?,
+        );
+        let mut fields = a
+            .keys()
+            .chain(b.keys())
+            .filter(|x| a.get(*x) != b.get(*x))
+            .cloned()
+            .collect::<Vec<_>>();
+        fields.sort();
+        fields.dedup();
+        Ok(fields)
+    }
+
+    /// Forward all log messages to the provided sink instead of the terminal
+    pub fn set_log_sink(&mut self, sink: LogSink) {
+        self.log_sink = Some(sink);
+    }
+
+    /// Return the set shell as result type
+    pub fn shell_ok(&self) -> Result<String> {
+        let shell = self.shell.as_ref().context("No shell set")?;
+        Ok(shell.into())
+    }
+
+    /// Returns true if multi node support is enabled
+    pub fn multi_node(&self) -> bool {
+        self.nodes() > 1
+    }
+
+    /// Retrieve the explicitly configured CRI runtimes by their node index
+    pub fn node_runtime_overrides(&self) -> Result<BTreeMap<u8, NodeRuntime>> {
+        let mut runtimes = BTreeMap::new();
+        for entry in self.node_runtimes() {
+            let (node, runtime) = entry.split_once('=').with_context(|| {
+                format!(
+                    "Invalid node runtime, expected format NODE=RUNTIME: {}",
+                    entry
+                )
+            })?;
+            let node = node
+                .parse::<u8>()
+                .with_context(|| format!("Invalid node index: {}", node))?;
+            if node >= self.nodes() {
+                bail!(
+                    "Node {} does not exist, the cluster has {} node(s)",
+                    node,
+                    self.nodes()
+                )
+            }
+            runtimes.insert(node, runtime.parse()?);
+        }
+        Ok(runtimes)
+    }
+
+    /// Retrieve the additional environment variables by their component
+    pub fn component_env_overrides(&self) -> Result<BTreeMap<String, BTreeMap<String, String>>> {
+        Self::parse_component_env(self.component_env())
+    }
+
+    /// Retrieve the additional environment variables of the provided
+    /// component, whereas invalid entries get rejected on bootstrap
+    pub fn component_env_vars(&self, component: &str) -> BTreeMap<String, String> {
+        self.component_env_overrides()
+            .ok()
+            .and_then(|mut x| x.remove(component))
+            .unwrap_or_default()
+    }
+
+    fn parse_component_env(
+        entries: &[String],
+    ) -> Result<BTreeMap<String, BTreeMap<String, String>>> {
+        let mut result = BTreeMap::new();
+        for entry in entries {
+            let (component, name, value) = entry
+                .split_once(':')
+                .and_then(|(c, x)| x.split_once('=').map(|(n, v)| (c, n, v)))
+                .filter(|(_, n, _)| !n.is_empty())
+                .with_context(|| {
+                    format!(
+                        "Invalid component environment, expected format COMPONENT:NAME=VALUE: {}",
+                        entry
+                    )
+                })?;
+            if !Unit::COMPONENTS.contains(&component) {
+                bail!(
+                    "Unknown component {}, supported are: {}",
+                    component,
+                    Unit::COMPONENTS.join(", ")
+                )
+            }
+            result
+                .entry(component.into())
+                .or_insert_with(BTreeMap::new)
+                .insert(name.into(), value.into());
+        }
+        Ok(result)
+    }
+
+    /// Store the component environment as table of the components
+    fn serialize_component_env<S: Serializer>(
+        entries: &[String],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        Self::parse_component_env(entries)
+            .map_err(ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    /// Read the overlays, whereas configuration files of former versions
+    /// contain a single `overlay` path
+    fn deserialize_overlays<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<PathBuf>, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Overlays {
+            Single(PathBuf),
+            Multiple(Vec<PathBuf>),
+        }
+        Ok(match Overlays::deserialize(deserializer)? {
+            Overlays::Single(overlay) => vec![overlay],
+            Overlays::Multiple(overlays) => overlays,
+        })
+    }
+
+    /// Read the component environment from the table of the components
+    fn deserialize_component_env<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<String>, D::Error> {
+        let table = BTreeMap::<String, BTreeMap<String, String>>::deserialize(deserializer)?;
+        let entries = table
+            .iter()
+            .flat_map(|(c, vars)| vars.iter().map(move |(n, v)| format!("{}:{}={}", c, n, v)))
+            .collect::<Vec<_>>();
+        Self::parse_component_env(&entries).map_err(de::Error::custom)?;
+        Ok(entries)
+    }
+
+    /// Retrieve the readiness timeouts in seconds by their component
+    pub fn component_timeout_overrides(&self) -> Result<BTreeMap<String, u64>> {
+        Self::parse_component_timeouts(self.component_timeouts())
+    }
+
+    fn parse_component_timeouts(entries: &[String]) -> Result<BTreeMap<String, u64>> {
+        let mut result = BTreeMap::new();
+        for entry in entries {
+            let (component, seconds) = entry
+                .split_once('=')
+                .and_then(|(c, s)| Some((c, s.parse::<u64>().ok()?)))
+                .with_context(|| {
+                    format!(
+                        "Invalid component timeout, expected format COMPONENT=SECONDS: {}",
+                        entry
+                    )
+                })?;
+            if !Unit::COMPONENTS.contains(&component) {
+                bail!(
+                    "Unknown component {}, supported are: {}",
+                    component,
+                    Unit::COMPONENTS.join(", ")
+                )
+            }
+            result.insert(component.into(), seconds);
+        }
+        Ok(result)
+    }
+
+    /// Store the component timeouts as table of the components
+    fn serialize_component_timeouts<S: Serializer>(
+        entries: &[String],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        Self::parse_component_timeouts(entries)
+            .map_err(ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    /// Read the component timeouts from the table of the components
+    fn deserialize_component_timeouts<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<String>, D::Error> {
+        let table = BTreeMap::<String, u64>::deserialize(deserializer)?;
+        let entries = table
+            .iter()
+            .map(|(c, s)| format!("{}={}", c, s))
+            .collect::<Vec<_>>();
+        Self::parse_component_timeouts(&entries).map_err(de::Error::custom)?;
+        Ok(entries)
+    }
+
+    /// Retrieve the CRI runtime of the provided node, which falls back to
+    /// the cluster wide runtime for invalid overrides since they get rejected
+    /// on bootstrap
+    pub fn node_runtime(&self, node: u8) -> NodeRuntime {
+        self.node_runtime_overrides()
+            .ok()
+            .and_then(|x| x.get(&node).copied())
+            .unwrap_or_else(|| self.cri())
+    }
+
+    /// Retrieve all OCI runtimes, whereas the default one comes first
+    pub fn oci_runtimes(&self) -> Vec<OciRuntime> {
+        let mut runtimes = vec![self.oci_runtime()];
+        for runtime in self.runtime_classes() {
+            if !runtimes.contains(runtime) {
+                runtimes.push(*runtime);
+            }
+        }
+        runtimes
+    }
+
+    /// Retrieve the image for the provided name, whereas explicit overrides
+    /// take precedence over the image mirror
+    pub fn image(&self, name: &str, default: &str) -> Result<String> {
+        for image in self.images() {
+            let mut split = image.splitn(2, '=');
+            match (split.next(), split.next()) {
+                (Some(n), Some(i)) if !n.is_empty() && !i.is_empty() => {
+                    if n == name {
+                        return Ok(i.into());
+                    }
+                }
+                _ => bail!(
+                    "Invalid image override, expected format NAME=IMAGE: {}",
+                    image
+                ),
+            }
+        }
+
+        Ok(match self.image_mirror() {
+            Some(mirror) => {
+                // Strip the registry domain if available
+                let path = match default.split_once('/') {
+                    Some((domain, path))
+                        if domain.contains('.')
+                            || domain.contains(':')
+                            || domain == "localhost" =>
+                    {
+                        path
+                    }
+                    _ => default,
+                };
+                format!("{}/{}", mirror.trim_end_matches('/'), path)
+            }
+            None => default.into(),
+        })
+    }
+
+    /// Retrieve the URL of the API server on the local host
+    pub fn api_server(&self) -> String {
+        format!("https://{}:{}", Ipv4Addr::LOCALHOST, self.api_port())
+    }
+
+    /// Retrieve the `--cloud-provider` argument of the Kubernetes components,
+    /// if a cloud provider is configured
+    pub fn cloud_provider_arg(&self) -> Option<String> {
+        self.cloud_provider()
+            .as_ref()
+            .map(|x| format!("--cloud-provider={}", x))
+    }
+
+    /// Retrieve the `--runtime-config` argument of the API server, which
+    /// enables all APIs if not explicitly overridden via `api/all`
+    pub fn runtime_config_arg(&self) -> Result<String> {
+        let mut entries = vec![];
+        for entry in self.runtime_config() {
+            match entry.split_once('=') {
+                Some((api, "true" | "false")) if !api.is_empty() => entries.push(entry.as_str()),
+                _ => bail!(
+                    "Invalid runtime config, expected format API=BOOL: {}",
+                    entry
+                ),
+            }
+        }
+        if !entries.iter().any(|x| x.starts_with("api/all=")) {
+            entries.insert(0, "api/all=true");
+        }
+        Ok(format!("--runtime-config={}", entries.join(",")))
+    }
+
+    /// Retrieve the parsed feature gates on top of the provided defaults
+    pub fn feature_gates_map(&self, defaults: &[(&str, bool)]) -> Result<BTreeMap<String, bool>> {
+        let mut gates = defaults
+            .iter()
+            .map(|(gate, enabled)| ((*gate).to_owned(), *enabled))
+            .collect::<BTreeMap<_, _>>();
+        for entry in self.feature_gates().iter().flat_map(|x| x.split(',')) {
+            match entry.split_once('=') {
+                Some((gate, value @ ("true" | "false")))
+                    if !gate.is_empty() && gate.chars().all(|x| x.is_ascii_alphanumeric()) =>
+                {
+                    gates.insert(gate.into(), value == "true");
+                }
+                _ => bail!("Invalid feature gate, expected format GATE=BOOL: {}", entry),
+            }
+        }
+        Ok(gates)
+    }
+
+    /// Retrieve the `--feature-gates` argument of the control plane
+    /// components if any feature gate is configured
+    pub fn feature_gates_arg(&self) -> Result<Option<String>> {
+        let gates = self.feature_gates_map(&[])?;
+        if gates.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(format!(
+            "--feature-gates={}",
+            gates
+                .iter()
+                .map(|(gate, enabled)| format!("{}={}", gate, enabled))
+                .collect::<Vec<_>>()
+                .join(",")
+        )))
+    }
+
+    /// Retrieve the feature gates on top of the provided defaults as YAML
+    /// flow mapping of the kubelet and kube-proxy configurations
+    pub fn feature_gates_yml(&self, defaults: &[(&str, bool)]) -> Result<String> {
+        Ok(format!(
+            "{{{}}}",
+            self.feature_gates_map(defaults)?
+                .iter()
+                .map(|(gate, enabled)| format!("{}: {}", gate, enabled))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+
+    /// Ensure that all additional arguments of the components are flags,
+    /// which avoids shifting the positional arguments of the commands
+    pub fn validate_extra_args(&self) -> Result<()> {
+        for arg in self
+            .apiserver_args()
+            .iter()
+            .chain(self.controller_manager_args())
+            .chain(self.scheduler_args())
+            .chain(self.kubelet_args())
+            .chain(self.etcd_args())
+        {
+            if !arg.starts_with("--") || arg.len() == 2 {
+                bail!(
+                    "Invalid component argument, expected format --FLAG[=VALUE]: {}",
+                    arg
+                )
+            }
+        }
+        Ok(())
+    }
+
+    /// Retrieve the directory where all UNIX sockets are created, which
+    /// should be short to not exceed the socket path length limit. The
+    /// default is unique per run root, even if their names are the same.
+    pub fn socket_dir(&self) -> PathBuf {
+        self.socket_dir.clone().unwrap_or_else(|| {
+            Path::new("/")
+                .join("run")
+                .join("kubernix")
+                .join(format!("{:08x}", self.root_hash()))
+        })
+    }
+
+    /// Retrieve a stable hash of the canonicalized run root, which
+    /// identifies the cluster on the host
+    pub fn root_hash(&self) -> u32 {
+        // FNV-1a, which is stable in contrast to the default hasher
+        self.root()
+            .to_string_lossy()
+            .bytes()
+            .fold(0x811c_9dc5_u32, |h, b| {
+                (h ^ u32::from(b)).wrapping_mul(0x0100_0193)
+            })
+    }
+
+    fn default_prefix() -> u8 {
+        24
+    }
+
+    fn default_api_port() -> u16 {
+        6443
+    }
+
+    fn default_etcd_port() -> u16 {
+        2379
+    }
+
+    fn default_kubelet_port() -> u16 {
+        11250
+    }
+
+    fn default_cert_validity() -> u16 {
+        365
+    }
+
+    fn default_gc_log_retention() -> u16 {
+        7
+    }
+
+    fn default_gc_etcd_snapshots() -> u8 {
+        1
+    }
+
+    fn default_max_restarts() -> u32 {
+        5
+    }
+
+    fn default_readiness_timeout() -> u64 {
+        120
+    }
+
+    fn default_stop_grace_period() -> u64 {
+        10
+    }
+
+    fn default_etcd_quota_backend_bytes() -> u64 {
+        2 * 1024 * 1024 * 1024
+    }
+
+    fn default_etcd_heartbeat_interval() -> u64 {
+        100
+    }
+
+    fn default_etcd_election_timeout() -> u64 {
+        1000
+    }
+
+    fn default_etcd_snapshot_count() -> u64 {
+        100_000
+    }
+
+    fn default_image_gc_high_threshold() -> u8 {
+        85
+    }
+
+    fn default_image_gc_low_threshold() -> u8 {
+        80
+    }
+
+    fn default_log_size_max() -> i64 {
+        -1
+    }
+
+    fn default_container_log_max_size() -> String {
+        "10Mi".into()
+    }
+
+    fn default_container_log_max_files() -> u8 {
+        5
+    }
+
+    fn default_ephemeral_size() -> String {
+        "4G".into()
+    }
+
+    fn create_root_dir(&self) -> Result<()> {
+        create_dir_all(self.root()).context("Unable to create root directory")
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    pub fn test_config() -> Result<Config> {
+        let mut c = Config::default();
+        c.root = tempdir()?.into_path();
+        c.canonicalize_root()?;
+        Ok(c)
+    }
+
+    pub fn test_config_with<F: FnOnce(&mut Config)>(f: F) -> Result<Config> {
+        let mut c = test_config()?;
+        f(&mut c);
+        Ok(c)
+    }
+
+    pub fn test_config_named(name: &str) -> Result<Config> {
+        let mut c = test_config()?;
+        c.root = Config::named_root(c.root(), name);
+        c.name = Some(name.into());
+        c.canonicalize_root()?;
+        Ok(c)
+    }
+
+    pub fn test_config_wrong_root() -> Result<Config> {
+        let mut c = test_config()?;
+        c.root = Path::new("/").join("proc");
+        Ok(c)
+    }
+
+    pub fn test_config_wrong_cidr() -> Result<Config> {
+        let mut c = test_config()?;
+        c.cidr = "10.0.0.1/25".parse()?;
+        Ok(c)
+    }
+
+    #[test]
+    fn image_success() -> Result<()> {
+        let mut c = Config::default();
+        assert_eq!(
+            c.image("coredns", "k8s.gcr.io/coredns:1.7.0")?,
+            "k8s.gcr.io/coredns:1.7.0"
+        );
+
+        c.image_mirror = Some("mirror:5000/k8s/".into());
+        assert_eq!(
+            c.image("coredns", "k8s.gcr.io/coredns:1.7.0")?,
+            "mirror:5000/k8s/coredns:1.7.0"
+        );
+        assert_eq!(
+            c.image("nix", "nixos/nix:latest")?,
+            "mirror:5000/k8s/nixos/nix:latest"
+        );
+
+        c.images = vec!["coredns=my/coredns:1.8.0".into()];
+        assert_eq!(
+            c.image("coredns", "k8s.gcr.io/coredns:1.7.0")?,
+            "my/coredns:1.8.0"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_config_arg_success() -> Result<()> {
+        let mut c = Config::default();
+        assert_eq!(c.runtime_config_arg()?, "--runtime-config=api/all=true");
+
+        c.runtime_config = vec!["policy/v1beta1=false".into()];
+        assert_eq!(
+            c.runtime_config_arg()?,
+            "--runtime-config=api/all=true,policy/v1beta1=false"
+        );
+
+        c.runtime_config = vec!["api/all=false".into(), "apps/v1=true".into()];
+        assert_eq!(
+            c.runtime_config_arg()?,
+            "--runtime-config=api/all=false,apps/v1=true"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_config_arg_failure() {
+        let mut c = Config::default();
+        for entry in &["policy/v1beta1", "=true", "apps/v1=yes"] {
+            c.runtime_config = vec![(*entry).into()];
+            assert!(c.runtime_config_arg().is_err());
+        }
+    }
+
+    #[test]
+    fn feature_gates_success() -> Result<()> {
+        let mut c = Config::default();
+        assert!(c.feature_gates_arg()?.is_none());
+        assert_eq!(
+            c.feature_gates_yml(&[("DevicePlugins", false)])?,
+            "{DevicePlugins: false}"
+        );
+
+        c.feature_gates = vec!["MemoryQoS=true,DevicePlugins=true".into(), "A=false".into()];
+        assert_eq!(
+            c.feature_gates_arg()?.context("no arg")?,
+            "--feature-gates=A=false,DevicePlugins=true,MemoryQoS=true"
+        );
+        assert_eq!(
+            c.feature_gates_yml(&[("DevicePlugins", false), ("B", true)])?,
+            "{A: false, B: true, DevicePlugins: true, MemoryQoS: true}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn feature_gates_failure() {
+        let mut c = Config::default();
+        for entry in &[
+            "MemoryQoS",
+            "=true",
+            "MemoryQoS=yes",
+            "Memory-QoS=true",
+            "A=true,",
+        ] {
+            c.feature_gates = vec![(*entry).into()];
+            assert!(c.feature_gates_arg().is_err());
+        }
+    }
+
+    #[test]
+    fn validate_extra_args_success() -> Result<()> {
+        let mut c = Config::default();
+        c.validate_extra_args()?;
+        c.apiserver_args = vec!["--feature-gates=A=true".into(), "--profiling".into()];
+        c.etcd_args = vec!["--log-level=debug".into()];
+        c.validate_extra_args()
+    }
+
+    #[test]
+    fn validate_extra_args_failure() {
+        let mut c = Config::default();
+        for arg in &["--", "-v=2", "value"] {
+            c.kubelet_args = vec![(*arg).into()];
+            assert!(c.validate_extra_args().is_err());
+        }
+    }
+
+    #[test]
+    fn image_failure() {
+        let c = Config {
+            images: vec!["coredns".into()],
+            ..Default::default()
+        };
+        assert!(c.image("coredns", "k8s.gcr.io/coredns:1.7.0").is_err());
+    }
+
+    #[test]
+    fn node_runtime_success() -> Result<()> {
+        let mut c = Config {
+            nodes: 3,
+            ..Default::default()
+        };
+        assert!(c.node_runtime_overrides()?.is_empty());
+        assert_eq!(c.node_runtime(1), NodeRuntime::Crio);
+
+        c.node_runtimes = vec!["1=containerd".into(), "2=crio".into()];
+        assert_eq!(c.node_runtime_overrides()?.len(), 2);
+        assert_eq!(c.node_runtime(0), NodeRuntime::Crio);
+        assert_eq!(c.node_runtime(1), NodeRuntime::Containerd);
+        assert_eq!(c.node_runtime(2), NodeRuntime::Crio);
+
+        c.cri = NodeRuntime::Containerd;
+        assert_eq!(c.node_runtime(0), NodeRuntime::Containerd);
+        assert_eq!(c.node_runtime(2), NodeRuntime::Crio);
+        Ok(())
+    }
+
+    #[test]
+    fn component_env_success() -> Result<()> {
+        let mut c = Config::default();
+        assert!(c.component_env_vars("apiserver").is_empty());
+
+        c.component_env = vec![
+            "apiserver:GOGC=50".into(),
+            "apiserver:HTTPS_PROXY=http://proxy:3128".into(),
+            "kubelet:GRPC_GO_LOG_SEVERITY_LEVEL=info".into(),
+        ];
+        let env = c.component_env_vars("apiserver");
+        assert_eq!(env.len(), 2);
+        assert_eq!(env["HTTPS_PROXY"], "http://proxy:3128");
+        assert_eq!(c.component_env_vars("kubelet").len(), 1);
+        assert!(c.component_env_vars("proxy").is_empty());
+
+        let value = toml::Value::try_from(&c)?;
+        assert_eq!(
+            value["component-env"]["apiserver"]["GOGC"].as_str(),
+            Some("50")
+        );
+        let c: Config = toml::from_str(&toml::to_string(&c)?)?;
+        assert_eq!(c.component_env().len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn component_env_failure() {
+        let mut c = Config::default();
+        for entry in &[
+            "GOGC=50",
+            "apiserver:=50",
+            "apiserver:GOGC",
+            "unknown:GOGC=50",
+        ] {
+            c.component_env = vec![(*entry).into()];
+            assert!(c.component_env_overrides().is_err());
+            assert!(toml::to_string(&c).is_err());
+        }
+    }
+
+    #[test]
+    fn component_timeouts_success() -> Result<()> {
+        let mut c = Config::default();
+        assert!(c.component_timeout_overrides()?.is_empty());
+
+        c.component_timeouts = vec!["apiserver=300".into(), "kubelet=60".into()];
+        let timeouts = c.component_timeout_overrides()?;
+        assert_eq!(timeouts.get("apiserver"), Some(&300));
+        assert_eq!(timeouts.get("kubelet"), Some(&60));
+
+        let value = toml::Value::try_from(&c)?;
+        assert_eq!(
+            value["component-timeouts"]["apiserver"].as_integer(),
+            Some(300)
+        );
+        let c: Config = toml::from_str(&toml::to_string(&c)?)?;
+        assert_eq!(c.component_timeouts().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn component_timeouts_failure() {
+        let mut c = Config::default();
+        for entry in &["300", "apiserver=", "apiserver=x", "unknown=300"] {
+            c.component_timeouts = vec![(*entry).into()];
+            assert!(c.component_timeout_overrides().is_err());
+            assert!(toml::to_string(&c).is_err());
+        }
+    }
+
+    #[test]
+    fn node_runtime_failure() {
+        let mut c = Config {
+            nodes: 2,
+            ..Default::default()
+        };
+        for entry in &["containerd", "x=containerd", "2=containerd", "1=docker"] {
+            c.node_runtimes = vec![(*entry).into()];
+            assert!(c.node_runtime_overrides().is_err());
+        }
+    }
+
+    #[test]
+    fn oci_runtimes_success() {
+        let mut c = Config::default();
+        assert_eq!(c.oci_runtimes(), vec![OciRuntime::Runc]);
+
+        c.oci_runtime = OciRuntime::Crun;
+        c.runtime_classes = vec![OciRuntime::Gvisor, OciRuntime::Crun, OciRuntime::Runc];
+        assert_eq!(
+            c.oci_runtimes(),
+            vec![OciRuntime::Crun, OciRuntime::Gvisor, OciRuntime::Runc]
+        );
+    }
+
+    #[test]
+    fn socket_dir_success() -> Result<()> {
+        let mut c = Config {
+            root: Path::new("/").join("some").join("cluster"),
+            ..Default::default()
+        };
+        assert_eq!(c.socket_dir(), Path::new("/run/kubernix/1531a32b"));
+
+        // Run roots of the same name do not share their sockets
+        let other = Config {
+            root: Path::new("/").join("other").join("cluster"),
+            ..Default::default()
+        };
+        assert_ne!(c.socket_dir(), other.socket_dir());
+
+        c.socket_dir = Some("/sockets".into());
+        assert_eq!(c.socket_dir(), Path::new("/sockets"));
+        Ok(())
+    }
+
+    #[test]
+    fn canonicalize_root_success() -> Result<()> {
+        let mut c = Config::default();
+        c.root = tempdir()?.into_path();
+        c.canonicalize_root()
+    }
+
+    #[test]
+    fn named_root_success() {
+        assert_eq!(
+            Config::named_root(Path::new("kubernix-run"), "test"),
+            Path::new("kubernix-run-test")
+        );
+        assert_eq!(
+            Config::named_root(Path::new("/tmp/run"), "test"),
+            Path::new("/tmp/run-test")
+        );
+    }
+
+    #[test]
+    fn clusters_success() -> Result<()> {
+        let c = test_config_named("test")?;
+        let base = PathBuf::from(
+            c.root()
+                .to_string_lossy()
+                .strip_suffix("-test")
+                .context("no named root")?,
+        );
+        create_dir_all(&base)?;
+        fs::write(base.join(Config::FILENAME), "")?;
+        c.to_file()?;
+
+        assert_eq!(
+            c.clusters()?,
+            vec![(None, base), (Some("test".into()), c.root().clone())]
+        );
+        assert_eq!(c.cluster_name(), "kubernix-test");
+        Ok(())
+    }
+
+    #[test]
+    fn validate_name_success() -> Result<()> {
+        test_config()?.validate_name()?;
+        test_config_named("dev-1")?.validate_name()
+    }
+
+    #[test]
+    fn validate_name_failure() -> Result<()> {
+        for name in &["Dev", "dev_1", "-dev", "dev/1"] {
+            let mut c = test_config()?;
+            c.name = Some((*name).into());
+            assert!(c.validate_name().is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn canonicalize_root_failure() {
+        let mut c = Config::default();
+        c.root = Path::new("/").join("proc").join("invalid");
+        assert!(c.canonicalize_root().is_err())
+    }
+
+    #[test]
+    fn apply_spec_success() -> Result<()> {
+        let mut c = test_config()?;
+        let spec = c.root().join("kubernix.yaml");
+        fs::write(
+            &spec,
+            "nodes: [{}, {runtime: containerd}]\naddons: [{name: ingress}]\n",
+        )?;
+        c.spec = Some(spec);
+        c.apply_spec()?;
+        assert_eq!(c.nodes(), 2);
+        assert_eq!(c.node_runtime(1), NodeRuntime::Containerd);
+        assert_eq!(c.addons(), &["ingress"]);
+        assert!(c.requested.iter().any(|x| x == "nodes"));
+        assert!(!c.requested.iter().any(|x| x == "manifests"));
+        Ok(())
+    }
+
+    #[test]
+    fn apply_spec_failure() {
+        let mut c = Config {
+            spec: Some("/does/not/exist.yaml".into()),
+            ..Default::default()
+        };
+        assert!(c.apply_spec().is_err());
+    }
+
+    #[test]
+    fn to_file_success() -> Result<()> {
+        let mut c = Config::default();
+        c.root = tempdir()?.into_path();
+        c.to_file()
+    }
+
+    #[test]
+    fn to_file_success_registry_logins() -> Result<()> {
+        let mut c = test_config()?;
+        c.registry_logins = vec!["localhost:5000=user:pass".into()];
+        c.to_file()?;
+        let content = read_to_string(c.root().join(Config::FILENAME))?;
+        assert!(!content.contains("user:pass"));
+        Ok(())
+    }
+
+    #[test]
+    fn to_file_failure() {
+        let mut c = Config::default();
+        c.root = Path::new("/").join("proc").join("invalid");
+        assert!(c.to_file().is_err())
+    }
+
+    #[test]
+    fn color_mode_success() -> Result<()> {
+        for mode in &[ColorMode::Auto, ColorMode::Always, ColorMode::Never] {
+            assert_eq!(mode.to_string().parse::<ColorMode>()?, *mode);
+        }
+        assert!(!ColorMode::Never.animated());
+        assert!("sometimes".parse::<ColorMode>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn bootstrap_phase_success() -> Result<()> {
+        for name in BootstrapPhase::NAMES {
+            assert_eq!(name.parse::<BootstrapPhase>()?.to_string(), *name);
+        }
+        assert!("preflight".parse::<BootstrapPhase>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn pki_key_type_success() -> Result<()> {
+        for key_type in &[
+            PkiKeyType::Rsa2048,
+            PkiKeyType::Rsa4096,
+            PkiKeyType::EcdsaP256,
+            PkiKeyType::Ed25519,
+        ] {
+            assert_eq!(key_type.to_string().parse::<PkiKeyType>()?, *key_type);
+            assert_eq!(
+                serde_json::to_string(key_type)?,
+                format!("\"{}\"", key_type)
+            );
+        }
+        assert!("dsa".parse::<PkiKeyType>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encryption_provider_success() -> Result<()> {
+        for provider in &[
+            EncryptionProvider::Aescbc,
+            EncryptionProvider::Secretbox,
+            EncryptionProvider::Kms,
+            EncryptionProvider::Identity,
+        ] {
+            assert_eq!(
+                provider.to_string().parse::<EncryptionProvider>()?,
+                *provider
+            );
+            assert_eq!(
+                serde_json::to_string(provider)?,
+                format!("\"{}\"", provider)
+            );
+        }
+        assert!("aesgcm".parse::<EncryptionProvider>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn try_load_file_success() -> Result<()> {
+        let mut c = Config::default();
+        c.root = tempdir()?.into_path();
+        fs::write(
+            c.root.join(Config::FILENAME),
+            r#"
+cidr = "1.1.1.1/16"
+container-runtime = "podman"
+log-level = "DEBUG"
+no-shell = false
+nodes = 1
+packages = []
+root = "root"
+            "#,
+        )?;
+        c.try_load_file()?;
+        assert_eq!(c.root(), Path::new("root"));
+        assert_eq!(c.log_level(), LevelFilter::Debug);
+        assert_eq!(&c.cidr().to_string(), "1.1.1.1/16");
+        Ok(())
+    }
+
+    #[test]
+    fn try_load_file_success_single_overlay() -> Result<()> {
+        let mut c = test_config()?;
+        c.to_file()?;
+        let file = c.root().join(Config::FILENAME);
+        let content = read_to_string(&file)?.replace("overlays = []", "overlay = \"overlay.nix\"");
+        fs::write(&file, content)?;
+        c.try_load_file()?;
+        assert_eq!(c.overlays(), &[PathBuf::from("overlay.nix")]);
+        Ok(())
+    }
+
+    #[test]
+    fn try_load_file_success_requested_changes() -> Result<()> {
+        let mut c = test_config()?;
+        c.to_file()?;
+        let pki = c.root().join("pki");
+        create_dir_all(&pki)?;
+
+        c.nodes = 2;
+        c.image_gc_high_threshold = 90;
+        c.requested = vec!["nodes".into()];
+        c.yes = true;
+        c.try_load_file()?;
+
+        assert_eq!(c.nodes(), 2);
+        assert_eq!(c.image_gc_high_threshold(), 85);
+        assert!(!pki.exists());
+        assert_eq!(c.reload()?.nodes(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn reload_success() -> Result<()> {
+        let c = test_config()?;
+        c.to_file()?;
+        let mut r = c.reload()?;
+        assert_eq!(r.root(), c.root());
+        assert!(c.changed_fields(&r)?.is_empty());
+
+        r.log_level = LevelFilter::Trace;
+        r.manifests = vec!["manifest.yml".into()];
+        assert_eq!(c.changed_fields(&r)?, vec!["log-level", "manifests"]);
         Ok(())
     }
 
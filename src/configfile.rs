@@ -0,0 +1,175 @@
+use crate::{
+    containerd::Containerd, crio::Crio, graph::Unit, kubelet::Kubelet, network::Network,
+    proxy::Proxy, scheduler::Scheduler, Config,
+};
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters};
+use std::{
+    fs::{metadata, read_to_string},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// A generated configuration file of a unit, which can be edited by the user
+/// and gets applied by restarting the unit
+#[derive(CopyGetters, Getters)]
+pub struct ConfigFile {
+    #[get_copy = "pub"]
+    /// The unit using the configuration
+    unit: Unit,
+
+    #[get = "pub"]
+    /// The path to the configuration file
+    path: PathBuf,
+
+    /// The last seen modification time
+    modified: Option<SystemTime>,
+}
+
+impl ConfigFile {
+    /// The component names of all units with a configuration file
+    pub const COMPONENTS: &'static [&'static str] = &[
+        "crio",
+        "containerd",
+        "kubelet",
+        "proxy",
+        "scheduler",
+        "secondary-scheduler",
+    ];
+
+    /// Create a new configuration file for the provided unit
+    pub fn new(config: &Config, network: &Network, unit: Unit) -> Result<ConfigFile> {
+        let path = match unit {
+            Unit::Crio(n) => Crio::config_file(config, network, n),
+            Unit::Containerd(n) => Containerd::config_file(config, network, n),
+            Unit::Kubelet(n) => Kubelet::config_file(config, network, n),
+            Unit::Proxy(n) => Proxy::config_file(config, network, n),
+            Unit::Scheduler => Scheduler::config_file(config, false),
+            Unit::SecondaryScheduler => Scheduler::config_file(config, true),
+            _ => bail!("Component {} has no configuration file", unit),
+        };
+        let modified = Self::modified_time(&path);
+        Ok(ConfigFile {
+            unit,
+            path,
+            modified,
+        })
+    }
+
+    /// Retrieve the configuration files of all provided units which have one
+    pub fn all(config: &Config, network: &Network, units: &[Unit]) -> Vec<ConfigFile> {
+        units
+            .iter()
+            .filter_map(|x| Self::new(config, network, *x).ok())
+            .collect()
+    }
+
+    /// Validate the syntax of the configuration file
+    pub fn validate(&self) -> Result<()> {
+        let content = read_to_string(&self.path)
+            .with_context(|| format!("Unable to read '{}'", self.path.display()))?;
+        match self.unit {
+            Unit::Crio(_) | Unit::Containerd(_) => {
+                content
+                    .parse::<toml::Value>()
+                    .with_context(|| format!("Invalid TOML in '{}'", self.path.display()))?;
+            }
+            _ => {
+                let value = serde_yaml::from_str::<serde_yaml::Value>(&content)
+                    .with_context(|| format!("Invalid YAML in '{}'", self.path.display()))?;
+                if !value.is_mapping() {
+                    bail!(
+                        "Configuration '{}' does not contain a mapping",
+                        self.path.display()
+                    )
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if the file got modified since the last call
+    pub fn changed(&mut self) -> bool {
+        let modified = Self::modified_time(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+
+    fn modified_time(path: &Path) -> Option<SystemTime> {
+        metadata(path).and_then(|x| x.modified()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::test_config, network::tests::test_network};
+    use std::fs::{create_dir_all, write};
+
+    fn config_file(unit: Unit) -> Result<ConfigFile> {
+        let c = test_config()?;
+        let n = test_network()?;
+        let file = ConfigFile::new(&c, &n, unit)?;
+        create_dir_all(file.path().parent().context("no parent")?)?;
+        Ok(file)
+    }
+
+    #[test]
+    fn new_success() -> Result<()> {
+        let c = test_config()?;
+        let n = test_network()?;
+        for component in ConfigFile::COMPONENTS {
+            assert!(ConfigFile::new(&c, &n, Unit::new(component, 0)?).is_ok());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn new_failure() -> Result<()> {
+        let c = test_config()?;
+        let n = test_network()?;
+        assert!(ConfigFile::new(&c, &n, Unit::Etcd).is_err());
+        assert_eq!(
+            ConfigFile::all(&c, &n, &[Unit::Etcd, Unit::Kubelet(0)]).len(),
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validate_success() -> Result<()> {
+        let file = config_file(Unit::Kubelet(0))?;
+        write(file.path(), "kind: KubeletConfiguration\n")?;
+        file.validate()?;
+
+        let file = config_file(Unit::Crio(0))?;
+        write(file.path(), "[crio]\nlog_dir = \"/\"\n")?;
+        file.validate()
+    }
+
+    #[test]
+    fn validate_failure() -> Result<()> {
+        let file = config_file(Unit::Scheduler)?;
+        assert!(file.validate().is_err());
+        write(file.path(), "- not\n- a\n- mapping\n")?;
+        assert!(file.validate().is_err());
+
+        let file = config_file(Unit::Crio(0))?;
+        write(file.path(), "[crio\n")?;
+        assert!(file.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn changed_success() -> Result<()> {
+        let mut file = config_file(Unit::Proxy(0))?;
+        assert!(!file.changed());
+        write(file.path(), "kind: KubeProxyConfiguration\n")?;
+        assert!(file.changed());
+        assert!(!file.changed());
+        Ok(())
+    }
+}
@@ -1,30 +1,58 @@
-use crate::{nix::Nix, podman::Podman, process::Process, system::System, Config};
-use anyhow::{bail, Result};
-use log::{debug, info, trace, LevelFilter};
+use crate::{
+    config::NodeRuntime,
+    containerd::Containerd,
+    crio::{CriSocket, Crio, POD_LOG_DIR},
+    network::Network,
+    nix::Nix,
+    node::Node,
+    podman::Podman,
+    process::{Process, ProcessOptions},
+    record::Recorded,
+    spec::Spec,
+    system::System,
+    Config,
+};
+use anyhow::{bail, Context, Result};
+use log::{debug, info, trace, warn, LevelFilter};
+use serde_json::{json, to_string_pretty, Map, Value};
 use std::{
+    collections::BTreeMap,
     fmt::Display,
     fs,
     path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
+const BASE_IMAGE: &str = "docker.io/nixos/nix:latest";
 const DEFAULT_IMAGE: &str = "kubernix:base";
 const DEFAULT_ROOT: &str = "kubernix";
+const REGISTRIES_CONF_ENV: &str = "CONTAINERS_REGISTRIES_CONF";
 
 pub struct Container;
 
+/// The destination of an image loaded into a node
+enum ImageStore {
+    /// The containers-storage(5) reference of CRI-O
+    Storage(String),
+
+    /// The socket of containerd
+    Containerd(CriSocket),
+}
+
 impl Container {
-    /// Build the base image used for the nodes
+    /// Build the base image used for the nodes and the control plane
     pub fn build(config: &Config) -> Result<()> {
         // Verify that the provided runtime exists
         System::find_executable(config.container_runtime())?;
 
-        // Write the policy file
+        // Write the policy and registries files
         let policy_json = Self::policy_json(config);
-        fs::write(&policy_json, include_str!("assets/policy.json"))?;
+        fs::write(&policy_json, Self::policy(config)?)?;
+        let registries_conf = Self::registries_conf(config);
+        fs::write(&registries_conf, Self::registries(config))?;
 
-        // Nothing needs to be done on single node runs or root users
-        if !config.multi_node() {
+        // Nothing needs to be done on single node runs without containers
+        if !config.multi_node() && !config.control_plane_container() {
             return Ok(());
         }
 
@@ -38,6 +66,7 @@ impl Container {
                 &file,
                 format!(
                     include_str!("assets/Dockerfile"),
+                    image = config.image("nix", BASE_IMAGE)?,
                     nix = Nix::DIR,
                     root = DEFAULT_ROOT
                 ),
@@ -55,12 +84,23 @@ impl Container {
 
         // Run the build
         debug!("Running container runtime with args: {}", args.join(" "));
-        let status = Command::new(config.container_runtime())
+        let mut cmd = Command::new(config.container_runtime());
+        if !config.insecure_registries().is_empty() {
+            if Podman::is_configured(config) {
+                cmd.env(REGISTRIES_CONF_ENV, &registries_conf);
+            } else {
+                warn!(
+                    "Insecure registries have to be configured manually for the '{}' runtime",
+                    config.container_runtime()
+                );
+            }
+        }
+        let status = cmd
             .current_dir(config.root())
             .args(args)
             .stderr(Self::stdio(config))
             .stdout(Self::stdio(config))
-            .status()?;
+            .recorded_status()?;
         if !status.success() {
             bail!("Unable to build container base image");
         }
@@ -69,11 +109,156 @@ impl Container {
         Ok(())
     }
 
+    /// Build an image via podman and copy it into the CRI-O storage of every
+    /// node, which makes it usable without a registry
+    pub fn build_and_load(
+        config: &Config,
+        network: &Network,
+        image: &str,
+        context: &Path,
+        file: Option<&Path>,
+    ) -> Result<()> {
+        System::find_executable(Podman::EXECUTABLE)?;
+
+        // Resolve all storages first to not build in vain, whereas containerd
+        // nodes import an image archive via their socket
+        let storages = (0..config.nodes())
+            .map(|x| {
+                let storage = match config.node_runtime(x) {
+                    NodeRuntime::Crio => ImageStore::Storage(Crio::storage(config, network, x)?),
+                    NodeRuntime::Containerd => {
+                        ImageStore::Containerd(Containerd::socket(config, network, x)?)
+                    }
+                };
+                Ok((Node::name(config, network, x), storage))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        info!("Building image '{}'", image);
+        let policy_json = Self::policy_json(config);
+        fs::write(&policy_json, Self::policy(config)?)?;
+        let mut args = Podman::build_args(config, &policy_json)?;
+        args.push(format!("--tag={}", image));
+        if let Some(file) = file {
+            args.push(format!("--file={}", file.display()));
+        }
+        args.push(context.display().to_string());
+        Self::podman(config, &args).context("Unable to build image")?;
+
+        let archive = config.root().join("image.tar");
+        if storages
+            .iter()
+            .any(|(_, x)| matches!(x, ImageStore::Containerd(_)))
+        {
+            debug!("Saving image archive to {}", archive.display());
+            let mut args = Podman::default_args(config)?;
+            args.extend(vec![
+                "save".into(),
+                "--format=docker-archive".into(),
+                format!("--output={}", archive.display()),
+                image.into(),
+            ]);
+            Self::podman(config, &args).context("Unable to save image archive")?;
+        }
+
+        for (node_name, storage) in storages {
+            info!("Loading image '{}' into {}", image, node_name);
+            match storage {
+                ImageStore::Storage(storage) => {
+                    let mut args = Podman::default_args(config)?;
+                    args.extend(vec![
+                        "push".into(),
+                        format!("--signature-policy={}", policy_json.display()),
+                        image.into(),
+                        format!("containers-storage:{}{}", storage, image),
+                    ]);
+                    Self::podman(config, &args)
+                }
+                ImageStore::Containerd(socket) => Self::ctr_import(config, &socket, &archive),
+            }
+            .with_context(|| format!("Unable to load image into {}", node_name))?;
+        }
+        if archive.exists() {
+            fs::remove_file(&archive)?;
+        }
+
+        info!("Image '{}' loaded into all nodes", image);
+        Ok(())
+    }
+
+    /// Run podman with the provided args, whereas its output is only shown
+    /// on debug log level
+    fn podman(config: &Config, args: &[String]) -> Result<()> {
+        debug!("Running podman with args: {}", args.join(" "));
+        let mut cmd = Command::new(Podman::EXECUTABLE);
+        if !config.insecure_registries().is_empty() {
+            cmd.env(REGISTRIES_CONF_ENV, Self::registries_conf(config));
+        }
+        let status = cmd
+            .args(args)
+            .stderr(Self::stdio(config))
+            .stdout(Self::stdio(config))
+            .recorded_status()?;
+        if !status.success() {
+            bail!("podman failed ({})", status)
+        }
+        Ok(())
+    }
+
+    /// Import the image archive into the Kubernetes namespace of containerd
+    fn ctr_import(config: &Config, socket: &CriSocket, archive: &Path) -> Result<()> {
+        let status = Command::new("ctr")
+            .arg(format!("--address={}", socket.path().display()))
+            .arg("--namespace=k8s.io")
+            .arg("images")
+            .arg("import")
+            .arg(archive)
+            .stderr(Self::stdio(config))
+            .stdout(Self::stdio(config))
+            .recorded_status()?;
+        if !status.success() {
+            bail!("ctr import failed ({})", status)
+        }
+        Ok(())
+    }
+
     /// Retrieve the default signature policy file location
     pub fn policy_json(config: &Config) -> PathBuf {
         config.root().join("policy.json")
     }
 
+    /// Retrieve the registries configuration file location
+    pub fn registries_conf(config: &Config) -> PathBuf {
+        config.root().join("registries.conf")
+    }
+
+    /// Render the signature policy, which trusts the insecure registries
+    fn policy(config: &Config) -> Result<String> {
+        let mut policy: Value = serde_json::from_str(include_str!("assets/policy.json"))?;
+        let registries = config
+            .insecure_registries()
+            .iter()
+            .map(|x| (x.into(), json!([{ "type": "insecureAcceptAnything" }])))
+            .collect::<Map<_, _>>();
+        if !registries.is_empty() {
+            policy["transports"] = json!({ "docker": registries });
+        }
+        Ok(to_string_pretty(&policy)?)
+    }
+
+    /// Render the containers-registries.conf(5) content
+    fn registries(config: &Config) -> String {
+        let mut content =
+            String::from("unqualified-search-registries = [\"docker.io\", \"quay.io\"]\n");
+        for registry in config.insecure_registries() {
+            content.push_str(&format!(
+                "\n[[registry]]\nlocation = \"{}\"\ninsecure = true\n",
+                registry
+            ));
+        }
+        content
+    }
+
     /// Start a new container based process
     pub fn start(
         config: &Config,
@@ -82,14 +267,24 @@ impl Container {
         process_name: &str,
         container_name: &str,
         args: &[&str],
+        env: &BTreeMap<String, String>,
     ) -> Result<Process> {
         // Cleanup possible containers
         Self::remove(config, container_name)?;
 
         // Prepare the arguments
         let arg_hostname = &format!("--hostname={}", container_name);
-        let arg_name = &format!("--name={}", Self::prefixed_container_name(container_name));
+        let arg_name = &format!(
+            "--name={}",
+            Self::prefixed_container_name(config, container_name)
+        );
         let arg_volume_root = &Self::volume_arg(config.root().display());
+        let arg_volume_socket_dir = &Self::volume_arg(config.socket_dir().display());
+        let arg_volume_pod_logs = &format!(
+            "--volume={}:{}",
+            Crio::pod_log_dir(config, dir).display(),
+            POD_LOG_DIR
+        );
         let mut args_vec = vec![
             "run",
             "--net=host",
@@ -98,6 +293,8 @@ impl Container {
             arg_hostname,
             arg_name,
             arg_volume_root,
+            arg_volume_socket_dir,
+            arg_volume_pod_logs,
         ];
 
         // Podman specific arguments
@@ -106,6 +303,10 @@ impl Container {
             args_vec.extend(podman_args.iter().map(|x| x.as_str()).collect::<Vec<_>>())
         }
 
+        // Limit the resources of the node container
+        let resource_args = Spec::resource_args(config, container_name)?;
+        args_vec.extend(resource_args.iter().map(String::as_str));
+
         // Mount /dev/mapper if available
         let dev_mapper = PathBuf::from("/").join("dev").join("mapper");
         let arg_volume_dev_mapper = &Self::volume_arg(dev_mapper.display());
@@ -113,19 +314,36 @@ impl Container {
             args_vec.push(arg_volume_dev_mapper);
         }
 
+        // Pass the additional environment variables of the process
+        let env_args = Self::env_args(env);
+        args_vec.extend(env_args.iter().map(String::as_str));
+
         // Add the process and the user provided args
         args_vec.extend(&[DEFAULT_IMAGE, process_name]);
         args_vec.extend(args);
 
         // Start the process
         trace!("Container runtime start args: {:?}", args_vec);
-        Process::start(dir, identifier, config.container_runtime(), &args_vec)
+        Process::start(
+            &ProcessOptions::new(config),
+            dir,
+            identifier,
+            config.container_runtime(),
+            &args_vec,
+            &BTreeMap::new(),
+        )
     }
 
     fn volume_arg<T: Display>(volume: T) -> String {
         format!("--volume={v}:{v}", v = volume)
     }
 
+    fn env_args(env: &BTreeMap<String, String>) -> Vec<String> {
+        env.iter()
+            .map(|(name, value)| format!("--env={}={}", name, value))
+            .collect()
+    }
+
     /// Exec a command on a container instance
     pub fn exec(
         config: &Config,
@@ -134,6 +352,7 @@ impl Container {
         process_name: &str,
         container_name: &str,
         args: &[&str],
+        env: &BTreeMap<String, String>,
     ) -> Result<Process> {
         // Prepare the args
         let mut args_vec = vec![];
@@ -143,9 +362,11 @@ impl Container {
             args_vec.extend(podman_args.iter().map(|x| x.as_str()).collect::<Vec<_>>())
         }
 
-        let name = Self::prefixed_container_name(container_name);
+        let name = Self::prefixed_container_name(config, container_name);
+        let env_args = Self::env_args(env);
+        args_vec.push("exec");
+        args_vec.extend(env_args.iter().map(String::as_str));
         args_vec.extend(vec![
-            "exec",
             &name,
             "nix",
             "run",
@@ -158,7 +379,41 @@ impl Container {
 
         // Run as usual process
         trace!("Container runtime exec args: {:?}", args_vec);
-        Process::start(dir, identifier, config.container_runtime(), &args_vec)
+        Process::start(
+            &ProcessOptions::new(config),
+            dir,
+            identifier,
+            config.container_runtime(),
+            &args_vec,
+            &BTreeMap::new(),
+        )
+    }
+
+    /// Remove all (maybe running) containers of the cluster, which remain
+    /// if kubernix got killed before stopping them
+    pub fn remove_all(config: &Config) -> Result<()> {
+        let output = Command::new(config.container_runtime())
+            .arg("ps")
+            .arg("--all")
+            .arg("--format={{.Names}}")
+            .arg(format!("--filter=name=^{}-", config.cluster_name()))
+            .recorded_output()
+            .context("Unable to list containers")?;
+        if !output.status.success() {
+            bail!(
+                "Unable to list containers: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+        let prefix = format!("{}-", config.cluster_name());
+        for name in String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|x| x.trim().strip_prefix(&prefix))
+        {
+            info!("Removing container {}{}", prefix, name);
+            Self::remove(config, name)?;
+        }
+        Ok(())
     }
 
     /// Remove the provided (maybe running) container
@@ -166,10 +421,10 @@ impl Container {
         Command::new(config.container_runtime())
             .arg("rm")
             .arg("-f")
-            .arg(Self::prefixed_container_name(name))
+            .arg(Self::prefixed_container_name(config, name))
             .stderr(Stdio::null())
             .stdout(Stdio::null())
-            .status()?;
+            .recorded_status()?;
         Ok(())
     }
 
@@ -182,8 +437,8 @@ impl Container {
         }
     }
 
-    /// Retrieve a prefixed container name
-    fn prefixed_container_name(name: &str) -> String {
-        format!("{}-{}", DEFAULT_ROOT, name)
+    /// Retrieve the container name prefixed by the cluster name
+    fn prefixed_container_name(config: &Config, name: &str) -> String {
+        format!("{}-{}", config.cluster_name(), name)
     }
 }
@@ -0,0 +1,272 @@
+use crate::{
+    cni::Cni,
+    container::Container,
+    crio::{CriSocket, Crio, PAUSE_IMAGE},
+    network::Network,
+    node::Node,
+    process::{Process, ProcessOptions, ProcessState, Readiness, Stoppable},
+    registryauth::RegistryAuth,
+    runtimeclass::RuntimeClasses,
+    system::System,
+    Config,
+};
+use anyhow::{Context, Result};
+use log::warn;
+use serde_json::Value;
+use std::{
+    fs::{self, create_dir_all, read_to_string},
+    path::PathBuf,
+};
+
+pub struct Containerd {
+    process: Process,
+    socket: CriSocket,
+    node_name: String,
+}
+
+/// The name of the containerd executable and working directory
+pub const CONTAINERD: &str = "containerd";
+
+/// The table of the CRI plugin within the configuration
+const CRI_PLUGIN: &str = "plugins.\"io.containerd.grpc.v1.cri\"";
+
+impl Containerd {
+    pub fn start(
+        config: &Config,
+        node: u8,
+        network: &Network,
+        registry_auth: &RegistryAuth,
+    ) -> ProcessState {
+        let node_name = Node::name(config, network, node);
+
+        let cni_plugin = Cni::plugin_dir(config)?;
+
+        let dir = Self::path(config, network, node);
+        let config_file = Self::config_file(config, network, node);
+        let network_dir = dir.join("cni");
+        let socket = Self::socket(config, network, node)?;
+        create_dir_all(config.socket_dir())?;
+
+        // The configs may have been removed to be regenerated
+        if !config_file.exists() {
+            create_dir_all(&dir)?;
+            create_dir_all(&network_dir)?;
+
+            let root = dir.join("root");
+            let snapshotter = if config.multi_node() || System::in_container()? {
+                "native"
+            } else {
+                match System::storage_driver(&root)? {
+                    Some("vfs") => "native",
+                    Some(driver) => driver,
+                    None => "overlayfs",
+                }
+            };
+            if config.storage_quota().is_some() {
+                warn!("Storage quota is not supported by containerd");
+            }
+
+            fs::write(
+                &config_file,
+                format!(
+                    include_str!("assets/containerd.toml"),
+                    root = root.display(),
+                    state = dir.join("state").display(),
+                    socket = socket,
+                    pause_image = config.image("pause", PAUSE_IMAGE)?,
+                    snapshotter = snapshotter,
+                    default_runtime = RuntimeClasses::handler(config.oci_runtime()),
+                    runtimes = RuntimeClasses::containerd_runtimes(config, &dir)?,
+                    plugin_dir = cni_plugin.display(),
+                    network_dir = network_dir.display(),
+                    registries = Self::registries(config, registry_auth)?,
+                ),
+            )?;
+            Cni::write_config(config, network, node, &node_name, &network_dir)?;
+        }
+        System::symlink(socket.path(), &dir.join("containerd.sock"))?;
+        let args: &[&str] = &[&format!("--config={}", config_file.display())];
+
+        // The node containers do not inherit the proxy configuration
+        let mut env = network.proxy_env(config);
+        env.extend(config.component_env_vars(CONTAINERD));
+
+        let mut process = if config.multi_node() {
+            // Run inside a container, whereas the pod logs are kept per node
+            create_dir_all(Crio::pod_log_dir(config, &dir))?;
+            let identifier = format!("containerd {}", node_name);
+            Container::start(
+                config,
+                &dir,
+                &identifier,
+                CONTAINERD,
+                &node_name,
+                args,
+                &env,
+            )?
+        } else {
+            // Run as usual process
+            Process::start(
+                &ProcessOptions::new(config),
+                &dir,
+                "containerd",
+                CONTAINERD,
+                args,
+                &env,
+            )?
+        };
+        process.wait_ready(Readiness::Cri(socket.to_socket_string()))?;
+        process.describe(CONTAINERD, &socket.to_socket_string(), None);
+
+        Ok(Box::new(Self {
+            process,
+            socket,
+            node_name,
+        }))
+    }
+
+    /// Retrieve the CRI socket, which resides in the socket directory
+    pub fn socket(config: &Config, network: &Network, node: u8) -> Result<CriSocket> {
+        CriSocket::new(config.socket_dir().join(format!(
+            "containerd-{}.sock",
+            Node::name(config, network, node)
+        )))
+    }
+
+    /// Retrieve the working path for the node
+    fn path(config: &Config, network: &Network, node: u8) -> PathBuf {
+        config
+            .root()
+            .join(CONTAINERD)
+            .join(Node::name(config, network, node))
+    }
+
+    /// Retrieve the configuration file of the provided node
+    pub fn config_file(config: &Config, network: &Network, node: u8) -> PathBuf {
+        Self::path(config, network, node).join("config.toml")
+    }
+
+    /// Render the registry configuration for the insecure registries and the
+    /// registry credentials
+    fn registries(config: &Config, registry_auth: &RegistryAuth) -> Result<String> {
+        let auths = match registry_auth.path() {
+            Some(path) => Self::auths(&read_to_string(path).with_context(|| {
+                format!("Unable to read registry auth file '{}'", path.display())
+            })?)?,
+            None => vec![],
+        };
+        Ok(Self::render_registries(
+            config.insecure_registries(),
+            &auths,
+        ))
+    }
+
+    /// Retrieve the base64 encoded credentials by their registry
+    fn auths(auth_file: &str) -> Result<Vec<(String, String)>> {
+        let value: Value =
+            serde_json::from_str(auth_file).context("Unable to parse registry auth file")?;
+        Ok(value["auths"]
+            .as_object()
+            .map(|x| {
+                x.iter()
+                    .filter_map(|(registry, auth)| {
+                        auth["auth"].as_str().map(|x| (registry.clone(), x.into()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn render_registries(insecure_registries: &[String], auths: &[(String, String)]) -> String {
+        let quote = |x: &str| toml::Value::String(x.into()).to_string();
+        let mut out = String::new();
+        for registry in insecure_registries {
+            out.push_str(&format!(
+                "\n[{}.registry.mirrors.{}]\n  endpoint = [{}, {}]\n",
+                CRI_PLUGIN,
+                quote(registry),
+                quote(&format!("https://{}", registry)),
+                quote(&format!("http://{}", registry)),
+            ));
+            out.push_str(&format!(
+                "\n[{}.registry.configs.{}.tls]\n  insecure_skip_verify = true\n",
+                CRI_PLUGIN,
+                quote(registry),
+            ));
+        }
+        for (registry, auth) in auths {
+            out.push_str(&format!(
+                "\n[{}.registry.configs.{}.auth]\n  auth = {}\n",
+                CRI_PLUGIN,
+                quote(registry),
+                quote(auth),
+            ));
+        }
+        out
+    }
+}
+
+impl Stoppable for Containerd {
+    fn stop(&mut self) -> Result<()> {
+        // Remove all running containers
+        self.socket
+            .remove_all_pods(&self.node_name)
+            .with_context(|| {
+                format!(
+                    "Unable to remove containerd containers on {}",
+                    self.node_name
+                )
+            })?;
+
+        // Stop the process, should never really fail
+        self.process.stop()
+    }
+
+    fn restart(&mut self) -> Result<()> {
+        self.process.restart()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auths_success() -> Result<()> {
+        let auths =
+            Containerd::auths(r#"{"auths":{"quay.io":{"auth":"dXNlcjpwYXNz"},"docker.io":{}}}"#)?;
+        assert_eq!(auths, vec![("quay.io".into(), "dXNlcjpwYXNz".into())]);
+        assert!(Containerd::auths("{}")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn auths_failure() {
+        assert!(Containerd::auths("not json").is_err());
+    }
+
+    #[test]
+    fn render_registries_success() -> Result<()> {
+        let registries = Containerd::render_registries(
+            &["localhost:5000".into()],
+            &[("quay.io".into(), "dXNlcjpwYXNz".into())],
+        );
+        let value =
+            format!("version = 2\n[{}]\n{}", CRI_PLUGIN, registries).parse::<toml::Value>()?;
+        let registry = &value["plugins"]["io.containerd.grpc.v1.cri"]["registry"];
+        assert_eq!(
+            registry["mirrors"]["localhost:5000"]["endpoint"][1].as_str(),
+            Some("http://localhost:5000")
+        );
+        assert_eq!(
+            registry["configs"]["localhost:5000"]["tls"]["insecure_skip_verify"].as_bool(),
+            Some(true)
+        );
+        assert_eq!(
+            registry["configs"]["quay.io"]["auth"]["auth"].as_str(),
+            Some("dXNlcjpwYXNz")
+        );
+        assert!(Containerd::render_registries(&[], &[]).is_empty());
+        Ok(())
+    }
+}
@@ -1,18 +1,29 @@
 use crate::{
+    cni::Cni,
     config::Config,
+    controlplane::ControlPlane,
     kubeconfig::KubeConfig,
     network::Network,
     pki::Pki,
-    process::{Process, ProcessState, Stoppable},
+    process::{Process, ProcessState, Readiness, Stoppable},
 };
 use anyhow::Result;
-use std::fs::create_dir_all;
+use std::{fs::create_dir_all, net::Ipv4Addr};
 
 pub struct ControllerManager {
     process: Process,
 }
 
 impl ControllerManager {
+    /// The distance of the secure port to the one of the API server, which
+    /// results in the default port 10257
+    const PORT_OFFSET: u16 = 3814;
+
+    /// The secure port serving the health endpoint
+    pub fn port(config: &Config) -> u16 {
+        config.api_port() + Self::PORT_OFFSET
+    }
+
     pub fn start(
         config: &Config,
         network: &Network,
@@ -22,30 +33,73 @@ impl ControllerManager {
         let dir = config.root().join("controllermanager");
         create_dir_all(&dir)?;
 
-        let mut process = Process::start(
+        let args: &[&str] = &[
+            &format!(
+                "--authentication-kubeconfig={}",
+                kubeconfig.controller_manager().display()
+            ),
+            &format!(
+                "--authorization-kubeconfig={}",
+                kubeconfig.controller_manager().display()
+            ),
+            "--bind-address=0.0.0.0",
+            &format!("--client-ca-file={}", pki.ca().cert().display()),
+            &format!("--cluster-cidr={}", network.cluster_cidr()),
+            "--cluster-name=kubernetes",
+            &format!("--cluster-signing-cert-file={}", pki.ca().cert().display()),
+            &format!("--cluster-signing-key-file={}", pki.ca().key().display()),
+            &format!("--kubeconfig={}", kubeconfig.controller_manager().display()),
+            "--leader-elect=false",
+            &format!("--root-ca-file={}", pki.ca().cert().display()),
+            &format!("--secure-port={}", Self::port(config)),
+            &format!(
+                "--service-account-private-key-file={}",
+                pki.service_account().key().display()
+            ),
+            &format!("--service-cluster-ip-range={}", network.service_cidr()),
+            &format!(
+                "--tls-cert-file={}",
+                pki.controller_manager().cert().display()
+            ),
+            &format!(
+                "--tls-private-key-file={}",
+                pki.controller_manager().key().display()
+            ),
+            "--use-service-account-credentials=true",
+            "--v=2",
+        ];
+        let cloud_provider = config.cloud_provider_arg();
+        let feature_gates = config.feature_gates_arg()?;
+        let node_cidrs = if Cni::is_managed(config) {
+            vec![
+                "--allocate-node-cidrs=true".into(),
+                format!("--node-cidr-mask-size={}", config.node_prefix()),
+            ]
+        } else {
+            vec![]
+        };
+        let mut process = ControlPlane::exec(
+            config,
             &dir,
             "Controller Manager",
             "kube-controller-manager",
-            &[
-                "--bind-address=0.0.0.0",
-                &format!("--cluster-cidr={}", network.cluster_cidr()),
-                "--cluster-name=kubernetes",
-                &format!("--cluster-signing-cert-file={}", pki.ca().cert().display()),
-                &format!("--cluster-signing-key-file={}", pki.ca().key().display()),
-                &format!("--kubeconfig={}", kubeconfig.controller_manager().display()),
-                "--leader-elect=false",
-                &format!("--root-ca-file={}", pki.ca().cert().display()),
-                &format!(
-                    "--service-account-private-key-file={}",
-                    pki.service_account().key().display()
-                ),
-                &format!("--service-cluster-ip-range={}", network.service_cidr()),
-                "--use-service-account-credentials=true",
-                "--v=2",
-            ],
+            &args
+                .iter()
+                .copied()
+                .chain(cloud_provider.as_deref())
+                .chain(node_cidrs.iter().map(String::as_str))
+                .chain(feature_gates.as_deref())
+                .chain(config.controller_manager_args().iter().map(String::as_str))
+                .collect::<Vec<_>>(),
+            &config.component_env_vars("controller-manager"),
         )?;
 
-        process.wait_ready("Serving securely")?;
+        process.wait_ready(Readiness::healthz(Self::port(config), pki))?;
+        process.describe(
+            "kube-controller-manager",
+            &format!("https://{}:{}", Ipv4Addr::LOCALHOST, Self::port(config)),
+            Some(kubeconfig.controller_manager()),
+        );
         Ok(Box::new(Self { process }))
     }
 }
@@ -0,0 +1,103 @@
+use crate::{
+    config::Config,
+    container::Container,
+    crio::Crio,
+    process::{Process, ProcessOptions},
+};
+use anyhow::{bail, Result};
+use std::{collections::BTreeMap, fs::create_dir_all, path::Path};
+
+/// The processes of the control plane, which run either on the host or
+/// inside a dedicated container
+pub struct ControlPlane;
+
+impl ControlPlane {
+    /// The name of the control plane container
+    pub const NAME: &'static str = "control-plane";
+
+    /// Verify that all files passed to the control plane are available
+    /// inside the container, which mounts only the run root
+    pub fn preflight(config: &Config) -> Result<()> {
+        if !config.control_plane_container() {
+            return Ok(());
+        }
+        if let Some(key) = config
+            .service_account_keys()
+            .iter()
+            .find(|x| !x.starts_with(config.root()))
+        {
+            bail!(
+                "Service account key '{}' has to reside within the run root '{}' \
+                 to be usable by the control plane container",
+                key.display(),
+                config.root().display()
+            )
+        }
+        Ok(())
+    }
+
+    /// Start the first process of the control plane, which is etcd or kine.
+    /// If enabled, the process creates the control plane container, which
+    /// gets removed together with it.
+    pub fn start(
+        config: &Config,
+        dir: &Path,
+        identifier: &str,
+        command: &str,
+        args: &[&str],
+        env: &BTreeMap<String, String>,
+    ) -> Result<Process> {
+        if config.control_plane_container() {
+            create_dir_all(Crio::pod_log_dir(config, dir))?;
+            Container::start(config, dir, identifier, command, Self::NAME, args, env)
+        } else {
+            Process::start(
+                &ProcessOptions::new(config),
+                dir,
+                identifier,
+                command,
+                args,
+                env,
+            )
+        }
+    }
+
+    /// Start a subsequent process of the control plane, which joins the
+    /// control plane container if enabled
+    pub fn exec(
+        config: &Config,
+        dir: &Path,
+        identifier: &str,
+        command: &str,
+        args: &[&str],
+        env: &BTreeMap<String, String>,
+    ) -> Result<Process> {
+        if config.control_plane_container() {
+            Container::exec(config, dir, identifier, command, Self::NAME, args, env)
+        } else {
+            Process::start(
+                &ProcessOptions::new(config),
+                dir,
+                identifier,
+                command,
+                args,
+                env,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{test_config, test_config_with};
+
+    #[test]
+    fn preflight_success() -> Result<()> {
+        ControlPlane::preflight(&test_config()?)?;
+        ControlPlane::preflight(&test_config_with(|c| {
+            c.set_control_plane_container(true);
+        })?)?;
+        Ok(())
+    }
+}
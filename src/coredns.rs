@@ -1,4 +1,4 @@
-use crate::{config::Config, kubectl::Kubectl, network::Network};
+use crate::{addon::Addon, config::Config, kubectl::Kubectl, network::Network};
 use anyhow::{Context, Result};
 use log::info;
 use std::fs::{self, create_dir_all};
@@ -6,13 +6,25 @@ use std::fs::{self, create_dir_all};
 pub struct CoreDns;
 
 impl CoreDns {
-    pub fn apply(config: &Config, network: &Network, kubectl: &Kubectl) -> Result<()> {
-        info!("Deploying CoreDNS and waiting to be ready");
+    const IMAGE: &'static str = "k8s.gcr.io/coredns:1.7.0";
+}
+
+impl Addon for CoreDns {
+    fn name(&self) -> String {
+        "coredns".into()
+    }
+
+    fn apply(&self, config: &Config, network: &Network, kubectl: &Kubectl) -> Result<()> {
+        info!("Deploying CoreDNS");
 
         let dir = config.root().join("coredns");
         create_dir_all(&dir)?;
 
-        let yml = format!(include_str!("assets/coredns.yml"), network.dns()?);
+        let yml = format!(
+            include_str!("assets/coredns.yml"),
+            dns = network.dns()?,
+            image = config.image("coredns", CoreDns::IMAGE)?,
+        );
         let file = dir.join("coredns.yml");
 
         if !file.exists() {
@@ -20,7 +32,14 @@ impl CoreDns {
         }
 
         kubectl.apply(&file).context("Unable to deploy CoreDNS")?;
-        kubectl.wait_ready("coredns")?;
+        if config.no_proxy() {
+            // The service network is not available until the CNI replacing
+            // kube-proxy got deployed
+            info!("Skipping CoreDNS readiness check since kube-proxy is disabled");
+        } else {
+            info!("Waiting for CoreDNS to be ready");
+            kubectl.wait_ready("coredns")?;
+        }
         info!("CoreDNS deployed");
         Ok(())
     }
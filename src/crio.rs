@@ -1,18 +1,23 @@
 use crate::{
+    cni::Cni,
+    config::NodeRuntime,
     container::Container,
+    containerd::{Containerd, CONTAINERD},
     network::Network,
     node::Node,
-    process::{Process, ProcessState, Stoppable},
+    process::{Process, ProcessOptions, ProcessState, Readiness, Stoppable},
+    record::Recorded,
+    registryauth::RegistryAuth,
+    runtimeclass::RuntimeClasses,
     system::System,
     Config, RUNTIME_ENV,
 };
 use anyhow::{bail, Context, Result};
-use log::debug;
-use serde_json::{json, to_string_pretty};
+use log::{debug, warn};
 use std::{
     fmt::{self, Display, Formatter},
-    fs::{self, create_dir_all},
-    path::PathBuf,
+    fs::{self, create_dir_all, read_dir},
+    path::{Path, PathBuf},
     process::Command,
 };
 
@@ -34,38 +39,127 @@ impl Display for CriSocket {
 impl CriSocket {
     pub fn new(path: PathBuf) -> Result<CriSocket> {
         if path.display().to_string().len() > 100 {
-            bail!("Socket path '{}' is too long")
+            bail!(
+                "Socket path '{}' is too long, consider using a shorter --socket-dir",
+                path.display()
+            )
         }
         Ok(CriSocket(path))
     }
 
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
     pub fn to_socket_string(&self) -> String {
         format!("unix://{}", self.0.display())
     }
+
+    /// Retrieve the CRI socket of the node, depending on its runtime
+    pub fn of(config: &Config, network: &Network, node: u8) -> Result<CriSocket> {
+        match config.node_runtime(node) {
+            NodeRuntime::Crio => Crio::socket(config, network, node),
+            NodeRuntime::Containerd => Containerd::socket(config, network, node),
+        }
+    }
+
+    /// Remove all pods of the node via crictl invocations
+    pub fn remove_all_pods(&self, node_name: &str) -> Result<()> {
+        debug!("Removing all CRI workloads on {}", node_name);
+
+        let output = Command::new("crictl")
+            .env(RUNTIME_ENV, self.to_socket_string())
+            .arg("pods")
+            .arg("-q")
+            .recorded_output()?;
+        let stdout = String::from_utf8(output.stdout)?;
+        if !output.status.success() {
+            debug!("critcl pods stdout ({}): {}", node_name, stdout);
+            debug!(
+                "critcl pods stderr ({}): {}",
+                node_name,
+                String::from_utf8(output.stderr)?
+            );
+            bail!("crictl pods command failed ({})", node_name);
+        }
+
+        for x in stdout.lines() {
+            debug!("Removing pod {} on {}", x, node_name);
+            let output = Command::new("crictl")
+                .env(RUNTIME_ENV, self.to_socket_string())
+                .arg("rmp")
+                .arg("-f")
+                .arg(x)
+                .recorded_output()?;
+            if !output.status.success() {
+                debug!("critcl rmp ({}): {:?}", node_name, output);
+                bail!("crictl rmp command failed ({})", node_name);
+            }
+        }
+
+        debug!("All workloads removed on {}", node_name);
+        Ok(())
+    }
 }
 
 const CRIO: &str = "crio";
+pub const PAUSE_IMAGE: &str = "k8s.gcr.io/pause:3.2";
+
+/// The directory where the kubelet writes the container logs of all pods
+pub const POD_LOG_DIR: &str = "/var/log/pods";
 
 impl Crio {
-    pub fn start(config: &Config, node: u8, network: &Network) -> ProcessState {
+    pub fn start(
+        config: &Config,
+        node: u8,
+        network: &Network,
+        registry_auth: &RegistryAuth,
+    ) -> ProcessState {
         let node_name = Node::name(config, network, node);
 
         let conmon = System::find_executable("conmon")?;
-        let loopback = System::find_executable("loopback")?;
-        let cni_plugin = loopback.parent().context("Unable to find CNI plugin dir")?;
+        let cni_plugin = Cni::plugin_dir(config)?;
 
         let dir = Self::path(config, network, node);
-        let config_dir = dir.join("crio.conf.d");
-        let config_file = config_dir.join("crio.conf");
+        let config_file = Self::config_file(config, network, node);
+        let config_dir = config_file.parent().context("Unable to find config dir")?;
         let network_dir = dir.join("cni");
         let socket = Self::socket(config, network, node)?;
+        create_dir_all(config.socket_dir())?;
+
+        // The configs may have been removed to be regenerated
+        if !config_file.exists() {
+            // Conmon needs to be able to fill its read buffer
+            let log_size_max = config.log_size_max();
+            if log_size_max > 0 && log_size_max < 8192 {
+                bail!(
+                    "Log size max of {} bytes is below the minimum of 8192 bytes",
+                    log_size_max
+                )
+            }
 
-        if !dir.exists() {
             create_dir_all(&dir)?;
             create_dir_all(&network_dir)?;
-            create_dir_all(&config_dir)?;
+            create_dir_all(config_dir)?;
 
             let containers_dir = dir.join("containers");
+            let storage_driver = if config.multi_node() || System::in_container()? {
+                "vfs"
+            } else {
+                System::storage_driver(&containers_dir)?.unwrap_or("overlay")
+            };
+            let mut storage_options = vec![];
+            if let Some(quota) = config.storage_quota() {
+                if storage_driver == "overlay" {
+                    storage_options.push(format!("{}.size={}", storage_driver, quota));
+                } else {
+                    warn!(
+                        "Storage quota is not supported by the {} storage driver",
+                        storage_driver
+                    );
+                }
+            }
+
             fs::write(
                 &config_file,
                 format!(
@@ -75,54 +169,53 @@ impl Crio {
                     containers_runroot = containers_dir.join("run").display(),
                     listen = socket,
                     log_dir = dir.join("log").display(),
+                    log_size_max = config.log_size_max(),
                     network_dir = network_dir.display(),
+                    pause_image = config.image("pause", PAUSE_IMAGE)?,
                     plugin_dir = cni_plugin.display(),
                     exits_dir = dir.join("exits").display(),
-                    runtime_path = System::find_executable("runc")?.display(),
-                    runtime_root = dir.join("runc").display(),
+                    global_auth_file = registry_auth
+                        .path()
+                        .as_ref()
+                        .map(|x| x.display().to_string())
+                        .unwrap_or_default(),
+                    insecure_registries = toml::Value::try_from(config.insecure_registries())?,
+                    default_runtime = RuntimeClasses::handler(config.oci_runtime()),
+                    runtimes = RuntimeClasses::crio_runtimes(config, &dir)?,
                     signature_policy = Container::policy_json(config).display(),
-                    storage_driver = if config.multi_node() || System::in_container()? {
-                        "vfs"
-                    } else {
-                        "overlay"
-                    },
+                    storage_driver = storage_driver,
+                    storage_options = toml::Value::try_from(storage_options)?,
                     version_file = dir.join("version").display(),
                 ),
             )?;
 
-            let cidr = network
-                .crio_cidrs()
-                .get(node as usize)
-                .with_context(|| format!("Unable to find CIDR for {}", node_name))?;
-            fs::write(
-                network_dir.join("10-bridge.json"),
-                to_string_pretty(&json!({
-                    "cniVersion": "0.3.1",
-                    "name": format!("kubernix-{}", node_name),
-                    "type": "bridge",
-                    "bridge": format!("{}.{}", Network::INTERFACE_PREFIX, node),
-                    "isGateway": true,
-                    "ipMasq": true,
-                    "hairpinMode": true,
-                    "ipam": {
-                        "type": "host-local",
-                        "routes": [{ "dst": "0.0.0.0/0" }],
-                        "ranges": [[{ "subnet": cidr }]]
-                    }
-                }))?,
-            )?;
+            Cni::write_config(config, network, node, &node_name, &network_dir)?;
         }
+        System::symlink(&socket.0, &dir.join("crio.sock"))?;
         let args: &[&str] = &[&format!("--config-dir={}", config_file.display())];
 
+        // The node containers do not inherit the proxy configuration
+        let mut env = network.proxy_env(config);
+        env.extend(config.component_env_vars(CRIO));
+
         let mut process = if config.multi_node() {
-            // Run inside a container
+            // Run inside a container, whereas the pod logs are kept per node
+            create_dir_all(Self::pod_log_dir(config, &dir))?;
             let identifier = format!("CRI-O {}", node_name);
-            Container::start(config, &dir, &identifier, CRIO, &node_name, args)?
+            Container::start(config, &dir, &identifier, CRIO, &node_name, args, &env)?
         } else {
             // Run as usual process
-            Process::start(&dir, "CRI-O", CRIO, args)?
+            Process::start(
+                &ProcessOptions::new(config),
+                &dir,
+                "CRI-O",
+                CRIO,
+                args,
+                &env,
+            )?
         };
-        process.wait_ready("Sandboxes:")?;
+        process.wait_ready(Readiness::Cri(socket.to_socket_string()))?;
+        process.describe(CRIO, &socket.to_socket_string(), None);
 
         Ok(Box::new(Self {
             process,
@@ -131,9 +224,13 @@ impl Crio {
         }))
     }
 
-    /// Retrieve the CRI socket
+    /// Retrieve the CRI socket, which resides in the socket directory
     pub fn socket(config: &Config, network: &Network, node: u8) -> Result<CriSocket> {
-        CriSocket::new(Self::path(config, network, node).join("crio.sock"))
+        CriSocket::new(
+            config
+                .socket_dir()
+                .join(format!("crio-{}.sock", Node::name(config, network, node))),
+        )
     }
 
     /// Retrieve the working path for the node
@@ -144,41 +241,105 @@ impl Crio {
             .join(Node::name(config, network, node))
     }
 
-    /// Remove all containers via crictl invocations
-    fn remove_all_containers(&self) -> Result<()> {
-        debug!("Removing all CRI-O workloads on {}", self.node_name);
+    /// Retrieve the configuration file of the provided node
+    pub fn config_file(config: &Config, network: &Network, node: u8) -> PathBuf {
+        Self::path(config, network, node)
+            .join("crio.conf.d")
+            .join("crio.conf")
+    }
 
-        let output = Command::new("crictl")
-            .env(RUNTIME_ENV, self.socket.to_socket_string())
-            .arg("pods")
-            .arg("-q")
-            .output()?;
-        let stdout = String::from_utf8(output.stdout)?;
-        if !output.status.success() {
-            debug!("critcl pods stdout ({}): {}", self.node_name, stdout);
-            debug!(
-                "critcl pods stderr ({}): {}",
-                self.node_name,
-                String::from_utf8(output.stderr)?
-            );
-            bail!("crictl pods command failed ({})", self.node_name);
+    /// Retrieve the containers-storage(5) reference of the node in the format
+    /// `[driver@root+runroot]`, which is read from its configuration file
+    pub fn storage(config: &Config, network: &Network, node: u8) -> Result<String> {
+        let file = Self::config_file(config, network, node);
+        let content = fs::read_to_string(&file).with_context(|| {
+            format!(
+                "Unable to read CRI-O config '{}', is the cluster bootstrapped?",
+                file.display()
+            )
+        })?;
+        Self::storage_reference(&content)
+            .with_context(|| format!("Invalid CRI-O config '{}'", file.display()))
+    }
+
+    fn storage_reference(content: &str) -> Result<String> {
+        let value = content.parse::<toml::Value>()?;
+        let field = |name| {
+            value
+                .get("crio")
+                .and_then(|x| x.get(name))
+                .and_then(toml::Value::as_str)
+                .with_context(|| format!("No {} configured", name))
+        };
+        Ok(format!(
+            "[{}@{}+{}]",
+            field("storage_driver")?,
+            field("root")?,
+            field("runroot")?
+        ))
+    }
+
+    /// Retrieve the pod log directory of the provided node directory on the
+    /// host, which is mounted into the container on multi node clusters
+    pub fn pod_log_dir(config: &Config, dir: &Path) -> PathBuf {
+        if config.multi_node() {
+            dir.join("pods")
+        } else {
+            POD_LOG_DIR.into()
         }
+    }
 
-        for x in stdout.lines() {
-            debug!("Removing pod {} on {}", x, self.node_name);
-            let output = Command::new("crictl")
-                .env(RUNTIME_ENV, self.socket.to_socket_string())
-                .arg("rmp")
-                .arg("-f")
-                .arg(x)
-                .output()?;
-            if !output.status.success() {
-                debug!("critcl rmp ({}): {:?}", self.node_name, output);
-                bail!("crictl rmp command failed ({})", self.node_name);
+    /// Retrieve all pod log files per node name, whereas only the pods
+    /// containing the filter in their name are considered
+    pub fn pod_logs(config: &Config, filter: Option<&str>) -> Result<Vec<(String, PathBuf)>> {
+        let mut logs = vec![];
+        for runtime in &[CRIO, CONTAINERD] {
+            let nodes = config.root().join(runtime);
+            if nodes.is_dir() {
+                Self::node_pod_logs(config, &nodes, filter, &mut logs)?;
             }
         }
+        logs.sort();
+        Ok(logs)
+    }
 
-        debug!("All workloads removed on {}", self.node_name);
+    /// Collect the pod logs of all nodes within the runtime directory
+    fn node_pod_logs(
+        config: &Config,
+        nodes: &Path,
+        filter: Option<&str>,
+        logs: &mut Vec<(String, PathBuf)>,
+    ) -> Result<()> {
+        for node in read_dir(nodes)? {
+            let node = node?;
+            if !node.file_type()?.is_dir() {
+                continue;
+            }
+            let name = node.file_name().to_string_lossy().to_string();
+            let pod_log_dir = Self::pod_log_dir(config, &node.path());
+            if !pod_log_dir.is_dir() {
+                continue;
+            }
+            for pod in read_dir(&pod_log_dir)? {
+                let pod = pod?.path();
+                let pod_name = pod.file_name().unwrap_or_default().to_string_lossy();
+                if filter.map_or(true, |x| pod_name.contains(x)) {
+                    Self::collect_logs(&pod, &name, logs)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Recursively collect all log files, including the rotated ones
+    fn collect_logs(path: &Path, node: &str, logs: &mut Vec<(String, PathBuf)>) -> Result<()> {
+        if path.is_dir() {
+            for entry in read_dir(path)? {
+                Self::collect_logs(&entry?.path(), node, logs)?;
+            }
+        } else if path.to_string_lossy().contains(".log") {
+            logs.push((node.into(), path.into()));
+        }
         Ok(())
     }
 }
@@ -186,17 +347,23 @@ impl Crio {
 impl Stoppable for Crio {
     fn stop(&mut self) -> Result<()> {
         // Remove all running containers
-        self.remove_all_containers()
+        self.socket
+            .remove_all_pods(&self.node_name)
             .with_context(|| format!("Unable to remove CRI-O containers on {}", self.node_name,))?;
 
         // Stop the process, should never really fail
         self.process.stop()
     }
+
+    fn restart(&mut self) -> Result<()> {
+        self.process.restart()
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::config::tests::test_config_with;
 
     #[test]
     fn cri_socket_success() -> Result<()> {
@@ -204,8 +371,46 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn storage_reference_success() -> Result<()> {
+        let content = "[crio]\nroot = \"/a\"\nrunroot = \"/b\"\nstorage_driver = \"vfs\"\n";
+        assert_eq!(Crio::storage_reference(content)?, "[vfs@/a+/b]");
+        Ok(())
+    }
+
+    #[test]
+    fn storage_reference_failure() {
+        assert!(Crio::storage_reference("[crio]\nroot = \"/a\"\n").is_err());
+        assert!(Crio::storage_reference("[crio").is_err());
+    }
+
     #[test]
     fn cri_socket_failure() {
         assert!(CriSocket::new("a".repeat(101).into()).is_err());
     }
+
+    #[test]
+    fn pod_logs_success() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_nodes(2);
+        })?;
+        let pod = c
+            .root()
+            .join(CRIO)
+            .join("node-1")
+            .join("pods")
+            .join("default_nginx_1234")
+            .join("nginx");
+        create_dir_all(&pod)?;
+        fs::write(pod.join("0.log"), "")?;
+        fs::write(pod.join("0.log.20200101-000000.gz"), "")?;
+        create_dir_all(c.root().join(CRIO).join("node-0").join("pods"))?;
+
+        let logs = Crio::pod_logs(&c, None)?;
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0], ("node-1".into(), pod.join("0.log")));
+        assert_eq!(Crio::pod_logs(&c, Some("nginx"))?.len(), 2);
+        assert!(Crio::pod_logs(&c, Some("redis"))?.is_empty());
+        Ok(())
+    }
 }
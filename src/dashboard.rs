@@ -0,0 +1,130 @@
+use crate::{
+    addon::Addon, config::Config, kubectl::Kubectl, network::Network, system::System, token::Token,
+};
+use anyhow::{Context, Result};
+use log::info;
+use std::{
+    fs::{self, create_dir_all},
+    path::PathBuf,
+};
+
+/// The Kubernetes Dashboard addon, which is reachable via a node port on the
+/// host and accessible via an admin token
+pub struct Dashboard;
+
+impl Dashboard {
+    /// The name of the addon
+    pub const NAME: &'static str = "dashboard";
+
+    /// The label of the dashboard pod
+    const APP: &'static str = "kubernetes-dashboard";
+
+    const IMAGE: &'static str = "kubernetesui/dashboard:v2.0.4";
+
+    /// The node port serving the dashboard via HTTPS
+    pub const PORT: u16 = 30444;
+
+    /// The service account whose token grants admin access to the dashboard
+    const SERVICE_ACCOUNT: &'static str = "kubernix-dashboard-admin";
+
+    /// The expiration of the admin token in seconds, whereas a new token gets
+    /// requested on every start of the cluster
+    const TOKEN_DURATION: u64 = 12 * 60 * 60;
+
+    /// The URL of the dashboard on the host
+    pub fn url() -> String {
+        format!("https://127.0.0.1:{}", Self::PORT)
+    }
+
+    /// The environment variables exporting the dashboard URL and admin token,
+    /// if the dashboard has been deployed
+    pub fn env(config: &Config) -> Result<Option<String>> {
+        let file = Self::env_file(config);
+        if !file.exists() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read_to_string(&file).with_context(|| {
+            format!("Unable to read '{}'", file.display())
+        })?))
+    }
+
+    fn env_file(config: &Config) -> PathBuf {
+        config.root().join(Self::NAME).join("dashboard.env")
+    }
+
+    fn exports(token: &str) -> String {
+        format!(
+            "export KUBERNIX_DASHBOARD_URL={}\nexport KUBERNIX_DASHBOARD_TOKEN={}",
+            Self::url(),
+            token
+        )
+    }
+}
+
+impl Addon for Dashboard {
+    fn name(&self) -> String {
+        Dashboard::NAME.into()
+    }
+
+    fn apply(&self, config: &Config, _: &Network, kubectl: &Kubectl) -> Result<()> {
+        info!("Deploying Kubernetes Dashboard and waiting to be ready");
+
+        let dir = config.root().join(Dashboard::NAME);
+        create_dir_all(&dir)?;
+
+        let yml = format!(
+            include_str!("assets/dashboard.yml"),
+            image = config.image(Dashboard::NAME, Dashboard::IMAGE)?,
+            port = Dashboard::PORT,
+        );
+        let file = dir.join("dashboard.yml");
+
+        if !file.exists() {
+            fs::write(&file, yml)?;
+        }
+
+        kubectl
+            .apply(&file)
+            .context("Unable to deploy Kubernetes Dashboard")?;
+        kubectl.wait_ready(Dashboard::APP)?;
+
+        let token = Token::new(
+            Dashboard::SERVICE_ACCOUNT,
+            "kube-system",
+            "cluster-admin",
+            Dashboard::TOKEN_DURATION,
+        )
+        .request(config, kubectl)
+        .context("Unable to create dashboard admin token")?;
+        System::write_private(&Dashboard::env_file(config), &Dashboard::exports(&token))?;
+        info!(
+            "Kubernetes Dashboard deployed on {}, its admin token is available via $KUBERNIX_DASHBOARD_TOKEN",
+            Dashboard::url()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_config;
+
+    #[test]
+    fn env_success() -> Result<()> {
+        let c = test_config()?;
+        assert!(Dashboard::env(&c)?.is_none());
+
+        create_dir_all(c.root().join(Dashboard::NAME))?;
+        fs::write(Dashboard::env_file(&c), Dashboard::exports("abc"))?;
+        assert_eq!(
+            Dashboard::env(&c)?,
+            Some(
+                "export KUBERNIX_DASHBOARD_URL=https://127.0.0.1:30444\n\
+                 export KUBERNIX_DASHBOARD_TOKEN=abc"
+                    .into()
+            )
+        );
+        Ok(())
+    }
+}
@@ -1,12 +1,16 @@
-use crate::Config;
-use anyhow::Result;
+use crate::{
+    config::{Config, EncryptionProvider},
+    entropy::Entropy,
+};
+use anyhow::{bail, Context, Result};
 use base64::encode;
 use getset::Getters;
 use log::info;
-use rand::{thread_rng, Rng};
+use rand::Rng;
+use serde_json::{json, Value};
 use std::{
     fs::{self, create_dir_all},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 #[derive(Getters)]
@@ -21,29 +25,239 @@ impl EncryptionConfig {
         create_dir_all(dir)?;
         let path = dir.join("config.yml");
 
-        // Create only if not already existing to make cluster reuse work
-        if !path.exists() {
+        // Reuse the existing keys to make cluster reuse work
+        let mut providers = if path.exists() {
+            Self::load(&path)?
+        } else {
             info!("Creating encryption config");
-            let rnd = thread_rng().gen::<[u8; 32]>();
-            let b64 = encode(&rnd);
-            let yml = format!(include_str!("assets/encryptionconfig.yml"), b64);
-            fs::write(&path, yml)?;
+            vec![]
+        };
+
+        // A changed provider gets prepended, whereas the previous ones are
+        // still able to read the existing secrets
+        let provider = config.encryption_provider();
+        let primary = providers.first().and_then(Self::kind);
+        if primary != Some(&provider.to_string()) || provider == EncryptionProvider::Kms {
+            let new = Self::provider(config, &Self::next_key_name(&providers))?;
+            if primary == Some(&provider.to_string()) {
+                providers[0] = new;
+            } else {
+                if !providers.is_empty() {
+                    info!("Switching encryption provider to {}", provider);
+                }
+                providers.insert(0, new);
+            }
+            Self::write(&path, &providers)?;
         }
 
         Ok(EncryptionConfig { path })
     }
+
+    /// Prepend a new key to the primary provider, which returns false if the
+    /// provider does not use any keys
+    pub fn rotate(config: &Config) -> Result<bool> {
+        let path = Self::new(config)?.path;
+        let mut providers = Self::load(&path)?;
+        let name = Self::next_key_name(&providers);
+        let keys = match providers[0]
+            .as_object_mut()
+            .and_then(|x| x.values_mut().next())
+            .and_then(|x| x["keys"].as_array_mut())
+        {
+            Some(keys) => keys,
+            None => return Ok(false),
+        };
+        info!("Adding encryption key {}", name);
+        keys.insert(0, Self::key(&name));
+        Self::write(&path, &providers)?;
+        Ok(true)
+    }
+
+    /// Remove all keys and providers except the first key of the primary
+    /// provider, which requires that all secrets have been rewritten before
+    pub fn prune(config: &Config) -> Result<()> {
+        let path = Self::new(config)?.path;
+        let mut providers = Self::load(&path)?;
+        providers.truncate(1);
+        if let Some(keys) = providers[0]
+            .as_object_mut()
+            .and_then(|x| x.values_mut().next())
+            .and_then(|x| x["keys"].as_array_mut())
+        {
+            keys.truncate(1);
+        }
+        info!("Pruning previous encryption keys and providers");
+        Self::write(&path, &providers)
+    }
+
+    /// Load the providers of the encryption config
+    fn load(path: &Path) -> Result<Vec<Value>> {
+        let yml = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read encryption config '{}'", path.display()))?;
+        let value: Value = serde_yaml::from_str(&yml)?;
+        let providers = value["resources"][0]["providers"]
+            .as_array()
+            .with_context(|| format!("No providers found in '{}'", path.display()))?;
+        Ok(providers.clone())
+    }
+
+    /// Write the encryption config, whereas the identity provider is always
+    /// part of it to read secrets which have been never encrypted
+    fn write(path: &Path, providers: &[Value]) -> Result<()> {
+        let mut providers = providers
+            .iter()
+            .enumerate()
+            .filter(|(i, x)| *i == 0 || Self::kind(x) != Some("identity"))
+            .map(|(_, x)| x.clone())
+            .collect::<Vec<_>>();
+        if providers.first().and_then(Self::kind) != Some("identity") {
+            providers.push(json!({ "identity": {} }));
+        }
+        fs::write(
+            path,
+            serde_yaml::to_string(&json!({
+                "kind": "EncryptionConfiguration",
+                "apiVersion": "apiserver.config.k8s.io/v1",
+                "resources": [{ "resources": ["secrets"], "providers": providers }],
+            }))?,
+        )?;
+        Ok(())
+    }
+
+    /// Retrieve the kind of the provider
+    fn kind(provider: &Value) -> Option<&str> {
+        provider.as_object()?.keys().next().map(String::as_str)
+    }
+
+    /// Retrieve the name of the next key, which counts up across all
+    /// providers
+    fn next_key_name(providers: &[Value]) -> String {
+        let last = providers
+            .iter()
+            .filter_map(|x| x.as_object()?.values().next()?["keys"].as_array())
+            .flatten()
+            .filter_map(|x| x["name"].as_str()?.strip_prefix("key")?.parse::<u32>().ok())
+            .max()
+            .unwrap_or_default();
+        format!("key{}", last + 1)
+    }
+
+    /// Create the configured provider, whereas a new key gets the provided
+    /// name
+    fn provider(config: &Config, name: &str) -> Result<Value> {
+        Ok(match config.encryption_provider() {
+            EncryptionProvider::Aescbc => json!({ "aescbc": { "keys": [Self::key(name)] } }),
+            EncryptionProvider::Secretbox => json!({ "secretbox": { "keys": [Self::key(name)] } }),
+            EncryptionProvider::Kms => {
+                let endpoint = match config.kms_endpoint() {
+                    Some(endpoint) => endpoint,
+                    None => bail!("The kms encryption provider requires a --kms-endpoint"),
+                };
+                json!({
+                    "kms": {
+                        "apiVersion": "v2",
+                        "name": "kubernix",
+                        "endpoint": format!("unix://{}", endpoint.display()),
+                        "timeout": "3s",
+                    }
+                })
+            }
+            EncryptionProvider::Identity => json!({ "identity": {} }),
+        })
+    }
+
+    /// Generate a new random key of 32 bytes
+    fn key(name: &str) -> Value {
+        // The first key keeps the purpose of the initial single key
+        let purpose = match name {
+            "key1" => "encryptionconfig".to_owned(),
+            _ => format!("encryptionconfig-{}", name),
+        };
+        let rnd = Entropy::rng(&purpose).gen::<[u8; 32]>();
+        json!({ "name": name, "secret": encode(rnd) })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::tests::{test_config, test_config_wrong_root};
+    use crate::config::tests::{test_config, test_config_with, test_config_wrong_root};
+
+    fn providers(c: &Config) -> Result<Vec<Value>> {
+        let value: Value =
+            serde_yaml::from_str(&fs::read_to_string(EncryptionConfig::new(c)?.path())?)?;
+        Ok(value["resources"][0]["providers"]
+            .as_array()
+            .context("no providers")?
+            .clone())
+    }
 
     #[test]
     fn encryptionconfig_success() -> Result<()> {
         let c = test_config()?;
         let e = EncryptionConfig::new(&c)?;
         assert!(e.path().exists());
+        let p = providers(&c)?;
+        assert_eq!(p.len(), 2);
+        assert_eq!(p[0]["aescbc"]["keys"][0]["name"], "key1");
+        assert!(p[1]["identity"].is_object());
+        Ok(())
+    }
+
+    #[test]
+    fn encryptionconfig_success_kms() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_encryption_provider(EncryptionProvider::Kms)
+                .set_kms_endpoint(Some("/run/kms.sock".into()));
+        })?;
+        let p = providers(&c)?;
+        assert_eq!(p[0]["kms"]["apiVersion"], "v2");
+        assert_eq!(p[0]["kms"]["endpoint"], "unix:///run/kms.sock");
+        Ok(())
+    }
+
+    #[test]
+    fn encryptionconfig_success_switch_provider() -> Result<()> {
+        let c = test_config()?;
+        let s = test_config_with(|c| {
+            c.set_encryption_provider(EncryptionProvider::Secretbox);
+        })?;
+        let dir = s.root().join("encryptionconfig");
+        create_dir_all(&dir)?;
+        fs::copy(EncryptionConfig::new(&c)?.path(), dir.join("config.yml"))?;
+        let p = providers(&s)?;
+        assert_eq!(p.len(), 3);
+        assert_eq!(p[0]["secretbox"]["keys"][0]["name"], "key2");
+        assert_eq!(p[1]["aescbc"]["keys"][0]["name"], "key1");
+
+        // Unchanged providers keep the encryption config
+        assert_eq!(providers(&s)?, p);
+        Ok(())
+    }
+
+    #[test]
+    fn rotate_success() -> Result<()> {
+        let c = test_config()?;
+        assert!(EncryptionConfig::rotate(&c)?);
+        let p = providers(&c)?;
+        assert_eq!(p[0]["aescbc"]["keys"][0]["name"], "key2");
+        assert_eq!(p[0]["aescbc"]["keys"][1]["name"], "key1");
+
+        EncryptionConfig::prune(&c)?;
+        let p = providers(&c)?;
+        assert_eq!(p.len(), 2);
+        assert_eq!(p[0]["aescbc"]["keys"].as_array().map(Vec::len), Some(1));
+        assert_eq!(p[0]["aescbc"]["keys"][0]["name"], "key2");
+        Ok(())
+    }
+
+    #[test]
+    fn rotate_success_identity() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_encryption_provider(EncryptionProvider::Identity);
+        })?;
+        assert!(!EncryptionConfig::rotate(&c)?);
+        assert_eq!(providers(&c)?.len(), 1);
         Ok(())
     }
 
@@ -53,4 +267,13 @@ mod tests {
         assert!(EncryptionConfig::new(&c).is_err());
         Ok(())
     }
+
+    #[test]
+    fn encryptionconfig_failure_kms() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_encryption_provider(EncryptionProvider::Kms);
+        })?;
+        assert!(EncryptionConfig::new(&c).is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,105 @@
+use crate::{config::PkiKeyType, Config};
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use log::warn;
+use parking_lot::RwLock;
+use rand::{rngs::StdRng, SeedableRng};
+
+lazy_static! {
+    /// The seed of all generated keys and secrets, if configured
+    static ref SEED: RwLock<Option<u64>> = RwLock::new(None);
+}
+
+/// The source of randomness for generated keys and secrets
+pub struct Entropy;
+
+impl Entropy {
+    /// Verify that everything generated from the configured seed can be
+    /// reproduced
+    pub fn preflight(config: &Config) -> Result<()> {
+        if config.seed().is_some()
+            && !matches!(
+                config.pki_key_type(),
+                PkiKeyType::Rsa2048 | PkiKeyType::Rsa4096
+            )
+        {
+            bail!(
+                "A seed requires an RSA PKI key type, because {} keys cannot be generated \
+                 deterministically",
+                config.pki_key_type()
+            )
+        }
+        Ok(())
+    }
+
+    /// Use the seed of the provided config for all subsequently generated
+    /// keys and secrets
+    pub fn init(config: &Config) {
+        if let Some(seed) = config.seed() {
+            warn!(
+                "Generating keys and secrets from seed {}, which is INSECURE and only meant for tests",
+                seed
+            );
+        }
+        *SEED.write() = config.seed();
+    }
+
+    /// Returns true if everything gets generated from a seed
+    pub fn is_seeded() -> bool {
+        SEED.read().is_some()
+    }
+
+    /// Retrieve a random number generator for the provided purpose, which is
+    /// deterministic if a seed is configured
+    pub fn rng(purpose: &str) -> StdRng {
+        match *SEED.read() {
+            Some(seed) => Self::seeded(seed, purpose),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// Derive an independent generator for every purpose, which keeps the
+    /// result stable regardless of the generation order
+    fn seeded(seed: u64, purpose: &str) -> StdRng {
+        // FNV-1a, because the standard hasher is not stable across releases
+        let hash = purpose
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325, |hash: u64, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3)
+            });
+        StdRng::seed_from_u64(seed ^ hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{test_config, test_config_with};
+    use rand::Rng;
+
+    #[test]
+    fn preflight_success() -> Result<()> {
+        Entropy::preflight(&test_config()?)?;
+        Entropy::preflight(&test_config_with(|c| {
+            c.set_seed(Some(42)).set_pki_key_type(PkiKeyType::Rsa2048);
+        })?)?;
+        Ok(())
+    }
+
+    #[test]
+    fn preflight_failure() -> Result<()> {
+        assert!(Entropy::preflight(&test_config_with(|c| {
+            c.set_seed(Some(42)).set_pki_key_type(PkiKeyType::EcdsaP256);
+        })?)
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_success() {
+        let bytes = |seed, purpose| Entropy::seeded(seed, purpose).gen::<[u8; 32]>();
+        assert_eq!(bytes(42, "ca"), bytes(42, "ca"));
+        assert_ne!(bytes(42, "ca"), bytes(42, "admin"));
+        assert_ne!(bytes(42, "ca"), bytes(43, "ca"));
+    }
+}
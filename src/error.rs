@@ -0,0 +1,161 @@
+use anyhow::Error;
+use serde::Serialize;
+use serde_json::json;
+use std::fmt::{self, Display, Formatter};
+
+/// The failure categories of kubernix, each of them mapped to a stable exit code
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Any failure which does not fit into a more specific category
+    Other,
+
+    /// The system or configuration is not suitable to run kubernix
+    Preflight,
+
+    /// The nix environment could not be bootstrapped
+    Nix,
+
+    /// At least one process failed to start
+    ProcessStart,
+
+    /// At least one process did not become ready in time
+    ReadinessTimeout,
+
+    /// The cluster could not be cleaned up completely
+    Cleanup,
+}
+
+impl ErrorKind {
+    /// Retrieve the exit code of the failure category
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Preflight => 3,
+            ErrorKind::Nix => 4,
+            ErrorKind::ProcessStart => 5,
+            ErrorKind::ReadinessTimeout => 6,
+            ErrorKind::Cleanup => 7,
+        }
+    }
+
+    /// Retrieve the failure category of an exit code
+    pub fn from_exit_code(code: i32) -> Option<Self> {
+        [
+            ErrorKind::Preflight,
+            ErrorKind::Nix,
+            ErrorKind::ProcessStart,
+            ErrorKind::ReadinessTimeout,
+            ErrorKind::Cleanup,
+        ]
+        .iter()
+        .copied()
+        .find(|x| x.exit_code() == code)
+    }
+
+    /// Retrieve the failure category of an error, by using the outermost
+    /// category in its chain
+    pub fn of(error: &Error) -> Self {
+        error
+            .downcast_ref::<Self>()
+            .copied()
+            .or_else(|| error.downcast_ref::<Reported>().map(|x| x.0))
+            .unwrap_or(ErrorKind::Other)
+    }
+
+    /// Retrieve the machine readable JSON line for an error
+    pub fn json_line(error: &Error) -> String {
+        let kind = Self::of(error);
+        json!({
+            "result": "error",
+            "kind": kind,
+            "exit_code": kind.exit_code(),
+            "message": error
+                .chain()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(": "),
+        })
+        .to_string()
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ErrorKind::Other => "Unexpected failure",
+                ErrorKind::Preflight => "Preflight check failed",
+                ErrorKind::Nix => "Nix environment failed",
+                ErrorKind::ProcessStart => "Unable to start all processes",
+                ErrorKind::ReadinessTimeout => "Timed out waiting for process readiness",
+                ErrorKind::Cleanup => "Unable to cleanup the cluster",
+            }
+        )
+    }
+}
+
+impl std::error::Error for ErrorKind {}
+
+/// A failure which has already been reported by a nested kubernix process
+#[derive(Debug)]
+pub struct Reported(pub ErrorKind);
+
+impl Display for Reported {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (already reported)", self.0)
+    }
+}
+
+impl std::error::Error for Reported {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context, Result};
+
+    #[test]
+    fn of_success() {
+        let e = anyhow!("inner")
+            .context(ErrorKind::ReadinessTimeout)
+            .context("outer");
+        assert_eq!(ErrorKind::of(&e), ErrorKind::ReadinessTimeout);
+
+        let e = Err::<(), _>(ErrorKind::Nix).context("outer").unwrap_err();
+        assert_eq!(ErrorKind::of(&e), ErrorKind::Nix);
+
+        let e = Error::new(Reported(ErrorKind::Cleanup));
+        assert_eq!(ErrorKind::of(&e), ErrorKind::Cleanup);
+    }
+
+    #[test]
+    fn of_other() {
+        assert_eq!(ErrorKind::of(&anyhow!("error")), ErrorKind::Other);
+    }
+
+    #[test]
+    fn exit_code_roundtrip() {
+        for kind in &[
+            ErrorKind::Preflight,
+            ErrorKind::Nix,
+            ErrorKind::ProcessStart,
+            ErrorKind::ReadinessTimeout,
+            ErrorKind::Cleanup,
+        ] {
+            assert_eq!(ErrorKind::from_exit_code(kind.exit_code()), Some(*kind));
+        }
+        assert_eq!(ErrorKind::from_exit_code(1), None);
+    }
+
+    #[test]
+    fn json_line_success() -> Result<()> {
+        let e = anyhow!("inner").context(ErrorKind::Preflight);
+        let v: serde_json::Value = serde_json::from_str(&ErrorKind::json_line(&e))?;
+        assert_eq!(v["kind"], "preflight");
+        assert_eq!(v["exit_code"], 3);
+        assert_eq!(v["message"], "Preflight check failed: inner");
+        Ok(())
+    }
+}
@@ -1,11 +1,14 @@
 use crate::{
     config::Config,
+    controlplane::ControlPlane,
     network::Network,
     pki::Pki,
-    process::{Process, ProcessState, Stoppable},
+    process::{Process, ProcessState, Readiness, Stoppable},
 };
-use anyhow::Result;
-use std::fs::create_dir_all;
+use anyhow::{bail, Result};
+use std::{fs::create_dir_all, path::PathBuf};
+
+pub const ETCD: &str = "etcd";
 
 pub struct Etcd {
     process: Process,
@@ -13,11 +16,20 @@ pub struct Etcd {
 
 impl Etcd {
     pub fn start(config: &Config, network: &Network, pki: &Pki) -> ProcessState {
-        const ETCD: &str = "etcd";
         let dir = config.root().join(ETCD);
         create_dir_all(&dir)?;
 
-        let mut process = Process::start(
+        // etcd refuses to start otherwise
+        if config.etcd_election_timeout() < 5 * config.etcd_heartbeat_interval() {
+            bail!(
+                "The etcd election timeout ({}ms) has to be at least five times the heartbeat interval ({}ms)",
+                config.etcd_election_timeout(),
+                config.etcd_heartbeat_interval()
+            )
+        }
+
+        let mut process = ControlPlane::start(
+            config,
             &dir,
             ETCD,
             ETCD,
@@ -31,23 +43,52 @@ impl Etcd {
                     network.etcd_peer()
                 ),
                 &format!("--advertise-client-urls=https://{}", network.etcd_client()),
-                &format!("--cert-file={}", pki.apiserver().cert().display()),
-                &format!("--data-dir={}", dir.join("run").display()),
+                &format!("--cert-file={}", pki.etcd_server().cert().display()),
+                &format!("--data-dir={}", Self::data_dir(config).display()),
+                &format!("--election-timeout={}", config.etcd_election_timeout()),
+                &format!("--heartbeat-interval={}", config.etcd_heartbeat_interval()),
                 &format!("--initial-cluster=etcd=https://{}", network.etcd_peer()),
-                &format!("--key-file={}", pki.apiserver().key().display()),
+                &format!("--key-file={}", pki.etcd_server().key().display()),
                 &format!("--listen-client-urls=https://{}", network.etcd_client()),
                 &format!("--listen-peer-urls=https://{}", network.etcd_peer()),
                 &format!("--name={}", ETCD),
-                &format!("--peer-cert-file={}", pki.apiserver().cert().display()),
-                &format!("--peer-key-file={}", pki.apiserver().key().display()),
-                &format!("--peer-trusted-ca-file={}", pki.ca().cert().display()),
-                &format!("--trusted-ca-file={}", pki.ca().cert().display()),
-            ],
+                &format!("--peer-cert-file={}", pki.etcd_peer().cert().display()),
+                &format!("--peer-key-file={}", pki.etcd_peer().key().display()),
+                &format!("--peer-trusted-ca-file={}", pki.etcd_ca().cert().display()),
+                &format!(
+                    "--quota-backend-bytes={}",
+                    config.etcd_quota_backend_bytes()
+                ),
+                &format!("--snapshot-count={}", config.etcd_snapshot_count()),
+                &format!("--trusted-ca-file={}", pki.etcd_ca().cert().display()),
+            ]
+            .iter()
+            .copied()
+            .chain(config.etcd_args().iter().map(String::as_str))
+            .collect::<Vec<_>>(),
+            &config.component_env_vars("etcd"),
         )?;
 
-        process.wait_ready("ready to serve client requests")?;
+        process.wait_ready(Readiness::HttpGet {
+            url: format!("https://{}/health", network.etcd_client()),
+            ca: Some(pki.etcd_ca().cert().into()),
+            client: Some((
+                pki.apiserver_etcd_client().cert().into(),
+                pki.apiserver_etcd_client().key().into(),
+            )),
+        })?;
+        process.describe(ETCD, &format!("https://{}", network.etcd_client()), None);
         Ok(Box::new(Self { process }))
     }
+
+    /// Retrieve the directory where etcd writes its snapshots to disk
+    pub fn snapshot_dir(config: &Config) -> PathBuf {
+        Self::data_dir(config).join("member").join("snap")
+    }
+
+    fn data_dir(config: &Config) -> PathBuf {
+        config.root().join(ETCD).join("run")
+    }
 }
 
 impl Stoppable for Etcd {
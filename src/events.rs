@@ -0,0 +1,241 @@
+use crate::{
+    status::{AddonState, Phase},
+    Config,
+};
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::remove_file,
+    io::Write,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A machine readable bootstrap event
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A bootstrap phase has been completed
+    PhaseCompleted { phase: String },
+
+    /// A process passed its readiness check
+    ComponentReady { component: String },
+
+    /// A cluster addon changed its state
+    Addon { addon: String, state: AddonState },
+
+    /// The overall cluster phase changed
+    Cluster { phase: Phase },
+}
+
+/// A single line written to the subscribers
+#[derive(Serialize)]
+struct Message<'a> {
+    /// The milliseconds since the UNIX epoch
+    timestamp: u128,
+
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+#[derive(Default)]
+struct State {
+    path: Option<PathBuf>,
+    phase: Option<String>,
+    history: Vec<String>,
+    subscribers: Vec<UnixStream>,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+/// The stream of bootstrap events, served as JSON lines via a UNIX socket
+pub struct Events;
+
+impl Events {
+    pub const FILENAME: &'static str = "events.sock";
+
+    /// The maximum time a subscriber may block the bootstrap per event
+    const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// The path to the events socket of the cluster
+    pub fn path(config: &Config) -> PathBuf {
+        config.root().join(Self::FILENAME)
+    }
+
+    /// Start serving the events socket inside the run root, whereas every
+    /// subscriber receives all previous events on connect
+    pub fn init(config: &Config) -> Result<()> {
+        let path = Self::path(config);
+        if path.display().to_string().len() > 100 {
+            warn!(
+                "Events socket path '{}' is too long, not serving bootstrap events",
+                path.display()
+            );
+            return Ok(());
+        }
+        if path.exists() {
+            remove_file(&path)
+                .with_context(|| format!("Unable to remove stale socket '{}'", path.display()))?;
+        }
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("Unable to bind events socket '{}'", path.display()))?;
+        debug!("Serving bootstrap events on {}", path.display());
+
+        {
+            let mut state = STATE.lock();
+            state.path = Some(path);
+            state.history.clear();
+            state.subscribers.clear();
+        }
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => Self::subscribe(stream),
+                    Err(e) => debug!("Unable to accept events subscriber: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Add a subscriber and replay the history to it
+    fn subscribe(mut stream: UnixStream) {
+        let mut state = STATE.lock();
+        if state.path.is_none() {
+            return;
+        }
+        if let Err(e) = stream.set_write_timeout(Some(Self::WRITE_TIMEOUT)) {
+            debug!("Unable to set events subscriber timeout: {}", e);
+            return;
+        }
+        if state
+            .history
+            .iter()
+            .all(|x| stream.write_all(x.as_bytes()).is_ok())
+        {
+            debug!("New bootstrap events subscriber");
+            state.subscribers.push(stream);
+        }
+    }
+
+    /// Start a new bootstrap phase, which completes the previous one
+    pub fn start_phase(name: &str) {
+        let previous = STATE.lock().phase.replace(name.into());
+        match previous {
+            Some(phase) if phase != name => Self::emit(&Event::PhaseCompleted { phase }),
+            _ => {}
+        }
+    }
+
+    /// Complete the current bootstrap phase
+    pub fn finish_phase() {
+        let current = STATE.lock().phase.take();
+        if let Some(phase) = current {
+            Self::emit(&Event::PhaseCompleted { phase })
+        }
+    }
+
+    /// Send the event to all subscribers, whereas disconnected ones get
+    /// dropped
+    pub fn emit(event: &Event) {
+        let mut state = STATE.lock();
+        if state.path.is_none() {
+            return;
+        }
+        let line = match Self::line(event) {
+            Ok(line) => line,
+            Err(e) => {
+                debug!("Unable to serialize event: {:#}", e);
+                return;
+            }
+        };
+        state
+            .subscribers
+            .retain(|mut x| x.write_all(line.as_bytes()).is_ok());
+        state.history.push(line);
+    }
+
+    /// Disconnect all subscribers and remove the events socket
+    pub fn close() {
+        let mut state = STATE.lock();
+        state.subscribers.clear();
+        state.phase = None;
+        if let Some(path) = state.path.take() {
+            Self::remove(&path)
+        }
+    }
+
+    fn remove(path: &Path) {
+        if let Err(e) = remove_file(path) {
+            debug!("Unable to remove events socket: {}", e)
+        }
+    }
+
+    fn line(event: &Event) -> Result<String> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let mut line = serde_json::to_string(&Message { timestamp, event })?;
+        line.push('\n');
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_config;
+    use serde_json::Value;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn line_success() -> Result<()> {
+        let line = Events::line(&Event::Addon {
+            addon: "coredns".into(),
+            state: AddonState::Applied,
+        })?;
+        assert!(line.ends_with('\n'));
+
+        let value: Value = serde_json::from_str(&line)?;
+        assert_eq!(value["event"], "addon");
+        assert_eq!(value["addon"], "coredns");
+        assert_eq!(value["state"], "applied");
+        assert!(value["timestamp"].as_u64().is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn events_success() -> Result<()> {
+        let c = test_config()?;
+        Events::init(&c)?;
+        Events::start_phase("preflight");
+        Events::start_phase("certificates");
+
+        // Late subscribers retrieve the previous events, too
+        let stream = UnixStream::connect(Events::path(&c))?;
+        let mut lines = BufReader::new(stream).lines();
+        let first: Value = serde_json::from_str(&lines.next().context("no event")??)?;
+        assert_eq!(first["event"], "phase_completed");
+        assert_eq!(first["phase"], "preflight");
+
+        Events::emit(&Event::ComponentReady {
+            component: "apiserver".into(),
+        });
+        Events::finish_phase();
+        let second: Value = serde_json::from_str(&lines.next().context("no event")??)?;
+        assert_eq!(second["event"], "component_ready");
+        assert_eq!(second["component"], "apiserver");
+        let third: Value = serde_json::from_str(&lines.next().context("no event")??)?;
+        assert_eq!(third["phase"], "certificates");
+
+        Events::close();
+        assert!(lines.next().is_none());
+        assert!(!Events::path(&c).exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{
+    config::Config,
+    kubeconfig::KubeConfig,
+    kubectl::Kubectl,
+    node::Node,
+    process::{Process, ProcessOptions, ProcessState, Readiness, Stoppable},
+};
+use anyhow::{Context, Result};
+use log::info;
+use std::{
+    fs::{self, create_dir_all},
+    net::Ipv4Addr,
+};
+
+/// Lightweight fake nodes simulated by kwok, which are registered in
+/// addition to the real ones but do not run any workloads
+pub struct FakeNodes {
+    process: Process,
+}
+
+impl FakeNodes {
+    /// The nix package and binary simulating the nodes
+    pub const PACKAGE: &'static str = "kwok";
+
+    /// The annotation selecting the nodes managed by kwok, which is also the
+    /// key of their taint
+    const ANNOTATION: &'static str = "kwok.x-k8s.io/node";
+
+    /// The label selector matching all registered fake nodes
+    const SELECTOR: &'static str = "type=kwok";
+
+    /// The distance of the health port to the one of the API server, which
+    /// results in the default port 10247
+    const PORT_OFFSET: u16 = 3804;
+
+    /// The port serving the health endpoint of kwok
+    fn port(config: &Config) -> u16 {
+        config.api_port() + Self::PORT_OFFSET
+    }
+
+    /// The name of the provided fake node
+    pub fn name(number: u8) -> String {
+        format!("fake-{}", Node::raw(number))
+    }
+
+    pub fn start(config: &Config, kubeconfig: &KubeConfig, kubectl: &Kubectl) -> ProcessState {
+        let dir = config.root().join("fake-nodes");
+        create_dir_all(&dir)?;
+
+        let mut process = Process::start(
+            &ProcessOptions::new(config),
+            &dir,
+            "Fake Nodes",
+            Self::PACKAGE,
+            &[
+                &format!("--kubeconfig={}", kubeconfig.admin().display()),
+                "--manage-all-nodes=false",
+                &format!(
+                    "--manage-nodes-with-annotation-selector={}=fake",
+                    Self::ANNOTATION
+                ),
+                &format!("--node-ip={}", Ipv4Addr::LOCALHOST),
+                &format!(
+                    "--server-address={}:{}",
+                    Ipv4Addr::LOCALHOST,
+                    Self::port(config)
+                ),
+            ],
+            &config.component_env_vars("fake-nodes"),
+        )?;
+        process.wait_ready(Readiness::http_healthz(Self::port(config)))?;
+
+        // Registering the nodes is idempotent, whereas surplus nodes of
+        // previous runs with a higher count get removed
+        let file = dir.join("nodes.yml");
+        fs::write(&file, Self::nodes(config.fake_nodes()))?;
+        kubectl
+            .apply(&file)
+            .context("Unable to register the fake nodes")?;
+        Self::prune(kubectl, config.fake_nodes()).context("Unable to remove surplus fake nodes")?;
+        process.describe(
+            Self::PACKAGE,
+            &format!("http://{}:{}", Ipv4Addr::LOCALHOST, Self::port(config)),
+            Some(kubeconfig.admin()),
+        );
+        Ok(Box::new(Self { process }))
+    }
+
+    /// Remove the registered fake nodes which exceed the provided count
+    fn prune(kubectl: &Kubectl, count: u8) -> Result<()> {
+        let output = kubectl.execute(&["get", "nodes", "-l", Self::SELECTOR, "-o", "name"])?;
+        let surplus = Self::surplus(&String::from_utf8(output.stdout)?, count);
+        if !surplus.is_empty() {
+            info!("Removing {} surplus fake nodes", surplus.len());
+            let mut args = vec!["delete"];
+            args.extend(surplus.iter().map(String::as_str));
+            kubectl.execute(&args)?;
+        }
+        Ok(())
+    }
+
+    /// Filter the provided `node/<name>` resources for the ones exceeding
+    /// the count
+    fn surplus(resources: &str, count: u8) -> Vec<String> {
+        let expected = (0..count)
+            .map(|n| format!("node/{}", Self::name(n)))
+            .collect::<Vec<_>>();
+        resources
+            .lines()
+            .map(str::trim)
+            .filter(|x| !x.is_empty() && !expected.iter().any(|y| y == x))
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Render the provided number of fake nodes
+    fn nodes(count: u8) -> String {
+        (0..count)
+            .map(|n| {
+                format!(
+                    include_str!("assets/fake-node.yml"),
+                    name = Self::name(n),
+                    annotation = Self::ANNOTATION,
+                )
+            })
+            .collect()
+    }
+}
+
+impl Stoppable for FakeNodes {
+    fn stop(&mut self) -> Result<()> {
+        self.process.stop()
+    }
+
+    fn restart(&mut self) -> Result<()> {
+        self.process.restart()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surplus_success() {
+        let resources = "node/fake-node-0\nnode/fake-node-1\nnode/fake-node-2\n";
+        assert_eq!(
+            FakeNodes::surplus(resources, 2),
+            vec!["node/fake-node-2".to_owned()]
+        );
+        assert!(FakeNodes::surplus(resources, 3).is_empty());
+        assert_eq!(FakeNodes::surplus(resources, 0).len(), 3);
+    }
+
+    #[test]
+    fn nodes_success() -> Result<()> {
+        assert!(FakeNodes::nodes(0).is_empty());
+
+        let docs = FakeNodes::nodes(3)
+            .split("---\n")
+            .filter(|x| !x.trim().is_empty())
+            .map(serde_yaml::from_str::<serde_yaml::Value>)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs[2]["metadata"]["name"].as_str(), Some("fake-node-2"));
+        assert_eq!(
+            docs[0]["metadata"]["annotations"]["kwok.x-k8s.io/node"].as_str(),
+            Some("fake")
+        );
+        assert_eq!(
+            docs[0]["spec"]["taints"][0]["key"].as_str(),
+            Some("kwok.x-k8s.io/node")
+        );
+        Ok(())
+    }
+}
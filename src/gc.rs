@@ -0,0 +1,220 @@
+use crate::{apiserver::ApiServer, config::Config, crio::Crio, etcd::Etcd};
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::{
+    ffi::OsStr,
+    fs::{metadata, read_dir, remove_file},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The garbage collection of artifacts which accumulate within the run root
+pub struct Gc;
+
+impl Gc {
+    /// Retrieve all rotated logs and etcd snapshots which exceed the retention
+    /// settings of the configuration
+    pub fn collect(config: &Config) -> Result<Vec<PathBuf>> {
+        let mut garbage = Self::rotated_logs(config)?;
+        garbage.extend(Self::etcd_snapshots(config)?);
+        Ok(garbage)
+    }
+
+    /// Remove the provided files and return the amount of freed bytes
+    pub fn remove(garbage: &[PathBuf]) -> Result<u64> {
+        let mut freed = 0;
+        for path in garbage {
+            debug!("Removing {}", path.display());
+            freed += metadata(path).map(|x| x.len()).unwrap_or_default();
+            remove_file(path).with_context(|| format!("Unable to remove '{}'", path.display()))?;
+        }
+        Ok(freed)
+    }
+
+    /// Rotated pod logs and audit log backups older than the log retention
+    fn rotated_logs(config: &Config) -> Result<Vec<PathBuf>> {
+        let retention = Duration::from_secs(u64::from(config.gc_log_retention()) * 24 * 60 * 60);
+        let deadline = SystemTime::now()
+            .checked_sub(retention)
+            .unwrap_or(UNIX_EPOCH);
+
+        let mut logs = Crio::pod_logs(config, None)?
+            .into_iter()
+            .map(|(_, x)| x)
+            .filter(|x| Self::file_name(x).contains(".log."))
+            .collect::<Vec<_>>();
+        logs.extend(Self::audit_log_backups(config)?);
+
+        Ok(logs
+            .into_iter()
+            .filter(|x| matches!(metadata(x).and_then(|x| x.modified()), Ok(x) if x < deadline))
+            .collect())
+    }
+
+    /// The backups of the audit log, which follow the pattern
+    /// `audit-<timestamp>.log` and may be compressed
+    fn audit_log_backups(config: &Config) -> Result<Vec<PathBuf>> {
+        let audit_log = ApiServer::audit_log(config);
+        let dir = match audit_log.parent() {
+            Some(dir) if dir.is_dir() => dir,
+            _ => return Ok(vec![]),
+        };
+        let prefix = format!(
+            "{}-",
+            audit_log.file_stem().unwrap_or_default().to_string_lossy()
+        );
+
+        let mut backups = vec![];
+        for entry in read_dir(dir)? {
+            let path = entry?.path();
+            let name = Self::file_name(&path);
+            if path.is_file()
+                && name.starts_with(&prefix)
+                && (name.ends_with(".log") || name.ends_with(".log.gz"))
+            {
+                backups.push(path);
+            }
+        }
+        Ok(backups)
+    }
+
+    /// All etcd snapshots except the newest ones to be kept
+    fn etcd_snapshots(config: &Config) -> Result<Vec<PathBuf>> {
+        if config.gc_etcd_snapshots() == 0 {
+            bail!("At least one etcd snapshot has to be kept")
+        }
+        let dir = Etcd::snapshot_dir(config);
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut snapshots = vec![];
+        for entry in read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension() == Some(OsStr::new("snap")) {
+                snapshots.push(path);
+            }
+        }
+
+        // The file names consist of the zero padded term and index in hex
+        snapshots.sort();
+        let keep = usize::from(config.gc_etcd_snapshots());
+        snapshots.truncate(snapshots.len().saturating_sub(keep));
+        Ok(snapshots)
+    }
+
+    fn file_name(path: &Path) -> String {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_config_with;
+    use std::fs::{create_dir_all, write};
+
+    fn test_config_gc(log_retention: u16, etcd_snapshots: u8) -> Result<Config> {
+        // Multiple nodes keep the pod logs within the run root
+        test_config_with(|c| {
+            c.set_nodes(2)
+                .set_gc_log_retention(log_retention)
+                .set_gc_etcd_snapshots(etcd_snapshots);
+        })
+    }
+
+    #[test]
+    fn collect_success() -> Result<()> {
+        let c = test_config_gc(7, 1)?;
+        assert!(Gc::collect(&c)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn collect_failure() -> Result<()> {
+        let c = test_config_gc(7, 0)?;
+        assert!(Gc::collect(&c).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rotated_logs_success() -> Result<()> {
+        let c = test_config_gc(0, 1)?;
+        let pod = c
+            .root()
+            .join("crio")
+            .join("node-0")
+            .join("pods")
+            .join("default_nginx_1234")
+            .join("nginx");
+        create_dir_all(&pod)?;
+        write(pod.join("0.log"), "")?;
+        write(pod.join("0.log.20200101-000000.gz"), "")?;
+
+        let audit_log = ApiServer::audit_log(&c);
+        let dir = audit_log.parent().context("no parent")?;
+        create_dir_all(dir)?;
+        write(&audit_log, "")?;
+        write(dir.join("audit-policy.yml"), "")?;
+        write(dir.join("audit-2020-01-01T00-00-00.000.log"), "")?;
+
+        let mut logs = Gc::rotated_logs(&c)?;
+        logs.sort();
+        assert_eq!(
+            logs,
+            vec![
+                dir.join("audit-2020-01-01T00-00-00.000.log"),
+                pod.join("0.log.20200101-000000.gz"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rotated_logs_retention_success() -> Result<()> {
+        let c = test_config_gc(7, 1)?;
+        let pod = c
+            .root()
+            .join("crio")
+            .join("node-0")
+            .join("pods")
+            .join("default_nginx_1234")
+            .join("nginx");
+        create_dir_all(&pod)?;
+        write(pod.join("0.log.20200101-000000.gz"), "")?;
+        assert!(Gc::rotated_logs(&c)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn etcd_snapshots_success() -> Result<()> {
+        let c = test_config_gc(7, 1)?;
+        let dir = Etcd::snapshot_dir(&c);
+        create_dir_all(&dir)?;
+        for name in &[
+            "0000000000000002-0000000000002711.snap",
+            "0000000000000002-0000000000004e22.snap",
+            "0000000000000002-0000000000007533.snap",
+            "db",
+        ] {
+            write(dir.join(name), "")?;
+        }
+
+        let snapshots = Gc::etcd_snapshots(&c)?;
+        assert_eq!(
+            snapshots,
+            vec![
+                dir.join("0000000000000002-0000000000002711.snap"),
+                dir.join("0000000000000002-0000000000004e22.snap"),
+            ]
+        );
+
+        assert_eq!(Gc::remove(&snapshots)?, 0);
+        assert!(Gc::etcd_snapshots(&c)?.is_empty());
+        assert!(dir.join("db").exists());
+        Ok(())
+    }
+}
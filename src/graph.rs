@@ -0,0 +1,544 @@
+use crate::{
+    config::NodeRuntime,
+    etcd::ETCD,
+    kine::KINE,
+    node::Node,
+    process::{Process, ProcessState},
+    Config,
+};
+use anyhow::{bail, Result};
+use log::debug;
+use parking_lot::Mutex;
+use rayon::{scope, Scope};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+};
+
+/// A supervised process of the cluster
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Unit {
+    Etcd,
+    ApiServer,
+    ControllerManager,
+    Scheduler,
+    SecondaryScheduler,
+    FakeNodes,
+    Crio(u8),
+    Containerd(u8),
+    Kubelet(u8),
+    Proxy(u8),
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.split() {
+            (component, Some(n)) => write!(f, "{}-{}", component, n),
+            (component, None) => write!(f, "{}", component),
+        }
+    }
+}
+
+impl Unit {
+    /// The component names of all units
+    pub const COMPONENTS: &'static [&'static str] = &[
+        "etcd",
+        "apiserver",
+        "controller-manager",
+        "scheduler",
+        "secondary-scheduler",
+        "fake-nodes",
+        "crio",
+        "containerd",
+        "kubelet",
+        "proxy",
+    ];
+
+    /// Create a new unit from its component name, whereas the node is only
+    /// relevant for node components
+    pub fn new(component: &str, node: u8) -> Result<Unit> {
+        Ok(match component {
+            "etcd" => Unit::Etcd,
+            "apiserver" => Unit::ApiServer,
+            "controller-manager" => Unit::ControllerManager,
+            "scheduler" => Unit::Scheduler,
+            "secondary-scheduler" => Unit::SecondaryScheduler,
+            "fake-nodes" => Unit::FakeNodes,
+            "crio" => Unit::Crio(node),
+            "containerd" => Unit::Containerd(node),
+            "kubelet" => Unit::Kubelet(node),
+            "proxy" => Unit::Proxy(node),
+            _ => bail!("Unknown component: {}", component),
+        })
+    }
+
+    /// Parse a unit from its name, like `etcd` or `kubelet-1`, whereas node
+    /// components without number refer to the first node
+    pub fn parse(name: &str) -> Result<Unit> {
+        const NODE_COMPONENTS: &[&str] = &["crio", "containerd", "kubelet", "proxy"];
+        match name
+            .rsplit_once('-')
+            .and_then(|(component, node)| Some((component, node.parse().ok()?)))
+        {
+            Some((component, node)) if NODE_COMPONENTS.contains(&component) => {
+                Self::new(component, node)
+            }
+            _ => Self::new(name, 0),
+        }
+    }
+
+    /// The component names of all units together with the names of their
+    /// processes, whereas etcd gets replaced by kine in single binary mode
+    const PROCESS_NAMES: &'static [(&'static str, &'static str)] = &[
+        ("etcd", ETCD),
+        ("etcd", KINE),
+        ("apiserver", "API Server"),
+        ("controller-manager", "Controller Manager"),
+        ("scheduler", "Scheduler"),
+        ("secondary-scheduler", "Secondary Scheduler"),
+        ("fake-nodes", "Fake Nodes"),
+        ("crio", "CRI-O"),
+        ("containerd", "containerd"),
+        ("kubelet", "Kubelet"),
+        ("proxy", "Proxy"),
+    ];
+
+    /// The component name of the provided process identifier, like `kubelet`
+    /// for `Kubelet node-1`
+    pub fn component(identifier: &str) -> Option<&'static str> {
+        let name = identifier.split(" node-").next()?;
+        Self::PROCESS_NAMES
+            .iter()
+            .find(|(_, process)| *process == name)
+            .map(|(component, _)| *component)
+    }
+
+    /// The identifier of the process of the unit, which is the key of its
+    /// component within the cluster status
+    pub fn identifier(self, config: &Config) -> String {
+        let (component, node) = self.split();
+        let name = match self {
+            Unit::Etcd if config.single_binary() => KINE,
+            _ => Self::PROCESS_NAMES
+                .iter()
+                .find(|(x, _)| *x == component)
+                .map_or(component, |(_, process)| *process),
+        };
+        match node {
+            Some(n) if config.multi_node() => format!("{} {}", name, Node::raw(n)),
+            _ => name.into(),
+        }
+    }
+
+    /// The component name of the unit together with its node, if it is a
+    /// node component
+    fn split(self) -> (&'static str, Option<u8>) {
+        match self {
+            Unit::Etcd => ("etcd", None),
+            Unit::ApiServer => ("apiserver", None),
+            Unit::ControllerManager => ("controller-manager", None),
+            Unit::Scheduler => ("scheduler", None),
+            Unit::SecondaryScheduler => ("secondary-scheduler", None),
+            Unit::FakeNodes => ("fake-nodes", None),
+            Unit::Crio(n) => ("crio", Some(n)),
+            Unit::Containerd(n) => ("containerd", Some(n)),
+            Unit::Kubelet(n) => ("kubelet", Some(n)),
+            Unit::Proxy(n) => ("proxy", Some(n)),
+        }
+    }
+
+    /// The CRI runtime unit of the provided node
+    pub fn cri(config: &Config, node: u8) -> Unit {
+        match config.node_runtime(node) {
+            NodeRuntime::Crio => Unit::Crio(node),
+            NodeRuntime::Containerd => Unit::Containerd(node),
+        }
+    }
+
+    /// The units which have to be started before this one, if they are part
+    /// of the graph
+    pub fn dependencies(self) -> Vec<Unit> {
+        match self {
+            Unit::Etcd | Unit::Crio(_) | Unit::Containerd(_) => vec![],
+            Unit::ApiServer => vec![Unit::Etcd],
+            Unit::ControllerManager
+            | Unit::Scheduler
+            | Unit::SecondaryScheduler
+            | Unit::FakeNodes => vec![Unit::ApiServer],
+            Unit::Kubelet(n) => vec![Unit::Crio(n), Unit::Containerd(n)],
+            Unit::Proxy(n) => vec![Unit::Kubelet(n)],
+        }
+    }
+}
+
+/// The dependency graph of all configured units, which determines the
+/// startup and shutdown order
+pub struct Graph {
+    units: Vec<Unit>,
+}
+
+impl Graph {
+    /// Create the graph for the provided config
+    pub fn new(config: &Config) -> Graph {
+        let mut units = vec![
+            Unit::Etcd,
+            Unit::ApiServer,
+            Unit::ControllerManager,
+            Unit::Scheduler,
+        ];
+        if config.secondary_scheduler().is_some() {
+            units.push(Unit::SecondaryScheduler);
+        }
+        if config.fake_nodes() > 0 {
+            units.push(Unit::FakeNodes);
+        }
+        for node in 0..config.nodes() {
+            units.push(Unit::cri(config, node));
+            units.push(Unit::Kubelet(node));
+        }
+
+        // All nodes share the host network and therefore a single kube-proxy
+        if !config.self_hosted() && !config.no_proxy() {
+            units.push(Unit::Proxy(0));
+        }
+        Graph { units }
+    }
+
+    /// Retrieve all units of the graph
+    pub fn units(&self) -> &[Unit] {
+        &self.units
+    }
+
+    /// Retrieve the units of the graph which the provided one depends on
+    pub fn dependencies(&self, unit: Unit) -> Vec<Unit> {
+        unit.dependencies()
+            .into_iter()
+            .filter(|x| self.units.contains(x))
+            .collect()
+    }
+
+    /// Retrieve the units which directly depend on the provided one
+    pub fn dependents(&self, unit: Unit) -> Vec<Unit> {
+        self.units
+            .iter()
+            .copied()
+            .filter(|x| x.dependencies().contains(&unit))
+            .collect()
+    }
+
+    /// Retrieve all units in startup order, whereas every unit follows its
+    /// dependencies
+    pub fn startup_order(&self) -> Vec<Unit> {
+        let mut order: Vec<Unit> = vec![];
+        while order.len() < self.units.len() {
+            let next = self
+                .units
+                .iter()
+                .copied()
+                .filter(|x| !order.contains(x))
+                .filter(|x| self.dependencies(*x).iter().all(|d| order.contains(d)))
+                .collect::<Vec<_>>();
+            order.extend(next);
+        }
+        order
+    }
+
+    /// Retrieve all units in shutdown order, whereas every unit precedes its
+    /// dependencies
+    pub fn shutdown_order(&self) -> Vec<Unit> {
+        let mut order = self.startup_order();
+        order.reverse();
+        order
+    }
+
+    /// Retrieve the unit and everything which transitively depends on it in
+    /// shutdown order
+    pub fn subtree(&self, unit: Unit) -> Vec<Unit> {
+        let mut subtree = vec![unit];
+        let mut i = 0;
+        while i < subtree.len() {
+            for dependent in self.dependents(subtree[i]) {
+                if !subtree.contains(&dependent) {
+                    subtree.push(dependent);
+                }
+            }
+            i += 1;
+        }
+        self.shutdown_order()
+            .into_iter()
+            .filter(|x| subtree.contains(x))
+            .collect()
+    }
+
+    /// Start all units in parallel as soon as their dependencies are started
+    /// successfully. The states are returned in shutdown order, whereas units
+    /// with failed dependencies are not started at all.
+    pub fn start<F>(&self, start: F) -> Vec<(Unit, ProcessState)>
+    where
+        F: Fn(Unit) -> ProcessState + Sync,
+    {
+        let states = Mutex::new(BTreeMap::new());
+        scope(|s| {
+            for unit in self
+                .units
+                .iter()
+                .filter(|x| self.dependencies(**x).is_empty())
+            {
+                self.spawn(s, *unit, &start, &states);
+            }
+        });
+
+        let mut states = states.into_inner();
+        self.shutdown_order()
+            .into_iter()
+            .map(|x| (x, states.remove(&x).unwrap_or_else(Process::stopped)))
+            .collect()
+    }
+
+    fn spawn<'a, F>(
+        &'a self,
+        s: &Scope<'a>,
+        unit: Unit,
+        start: &'a F,
+        states: &'a Mutex<BTreeMap<Unit, ProcessState>>,
+    ) where
+        F: Fn(Unit) -> ProcessState + Sync,
+    {
+        s.spawn(move |s| {
+            debug!("Starting unit {}", unit);
+            let state = start(unit);
+            let started = state.is_ok();
+
+            // Checking the dependents while holding the lock ensures that
+            // every unit gets spawned only once
+            let mut guard = states.lock();
+            guard.insert(unit, state);
+            if !started {
+                return;
+            }
+            for dependent in self.dependents(unit) {
+                if self
+                    .dependencies(dependent)
+                    .iter()
+                    .all(|x| matches!(guard.get(x), Some(Ok(_))))
+                {
+                    self.spawn(s, dependent, start, states);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::tests::{test_config, test_config_with},
+        process::Stoppable,
+    };
+
+    struct Started;
+
+    impl Stoppable for Started {
+        fn stop(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn graph() -> Graph {
+        Graph {
+            units: vec![
+                Unit::Etcd,
+                Unit::ApiServer,
+                Unit::Scheduler,
+                Unit::Crio(0),
+                Unit::Kubelet(0),
+                Unit::Proxy(0),
+            ],
+        }
+    }
+
+    #[test]
+    fn unit_new_success() -> Result<()> {
+        for component in Unit::COMPONENTS {
+            assert!(Unit::new(component, 0).is_ok());
+        }
+        assert_eq!(Unit::new("kubelet", 2)?, Unit::Kubelet(2));
+        assert_eq!(Unit::new("etcd", 2)?, Unit::Etcd);
+        assert!(Unit::new("kube-apiserver", 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_success() -> Result<()> {
+        assert_eq!(Unit::parse("etcd")?, Unit::Etcd);
+        assert_eq!(
+            Unit::parse("secondary-scheduler")?,
+            Unit::SecondaryScheduler
+        );
+        assert_eq!(Unit::parse("kubelet")?, Unit::Kubelet(0));
+        assert_eq!(Unit::parse("crio-1")?, Unit::Crio(1));
+        for unit in graph().units() {
+            assert_eq!(Unit::parse(&unit.to_string())?, *unit);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn component_success() {
+        assert_eq!(Unit::component("API Server"), Some("apiserver"));
+        assert_eq!(Unit::component("kine"), Some("etcd"));
+        assert_eq!(Unit::component("Kubelet node-1"), Some("kubelet"));
+        assert_eq!(Unit::component("CRI-O"), Some("crio"));
+        assert_eq!(Unit::component(""), None);
+    }
+
+    #[test]
+    fn identifier_success() -> Result<()> {
+        let c = test_config()?;
+        assert_eq!(Unit::ApiServer.identifier(&c), "API Server");
+        assert_eq!(Unit::Kubelet(0).identifier(&c), "Kubelet");
+
+        let c = test_config_with(|c| {
+            c.set_nodes(2);
+        })?;
+        assert_eq!(Unit::Crio(1).identifier(&c), "CRI-O node-1");
+        Ok(())
+    }
+
+    #[test]
+    fn identifier_component_roundtrip_success() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_nodes(2);
+        })?;
+        for component in Unit::COMPONENTS {
+            let unit = Unit::new(component, 1)?;
+            assert_eq!(Unit::component(&unit.identifier(&c)), Some(*component));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_failure() {
+        assert!(Unit::parse("etcd-1").is_err());
+        assert!(Unit::parse("kubelet-x").is_err());
+    }
+
+    #[test]
+    fn startup_order_success() {
+        assert_eq!(
+            graph().startup_order(),
+            vec![
+                Unit::Etcd,
+                Unit::Crio(0),
+                Unit::ApiServer,
+                Unit::Kubelet(0),
+                Unit::Scheduler,
+                Unit::Proxy(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn shutdown_order_success() {
+        let g = graph();
+        let order = g.shutdown_order();
+        for unit in g.units() {
+            let position = order.iter().position(|x| x == unit);
+            for dependency in g.dependencies(*unit) {
+                assert!(position < order.iter().position(|x| *x == dependency));
+            }
+        }
+    }
+
+    #[test]
+    fn subtree_success() {
+        let g = graph();
+        assert_eq!(
+            g.subtree(Unit::Crio(0)),
+            vec![Unit::Proxy(0), Unit::Kubelet(0), Unit::Crio(0)]
+        );
+        assert_eq!(
+            g.subtree(Unit::ApiServer),
+            vec![Unit::Scheduler, Unit::ApiServer]
+        );
+    }
+
+    #[test]
+    fn new_success_node_runtimes() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_nodes(2)
+                .set_node_runtimes(vec!["1=containerd".into()]);
+        })?;
+        let g = Graph::new(&c);
+        assert!(g.units().contains(&Unit::Crio(0)));
+        assert!(g.units().contains(&Unit::Containerd(1)));
+        assert!(!g.units().contains(&Unit::Crio(1)));
+        assert_eq!(g.dependencies(Unit::Kubelet(1)), vec![Unit::Containerd(1)]);
+
+        let order = g.startup_order();
+        let position = |unit| order.iter().position(|x| *x == unit);
+        assert!(position(Unit::Containerd(1)) < position(Unit::Kubelet(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn new_success_single_proxy() -> Result<()> {
+        let g = Graph::new(&test_config_with(|c| {
+            c.set_nodes(3);
+        })?);
+        let proxies = g
+            .units()
+            .iter()
+            .filter(|x| matches!(x, Unit::Proxy(_)))
+            .collect::<Vec<_>>();
+        assert_eq!(proxies, vec![&Unit::Proxy(0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn new_success_no_proxy() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_nodes(2).set_no_proxy(true);
+        })?;
+        let g = Graph::new(&c);
+        assert_eq!(g.units().len(), 8);
+        assert!(!g.units().iter().any(|x| matches!(x, Unit::Proxy(_))));
+        assert!(g.startup_order().contains(&Unit::Kubelet(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn new_success_fake_nodes() -> Result<()> {
+        let g = Graph::new(&test_config()?);
+        assert!(!g.units().contains(&Unit::FakeNodes));
+
+        let g = Graph::new(&test_config_with(|c| {
+            c.set_fake_nodes(10);
+        })?);
+        assert_eq!(g.dependencies(Unit::FakeNodes), vec![Unit::ApiServer]);
+        assert!(g.subtree(Unit::ApiServer).contains(&Unit::FakeNodes));
+        Ok(())
+    }
+
+    #[test]
+    fn start_failure_dependency() {
+        let started = Mutex::new(vec![]);
+        let states = graph().start(|unit| {
+            started.lock().push(unit);
+            if unit == Unit::Crio(0) {
+                bail!("Unable to start")
+            }
+            Ok(Box::new(Started))
+        });
+        assert_eq!(states.len(), 6);
+        let started = started.into_inner();
+        assert!(started.contains(&Unit::Scheduler));
+        assert!(!started.contains(&Unit::Kubelet(0)));
+        assert!(states
+            .iter()
+            .filter(|(x, _)| *x == Unit::Kubelet(0) || *x == Unit::Proxy(0))
+            .all(|(_, x)| x.is_err()));
+    }
+}
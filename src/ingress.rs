@@ -0,0 +1,60 @@
+use crate::{addon::Addon, config::Config, kubectl::Kubectl, network::Network};
+use anyhow::{Context, Result};
+use log::info;
+use std::fs::{self, create_dir_all};
+
+/// The ingress-nginx controller addon, which is reachable via node ports on
+/// the host
+pub struct Ingress;
+
+impl Ingress {
+    /// The name of the addon
+    pub const NAME: &'static str = "ingress";
+
+    /// The label of the controller pod
+    const APP: &'static str = "ingress-nginx";
+
+    const IMAGE: &'static str = "k8s.gcr.io/ingress-nginx/controller:v0.41.2";
+
+    /// The node port serving HTTP
+    pub const HTTP_PORT: u16 = 30080;
+
+    /// The node port serving HTTPS
+    pub const HTTPS_PORT: u16 = 30443;
+}
+
+impl Addon for Ingress {
+    fn name(&self) -> String {
+        Ingress::NAME.into()
+    }
+
+    fn apply(&self, config: &Config, _: &Network, kubectl: &Kubectl) -> Result<()> {
+        info!("Deploying ingress-nginx and waiting to be ready");
+
+        let dir = config.root().join(Ingress::NAME);
+        create_dir_all(&dir)?;
+
+        let yml = format!(
+            include_str!("assets/ingress.yml"),
+            image = config.image(Ingress::NAME, Ingress::IMAGE)?,
+            http_port = Ingress::HTTP_PORT,
+            https_port = Ingress::HTTPS_PORT,
+        );
+        let file = dir.join("ingress.yml");
+
+        if !file.exists() {
+            fs::write(&file, yml)?;
+        }
+
+        kubectl
+            .apply(&file)
+            .context("Unable to deploy ingress-nginx")?;
+        kubectl.wait_ready(Ingress::APP)?;
+        info!(
+            "ingress-nginx deployed, serving HTTP on port {} and HTTPS on port {}",
+            Ingress::HTTP_PORT,
+            Ingress::HTTPS_PORT
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{
+    config::Config,
+    controlplane::ControlPlane,
+    process::{Process, ProcessState, Stoppable},
+};
+use anyhow::{bail, Result};
+use std::{fs::create_dir_all, path::PathBuf};
+
+/// The name of the kine executable and working directory
+pub const KINE: &str = "kine";
+
+/// The etcd API shim backed by sqlite, which replaces etcd for lightweight
+/// clusters
+pub struct Kine {
+    process: Process,
+}
+
+impl Kine {
+    pub fn start(config: &Config) -> ProcessState {
+        let dir = config.root().join(KINE);
+        create_dir_all(&dir)?;
+        create_dir_all(config.socket_dir())?;
+
+        let endpoint = Self::endpoint(config)?;
+        let mut process = ControlPlane::start(
+            config,
+            &dir,
+            KINE,
+            KINE,
+            &[
+                &format!(
+                    "--endpoint=sqlite://{}?_journal=WAL&cache=shared",
+                    Self::database(config).display()
+                ),
+                &format!("--listen-address={}", endpoint),
+            ],
+            &config.component_env_vars("etcd"),
+        )?;
+
+        process.wait_ready("Kine listening on")?;
+        process.describe(KINE, &endpoint, None);
+        Ok(Box::new(Self { process }))
+    }
+
+    /// Retrieve the etcd endpoint served by kine, which is a UNIX socket in
+    /// the socket directory
+    pub fn endpoint(config: &Config) -> Result<String> {
+        let socket = config.socket_dir().join("kine.sock");
+        if socket.display().to_string().len() > 100 {
+            bail!(
+                "Socket path '{}' is too long, consider using a shorter --socket-dir",
+                socket.display()
+            )
+        }
+        Ok(format!("unix://{}", socket.display()))
+    }
+
+    /// Retrieve the path to the sqlite database
+    fn database(config: &Config) -> PathBuf {
+        config.root().join(KINE).join("state.db")
+    }
+}
+
+impl Stoppable for Kine {
+    fn stop(&mut self) -> Result<()> {
+        self.process.stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_config;
+
+    #[test]
+    fn endpoint_success() -> Result<()> {
+        let c = test_config()?;
+        let endpoint = Kine::endpoint(&c)?;
+        assert!(endpoint.starts_with("unix:///run/kubernix/"));
+        assert!(endpoint.ends_with("/kine.sock"));
+        Ok(())
+    }
+}
@@ -1,15 +1,14 @@
 use crate::{
     kubectl::Kubectl,
-    pki::{Idendity, Pki},
+    pki::{Idendity, Pki, ADMIN_NAME},
     Config,
 };
-use anyhow::{format_err, Context, Result};
+use anyhow::{bail, format_err, Context, Result};
 use getset::Getters;
 use log::{debug, info};
 use nix::sys::stat::{fchmod, Mode};
 use std::{
-    fs::{create_dir_all, File},
-    net::Ipv4Addr,
+    fs::{create_dir_all, remove_dir_all, File},
     os::unix::io::AsRawFd,
     path::{Path, PathBuf},
 };
@@ -33,9 +32,11 @@ pub struct KubeConfig {
 }
 
 impl KubeConfig {
+    const DIR: &'static str = "kubeconfig";
+
     pub fn new(config: &Config, pki: &Pki) -> Result<KubeConfig> {
         // Create the target dir
-        let dir = config.root().join("kubeconfig");
+        let dir = Self::dir(config);
 
         if dir.exists() {
             info!("Kubeconfig directory already exists, skipping generation");
@@ -60,24 +61,41 @@ impl KubeConfig {
             let kubelets = pki
                 .kubelets()
                 .iter()
-                .map(|x| Self::setup_kubeconfig(&dir, x, pki.ca().cert()))
+                .map(|x| Self::setup_kubeconfig(&dir, config, x, pki.ca().cert()))
                 .collect::<Result<Vec<_>, _>>()?;
 
             Ok(KubeConfig {
                 kubelets,
-                proxy: Self::setup_kubeconfig(&dir, pki.proxy(), pki.ca().cert())?,
+                proxy: Self::setup_kubeconfig(&dir, config, pki.proxy(), pki.ca().cert())?,
                 controller_manager: Self::setup_kubeconfig(
                     &dir,
+                    config,
                     pki.controller_manager(),
                     pki.ca().cert(),
                 )?,
-                scheduler: Self::setup_kubeconfig(&dir, pki.scheduler(), pki.ca().cert())?,
-                admin: Self::setup_kubeconfig(&dir, pki.admin(), pki.ca().cert())?,
+                scheduler: Self::setup_kubeconfig(&dir, config, pki.scheduler(), pki.ca().cert())?,
+                admin: Self::setup_kubeconfig(&dir, config, pki.admin(), pki.ca().cert())?,
             })
         }
     }
 
-    fn setup_kubeconfig(dir: &Path, idendity: &Idendity, ca: &Path) -> Result<PathBuf> {
+    /// Create all kubeconfigs again, which embed the renewed certificates of
+    /// the provided PKI
+    pub fn renew(config: &Config, pki: &Pki) -> Result<KubeConfig> {
+        let dir = Self::dir(config);
+        if dir.exists() {
+            remove_dir_all(&dir)
+                .with_context(|| format!("Unable to remove '{}'", dir.display()))?;
+        }
+        Self::new(config, pki)
+    }
+
+    fn setup_kubeconfig(
+        dir: &Path,
+        config: &Config,
+        idendity: &Idendity,
+        ca: &Path,
+    ) -> Result<PathBuf> {
         debug!("Creating kubeconfig for {}", idendity.name());
         let kubeconfig = Self::target_config(dir, idendity);
 
@@ -88,7 +106,7 @@ impl KubeConfig {
             "set-cluster",
             cluster,
             &format!("--certificate-authority={}", ca.display()),
-            &format!("--server=https://{}:6443", &Ipv4Addr::LOCALHOST),
+            &format!("--server={}", config.api_server()),
             embed_certs,
         ])?;
 
@@ -100,7 +118,7 @@ impl KubeConfig {
             embed_certs,
         ])?;
 
-        let context = "kubernix";
+        let context = &config.cluster_name();
         kubectl.config(&[
             "set-context",
             context,
@@ -123,8 +141,44 @@ impl KubeConfig {
         Ok(kubeconfig)
     }
 
+    /// Retrieve the admin kubeconfig path of an already bootstrapped cluster
+    pub fn existing_admin(config: &Config) -> Result<PathBuf> {
+        Self::existing_admin_root(config.root())
+    }
+
+    /// Retrieve the admin kubeconfig path of an already bootstrapped cluster
+    /// within the provided run root
+    pub fn existing_admin_root(root: &Path) -> Result<PathBuf> {
+        let path = Self::admin_path(root);
+        if !path.exists() {
+            bail!(
+                "Admin kubeconfig '{}' does not exist, is the cluster bootstrapped?",
+                path.display()
+            )
+        }
+        Ok(path)
+    }
+
+    /// Retrieve the path of the admin kubeconfig within the run root
+    pub fn admin_path(root: &Path) -> PathBuf {
+        Self::target_path(&root.join(Self::DIR), ADMIN_NAME)
+    }
+
+    /// Retrieve the path of the kubelet kubeconfig of the provided node
+    pub fn kubelet_path(root: &Path, node: &str) -> PathBuf {
+        Self::target_path(&root.join(Self::DIR), node)
+    }
+
+    fn dir(config: &Config) -> PathBuf {
+        config.root().join(Self::DIR)
+    }
+
     fn target_config(dir: &Path, idendity: &Idendity) -> PathBuf {
-        dir.join(format!("{}.kubeconfig", idendity.name()))
+        Self::target_path(dir, idendity.name())
+    }
+
+    fn target_path(dir: &Path, name: &str) -> PathBuf {
+        dir.join(format!("{}.kubeconfig", name))
     }
 }
 
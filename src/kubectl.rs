@@ -1,14 +1,16 @@
-use anyhow::{bail, Result};
+use crate::record::Recorded;
+use anyhow::{bail, Context, Result};
 use getset::Getters;
 use log::{debug, trace};
 use std::{
+    io::Write,
     path::{Path, PathBuf},
-    process::{Command, Output},
+    process::{Command, Output, Stdio},
     thread::sleep,
     time::{Duration, Instant},
 };
 
-#[derive(Getters)]
+#[derive(Clone, Getters)]
 pub struct Kubectl {
     #[get = "pub"]
     kubeconfig: PathBuf,
@@ -24,11 +26,7 @@ impl Kubectl {
 
     /// Run a generic kubectl command
     pub fn execute(&self, args: &[&str]) -> Result<Output> {
-        let output = Command::new("kubectl")
-            .args(args)
-            .arg("--kubeconfig")
-            .arg(&self.kubeconfig)
-            .output()?;
+        let output = self.execute_unchecked(args)?;
         if !output.status.success() {
             trace!("kubectl args: {:?}", args);
             debug!("kubectl output: {:?}", output);
@@ -37,6 +35,16 @@ impl Kubectl {
         Ok(output)
     }
 
+    /// Run a generic kubectl command without failing on a non zero exit
+    /// code, for example since it carries a decision
+    pub fn execute_unchecked(&self, args: &[&str]) -> Result<Output> {
+        Ok(Command::new("kubectl")
+            .args(args)
+            .arg("--kubeconfig")
+            .arg(&self.kubeconfig)
+            .recorded_output()?)
+    }
+
     /// Run kubectl config
     pub fn config(&self, args: &[&str]) -> Result<()> {
         let mut final_args = vec!["config"];
@@ -53,6 +61,55 @@ impl Kubectl {
         Ok(())
     }
 
+    /// Validate the manifest via a server side dry run, whereas the error
+    /// contains the output of kubectl
+    pub fn dry_run(&self, file: &Path) -> Result<()> {
+        let output = Command::new("kubectl")
+            .arg("apply")
+            .arg("--server-side")
+            .arg("--dry-run=server")
+            .arg("--field-manager=kubernix-check")
+            .arg("-f")
+            .arg(file)
+            .arg("--kubeconfig")
+            .arg(&self.kubeconfig)
+            .output()?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim())
+        }
+        Ok(())
+    }
+
+    /// Replace all objects of the resource within every namespace with
+    /// themselves, which stores them again, for example encrypted with the
+    /// current key
+    pub fn rewrite(&self, resource: &str) -> Result<()> {
+        let objects = self.execute(&["get", resource, "--all-namespaces", "--output=json"])?;
+        let mut child = Command::new("kubectl")
+            .arg("replace")
+            .arg("--filename=-")
+            .arg("--kubeconfig")
+            .arg(&self.kubeconfig)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        child
+            .stdin
+            .take()
+            .context("Unable to retrieve kubectl stdin")?
+            .write_all(&objects.stdout)?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            bail!(
+                "Unable to rewrite {}: {}",
+                resource,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+        Ok(())
+    }
+
     /// Wait for a pod to be ready
     pub fn wait_ready(&self, name: &str) -> Result<()> {
         debug!("Waiting for {} to be ready", name);
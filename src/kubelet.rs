@@ -1,21 +1,50 @@
 use crate::{
     config::Config,
     container::Container,
-    crio::Crio,
+    crio::CriSocket,
     kubeconfig::KubeConfig,
+    kubectl::Kubectl,
     network::Network,
     node::Node,
     pki::Pki,
-    process::{Process, ProcessState, Stoppable},
+    process::{Process, ProcessOptions, ProcessState, Readiness, Stoppable},
+    spec::Spec,
+    system::System,
 };
 use anyhow::{bail, Context, Result};
-use std::fs::{self, create_dir_all};
+use log::debug;
+use std::{
+    fs::{self, create_dir_all},
+    path::{Path, PathBuf},
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
 pub struct Kubelet {
     process: Process,
 }
 
+const KUBELET: &str = "kubelet";
+
 impl Kubelet {
+    /// The distance of the healthz ports to the secure ports of the kubelets
+    pub const HEALTHZ_PORT_OFFSET: u16 = 1000;
+
+    /// The distance of the kube-proxy healthz ports to the secure ports of
+    /// the kubelets, whereas the metrics ports are 500 ports above
+    pub const PROXY_PORT_OFFSET: u16 = 2750;
+
+    /// The default feature gates of the kubelets, which can be overridden
+    /// via `--feature-gates`
+    const FEATURE_GATES: &'static [(&'static str, bool)] = &[
+        ("DevicePlugins", false),
+        ("LocalStorageCapacityIsolation", false),
+    ];
+
+    /// The maximum time to wait for the node to get registered at the API
+    /// server
+    const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(120);
+
     pub fn start(
         config: &Config,
         node: u8,
@@ -24,24 +53,31 @@ impl Kubelet {
         kubeconfig: &KubeConfig,
     ) -> ProcessState {
         let node_name = Node::name(config, network, node);
-        const KUBELET: &str = "kubelet";
+        let dir = Self::dir(config, network, node);
 
-        let dir = config.root().join(KUBELET).join(&node_name);
-        let root_dir = dir.join("run");
-        if root_dir.display().to_string().len() + "kubelet.sock".len() > 100 {
+        // The root dir contains sockets and is therefore part of the socket dir
+        let root_dir = config
+            .socket_dir()
+            .join(format!("{}-{}", KUBELET, node_name));
+        if root_dir.display().to_string().len() + "pod-resources/kubelet.sock".len() > 100 {
             bail!(
-                "Kubelet run path '{}' is too long for kubelet.sock",
+                "Kubelet run path '{}' is too long for kubelet.sock, consider using a shorter --socket-dir",
                 root_dir.display()
             )
         }
 
         create_dir_all(&dir)?;
+        create_dir_all(&root_dir)?;
+        System::symlink(&root_dir, &dir.join("run"))?;
 
-        let idendity = pki
-            .kubelets()
-            .get(node as usize)
-            .with_context(|| format!("Unable to retrieve kubelet idendity for {}", node_name))?;
+        let serving = pki.kubelet_servings().get(node as usize).with_context(|| {
+            format!(
+                "Unable to retrieve kubelet serving idendity for {}",
+                node_name
+            )
+        })?;
 
+        let port = Self::port(config, node);
         let yml = format!(
             include_str!("assets/kubelet.yml"),
             ca = pki.ca().cert().display(),
@@ -50,43 +86,51 @@ impl Kubelet {
                 .crio_cidrs()
                 .get(node as usize)
                 .context("Unable to retrieve kubelet CIDR")?,
-            cert = idendity.cert().display(),
-            key = idendity.key().display(),
-            port = 11250 + u16::from(node),
-            healthzPort = 12250 + u16::from(node),
+            cert = serving.cert().display(),
+            key = serving.key().display(),
+            port = port,
+            healthzPort = Self::healthz_port(config, node),
+            image_gc_high = config.image_gc_high_threshold(),
+            image_gc_low = config.image_gc_low_threshold(),
+            container_log_max_size = config.container_log_max_size(),
+            container_log_max_files = config.container_log_max_files(),
+            feature_gates = config.feature_gates_yml(Self::FEATURE_GATES)?,
         );
-        let cfg = dir.join("config.yml");
+        let cfg = Self::config_file(config, network, node);
 
         if !cfg.exists() {
-            fs::write(&cfg, yml)?;
+            fs::write(&cfg, Spec::patch_config(config, "kubelet", node, &yml)?)?;
         }
 
+        let node_kubeconfig = kubeconfig
+            .kubelets()
+            .get(node as usize)
+            .with_context(|| format!("Unable to retrieve kubelet config for {}", node_name))?;
         let args = &[
             "--container-runtime=remote",
             &format!("--config={}", cfg.display()),
             &format!("--root-dir={}", root_dir.display()),
             &format!(
                 "--container-runtime-endpoint={}",
-                Crio::socket(config, network, node)?.to_socket_string(),
-            ),
-            &format!(
-                "--kubeconfig={}",
-                kubeconfig
-                    .kubelets()
-                    .get(node as usize)
-                    .with_context(|| format!(
-                        "Unable to retrieve kubelet config for {}",
-                        node_name
-                    ))?
-                    .display()
+                CriSocket::of(config, network, node)?.to_socket_string(),
             ),
+            &format!("--kubeconfig={}", node_kubeconfig.display()),
             "--v=2",
         ];
+        let cloud_provider = config.cloud_provider_arg();
+        let args = &args
+            .iter()
+            .copied()
+            .chain(cloud_provider.as_deref())
+            .chain(config.kubelet_args().iter().map(String::as_str))
+            .collect::<Vec<_>>();
 
         let mut process = if config.multi_node() {
-            // Run inside a container
+            // Run inside a container, whereas the node IP has to be part of the
+            // serving certificate to let the API server connect to the kubelet
             let arg_hostname = &format!("--hostname-override={}", node_name);
-            let mut modargs: Vec<&str> = vec![arg_hostname];
+            let arg_node_ip = &format!("--node-ip={}", Node::ip(node));
+            let mut modargs: Vec<&str> = vec![arg_hostname, arg_node_ip];
             modargs.extend(args);
             Container::exec(
                 config,
@@ -95,18 +139,80 @@ impl Kubelet {
                 KUBELET,
                 &node_name,
                 &modargs,
+                &config.component_env_vars("kubelet"),
             )?
         } else {
             // Run as usual process
-            Process::start(&dir, "Kubelet", KUBELET, args)?
+            Process::start(
+                &ProcessOptions::new(config),
+                &dir,
+                "Kubelet",
+                KUBELET,
+                args,
+                &config.component_env_vars("kubelet"),
+            )?
         };
-        process.wait_ready("Successfully registered node")?;
+        process.wait_ready(Readiness::http_healthz(Self::healthz_port(config, node)))?;
+
+        // The addons label and patch the node right after the start
+        Self::wait_registered(&node_name, kubeconfig.admin())?;
+        process.describe(
+            KUBELET,
+            &format!("https://{}:{}", Node::ip(node), port),
+            Some(node_kubeconfig),
+        );
         Ok(Box::new(Self { process }))
     }
+
+    /// Wait until the Node object of the kubelet exists. The node is not
+    /// required to be ready, since the CNI may be deployed as addon later on.
+    fn wait_registered(node_name: &str, kubeconfig: &Path) -> Result<()> {
+        let kubectl = Kubectl::new(kubeconfig);
+        let now = Instant::now();
+        while now.elapsed() < Self::REGISTRATION_TIMEOUT {
+            let output = kubectl.execute_unchecked(&["get", "node", node_name])?;
+            if output.status.success() {
+                debug!("Node {} is registered", node_name);
+                return Ok(());
+            }
+            debug!("Node {} not registered yet", node_name);
+            sleep(Duration::from_secs(1));
+        }
+        bail!(
+            "Timed out waiting for the node {} to get registered",
+            node_name
+        )
+    }
+
+    /// The secure port of the kubelet on the provided node
+    pub fn port(config: &Config, node: u8) -> u16 {
+        config.kubelet_port() + u16::from(node)
+    }
+
+    /// The port of the kubelet healthz endpoint on the provided node
+    pub fn healthz_port(config: &Config, node: u8) -> u16 {
+        Self::port(config, node) + Self::HEALTHZ_PORT_OFFSET
+    }
+
+    /// Retrieve the configuration file of the provided node
+    pub fn config_file(config: &Config, network: &Network, node: u8) -> PathBuf {
+        Self::dir(config, network, node).join("config.yml")
+    }
+
+    fn dir(config: &Config, network: &Network, node: u8) -> PathBuf {
+        config
+            .root()
+            .join(KUBELET)
+            .join(Node::name(config, network, node))
+    }
 }
 
 impl Stoppable for Kubelet {
     fn stop(&mut self) -> Result<()> {
         self.process.stop()
     }
+
+    fn restart(&mut self) -> Result<()> {
+        self.process.restart()
+    }
 }
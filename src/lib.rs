@@ -1,74 +1,152 @@
 //! # kubernix
 #![deny(missing_docs)]
 
+mod addon;
+mod aggregation;
 mod apiserver;
+mod artifact;
+mod audit;
+mod certmanager;
+mod check;
+mod cloudprovider;
+mod clusterinfo;
+mod cni;
 mod config;
+mod configfile;
 mod container;
+mod containerd;
 mod controllermanager;
+mod controlplane;
 mod coredns;
 mod crio;
+mod dashboard;
 mod encryptionconfig;
+mod entropy;
+mod error;
 mod etcd;
+mod events;
+mod fakenodes;
+mod gc;
+mod graph;
+mod ingress;
+mod kine;
 mod kubeconfig;
 mod kubectl;
 mod kubelet;
 mod logger;
+mod logging;
+mod logs;
+mod metrics;
 mod network;
 mod nix;
 mod node;
+mod observability;
 mod pki;
+mod platform;
 mod podman;
 mod process;
 mod progress;
 mod proxy;
+mod rbac;
+mod record;
+mod registryauth;
+mod remote;
+mod restart;
+mod runtimeclass;
 mod scheduler;
+mod spec;
+mod status;
+mod summary;
 mod system;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod token;
+mod warnings;
 
-pub use config::Config;
-pub use logger::Logger;
+pub use clusterinfo::{ClusterInfo, NodeInfo};
+pub use config::{
+    BootstrapPhase, ColorMode, Config, EnvShell, Images, ImagesSubCommand, NodeCommand,
+    NodeSubCommand, OutputFormat, PhaseCommand, PhaseSubCommand, PkiKeyType, SubCommand,
+    TokenCommand, TokenSubCommand,
+};
+pub use error::{ErrorKind, Reported};
+pub use logger::{LogSink, Logger};
 
 use crate::nix::Nix;
+use addon::Addons;
 use apiserver::ApiServer;
+use audit::Audit;
+use check::Check;
+use cni::Cni;
+use configfile::ConfigFile;
 use container::Container;
+use containerd::Containerd;
 use controllermanager::ControllerManager;
-use coredns::CoreDns;
-use crio::Crio;
+use controlplane::ControlPlane;
+use crio::{CriSocket, Crio};
+use dashboard::Dashboard;
 use encryptionconfig::EncryptionConfig;
+use entropy::Entropy;
 use etcd::Etcd;
+use events::Events;
+use fakenodes::FakeNodes;
+use gc::Gc;
+use graph::{Graph, Unit};
+use kine::Kine;
 use kubeconfig::KubeConfig;
 use kubectl::Kubectl;
 use kubelet::Kubelet;
+use logging::Logging;
+use logs::Logs;
 use network::Network;
+use node::Node;
 use pki::Pki;
-use process::{Process, Stoppables};
-use progress::Progress;
+use platform::Platform;
+use process::{Process, Stoppables, RUNTIME};
+use progress::{Progress, Step};
 use proxy::Proxy;
+use record::Recorder;
+use registryauth::RegistryAuth;
+use remote::Remote;
+use restart::Restart;
 use scheduler::Scheduler;
+use status::{Phase, Status};
+use summary::Summary;
 use system::System;
+use token::Token;
 
 use ::nix::{
     mount::{umount2, MntFlags},
-    unistd::getuid,
+    sys::signal::{kill, Signal},
+    unistd::{getuid, Pid},
 };
-use anyhow::{bail, Context, Result};
-use log::{debug, error, info, set_boxed_logger};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, error, info, warn, LevelFilter};
+use parking_lot::Mutex;
 use proc_mounts::MountIter;
-use rayon::{prelude::*, scope};
-use signal_hook::{
-    consts::signal::{SIGHUP, SIGINT, SIGTERM},
-    flag,
-};
+use rayon::scope;
+use serde_json::json;
 use std::{
+    env::{args, current_exe, var},
+    ffi::OsStr,
     fs,
-    path::PathBuf,
+    future::Future,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
     process::{id, Command},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    thread::sleep,
+    thread::{sleep, spawn},
     time::{Duration, Instant},
 };
+use tokio::{
+    pin, select,
+    signal::unix::{signal, SignalKind},
+    sync::oneshot,
+    time,
+};
 
 const RUNTIME_ENV: &str = "CONTAINER_RUNTIME_ENDPOINT";
 
@@ -77,13 +155,29 @@ pub struct Kubernix {
     config: Config,
     network: Network,
     kubectl: Kubectl,
-    processes: Stoppables,
+    processes: Mutex<Stoppables>,
+    units: Vec<Unit>,
+    graph: Graph,
+    registry_auth: RegistryAuth,
     system: System,
+    cluster_info: ClusterInfo,
+    cleaned_up: bool,
 }
 
 impl Kubernix {
+    /// The interval to check the configuration files for changes
+    const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
     /// Start kubernix by consuming the provided configuration
     pub fn start(mut config: Config) -> Result<()> {
+        // The remote machine prepares its own environment
+        if let Some(target) = config.target() {
+            config.color().apply();
+            if !Logger::new(config.log_level()).install() {
+                debug!("Using already installed logger");
+            }
+            return Remote::new(target)?.bootstrap(&config);
+        }
         Self::prepare_env(&mut config)?;
 
         // Bootstrap if we're not inside a nix shell
@@ -124,14 +218,689 @@ impl Kubernix {
         Ok(())
     }
 
+    /// Remove all unused container images from every node
+    pub fn prune_images(mut config: Config) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+        let network = Network::new(&config)?;
+        Self::prune_node_images(&config, &network)
+    }
+
+    /// Remove rotated logs and old etcd snapshots according to the retention
+    /// settings, as well as unused container images if the cluster is running
+    pub fn gc(mut config: Config, dry_run: bool) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+
+        let garbage = Gc::collect(&config)?;
+        if dry_run {
+            for path in &garbage {
+                println!("{}", path.display());
+            }
+            return Ok(());
+        }
+        let freed = Gc::remove(&garbage)?;
+        info!(
+            "Removed {} rotated log(s) and etcd snapshot(s) ({} bytes)",
+            garbage.len(),
+            freed
+        );
+
+        if Status::load(&config).map_or(false, |x| x.phase() == Phase::Running) {
+            let network = Network::new(&config)?;
+            Self::prune_node_images(&config, &network)
+        } else {
+            info!("Cluster is not running, skipping unused images");
+            Ok(())
+        }
+    }
+
+    fn prune_node_images(config: &Config, network: &Network) -> Result<()> {
+        for node in 0..config.nodes() {
+            let node_name = Node::name(config, network, node);
+            info!("Pruning unused images on {}", node_name);
+
+            let socket = CriSocket::of(config, network, node)?.to_socket_string();
+            if !Nix::execute(
+                config,
+                &[
+                    "crictl",
+                    &format!("--runtime-endpoint={}", socket),
+                    "rmi",
+                    "--prune",
+                ],
+            )?
+            .success()
+            {
+                bail!("Unable to prune images on {}", node_name)
+            }
+        }
+
+        info!("All unused images pruned");
+        Ok(())
+    }
+
+    /// Print a kubeconfig, which authenticates via a short-lived token of the
+    /// service account bound to the cluster role
+    pub fn token_kubeconfig(
+        mut config: Config,
+        service_account: &str,
+        namespace: &str,
+        cluster_role: &str,
+        duration: u64,
+        server: Option<&str>,
+    ) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+        if !Nix::is_active() {
+            return Self::rerun_in_nix(&config);
+        }
+
+        let kubectl = Kubectl::new(&KubeConfig::existing_admin(&config)?);
+        let network = Network::new(&config)?;
+        let pki = Pki::new(&config, &network)?;
+        let kubeconfig = Token::new(service_account, namespace, cluster_role, duration)
+            .kubeconfig(
+                &config,
+                &kubectl,
+                pki.ca().cert(),
+                &server
+                    .map(Into::into)
+                    .unwrap_or_else(|| config.api_server()),
+            )?;
+        print!("{}", kubeconfig);
+        Ok(())
+    }
+
+    /// Build a container image and load it into every node
+    pub fn build_and_load(
+        mut config: Config,
+        image: &str,
+        context: &Path,
+        file: Option<&Path>,
+    ) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+        if !Nix::is_active() {
+            return Self::rerun_in_nix(&config);
+        }
+
+        let network = Network::new(&config)?;
+        Container::build_and_load(&config, &network, image, context, file)
+    }
+
+    /// Run kubectl by using the admin kubeconfig of the cluster
+    pub fn kubectl(mut config: Config, args: &[String]) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+
+        let kubeconfig = KubeConfig::existing_admin(&config)?.display().to_string();
+        let mut full_args = vec!["kubectl", "--kubeconfig", &kubeconfig];
+        full_args.extend(args.iter().map(String::as_str));
+
+        let status = Nix::execute(&config, &full_args)?;
+        if !status.success() {
+            bail!("kubectl command failed ({})", status)
+        }
+        Ok(())
+    }
+
+    /// Validate the manifests of the provided file or directory against the
+    /// running API server
+    pub fn check(mut config: Config, path: &Path) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+        if !Nix::is_active() {
+            return Self::rerun_in_nix(&config);
+        }
+        let kubectl = Kubectl::new(&KubeConfig::existing_admin(&config)?);
+        Check::run(&kubectl, path)
+    }
+
+    /// Expose the API server on localhost for the user who invoked kubernix
+    pub fn proxy(mut config: Config, port: u16) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+        let kubeconfig = KubeConfig::existing_admin(&config)?;
+
+        // Drop the privileges to the user who invoked sudo, which is able to
+        // read the world readable admin kubeconfig
+        let mut args = vec![];
+        let (uid_arg, gid_arg);
+        if let (Ok(uid), Ok(gid)) = (var("SUDO_UID"), var("SUDO_GID")) {
+            debug!("Running proxy as user {} and group {}", uid, gid);
+            uid_arg = format!("--reuid={}", uid);
+            gid_arg = format!("--regid={}", gid);
+            args.extend(vec!["setpriv", &uid_arg, &gid_arg, "--clear-groups"]);
+        }
+
+        let kubeconfig_arg = format!("--kubeconfig={}", kubeconfig.display());
+        let port_arg = format!("--port={}", port);
+        args.extend(vec![
+            "kubectl",
+            &kubeconfig_arg,
+            "proxy",
+            "--address=127.0.0.1",
+            &port_arg,
+        ]);
+
+        info!("Serving the API on http://127.0.0.1:{}", port);
+        let status = Nix::execute(&config, &args)?;
+        if !status.success() {
+            bail!("kubectl proxy failed ({})", status)
+        }
+        Ok(())
+    }
+
+    /// Print the status of the cluster
+    pub fn status(config: Config, output: OutputFormat) -> Result<()> {
+        let status = Status::load(&config)?;
+        match output {
+            OutputFormat::Text => print!("{}", status),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&status)?),
+        }
+        Ok(())
+    }
+
+    /// Print all clusters next to the run root together with their phase
+    pub fn list(config: Config, output: OutputFormat) -> Result<()> {
+        let clusters = config
+            .clusters()?
+            .into_iter()
+            .map(|(name, root)| {
+                let phase = Status::load_root(&root).ok().map(|x| x.phase());
+                (name, root, phase)
+            })
+            .collect::<Vec<_>>();
+        match output {
+            OutputFormat::Text => {
+                for (name, root, phase) in clusters {
+                    println!(
+                        "{}\t{}\t{}",
+                        name.as_deref().unwrap_or("-"),
+                        phase.map_or_else(|| "-".into(), |x| format!("{:?}", x)),
+                        root.display()
+                    );
+                }
+            }
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(
+                    &clusters
+                        .iter()
+                        .map(|(name, root, phase)| json!({
+                            "name": name,
+                            "root": root,
+                            "phase": phase,
+                        }))
+                        .collect::<Vec<_>>()
+                )?
+            ),
+        }
+        Ok(())
+    }
+
+    /// Print the log of a single component, whereas only lines newer than
+    /// `since` are considered and new lines get printed if `follow` is set
+    pub fn print_log(
+        config: Config,
+        component: &str,
+        follow: bool,
+        since: Option<&str>,
+    ) -> Result<()> {
+        let unit = Unit::parse(component)?;
+        let since = since.map(Logs::parse_since).transpose()?;
+        let file = Logs::file(&config, &Status::load(&config)?, unit)?;
+        Logs::print(&file, since, follow)
+    }
+
+    /// Print the log files of the cluster components or pods, whereas only
+    /// the ones containing the filter are considered
+    pub fn logs(config: Config, pods: bool, filter: Option<&str>) -> Result<()> {
+        if pods {
+            for (node, log) in Crio::pod_logs(&config, filter)? {
+                println!("{}\t{}", node, log.display());
+            }
+        } else {
+            let status = Status::load(&config)?;
+            for (name, component) in status.components() {
+                if filter.map_or(true, |x| name.contains(x)) {
+                    println!("{}\t{}", name, component.log_file().display());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-execute the recorded commands of the last bootstrap up to the last
+    /// one of the provided phase
+    pub fn replay(mut config: Config, until: Option<&str>, file: Option<PathBuf>) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+
+        // Replaying all commands as root is rarely intended, for example if
+        // the recording stems from another host
+        if until.is_none() && getuid().is_root() {
+            bail!("Replaying commands as root requires an explicit --until phase")
+        }
+
+        // The recorded commands rely on the binaries of the nix environment
+        if !Nix::is_active() {
+            return Self::rerun_in_nix(&config);
+        }
+
+        let file = file.unwrap_or_else(|| config.root().join(Recorder::FILENAME));
+        let records = Recorder::load(&file)?;
+        let records = Recorder::until(&records, until)?;
+        info!("Replaying {} recorded commands", records.len());
+        Recorder::replay(&records)?;
+        info!("All recorded commands replayed");
+        Ok(())
+    }
+
+    /// Print a kubectl script which replays the modifications recorded in
+    /// the API server audit log
+    pub fn replay_audit(config: Config, users: &[String], file: Option<PathBuf>) -> Result<()> {
+        let file = file.unwrap_or_else(|| ApiServer::audit_log(&config));
+        let log = fs::read_to_string(&file)
+            .with_context(|| format!("Unable to read audit log '{}'", file.display()))?;
+        print!("{}", Audit::replay(&log, users)?);
+        Ok(())
+    }
+
+    /// Print the environment of the cluster to be evaluated by the provided
+    /// shell
+    pub fn env(config: Config, shell: EnvShell) -> Result<()> {
+        let file = Self::env_file(&config);
+        let content = fs::read_to_string(&file).with_context(|| {
+            format!(
+                "Unable to read environment file '{}', is the cluster running?",
+                file.display()
+            )
+        })?;
+        print!("{}", Self::render_env(&content, shell)?);
+        Ok(())
+    }
+
+    /// Render the `export` lines of the environment file for the provided
+    /// shell, whereas all values get single quoted
+    fn render_env(content: &str, shell: EnvShell) -> Result<String> {
+        let mut out = String::new();
+        for line in content.lines().filter(|x| !x.trim().is_empty()) {
+            let (key, value) = line
+                .trim_start_matches("export ")
+                .split_once('=')
+                .with_context(|| format!("Invalid environment line '{}'", line))?;
+            out.push_str(&match shell {
+                EnvShell::Bash | EnvShell::Zsh => {
+                    format!("export {}='{}'\n", key, value.replace('\'', r"'\''"))
+                }
+                EnvShell::Fish => format!(
+                    "set -gx {} '{}';\n",
+                    key,
+                    value.replace('\\', r"\\").replace('\'', r"\'")
+                ),
+            });
+        }
+        Ok(out)
+    }
+
+    /// Restart the processes of the provided node after the downtime in
+    /// seconds, which is done by the main kubernix process
+    pub fn restart_node(mut config: Config, node: u8, downtime: u64) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+        if node >= config.nodes() {
+            bail!(
+                "Node {} does not exist, the cluster has {} node(s)",
+                node,
+                config.nodes()
+            )
+        }
+        Self::request_restart(&config, Unit::cri(&config, node), downtime, false)?;
+        info!("Node {} restarted", node);
+        Ok(())
+    }
+
+    /// Restart the component and all of its dependents after the downtime in
+    /// seconds, which is done by the main kubernix process
+    pub fn restart(mut config: Config, component: &str, node: u8, downtime: u64) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+        let unit = Self::cluster_unit(&config, component, node)?;
+        Self::request_restart(&config, unit, downtime, false)?;
+        info!("Component {} restarted", unit);
+        Ok(())
+    }
+
+    /// Validate the edited configuration file of the component and restart it
+    /// together with all of its dependents, which is done by the main
+    /// kubernix process
+    pub fn reload_component(mut config: Config, component: &str, node: u8) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+        let unit = Self::cluster_unit(&config, component, node)?;
+        Self::request_restart(&config, unit, 0, true)?;
+        info!("Component {} reloaded", unit);
+        Ok(())
+    }
+
+    /// Renew all certificates against the existing CAs and restart the
+    /// components of a running cluster to use them
+    pub fn renew_certs(mut config: Config) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+        if !Nix::is_active() {
+            return Self::rerun_in_nix(&config);
+        }
+
+        let network = Network::new(&config)?;
+        let pki = Pki::renew(&config, &network)?;
+        KubeConfig::renew(&config, &pki)?;
+        info!("Certificates renewed");
+
+        if !Status::load(&config).map_or(false, |x| x.phase() == Phase::Running) {
+            info!("Cluster is not running, the certificates get used on the next start");
+            return Ok(());
+        }
+
+        // Restarting etcd and the kubelets covers all of their dependents
+        for unit in Graph::new(&config)
+            .units()
+            .iter()
+            .filter(|x| matches!(x, Unit::Etcd | Unit::Kubelet(_)))
+        {
+            Self::request_restart(&config, *unit, 0, false)?;
+        }
+        info!("All components use the renewed certificates");
+        Ok(())
+    }
+
+    /// Rotate the encryption key of the running cluster by prepending a new
+    /// one, rewriting all secrets and pruning the previous keys afterwards
+    pub fn rotate_encryption_key(mut config: Config) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+        if !Nix::is_active() {
+            return Self::rerun_in_nix(&config);
+        }
+        if !Status::load(&config).map_or(false, |x| x.phase() == Phase::Running) {
+            bail!("Cluster is not running, which is required to rewrite all secrets")
+        }
+
+        // The API server has to be able to decrypt with the previous keys
+        // until all secrets are rewritten
+        if EncryptionConfig::rotate(&config)? {
+            Self::request_restart(&config, Unit::ApiServer, 0, false)?;
+        } else {
+            info!(
+                "The {} provider has no keys to rotate, only rewriting secrets",
+                config.encryption_provider()
+            );
+        }
+
+        info!("Rewriting all secrets");
+        Kubectl::new(&KubeConfig::existing_admin(&config)?).rewrite("secrets")?;
+        EncryptionConfig::prune(&config)?;
+        Self::request_restart(&config, Unit::ApiServer, 0, false)?;
+        info!("Encryption key rotated");
+        Ok(())
+    }
+
+    /// Run a single phase of the bootstrap, which keeps all existing
+    /// artifacts and can therefore be run again after changing its inputs
+    pub fn run_phase(mut config: Config, phase: BootstrapPhase) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+        if !Nix::is_active() {
+            return Self::rerun_in_nix(&config);
+        }
+
+        info!("Running bootstrap phase {}", phase);
+        let running = Status::load(&config).map_or(false, |x| x.phase() == Phase::Running);
+        match phase {
+            BootstrapPhase::System => {
+                System::setup(&config).context("Unable to setup system")?;
+            }
+            BootstrapPhase::Image => Container::build(&config)?,
+            BootstrapPhase::Network => {
+                // The interfaces of the running cluster conflict by design
+                if !running {
+                    Network::remove_stale_interfaces(&config)?;
+                }
+                Network::new(&config)?;
+            }
+            BootstrapPhase::Pki => {
+                Pki::new(&config, &Network::new(&config)?)?;
+            }
+            BootstrapPhase::KubeConfig => {
+                let network = Network::new(&config)?;
+                let pki = Pki::new(&config, &network)?;
+                Self::setup_configs(&config, &network, &pki)?;
+            }
+            BootstrapPhase::Processes | BootstrapPhase::Addons if !running => {
+                bail!("The {} phase requires a running cluster", phase)
+            }
+            BootstrapPhase::Processes => {
+                // Restarting etcd and the CRI runtimes covers all other units
+                for unit in Graph::new(&config)
+                    .units()
+                    .iter()
+                    .filter(|x| matches!(x, Unit::Etcd | Unit::Crio(_) | Unit::Containerd(_)))
+                {
+                    Self::request_restart(&config, *unit, 0, false)?;
+                }
+            }
+            BootstrapPhase::Addons => {
+                let network = Network::new(&config)?;
+                let kubectl = Kubectl::new(&KubeConfig::existing_admin(&config)?);
+                let registry_auth = RegistryAuth::new(&config)?;
+                Addons::new(&config, &network, &kubectl, &registry_auth).apply()?;
+            }
+        }
+        info!("Bootstrap phase {} done", phase);
+        Ok(())
+    }
+
+    /// Retrieve the unit of the component, which has to be part of the cluster
+    fn cluster_unit(config: &Config, component: &str, node: u8) -> Result<Unit> {
+        if node >= config.nodes() {
+            bail!(
+                "Node {} does not exist, the cluster has {} node(s)",
+                node,
+                config.nodes()
+            )
+        }
+        let unit = Unit::new(component, node)?;
+        if !Graph::new(config).units().contains(&unit) {
+            bail!("Component {} is not part of the cluster", unit)
+        }
+        Ok(unit)
+    }
+
+    /// Request the restart of the unit from the running main process
+    fn request_restart(config: &Config, unit: Unit, downtime: u64, reload: bool) -> Result<()> {
+        let status = Status::load(config)?;
+        if status.phase() != Phase::Running {
+            bail!("Cluster is not running ({:?})", status.phase())
+        }
+        info!("Requesting restart of {}", unit);
+        Restart::request(config, status.pid(), unit, downtime, reload)
+    }
+
+    /// Gracefully stop the running kubernix instance and wait for its cleanup,
+    /// whereas orphaned processes and stale mounts of a crashed instance get
+    /// removed
+    pub fn stop(mut config: Config, timeout: u64) -> Result<()> {
+        Self::prepare_env(&mut config)?;
+
+        let pid = match Self::running_pid(&config) {
+            Some(pid) => pid,
+            None if Self::instance_pid(&config).is_some() => bail!(
+                "Kubernix instance is not running yet ({:?}), please retry later",
+                Status::load(&config)?.phase()
+            ),
+            None => {
+                info!("No running kubernix instance found, removing stale processes and mounts");
+                Process::kill_orphans(&config)?;
+                Self::umount(&config);
+                Self::remove_pid_file(&config)?;
+                info!("Cleanup done");
+                return Ok(());
+            }
+        };
+
+        info!("Stopping kubernix instance (pid {})", pid);
+        kill(pid, Signal::SIGTERM).context("Unable to signal kubernix instance")?;
+        let now = Instant::now();
+        while kill(pid, None).is_ok() {
+            if now.elapsed().as_secs() >= timeout {
+                bail!(
+                    "Timed out waiting for kubernix instance (pid {}) to stop",
+                    pid
+                )
+            }
+            sleep(Duration::from_millis(500));
+        }
+        Self::remove_pid_file(&config)?;
+
+        match Status::load(&config).map(|x| x.phase()) {
+            Ok(Phase::Stopped) => {
+                info!("Cleanup done");
+                Ok(())
+            }
+            Ok(phase) => bail!("Kubernix instance stopped in phase {:?}", phase),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Remove the state of a killed kubernix instance, which includes
+    /// orphaned processes, node containers, hosts file entries and mounts.
+    /// The run root gets deleted afterwards if requested.
+    pub fn cleanup_orphans(mut config: Config, remove_root: bool) -> Result<()> {
+        // Never remove a directory which has not been setup by kubernix
+        if remove_root && config.root().exists() && !config.root().join(Config::FILENAME).exists() {
+            bail!(
+                "Run root '{}' contains no {}, refusing to remove it",
+                config.root().display(),
+                Config::FILENAME
+            )
+        }
+        Self::prepare_env(&mut config)?;
+        if let Some(pid) = Self::instance_pid(&config) {
+            bail!(
+                "Kubernix instance (pid {}) is still running, please use the stop subcommand",
+                pid
+            )
+        }
+
+        info!("Removing orphaned processes");
+        Process::kill_orphans(&config)?;
+        if config.multi_node() || config.control_plane_container() {
+            if let Err(e) = Container::remove_all(&config) {
+                warn!("Unable to remove containers: {:#}", e)
+            }
+        }
+        System::remove_hosts(&config)?;
+        info!("Removing stale mounts");
+        Self::umount(&config);
+        Self::remove_pid_file(&config)?;
+
+        if remove_root {
+            info!("Removing run root '{}'", config.root().display());
+            fs::remove_dir_all(config.root()).with_context(|| {
+                format!("Unable to remove run root '{}'", config.root().display())
+            })?;
+        }
+        info!("Cleanup done");
+        Ok(())
+    }
+
+    /// Retrieve the pid of the kubernix instance if it is in the running
+    /// phase
+    fn running_pid(config: &Config) -> Option<Pid> {
+        let pid = Self::instance_pid(config)?;
+        match Status::load(config).map(|x| x.phase()) {
+            Ok(Phase::Running) => Some(pid),
+            _ => None,
+        }
+    }
+
+    /// Retrieve the pid of the alive kubernix instance from the pid file,
+    /// whereas the status file is used for instances running a shell. A pid
+    /// which has been reused by another process is never returned.
+    fn instance_pid(config: &Config) -> Option<Pid> {
+        let (pid, start_time) = match fs::read_to_string(Self::pid_file(config)) {
+            Ok(content) => Self::parse_pid_file(&content)?,
+            Err(_) => (Status::load(config).ok()?.pid(), None),
+        };
+        if start_time.is_some() && Process::start_time(pid) != start_time {
+            debug!("Pid {} has been reused by another process", pid);
+            return None;
+        }
+        let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+        let binary = cmdline.split(|x| *x == 0).next().unwrap_or_default();
+        let binary = Path::new(OsStr::from_bytes(binary));
+        match binary.file_name() {
+            Some(x) if x.to_string_lossy().contains("kubernix") => Some(Pid::from_raw(pid as i32)),
+            _ => {
+                debug!("Pid {} does not belong to kubernix", pid);
+                None
+            }
+        }
+    }
+
+    /// Parse the pid and the optional process start time of the pid file
+    fn parse_pid_file(content: &str) -> Option<(u32, Option<u64>)> {
+        let mut fields = content.split_whitespace();
+        let pid = fields.next()?.parse().ok()?;
+        Some((pid, fields.next().and_then(|x| x.parse().ok())))
+    }
+
+    /// Retrieve the path to the pid file
+    fn pid_file(config: &Config) -> PathBuf {
+        config.root().join("kubernix.pid")
+    }
+
+    /// Remove a possibly stale pid file
+    fn remove_pid_file(config: &Config) -> Result<()> {
+        let file = Self::pid_file(config);
+        if file.exists() {
+            fs::remove_file(&file)
+                .with_context(|| format!("Unable to remove pid file '{}'", file.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Print the version of kubernix
+    pub fn version(output: OutputFormat) -> Result<()> {
+        let version = env!("CARGO_PKG_VERSION");
+        match output {
+            OutputFormat::Text => println!("kubernix {}", version),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&json!({ "version": version }))?
+            ),
+        }
+        Ok(())
+    }
+
+    /// Run the current kubernix invocation again inside the nix environment
+    fn rerun_in_nix(config: &Config) -> Result<()> {
+        let exe = current_exe()?.display().to_string();
+        let forwarded = args().skip(1).collect::<Vec<_>>();
+        let mut full_args = vec![exe.as_str()];
+        full_args.extend(forwarded.iter().map(String::as_str));
+
+        // The nested kubernix process already reported its own failures
+        let status = Nix::run(config, &full_args)?;
+        match status.code().and_then(ErrorKind::from_exit_code) {
+            _ if status.success() => Ok(()),
+            Some(kind) => Err(Reported(kind).into()),
+            None => Err(anyhow!("Nested kubernix run failed ({})", status)),
+        }
+    }
+
     /// Prepare the environment based on the provided config
     fn prepare_env(config: &mut Config) -> Result<()> {
+        // Unsupported platforms would fail somewhere within the bootstrap
+        let platform = Platform::detect();
+        platform.check().context(ErrorKind::Preflight)?;
+
         // Rootless is currently not supported
         if !getuid().is_root() {
-            bail!("Please run kubernix as root")
+            return Err(anyhow!("Please run kubernix as root").context(ErrorKind::Preflight));
         }
 
         // Prepare the configuration
+        config.validate_name().context(ErrorKind::Preflight)?;
+        config.apply_spec().context(ErrorKind::Preflight)?;
         if config.root().exists() {
             config.try_load_file()?;
         } else {
@@ -139,109 +908,171 @@ impl Kubernix {
         }
         config.canonicalize_root()?;
 
-        // Setup the logger
-        set_boxed_logger(Logger::new(config.log_level())).context("Unable to set logger")
+        // Setup the logger, whereas an already installed one will be reused
+        config.color().apply();
+        let logger = match config.log_sink() {
+            Some(sink) => Logger::with_sink(config.log_level(), sink.clone()),
+            None => Logger::new(config.log_level()),
+        };
+        if !logger.install() {
+            debug!("Using already installed logger");
+        }
+        debug!(
+            "Running on {} ({}) with kernel: {}",
+            platform.os(),
+            platform.arch(),
+            platform.kernel()
+        );
+
+        // Every spawned process inherits the proxy configuration
+        if config.http_proxy().is_some() || config.https_proxy().is_some() {
+            Network::new(config)?.export_proxy_env(config);
+        }
+        Ok(())
     }
 
     /// Stop kubernix by cleaning up all running processes
-    fn stop(&mut self) {
-        for x in &mut self.processes {
+    fn stop_processes(&mut self) -> Result<()> {
+        let mut failed = false;
+        for x in self.processes.get_mut() {
             if let Err(e) = x.stop() {
-                debug!("{}", e)
+                debug!("{}", e);
+                failed = true;
             }
         }
+        if failed {
+            bail!("Unable to stop all processes")
+        }
+        Ok(())
     }
 
     /// The amount of processes to be run
     fn processes(config: &Config) -> u64 {
-        5 + 2 * u64::from(config.nodes())
+        Graph::new(config).units().len() as u64
+    }
+
+    /// The weighted steps of the bootstrap progress
+    fn bootstrap_steps(config: &Config) -> Vec<Step> {
+        let image = if config.multi_node() {
+            2 * u64::from(config.nodes())
+        } else if config.control_plane_container() {
+            2
+        } else {
+            1
+        };
+        vec![
+            Step::new("preflight", 2),
+            Step::new("image", image),
+            Step::new("certificates", 4),
+            Step::new("configs", 2),
+            Step::new("processes", Self::processes(config)),
+            Step::new("addons", 3),
+        ]
     }
 
     /// Bootstrap the whole cluster, which assumes to be inside a nix shell
     fn bootstrap_cluster(config: Config) -> Result<()> {
+        // Interfaces of crashed runs would break the cluster networking
+        Network::remove_stale_interfaces(&config).context(ErrorKind::Preflight)?;
+        config
+            .node_runtime_overrides()
+            .context(ErrorKind::Preflight)?;
+        Proxy::preflight(&config).context(ErrorKind::Preflight)?;
+        Cni::preflight(&config).context(ErrorKind::Preflight)?;
+        ControlPlane::preflight(&config).context(ErrorKind::Preflight)?;
+        Entropy::preflight(&config).context(ErrorKind::Preflight)?;
+        config
+            .component_env_overrides()
+            .context(ErrorKind::Preflight)?;
+        config
+            .component_timeout_overrides()
+            .context(ErrorKind::Preflight)?;
+        config.validate_extra_args().context(ErrorKind::Preflight)?;
+        config.feature_gates_arg().context(ErrorKind::Preflight)?;
+
         // Setup the progress bar
-        const BASE_STEPS: u64 = 15;
-        let steps = if config.multi_node() {
-            u64::from(config.nodes()) * 2 + BASE_STEPS
-        } else {
-            BASE_STEPS
-        } + Self::processes(&config);
-        let p = Progress::new(steps, config.log_level());
+        let p = Progress::new(
+            &Self::bootstrap_steps(&config),
+            config.log_level(),
+            config.color().animated(),
+            Some(config.root()),
+        );
+        Self::step(&p, "preflight");
         info!("Bootstrapping cluster");
+        if config.ephemeral() {
+            System::mount_ephemeral_root(&config).context(ErrorKind::Preflight)?;
+        }
+        Status::init(&config)?;
+        Recorder::init(&config).context(ErrorKind::Preflight)?;
+        Events::init(&config).context(ErrorKind::Preflight)?;
+        if Self::running_pid(&config).is_none() {
+            Process::kill_orphans(&config).context(ErrorKind::Preflight)?;
+        }
+        Entropy::init(&config);
 
         // Ensure that the system is prepared
-        let system = System::setup(&config).context("Unable to setup system")?;
+        let system = System::setup(&config)
+            .context("Unable to setup system")
+            .context(ErrorKind::Preflight)?;
+        Self::step(&p, "image");
         Container::build(&config)?;
 
         // Setup the network
+        Self::step(&p, "certificates");
         let network = Network::new(&config)?;
 
         // Setup the public key infrastructure
         let pki = Pki::new(&config, &network)?;
 
         // Setup the configs
-        let kubeconfig = KubeConfig::new(&config, &pki)?;
+        Self::step(&p, "configs");
+        let (kubeconfig, cluster_info, encryptionconfig, registry_auth) =
+            Self::setup_configs(&config, &network, &pki)?;
+        Status::set_nodes(cluster_info.nodes().clone());
         let kubectl = Kubectl::new(kubeconfig.admin());
-        let encryptionconfig = EncryptionConfig::new(&config)?;
 
         // All processes
+        Self::step(&p, "processes");
         info!("Starting processes");
-        let mut api_server = Process::stopped();
-        let mut controller_manager = Process::stopped();
-        let mut etcd = Process::stopped();
-        let mut scheduler = Process::stopped();
-        let mut proxy = Process::stopped();
-        let mut crios = (0..config.nodes())
-            .map(|_| Process::stopped())
-            .collect::<Vec<_>>();
-        let mut kubelets = (0..config.nodes())
-            .map(|_| Process::stopped())
-            .collect::<Vec<_>>();
-
-        // Spawn the processes
-        scope(|a| {
-            // Control plane
-            a.spawn(|b| {
-                etcd = Etcd::start(&config, &network, &pki);
-                b.spawn(|c| {
-                    api_server =
-                        ApiServer::start(&config, &network, &pki, &encryptionconfig, &kubectl);
-                    c.spawn(|_| {
-                        controller_manager =
-                            ControllerManager::start(&config, &network, &pki, &kubeconfig)
-                    });
-                    c.spawn(|_| scheduler = Scheduler::start(&config, &kubeconfig));
-                });
-            });
-
-            // Node processes
-            a.spawn(|c| {
-                crios
-                    .par_iter_mut()
-                    .zip(kubelets.par_iter_mut())
-                    .enumerate()
-                    .for_each(|(i, (c, k))| {
-                        *c = Crio::start(&config, i as u8, &network);
-                        if c.is_ok() {
-                            *k = Kubelet::start(&config, i as u8, &network, &pki, &kubeconfig);
-                        }
-                    });
-                c.spawn(|_| proxy = Proxy::start(&config, &network, &kubeconfig));
-            });
+        let graph = Graph::new(&config);
+        let results = graph.start(|unit| match unit {
+            Unit::Etcd if config.single_binary() => Kine::start(&config),
+            Unit::Etcd => Etcd::start(&config, &network, &pki),
+            Unit::ApiServer => {
+                ApiServer::start(&config, &network, &pki, &encryptionconfig, &kubectl)
+            }
+            Unit::ControllerManager => {
+                ControllerManager::start(&config, &network, &pki, &kubeconfig)
+            }
+            Unit::Scheduler => Scheduler::start(&config, &pki, &kubeconfig),
+            Unit::SecondaryScheduler => match config.secondary_scheduler() {
+                Some(binary) => Scheduler::start_secondary(&config, &pki, &kubeconfig, binary),
+                None => Process::stopped(),
+            },
+            Unit::FakeNodes => FakeNodes::start(&config, &kubeconfig, &kubectl),
+            Unit::Crio(n) => Crio::start(&config, n, &network, &registry_auth),
+            Unit::Containerd(n) => Containerd::start(&config, n, &network, &registry_auth),
+            Unit::Kubelet(n) => Kubelet::start(&config, n, &network, &pki, &kubeconfig),
+            Unit::Proxy(_) => Proxy::start(&config, &network, &kubeconfig),
         });
-
-        // This order is important since we will shut down the processes in order
-        let mut results = vec![scheduler, proxy, controller_manager, api_server, etcd];
-        results.extend(kubelets);
-        results.extend(crios);
-        let all_ok = results.iter().all(|x| x.is_ok());
+        let all_ok = results.iter().all(|(_, x)| x.is_ok());
 
         // Note: wait for `drain_filter()` to be stable and make it more straightforward
         let mut processes = vec![];
-        for process in results {
+        let mut units = vec![];
+        let mut error_kind = ErrorKind::ProcessStart;
+        for (unit, process) in results {
             match process {
-                Ok(p) => processes.push(p),
-                Err(e) => debug!("{}", e),
+                Ok(p) => {
+                    processes.push(p);
+                    units.push(unit);
+                }
+                Err(e) => {
+                    debug!("{}: {}", unit, e);
+                    if ErrorKind::of(&e) == ErrorKind::ReadinessTimeout {
+                        error_kind = ErrorKind::ReadinessTimeout;
+                    }
+                }
             }
         }
 
@@ -251,52 +1082,322 @@ impl Kubernix {
             config,
             network,
             kubectl,
-            processes,
+            processes: Mutex::new(processes),
+            units,
+            graph,
+            registry_auth,
             system,
+            cluster_info,
+            cleaned_up: false,
         };
 
         // No dead processes
-        if all_ok {
-            // Apply all cluster addons
-            kubernix.apply_addons()?;
-            kubernix.write_env_file()?;
-            info!("Everything is up and running");
+        let result = if all_ok {
+            kubernix.run(p, spawn_shell)
+        } else {
             p.reset();
+            Err(error_kind.into())
+        };
+        if result.is_err() {
+            Status::set_phase(Phase::Failed);
+        }
 
-            if spawn_shell {
-                kubernix.spawn_shell()?;
-            } else {
-                kubernix.wait()?;
-            }
-        } else {
-            error!("Unable to start all processes")
+        // Cleanup failures are only relevant if everything else succeeded
+        let cleanup = kubernix.cleanup().context(ErrorKind::Cleanup);
+        result.and(cleanup)
+    }
+
+    /// Setup all configurations of the cluster, which reuses existing ones
+    fn setup_configs(
+        config: &Config,
+        network: &Network,
+        pki: &Pki,
+    ) -> Result<(KubeConfig, ClusterInfo, EncryptionConfig, RegistryAuth)> {
+        let kubeconfig = KubeConfig::new(config, pki)?;
+        let cluster_info = ClusterInfo::new(config, network, pki, &kubeconfig)?;
+        let encryptionconfig = EncryptionConfig::new(config)?;
+        let registry_auth = RegistryAuth::new(config)?;
+        Ok((kubeconfig, cluster_info, encryptionconfig, registry_auth))
+    }
+
+    /// Advance the progress to the provided step, which is also the phase of
+    /// the recorded commands
+    fn step(p: &Progress, name: &str) {
+        Recorder::set_phase(name);
+        Events::start_phase(name);
+        p.step(name)
+    }
+
+    /// Run the bootstrapped cluster until the shell exits or a termination
+    /// signal occurs
+    fn run(&self, p: Progress, spawn_shell: bool) -> Result<()> {
+        let serve = || {
+            let (done, stopped) = oneshot::channel();
+            let mut result = Ok(());
+            scope(|s| {
+                s.spawn(|_| self.handle_restarts(stopped));
+                result = if spawn_shell {
+                    self.spawn_shell()
+                } else {
+                    self.wait()
+                };
+                done.send(()).ok();
+            });
+            result
+        };
+
+        // Apply the addons without blocking the control plane
+        if self.config.background_addons() {
+            self.write_env_file()?;
+            info!("Control plane is up and running, applying addons in background");
+            Recorder::set_phase("addons");
+            Events::start_phase("addons");
+            Status::set_phase(Phase::Running);
+            p.finish();
+            self.print_summary();
+
+            let mut result = Ok(());
+            scope(|s| {
+                s.spawn(|_| match self.apply_addons() {
+                    Ok(()) => Events::finish_phase(),
+                    Err(e) => error!("Unable to apply cluster addons: {:#}", e),
+                });
+                result = serve();
+            });
+            return result;
         }
 
-        Ok(())
+        // Apply all cluster addons
+        Self::step(&p, "addons");
+        if let Err(e) = self.apply_addons() {
+            p.reset();
+            return Err(e);
+        }
+        self.write_env_file()?;
+        info!("Everything is up and running");
+        Events::finish_phase();
+        Status::set_phase(Phase::Running);
+        p.finish();
+        self.print_summary();
+        serve()
+    }
+
+    /// Print the cluster summary if the terminal output is not suppressed or
+    /// redirected to a log sink
+    fn print_summary(&self) {
+        if self.config.log_level() < LevelFilter::Info || self.config.log_sink().is_some() {
+            return;
+        }
+        if let Some(status) = Status::current() {
+            Summary::print(&status, self.kubectl.kubeconfig())
+        }
     }
 
     /// Apply needed workloads to the running cluster. This method stops the cluster on any error.
-    fn apply_addons(&mut self) -> Result<()> {
-        info!("Applying cluster addons");
-        CoreDns::apply(&self.config, &self.network, &self.kubectl)
+    fn apply_addons(&self) -> Result<()> {
+        Addons::new(
+            &self.config,
+            &self.network,
+            &self.kubectl,
+            &self.registry_auth,
+        )
+        .apply()?;
+
+        // The environment file has been already written without the token
+        if self.config.background_addons() && Addons::enabled(&self.config, Dashboard::NAME) {
+            self.write_env_file()?;
+        }
+        Ok(())
     }
 
     /// Wait until a termination signal occurs
     fn wait(&self) -> Result<()> {
-        // Setup the signal handlers
-        let term = Arc::new(AtomicBool::new(false));
-        flag::register(SIGTERM, Arc::clone(&term))?;
-        flag::register(SIGINT, Arc::clone(&term))?;
-        flag::register(SIGHUP, Arc::clone(&term))?;
-        info!("Waiting for interrupt…");
-
-        // Write the pid file
-        let pid_file = self.config.root().join("kubernix.pid");
-        debug!("Writing pid file to: {}", pid_file.display());
-        fs::write(pid_file, id().to_string())?;
-
-        // Wait for the signals
-        while !term.load(Ordering::Relaxed) {}
+        RUNTIME.block_on(async {
+            // Setup the signal handlers
+            let signals = Self::signals()?;
+            info!("Waiting for interrupt…");
+
+            // Write the pid file
+            let pid_file = Self::pid_file(&self.config);
+            debug!("Writing pid file to: {}", pid_file.display());
+            let start_time = Process::start_time(id()).unwrap_or_default();
+            fs::write(pid_file, format!("{} {}", id(), start_time))?;
+
+            // Wait for the signals, dump the status or reload the configuration on demand
+            let mut usr1 = signal(SignalKind::user_defined1())?;
+            let mut hup = signal(SignalKind::hangup())?;
+            let reloading = Arc::new(AtomicBool::new(false));
+            pin!(signals);
+            loop {
+                select! {
+                    _ = &mut signals => return Ok(()),
+                    _ = usr1.recv() => Status::dump(),
+                    _ = hup.recv() => {
+                        if let Err(e) = self.reload(&reloading) {
+                            error!("Unable to reload configuration: {:#}", e)
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Register the termination signal handlers and return a future which
+    /// resolves on the first received signal
+    fn signals() -> Result<impl Future<Output = ()>> {
+        let mut term = signal(SignalKind::terminate())?;
+        let mut int = signal(SignalKind::interrupt())?;
+        Ok(async move {
+            select! {
+                _ = term.recv() => debug!("Got SIGTERM"),
+                _ = int.recv() => debug!("Got SIGINT"),
+            }
+        })
+    }
+
+    /// Handle the restarts requested via signal and watch the configuration
+    /// files for changes until the cluster stops serving
+    fn handle_restarts(&self, mut stopped: oneshot::Receiver<()>) {
+        let mut config_files = ConfigFile::all(&self.config, &self.network, &self.units);
+        let mut requests = {
+            let _guard = RUNTIME.enter();
+            match signal(SignalKind::from_raw(Restart::SIGNAL as i32)) {
+                Ok(requests) => requests,
+                Err(e) => {
+                    error!("Unable to handle restarts: {}", e);
+                    return;
+                }
+            }
+        };
+        while let Some(requested) = RUNTIME.block_on(async {
+            select! {
+                _ = requests.recv() => Some(true),
+                _ = time::sleep(Self::WATCH_INTERVAL) => Some(false),
+                _ = &mut stopped => None,
+            }
+        }) {
+            if !requested {
+                Self::watch_config_files(&mut config_files);
+                continue;
+            }
+            let request = match Restart::load(&self.config) {
+                Ok(request) => request,
+                Err(e) => {
+                    error!("Unable to load restart request: {:#}", e);
+                    continue;
+                }
+            };
+            let result = if request.reload() {
+                self.reload_unit(request.unit())
+            } else {
+                self.restart_units(request.unit(), request.downtime())
+            };
+            if let Err(e) = &result {
+                error!("Unable to restart {}: {:#}", request.unit(), e)
+            }
+            if let Err(e) = request.finish(&self.config, &result) {
+                error!("{:#}", e)
+            }
+        }
+    }
+
+    /// Notify the user about changed configuration files, which get applied
+    /// on reload
+    fn watch_config_files(config_files: &mut [ConfigFile]) {
+        for file in config_files {
+            if file.changed() {
+                info!(
+                    "Configuration '{}' of {} changed, apply it via `kubernix reload`",
+                    file.path().display(),
+                    file.unit(),
+                );
+            }
+        }
+    }
+
+    /// Stop the unit and all of its dependents, wait for the downtime and
+    /// start them again from their run files, which keeps their identity
+    fn restart_units(&self, unit: Unit, downtime: u64) -> Result<()> {
+        if !self.units.contains(&unit) {
+            bail!("Component {} is not running", unit)
+        }
+        let units = self.graph.subtree(unit);
+        let indices = units
+            .iter()
+            .filter_map(|x| self.units.iter().position(|u| u == x))
+            .collect::<Vec<_>>();
+        let names = units
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut processes = self.processes.lock();
+
+        info!("Stopping {}", names);
+        for i in &indices {
+            processes[*i].stop()?;
+        }
+
+        if downtime > 0 {
+            info!("Waiting {}s before starting {} again", downtime, names);
+            sleep(Duration::from_secs(downtime));
+        }
+
+        info!("Starting {}", names);
+        for i in indices.iter().rev() {
+            processes[*i].restart()?;
+        }
+        info!("Restarted {}", names);
+        Ok(())
+    }
+
+    /// Validate the configuration file of the unit and restart it together
+    /// with its dependents to apply the changes
+    fn reload_unit(&self, unit: Unit) -> Result<()> {
+        let file = ConfigFile::new(&self.config, &self.network, unit)?;
+        file.validate()?;
+        info!("Configuration '{}' is valid", file.path().display());
+        self.restart_units(unit, 0)
+    }
+
+    /// Reload the configuration file and apply all changes which do not
+    /// require a restart of the cluster, whereas the manifests get applied in
+    /// a dedicated thread to keep handling signals
+    fn reload(&self, reloading: &Arc<AtomicBool>) -> Result<()> {
+        if reloading.load(Ordering::SeqCst) {
+            warn!("Configuration reload already in progress, skipping");
+            return Ok(());
+        }
+        info!("Reloading configuration");
+        let config = self.config.reload()?;
+        for field in self.config.changed_fields(&config)? {
+            match field.as_str() {
+                "log-level" => {
+                    info!("Changing log level to {}", config.log_level());
+                    Logger::set_level(config.log_level());
+                }
+                "manifests" | "apply-manifests" | "wait-for" | "pull-secret" => {}
+                _ => warn!("Changing '{}' requires a restart of the cluster", field),
+            }
+        }
+
+        // Applying the addons is idempotent
+        let network = self.network.clone();
+        let kubectl = self.kubectl.clone();
+        let registry_auth = self.registry_auth.clone();
+        let done = reloading.clone();
+        reloading.store(true, Ordering::SeqCst);
+        spawn(move || {
+            let addons = Addons::new(&config, &network, &kubectl, &registry_auth);
+            match addons
+                .apply_registry_auth()
+                .and_then(|_| addons.apply_manifests())
+            {
+                Ok(()) => info!("Configuration reloaded"),
+                Err(e) => error!("Unable to reload configuration: {:#}", e),
+            }
+            done.store(false, Ordering::SeqCst);
+        });
         Ok(())
     }
 
@@ -317,20 +1418,35 @@ impl Kubernix {
         Ok(())
     }
 
-    /// Lay out the env file
+    /// Lay out the env file and the cluster info
     fn write_env_file(&self) -> Result<()> {
         info!("Writing environment file");
-        fs::write(
-            Self::env_file(&self.config),
-            format!(
-                "export {}={}\nexport {}={}",
-                RUNTIME_ENV,
-                Crio::socket(&self.config, &self.network, 0)?.to_socket_string(),
-                "KUBECONFIG",
-                self.kubectl.kubeconfig().display(),
-            ),
-        )?;
-        Ok(())
+        self.cluster_info.write(&self.config)?;
+        let mut env = format!(
+            "export {}={}\nexport {}={}",
+            RUNTIME_ENV,
+            CriSocket::of(&self.config, &self.network, 0)?.to_socket_string(),
+            "KUBECONFIG",
+            self.kubectl.kubeconfig().display(),
+        );
+        env.push('\n');
+        env.push_str(self.cluster_info.env().trim_end());
+        if Addons::enabled(&self.config, Dashboard::NAME) {
+            if let Some(dashboard) = Dashboard::env(&self.config)? {
+                env.push('\n');
+                env.push_str(&dashboard);
+            }
+        }
+        if Addons::enabled(&self.config, Logging::NAME) {
+            env.push('\n');
+            env.push_str(&Logging::env(&self.config));
+        }
+        env.push_str(&format!(
+            "\nexport KUBERNIX_AUDIT_LOG={}",
+            ApiServer::audit_log(&self.config).display()
+        ));
+        // The file contains the dashboard admin token
+        System::write_private(&Self::env_file(&self.config), &env)
     }
 
     /// Retrieve the path to the env file
@@ -338,8 +1454,53 @@ impl Kubernix {
         config.root().join("kubernix.env")
     }
 
+    /// Cleanup the whole cluster, which happens only once
+    fn cleanup(&mut self) -> Result<()> {
+        if self.cleaned_up {
+            return Ok(());
+        }
+        self.cleaned_up = true;
+        Recorder::set_phase(Recorder::CLEANUP_PHASE);
+        let p = Progress::new(
+            &[
+                Step::new("processes", Self::processes(&self.config)),
+                Step::new("mounts", 1),
+                Step::new("system", 1),
+                Step::new("network", 1),
+            ],
+            self.config.log_level(),
+            self.config.color().animated(),
+            None,
+        );
+
+        p.step("processes");
+        info!("Cleaning up");
+        let failed = Status::current_phase() == Some(Phase::Failed);
+        Status::set_phase(Phase::Stopping);
+        let result = self.stop_processes();
+        p.step("mounts");
+        Self::umount(&self.config);
+        p.step("system");
+        self.system.cleanup();
+        p.step("network");
+        if let Err(e) = self.network.cleanup(&self.config) {
+            warn!("{:#}", e)
+        }
+        Status::set_phase(if failed || result.is_err() {
+            Phase::Failed
+        } else {
+            Phase::Stopped
+        });
+        Events::close();
+        info!("Cleanup done");
+
+        p.reset();
+        debug!("All done");
+        result
+    }
+
     /// Remove all stale mounts
-    fn umount(&self) {
+    fn umount(config: &Config) {
         debug!("Removing active mounts");
         let now = Instant::now();
         while now.elapsed().as_secs() < 5 {
@@ -350,10 +1511,13 @@ impl Kubernix {
                 }
                 Ok(mounts) => {
                     let mut found_mount = false;
+                    let socket_dir = config.socket_dir();
                     mounts
                         .filter_map(|x| x.ok())
-                        .filter(|x| x.dest.starts_with(self.config.root()))
-                        .filter(|x| !x.dest.eq(self.config.root()))
+                        .filter(|x| {
+                            x.dest.starts_with(config.root()) || x.dest.starts_with(&socket_dir)
+                        })
+                        .filter(|x| !x.dest.eq(config.root()))
                         .for_each(|m| {
                             found_mount = true;
                             debug!("Removing mount: {}", m.dest.display());
@@ -367,20 +1531,109 @@ impl Kubernix {
                 }
             };
         }
+
+        // The run root itself is only a mount point for ephemeral clusters
+        if config.ephemeral() {
+            debug!("Removing ephemeral run root");
+            if let Err(e) = umount2(config.root(), MntFlags::MNT_DETACH) {
+                warn!(
+                    "Unable to umount ephemeral run root '{}': {}",
+                    config.root().display(),
+                    e
+                );
+            }
+        }
     }
 }
 
 impl Drop for Kubernix {
     fn drop(&mut self) {
-        let p = Progress::new(Self::processes(&self.config), self.config.log_level());
+        if let Err(e) = self.cleanup() {
+            error!("{:#}", e)
+        }
+    }
+}
 
-        info!("Cleaning up");
-        self.stop();
-        self.umount();
-        self.system.cleanup();
-        info!("Cleanup done");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_config;
+    use ::nix::time::{clock_gettime, ClockId};
+    use tokio::time::timeout;
 
-        p.reset();
-        debug!("All done");
+    #[test]
+    fn signals_idle_cpu_usage() -> Result<()> {
+        let start = clock_gettime(ClockId::CLOCK_THREAD_CPUTIME_ID)?;
+        let signals = RUNTIME.block_on(async { Kubernix::signals() })?;
+        assert!(RUNTIME
+            .block_on(async { timeout(Duration::from_secs(1), signals).await })
+            .is_err());
+        let used = Duration::from(clock_gettime(ClockId::CLOCK_THREAD_CPUTIME_ID)? - start);
+        assert!(
+            used < Duration::from_millis(100),
+            "CPU time used: {:?}",
+            used
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn running_pid_success() -> Result<()> {
+        let c = test_config()?;
+        assert!(Kubernix::running_pid(&c).is_none());
+
+        // The test binary is named after the crate
+        fs::write(Kubernix::pid_file(&c), format!("{}\n", id()))?;
+        assert_eq!(Kubernix::instance_pid(&c), Some(Pid::this()));
+
+        // No status file means the instance is not running
+        assert!(Kubernix::running_pid(&c).is_none());
+
+        fs::write(Kubernix::pid_file(&c), i32::MAX.to_string())?;
+        assert!(Kubernix::instance_pid(&c).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn instance_pid_success_start_time() -> Result<()> {
+        let c = test_config()?;
+        let start_time = Process::start_time(id()).context("no start time")?;
+        fs::write(Kubernix::pid_file(&c), format!("{} {}", id(), start_time))?;
+        assert_eq!(Kubernix::instance_pid(&c), Some(Pid::this()));
+
+        fs::write(
+            Kubernix::pid_file(&c),
+            format!("{} {}", id(), start_time + 1),
+        )?;
+        assert!(Kubernix::instance_pid(&c).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_pid_file_success() {
+        assert_eq!(Kubernix::parse_pid_file("1 2\n"), Some((1, Some(2))));
+        assert_eq!(Kubernix::parse_pid_file("1\n"), Some((1, None)));
+        assert_eq!(Kubernix::parse_pid_file("wrong"), None);
+    }
+
+    #[test]
+    fn render_env_success() -> Result<()> {
+        let content = "export CONTAINER_RUNTIME_ENDPOINT=unix:///run/crio.sock\nexport KUBECONFIG=/it's/admin.kubeconfig";
+        assert_eq!(
+            Kubernix::render_env(content, EnvShell::Bash)?,
+            "export CONTAINER_RUNTIME_ENDPOINT='unix:///run/crio.sock'\n\
+             export KUBECONFIG='/it'\\''s/admin.kubeconfig'\n"
+        );
+        assert_eq!(
+            Kubernix::render_env(content, EnvShell::Fish)?,
+            "set -gx CONTAINER_RUNTIME_ENDPOINT 'unix:///run/crio.sock';\n\
+             set -gx KUBECONFIG '/it\\'s/admin.kubeconfig';\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn render_env_failure() {
+        assert!(Kubernix::render_env("invalid", EnvShell::Zsh).is_err());
     }
 }
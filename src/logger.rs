@@ -1,24 +1,68 @@
-use crate::progress::Progress;
+use crate::{progress::Progress, warnings::Warnings};
 use console::{style, Color};
-use log::{set_max_level, Level, LevelFilter, Log, Metadata, Record};
-use std::io::{stderr, Write};
+use log::{set_boxed_logger, set_max_level, Level, LevelFilter, Log, Metadata, Record};
+use std::{
+    io::{stderr, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// A callback which receives all log messages instead of the terminal
+pub type LogSink = Arc<dyn Fn(Level, &str) + Send + Sync>;
 
 /// The main logging faccade
 pub struct Logger {
     level: LevelFilter,
+    sink: Option<LogSink>,
 }
 
 impl Logger {
     /// Create a new logger
     pub fn new(level: LevelFilter) -> Box<Self> {
-        set_max_level(LevelFilter::Trace);
-        Self { level }.into()
+        Self { level, sink: None }.into()
+    }
+
+    /// Create a new logger which forwards all messages to the provided sink
+    pub fn with_sink(level: LevelFilter, sink: LogSink) -> Box<Self> {
+        Self {
+            level,
+            sink: Some(sink),
+        }
+        .into()
+    }
+
+    /// Install the logger globally. Returns false if another logger is
+    /// already installed, for example by an application embedding kubernix.
+    pub fn install(mut self: Box<Self>) -> bool {
+        // The global max level filters the messages, which allows changing
+        // the level at runtime
+        let level = self.level;
+        self.level = LevelFilter::Trace;
+        if set_boxed_logger(self).is_err() {
+            return false;
+        }
+        set_max_level(level);
+        INSTALLED.store(true, Ordering::SeqCst);
+        true
+    }
+
+    /// Change the level of the installed logger, which does nothing if another
+    /// logger has been installed
+    pub fn set_level(level: LevelFilter) {
+        if INSTALLED.load(Ordering::SeqCst) {
+            set_max_level(level);
+        }
     }
 
     /// Log an error message
     pub fn error(msg: &str) {
         Self {
             level: LevelFilter::Error,
+            sink: None,
         }
         .log(
             &Record::builder()
@@ -39,7 +83,25 @@ impl Log for Logger {
             return;
         }
 
+        // Repeated warnings are printed at most once per window together with
+        // the number of suppressed occurrences and summarized at the end
         let level = record.metadata().level();
+        let mut message = record.args().to_string();
+        if level == Level::Warn {
+            match Warnings::record(&message) {
+                None => return,
+                Some(0) => {}
+                Some(suppressed) => {
+                    message = format!("{} (suppressed {} times)", message, suppressed)
+                }
+            }
+        }
+
+        if let Some(sink) = &self.sink {
+            sink(level, &message);
+            return;
+        }
+
         let (level_name, level_color) = match level {
             Level::Error => ("ERROR", Color::Red),
             Level::Warn => ("WARN ", Color::Yellow),
@@ -49,18 +111,17 @@ impl Log for Logger {
         };
         let msg = format!(
             "{}{}{} {}",
-            style("[").white().dim(),
-            style(level_name).fg(level_color),
-            style("]").white().dim(),
-            style(record.args()),
+            style("[").white().dim().for_stderr(),
+            style(level_name).fg(level_color).for_stderr(),
+            style("]").white().dim().for_stderr(),
+            message,
         );
 
         if let Some(pb) = Progress::get() {
             if level != Level::Info {
-                pb.println(msg);
+                pb.println(&msg);
             } else {
-                pb.inc(1);
-                pb.set_message(&record.args().to_string());
+                pb.set_message(&message);
             }
         } else {
             writeln!(stderr(), "{}", msg).ok();
@@ -74,6 +135,7 @@ impl Log for Logger {
 pub mod tests {
     use super::*;
     use log::{MetadataBuilder, Record};
+    use parking_lot::Mutex;
 
     #[test]
     fn logger_success() {
@@ -89,4 +151,42 @@ pub mod tests {
         assert!(!l.enabled(&dbg_metadata));
         l.flush();
     }
+
+    #[test]
+    fn logger_success_sink() {
+        let messages = Arc::new(Mutex::new(vec![]));
+        let m = messages.clone();
+        let l = Logger::with_sink(
+            LevelFilter::Info,
+            Arc::new(move |level, msg| m.lock().push(format!("{} {}", level, msg))),
+        );
+        for level in &[Level::Info, Level::Debug] {
+            l.log(
+                &Record::builder()
+                    .args(format_args!("Message"))
+                    .level(*level)
+                    .build(),
+            );
+        }
+        assert_eq!(*messages.lock(), vec!["INFO Message"]);
+    }
+
+    #[test]
+    fn logger_success_deduplicate_warnings() {
+        let messages = Arc::new(Mutex::new(vec![]));
+        let m = messages.clone();
+        let l = Logger::with_sink(
+            LevelFilter::Info,
+            Arc::new(move |level, msg| m.lock().push(format!("{} {}", level, msg))),
+        );
+        for _ in 0..3 {
+            l.log(
+                &Record::builder()
+                    .args(format_args!("Repeated warning"))
+                    .level(Level::Warn)
+                    .build(),
+            );
+        }
+        assert_eq!(*messages.lock(), vec!["WARN Repeated warning"]);
+    }
 }
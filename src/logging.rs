@@ -0,0 +1,282 @@
+use crate::{addon::Addon, config::Config, crio::POD_LOG_DIR, kubectl::Kubectl, network::Network};
+use anyhow::{Context, Result};
+use log::info;
+use serde_json::{json, Value};
+use std::{
+    fs::{self, create_dir_all},
+    net::Ipv4Addr,
+};
+
+/// The logging addon, which deploys Loki on the host together with promtail
+/// collecting the pod logs and the log files of the cluster components
+pub struct Logging;
+
+impl Logging {
+    /// The name of the addon
+    pub const NAME: &'static str = "logging";
+
+    /// The nix package providing `logcli` to query Loki from the shell
+    pub const PACKAGE: &'static str = "grafana-loki";
+
+    const LOKI_IMAGE: &'static str = "docker.io/grafana/loki:2.9.1";
+    const PROMTAIL_IMAGE: &'static str = "docker.io/grafana/promtail:2.9.1";
+
+    /// The distance of the Loki host port below the API server port, which
+    /// results in the default port 3100
+    pub const LOKI_PORT_DISTANCE: u16 = 3343;
+
+    /// The distance of the Loki gRPC port to the API server port, which
+    /// results in the default port 9096
+    const LOKI_GRPC_PORT_OFFSET: u16 = 2653;
+
+    /// The distance of the promtail status port to the API server port, which
+    /// results in the default port 9080
+    const PROMTAIL_PORT_OFFSET: u16 = 2637;
+
+    /// The host port of Loki, which listens on the loopback interface
+    fn loki_port(config: &Config) -> u16 {
+        config.api_port() - Self::LOKI_PORT_DISTANCE
+    }
+
+    /// The URL of Loki on the host
+    pub fn url(config: &Config) -> String {
+        format!("http://{}:{}", Ipv4Addr::LOCALHOST, Self::loki_port(config))
+    }
+
+    /// The environment variable pointing `logcli` to Loki
+    pub fn env(config: &Config) -> String {
+        format!("export LOKI_ADDR={}", Self::url(config))
+    }
+
+    /// Create the Loki configuration, which keeps everything in memory and
+    /// on the local filesystem of the pod
+    fn loki_config(config: &Config) -> Value {
+        json!({
+            "auth_enabled": false,
+            "server": {
+                "http_listen_address": Ipv4Addr::LOCALHOST,
+                "http_listen_port": Self::loki_port(config),
+                "grpc_listen_address": Ipv4Addr::LOCALHOST,
+                "grpc_listen_port": config.api_port() + Self::LOKI_GRPC_PORT_OFFSET
+            },
+            "common": {
+                "instance_addr": Ipv4Addr::LOCALHOST,
+                "path_prefix": "/loki",
+                "replication_factor": 1,
+                "ring": { "kvstore": { "store": "inmemory" } },
+                "storage": {
+                    "filesystem": {
+                        "chunks_directory": "/loki/chunks",
+                        "rules_directory": "/loki/rules"
+                    }
+                }
+            },
+            "schema_config": {
+                "configs": [{
+                    "from": "2020-10-24",
+                    "store": "tsdb",
+                    "object_store": "filesystem",
+                    "schema": "v12",
+                    "index": { "prefix": "index_", "period": "24h" }
+                }]
+            },
+            "analytics": { "reporting_enabled": false }
+        })
+    }
+
+    /// Create the promtail configuration, which tails the CRI pod logs of
+    /// all nodes as well as the component logs within the run root
+    fn promtail_config(config: &Config) -> Value {
+        let root = config.root().display().to_string();
+        let pods = if config.multi_node() {
+            format!("{}/*/*/pods/*/*/*.log", root)
+        } else {
+            format!("{}/*/*/*.log", POD_LOG_DIR)
+        };
+        json!({
+            "server": {
+                "http_listen_address": Ipv4Addr::LOCALHOST,
+                "http_listen_port": config.api_port() + Self::PROMTAIL_PORT_OFFSET,
+                "grpc_listen_port": 0
+            },
+            "positions": { "filename": "/run/promtail/positions.yaml" },
+            "clients": [{ "url": format!("{}/loki/api/v1/push", Self::url(config)) }],
+            "scrape_configs": [
+                {
+                    "job_name": "pods",
+                    "static_configs": [{
+                        "targets": ["localhost"],
+                        "labels": { "job": "pods", "__path__": pods }
+                    }],
+                    "pipeline_stages": [
+                        { "cri": {} },
+                        {
+                            "regex": {
+                                "source": "filename",
+                                "expression": "/pods/(?P<namespace>[^_/]+)_(?P<pod>[^_/]+)_[^/]+/(?P<container>[^/]+)/[^/]+$"
+                            }
+                        },
+                        { "labels": { "namespace": null, "pod": null, "container": null } }
+                    ]
+                },
+                {
+                    "job_name": "components",
+                    "static_configs": [{
+                        "targets": ["localhost"],
+                        "labels": {
+                            "job": "components",
+                            "__path__": format!("{}/{{*,*/*}}/*.log", root)
+                        }
+                    }],
+                    "pipeline_stages": [
+                        {
+                            "regex": {
+                                "source": "filename",
+                                "expression": format!(
+                                    "^{}/(?P<component>[^/]+)/(?:(?P<node>[^/]+)/)?[^/]+\\.log$",
+                                    Self::escape(&root)
+                                )
+                            }
+                        },
+                        { "labels": { "component": null, "node": null } }
+                    ]
+                }
+            ]
+        })
+    }
+
+    /// The Loki datasource of Grafana, which gets provisioned by the
+    /// observability addon
+    pub fn datasource(config: &Config) -> Value {
+        json!({
+            "name": "Loki",
+            "type": "loki",
+            "access": "proxy",
+            "url": Self::url(config)
+        })
+    }
+
+    /// Render the manifest of the addon
+    fn render(config: &Config) -> Result<String> {
+        let quote =
+            |value: &Value| -> Result<String> { Ok(serde_json::to_string(&value.to_string())?) };
+        Ok(format!(
+            include_str!("assets/logging.yml"),
+            loki_config = quote(&Self::loki_config(config))?,
+            loki_image = config.image("loki", Self::LOKI_IMAGE)?,
+            promtail_config = quote(&Self::promtail_config(config))?,
+            promtail_image = config.image("promtail", Self::PROMTAIL_IMAGE)?,
+            root = config.root().display(),
+            pod_log_dir = POD_LOG_DIR,
+        ))
+    }
+
+    /// Escape the provided literal to be used within a regular expression
+    fn escape(literal: &str) -> String {
+        literal.chars().fold(String::new(), |mut out, c| {
+            if r"\.+*?()|[]{}^$".contains(c) {
+                out.push('\\');
+            }
+            out.push(c);
+            out
+        })
+    }
+}
+
+impl Addon for Logging {
+    fn name(&self) -> String {
+        Logging::NAME.into()
+    }
+
+    fn apply(&self, config: &Config, _: &Network, kubectl: &Kubectl) -> Result<()> {
+        info!("Deploying Loki and promtail and waiting to be ready");
+
+        let dir = config.root().join(Logging::NAME);
+        create_dir_all(&dir)?;
+
+        let file = dir.join("logging.yml");
+        if !file.exists() {
+            fs::write(&file, Logging::render(config)?)?;
+        }
+
+        kubectl
+            .apply(&file)
+            .context("Unable to deploy Loki and promtail")?;
+        kubectl.wait_ready("loki")?;
+        kubectl.wait_ready("promtail")?;
+        info!(
+            "Loki and promtail deployed, the logs are queryable on {}",
+            Logging::url(config)
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{test_config, test_config_with};
+
+    #[test]
+    fn url_success() -> Result<()> {
+        assert_eq!(Logging::url(&test_config()?), "http://127.0.0.1:3100");
+        let c = test_config_with(|c| {
+            c.set_api_port(7443)
+                .set_etcd_port(2379)
+                .set_kubelet_port(11250);
+        })?;
+        assert_eq!(Logging::url(&c), "http://127.0.0.1:4100");
+        Ok(())
+    }
+
+    #[test]
+    fn promtail_config_success() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_nodes(2);
+        })?;
+        let value = Logging::promtail_config(&c);
+        let root = c.root().display().to_string();
+        assert_eq!(
+            value["scrape_configs"][0]["static_configs"][0]["labels"]["__path__"],
+            format!("{}/*/*/pods/*/*/*.log", root)
+        );
+        assert_eq!(
+            value["scrape_configs"][1]["static_configs"][0]["labels"]["__path__"],
+            format!("{}/{{*,*/*}}/*.log", root)
+        );
+
+        let c = test_config()?;
+        let value = Logging::promtail_config(&c);
+        assert_eq!(
+            value["scrape_configs"][0]["static_configs"][0]["labels"]["__path__"],
+            "/var/log/pods/*/*/*.log"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn escape_success() {
+        assert_eq!(
+            Logging::escape("/tmp/kubernix.run-[a]"),
+            r"/tmp/kubernix\.run-\[a\]"
+        );
+    }
+
+    #[test]
+    fn render_success() -> Result<()> {
+        let c = test_config()?;
+        let yml = Logging::render(&c)?;
+        let docs = yml
+            .split("\n---\n")
+            .map(serde_yaml::from_str::<serde_yaml::Value>)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(docs.len(), 4);
+        let loki: serde_yaml::Value = serde_yaml::from_str(
+            docs[0]["data"]["loki.yml"]
+                .as_str()
+                .context("no loki config")?,
+        )?;
+        assert_eq!(loki["server"]["http_listen_port"], 3100);
+        Ok(())
+    }
+}
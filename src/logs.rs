@@ -0,0 +1,284 @@
+use crate::{
+    graph::Unit,
+    process::{FileWatch, RUNTIME},
+    status::Status,
+    Config,
+};
+use anyhow::{bail, Context, Result};
+use std::{
+    convert::TryFrom,
+    fs::{self, File},
+    io::{stdout, BufRead, BufReader, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    str,
+    time::Duration,
+};
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+use tokio::{
+    fs::File as AsyncFile,
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader as AsyncBufReader},
+};
+
+/// The log files of the cluster components
+pub struct Logs;
+
+impl Logs {
+    /// Retrieve the log file of the provided unit from the cluster status
+    pub fn file(config: &Config, status: &Status, unit: Unit) -> Result<PathBuf> {
+        let identifier = unit.identifier(config);
+        let component = status.components().get(&identifier).with_context(|| {
+            format!(
+                "No log file found for component '{}', is it part of the cluster?",
+                unit
+            )
+        })?;
+        Ok(component.log_file().clone())
+    }
+
+    /// Print the provided log file, whereas only lines not older than
+    /// `since` are considered. If `follow` is set, then new lines are printed
+    /// until the command gets interrupted.
+    pub fn print(path: &Path, since: Option<Duration>, follow: bool) -> Result<()> {
+        let cutoff = since.map(|x| OffsetDateTime::now_utc().unix_timestamp() - x.as_secs() as i64);
+        let file = File::open(path)
+            .with_context(|| format!("Unable to open log file '{}'", path.display()))?;
+        let position = Self::write(
+            BufReader::new(file),
+            cutoff,
+            Self::local_offset(),
+            &mut stdout(),
+        )?;
+        if follow {
+            RUNTIME.block_on(async { Self::follow(path, position).await })?;
+        }
+        Ok(())
+    }
+
+    /// Write all lines of the reader which are not older than the cutoff
+    /// timestamp, whereas lines without any timestamp belong to the previous
+    /// one. Returns the number of consumed bytes.
+    fn write(
+        mut reader: impl BufRead,
+        cutoff: Option<i64>,
+        offset: UtcOffset,
+        out: &mut impl Write,
+    ) -> Result<u64> {
+        let year = OffsetDateTime::now_utc().to_offset(offset).year();
+        let mut position = 0;
+        let mut included = cutoff.is_none();
+        let mut line = vec![];
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            position += line.len() as u64;
+            if let Some(cutoff) = cutoff {
+                if let Some(timestamp) =
+                    Self::timestamp(&String::from_utf8_lossy(&line), year, offset)
+                {
+                    included = timestamp >= cutoff;
+                }
+            }
+            if included {
+                out.write_all(&line)?;
+            }
+            line.clear();
+        }
+        out.flush()?;
+        Ok(position)
+    }
+
+    /// Print all lines appended to the log file after the provided position
+    async fn follow(path: &Path, mut position: u64) -> Result<()> {
+        let watch = FileWatch::new(path)?;
+        let mut file = AsyncFile::open(path).await?;
+        file.seek(SeekFrom::Start(position)).await?;
+        let mut reader = AsyncBufReader::new(file);
+
+        let mut line = vec![];
+        loop {
+            let read = reader.read_until(b'\n', &mut line).await?;
+            if read == 0 || !line.ends_with(b"\n") {
+                // The log file gets truncated if the component restarts
+                if fs::metadata(path)?.len() < position + line.len() as u64 {
+                    reader.seek(SeekFrom::Start(0)).await?;
+                    position = 0;
+                    line.clear();
+                }
+                watch.modified().await?;
+                continue;
+            }
+            position += line.len() as u64;
+            let mut out = stdout();
+            out.write_all(&line)?;
+            out.flush()?;
+            line.clear();
+        }
+    }
+
+    /// Parse a duration like `30s`, `10m` or `2h`
+    pub fn parse_since(value: &str) -> Result<Duration> {
+        let (number, factor) = match value.chars().last() {
+            Some('s') => (&value[..value.len() - 1], 1),
+            Some('m') => (&value[..value.len() - 1], 60),
+            Some('h') => (&value[..value.len() - 1], 60 * 60),
+            _ => bail!(
+                "Invalid duration '{}', expected a number followed by s, m or h",
+                value
+            ),
+        };
+        let number: u64 = number
+            .parse()
+            .with_context(|| format!("Invalid duration '{}'", value))?;
+        Ok(Duration::from_secs(number * factor))
+    }
+
+    /// Retrieve the UNIX timestamp of a log line, which supports the klog
+    /// header of the Kubernetes components as well as the RFC 3339 time
+    /// fields of etcd, CRI-O and containerd. Timestamps without year or
+    /// offset are considered to be in the provided ones.
+    fn timestamp(line: &str, year: i32, local: UtcOffset) -> Option<i64> {
+        // klog header, like `I0102 15:04:05.000000`
+        if line.len() > 14
+            && line.starts_with(&['I', 'W', 'E', 'F'][..])
+            && line.get(5..6) == Some(" ")
+        {
+            let date = Self::date(year, line.get(1..3)?, line.get(3..5)?)?;
+            return Self::unix_timestamp(date, line.get(6..14)?, local);
+        }
+
+        // Time fields, like `"ts":"2006-01-02T15:04:05.000Z"`
+        let start = ["\"ts\":\"", "time=\""]
+            .iter()
+            .find_map(|x| line.find(x).map(|i| i + x.len()))?;
+        let value = &line[start..];
+        let date = Self::date(
+            value.get(0..4)?.parse().ok()?,
+            value.get(5..7)?,
+            value.get(8..10)?,
+        )?;
+        let zone = value
+            .get(19..)?
+            .trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+        let offset = Self::offset(zone).unwrap_or(local);
+        Self::unix_timestamp(date, value.get(11..19)?, offset)
+    }
+
+    /// Parse an UTC offset like `Z`, `+01:00` or `-0500`
+    fn offset(zone: &str) -> Option<UtcOffset> {
+        let sign = match zone.chars().next()? {
+            'Z' => return Some(UtcOffset::UTC),
+            '+' => 1,
+            '-' => -1,
+            _ => return None,
+        };
+        let digits = zone[1..].replace(':', "");
+        let hours: i8 = digits.get(0..2)?.parse().ok()?;
+        let minutes: i8 = digits.get(2..4)?.parse().ok()?;
+        UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+    }
+
+    fn date(year: i32, month: &str, day: &str) -> Option<Date> {
+        let month = Month::try_from(month.parse::<u8>().ok()?).ok()?;
+        Date::from_calendar_date(year, month, day.parse().ok()?).ok()
+    }
+
+    /// Convert the date and a time like `15:04:05` into a UNIX timestamp
+    fn unix_timestamp(date: Date, time: &str, offset: UtcOffset) -> Option<i64> {
+        let time = Time::from_hms(
+            time.get(0..2)?.parse().ok()?,
+            time.get(3..5)?.parse().ok()?,
+            time.get(6..8)?.parse().ok()?,
+        )
+        .ok()?;
+        Some(
+            PrimitiveDateTime::new(date, time)
+                .assume_offset(offset)
+                .unix_timestamp(),
+        )
+    }
+
+    /// Retrieve the local UTC offset, which is used by klog, or fall back to
+    /// UTC if it cannot be determined
+    fn local_offset() -> UtcOffset {
+        Command::new("date")
+            .arg("+%z")
+            .output()
+            .ok()
+            .and_then(|x| Self::offset(str::from_utf8(&x.stdout).ok()?.trim()))
+            .unwrap_or(UtcOffset::UTC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_since_success() -> Result<()> {
+        assert_eq!(Logs::parse_since("30s")?, Duration::from_secs(30));
+        assert_eq!(Logs::parse_since("10m")?, Duration::from_secs(600));
+        assert_eq!(Logs::parse_since("2h")?, Duration::from_secs(7200));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_since_failure() {
+        assert!(Logs::parse_since("").is_err());
+        assert!(Logs::parse_since("10").is_err());
+        assert!(Logs::parse_since("xm").is_err());
+    }
+
+    #[test]
+    fn timestamp_success() -> Result<()> {
+        let utc = UtcOffset::UTC;
+        assert_eq!(
+            Logs::timestamp("I0102 15:04:05.000000    1234 server.go:1] msg", 2006, utc),
+            Some(1136214245)
+        );
+        assert_eq!(
+            Logs::timestamp(
+                r#"{"level":"info","ts":"2006-01-02T16:04:05.000+0100","msg":"x"}"#,
+                1970,
+                utc
+            ),
+            Some(1136214245)
+        );
+        assert_eq!(
+            Logs::timestamp(
+                r#"time="2006-01-02 15:04:05.999999999Z" level=info msg="x""#,
+                1970,
+                utc
+            ),
+            Some(1136214245)
+        );
+        assert_eq!(
+            Logs::timestamp(r#"time="2006-01-02T10:04:05-05:00" level=info"#, 1970, utc),
+            Some(1136214245)
+        );
+        assert_eq!(Logs::timestamp("goroutine 1 [running]:", 2006, utc), None);
+        Ok(())
+    }
+
+    #[test]
+    fn write_success() -> Result<()> {
+        let content = "I0102 15:04:05.000000 old\n\
+                       continued\n\
+                       I0102 15:04:10.000000 new\n\
+                       continued\n";
+        let utc = UtcOffset::UTC;
+
+        let mut out = vec![];
+        let position = Logs::write(content.as_bytes(), None, utc, &mut out)?;
+        assert_eq!(position, content.len() as u64);
+        assert_eq!(out, content.as_bytes());
+
+        let year = OffsetDateTime::now_utc().year();
+        let cutoff = Logs::timestamp("I0102 15:04:10.000000", year, utc);
+        let mut out = vec![];
+        Logs::write(content.as_bytes(), cutoff, utc, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "I0102 15:04:10.000000 new\ncontinued\n"
+        );
+        Ok(())
+    }
+}
@@ -1,16 +1,23 @@
 use anyhow::Result;
-use kubernix::{Config, Kubernix, Logger};
+use kubernix::{
+    Config, ErrorKind, Images, ImagesSubCommand, Kubernix, Logger, NodeCommand, NodeSubCommand,
+    PhaseCommand, PhaseSubCommand, Reported, SubCommand, TokenCommand, TokenSubCommand,
+};
 use std::process::exit;
 
 pub fn main() {
     if let Err(e) = run() {
-        Logger::error(
-            &e.chain()
-                .map(|x| x.to_string())
-                .collect::<Vec<_>>()
-                .join(": "),
-        );
-        exit(1);
+        let kind = ErrorKind::of(&e);
+        if e.downcast_ref::<Reported>().is_none() {
+            Logger::error(
+                &e.chain()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+                    .join(": "),
+            );
+            eprintln!("{}", ErrorKind::json_line(&e));
+        }
+        exit(kind.exit_code());
     }
 }
 
@@ -18,11 +25,161 @@ fn run() -> Result<()> {
     // Parse CLI arguments
     let config = Config::default();
 
-    if config.subcommand().is_some() {
+    match config.subcommand() {
         // Spawn only a new shell
-        Kubernix::new_shell(config)
-    } else {
+        Some(SubCommand::Shell) => Kubernix::new_shell(config),
+
+        // Prune the images on every node
+        Some(SubCommand::Images(Images {
+            subcommand: ImagesSubCommand::Prune,
+        })) => Kubernix::prune_images(config),
+
+        // Restart a node of the running cluster
+        Some(SubCommand::Node(NodeCommand {
+            subcommand: NodeSubCommand::Restart { node, downtime },
+        })) => {
+            let (node, downtime) = (*node, *downtime);
+            Kubernix::restart_node(config, node, downtime)
+        }
+
+        // Run a single bootstrap phase
+        Some(SubCommand::Phase(PhaseCommand {
+            subcommand: PhaseSubCommand::Run { phase },
+        })) => {
+            let phase = *phase;
+            Kubernix::run_phase(config, phase)
+        }
+
+        // Print a token based kubeconfig
+        Some(SubCommand::Token(TokenCommand {
+            subcommand:
+                TokenSubCommand::Kubeconfig {
+                    service_account,
+                    namespace,
+                    cluster_role,
+                    duration,
+                    server,
+                },
+        })) => {
+            let (service_account, namespace, cluster_role, server) = (
+                service_account.clone(),
+                namespace.clone(),
+                cluster_role.clone(),
+                server.clone(),
+            );
+            let duration = *duration;
+            Kubernix::token_kubeconfig(
+                config,
+                &service_account,
+                &namespace,
+                &cluster_role,
+                duration,
+                server.as_deref(),
+            )
+        }
+
+        // Build an image and load it into every node
+        Some(SubCommand::BuildAndLoad {
+            image,
+            context,
+            file,
+        }) => {
+            let (image, context, file) = (image.clone(), context.clone(), file.clone());
+            Kubernix::build_and_load(config, &image, &context, file.as_deref())
+        }
+
+        // Run kubectl against the cluster
+        Some(SubCommand::Kubectl { args }) => {
+            let args = args.clone();
+            Kubernix::kubectl(config, &args)
+        }
+        Some(SubCommand::Restart {
+            component,
+            node,
+            downtime,
+        }) => {
+            let (component, node, downtime) = (component.clone(), *node, *downtime);
+            Kubernix::restart(config, &component, node, downtime)
+        }
+        Some(SubCommand::Reload { component, node }) => {
+            let (component, node) = (component.clone(), *node);
+            Kubernix::reload_component(config, &component, node)
+        }
+        Some(SubCommand::RenewCerts) => Kubernix::renew_certs(config),
+        Some(SubCommand::RotateEncryptionKey) => Kubernix::rotate_encryption_key(config),
+        Some(SubCommand::Gc { dry_run }) => {
+            let dry_run = *dry_run;
+            Kubernix::gc(config, dry_run)
+        }
+        Some(SubCommand::Stop { timeout }) => {
+            let timeout = *timeout;
+            Kubernix::stop(config, timeout)
+        }
+        Some(SubCommand::Cleanup { remove_root }) => {
+            let remove_root = *remove_root;
+            Kubernix::cleanup_orphans(config, remove_root)
+        }
+        Some(SubCommand::Check { path }) => {
+            let path = path.clone();
+            Kubernix::check(config, &path)
+        }
+
+        // Serve the API on localhost
+        Some(SubCommand::Proxy { port }) => {
+            let port = *port;
+            Kubernix::proxy(config, port)
+        }
+
+        // Inspect the cluster
+        Some(SubCommand::Status { output }) => {
+            let output = *output;
+            Kubernix::status(config, output)
+        }
+        Some(SubCommand::Logs {
+            pods,
+            component,
+            follow,
+            since,
+            filter,
+        }) => {
+            let (pods, component, follow, since, filter) = (
+                *pods,
+                component.clone(),
+                *follow,
+                since.clone(),
+                filter.clone(),
+            );
+            match component {
+                Some(component) => {
+                    Kubernix::print_log(config, &component, follow, since.as_deref())
+                }
+                None => Kubernix::logs(config, pods, filter.as_deref()),
+            }
+        }
+        Some(SubCommand::Replay {
+            audit: true,
+            users,
+            file,
+            ..
+        }) => {
+            let (users, file) = (users.clone(), file.clone());
+            Kubernix::replay_audit(config, &users, file)
+        }
+        Some(SubCommand::Replay { until, file, .. }) => {
+            let (until, file) = (until.clone(), file.clone());
+            Kubernix::replay(config, until.as_deref(), file)
+        }
+        Some(SubCommand::Env { shell }) => {
+            let shell = *shell;
+            Kubernix::env(config, shell)
+        }
+        Some(SubCommand::List { output }) => {
+            let output = *output;
+            Kubernix::list(config, output)
+        }
+        Some(SubCommand::Version { output }) => Kubernix::version(*output),
+
         // Bootstrap the cluster
-        Kubernix::start(config)
+        None => Kubernix::start(config),
     }
 }
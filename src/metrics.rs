@@ -0,0 +1,48 @@
+use crate::{addon::Addon, config::Config, kubectl::Kubectl, network::Network};
+use anyhow::{Context, Result};
+use log::info;
+use std::fs::{self, create_dir_all};
+
+/// The metrics-server addon, which serves the resource metrics API
+pub struct MetricsServer;
+
+impl MetricsServer {
+    /// The name of the addon
+    pub const NAME: &'static str = "metrics-server";
+
+    const IMAGE: &'static str = "k8s.gcr.io/metrics-server/metrics-server:v0.4.1";
+
+    /// The host port metrics-server listens on
+    const PORT: u16 = 4443;
+}
+
+impl Addon for MetricsServer {
+    fn name(&self) -> String {
+        MetricsServer::NAME.into()
+    }
+
+    fn apply(&self, config: &Config, _: &Network, kubectl: &Kubectl) -> Result<()> {
+        info!("Deploying metrics-server and waiting to be ready");
+
+        let dir = config.root().join(MetricsServer::NAME);
+        create_dir_all(&dir)?;
+
+        let yml = format!(
+            include_str!("assets/metrics-server.yml"),
+            image = config.image(MetricsServer::NAME, MetricsServer::IMAGE)?,
+            port = MetricsServer::PORT,
+        );
+        let file = dir.join("metrics-server.yml");
+
+        if !file.exists() {
+            fs::write(&file, yml)?;
+        }
+
+        kubectl
+            .apply(&file)
+            .context("Unable to deploy metrics-server")?;
+        kubectl.wait_ready(MetricsServer::NAME)?;
+        info!("metrics-server deployed");
+        Ok(())
+    }
+}
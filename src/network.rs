@@ -1,15 +1,23 @@
-use crate::Config;
+use crate::{
+    addon::Addons, cni::Cni, kubelet::Kubelet, logging::Logging, node::Node,
+    observability::Observability, record::Recorded, Config,
+};
 use anyhow::{bail, Context, Result};
+use console::Term;
 use getset::Getters;
 use hostname::get;
 use ipnetwork::Ipv4Network;
-use log::{debug, warn};
+use log::{debug, info, warn};
 use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    env,
     net::{Ipv4Addr, SocketAddr},
+    path::Path,
     process::Command,
 };
 
-#[derive(Getters)]
+#[derive(Clone, Getters)]
 pub struct Network {
     #[get = "pub"]
     cluster_cidr: Ipv4Network,
@@ -34,48 +42,89 @@ impl Network {
     /// The global name for the interface
     pub const INTERFACE_PREFIX: &'static str = "kubernix";
 
+    /// The longest prefix of the carved subnets
+    const MAX_PREFIX: u8 = 28;
+
+    /// The range of ports above the API server port, which are used by the
+    /// controller manager, the schedulers and the monitoring addons
+    const API_PORT_RANGE: u16 = 3817;
+
     /// Create a new network from the provided config
     pub fn new(config: &Config) -> Result<Self> {
         // Preflight checks
-        if config.cidr().prefix() > 24 {
+        for (name, prefix) in &[
+            ("cluster", config.cluster_prefix()),
+            ("service", config.service_prefix()),
+            ("node", config.node_prefix()),
+        ] {
+            if *prefix < config.cidr().prefix() || *prefix > Self::MAX_PREFIX {
+                bail!(
+                    "The {} prefix /{} has to be between the prefix of {} and /{}",
+                    name,
+                    prefix,
+                    config.cidr(),
+                    Self::MAX_PREFIX
+                )
+            }
+        }
+        if config.etcd_port() == u16::MAX {
             bail!(
-                "Specified IP network {} is too small, please use at least a /24 subnet",
-                config.cidr()
+                "The etcd port {} leaves no room for the peer port",
+                u16::MAX
+            )
+        }
+        if config.api_port() > u16::MAX - Self::API_PORT_RANGE {
+            bail!(
+                "The API server port {} leaves no room for the controller manager and scheduler ports",
+                config.api_port()
+            )
+        }
+        for (name, distance) in &[
+            (Observability::NAME, Observability::GRAFANA_PORT_DISTANCE),
+            (Logging::NAME, Logging::LOKI_PORT_DISTANCE),
+        ] {
+            if Addons::enabled(config, name) && config.api_port() < *distance {
+                bail!(
+                    "The API server port {} leaves no room for the host port of the {} addon, \
+                     which has to be {} ports below",
+                    config.api_port(),
+                    name,
+                    distance
+                )
+            }
+        }
+        if u32::from(config.kubelet_port())
+            + u32::from(Kubelet::PROXY_PORT_OFFSET)
+            + 500
+            + u32::from(config.nodes())
+            > u32::from(u16::MAX) + 1
+        {
+            bail!(
+                "The kubelet port {} leaves no room for the healthz and kube-proxy ports of {} nodes",
+                config.kubelet_port(),
+                config.nodes()
             )
         }
         Self::warn_overlapping_route(config.cidr())?;
 
-        // Calculate the CIDRs
-        let cluster_cidr = Ipv4Network::new(config.cidr().ip(), 24)?;
+        // Calculate the CIDRs, which are aligned to their size
+        let mut offset = 0;
+        let cluster_cidr = Self::carve(config, &mut offset, config.cluster_prefix(), "cluster")?;
         debug!("Using cluster CIDR {}", cluster_cidr);
 
-        let service_cidr = Ipv4Network::new(
-            config
-                .cidr()
-                .nth(cluster_cidr.size())
-                .context("Unable to retrieve service CIDR start IP")?,
-            24,
-        )?;
+        let service_cidr = Self::carve(config, &mut offset, config.service_prefix(), "service")?;
         debug!("Using service CIDR {}", service_cidr);
 
         let mut crio_cidrs = vec![];
-        let mut offset = cluster_cidr.size() + service_cidr.size();
         for node in 0..config.nodes() {
-            let cidr = Ipv4Network::new(
-                config
-                    .cidr()
-                    .nth(offset)
-                    .context("Unable to retrieve CRI-O CIDR start IP")?,
-                24,
-            )?;
-            offset += cidr.size();
+            let cidr = Self::carve(config, &mut offset, config.node_prefix(), "CRI-O")?;
             debug!("Using CRI-O ({}) CIDR {}", node, cidr);
             crio_cidrs.push(cidr);
         }
 
         // Set the rest of the networking related adresses and paths
-        let etcd_client = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 2379);
-        let etcd_peer = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 2380);
+        let etcd_client = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), config.etcd_port());
+        let etcd_peer = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), config.etcd_port() + 1);
         let hostname = get()
             .context("Unable to get hostname")?
             .to_str()
@@ -92,13 +141,216 @@ impl Network {
         })
     }
 
-    /// Check if there are overlapping routes and warn
-    fn warn_overlapping_route(cidr: Ipv4Network) -> Result<()> {
-        let cmd = Command::new("ip").arg("route").output()?;
+    /// Carve the next subnet with the provided prefix from the configured
+    /// CIDR, whereas the offset gets aligned to the size of the subnet
+    fn carve(config: &Config, offset: &mut u64, prefix: u8, name: &str) -> Result<Ipv4Network> {
+        let size = 1u64 << (32 - u32::from(prefix));
+        let start = (*offset + size - 1) / size * size;
+        if start + size > u64::from(config.cidr().size()) {
+            bail!(
+                "Specified IP network {} is too small for the {} CIDR /{}",
+                config.cidr(),
+                name,
+                prefix
+            )
+        }
+        *offset = start + size;
+        let ip = config
+            .cidr()
+            .nth(u32::try_from(start)?)
+            .with_context(|| format!("Unable to retrieve {} CIDR start IP", name))?;
+        Ok(Ipv4Network::new(ip, prefix)?)
+    }
+
+    /// Retrieve the bridge interface name of the node, which is scoped to the
+    /// cluster by a short hash of its root to not interfere with other runs
+    pub fn interface(config: &Config, node: u8) -> String {
+        format!(
+            "{}{:03x}.{}",
+            Self::INTERFACE_PREFIX,
+            config.root_hash() & 0xfff,
+            node
+        )
+    }
+
+    /// Remove kubernix interfaces of previous runs whose routes conflict with
+    /// the cluster CIDR, which has to be confirmed if not forced
+    pub fn remove_stale_interfaces(config: &Config) -> Result<()> {
+        let stale = Self::conflicting_interfaces(&Self::routes()?, config.cidr());
+        if stale.is_empty() {
+            return Ok(());
+        }
+        warn!(
+            "Found kubernix interfaces with routes conflicting with {}: {}",
+            config.cidr(),
+            stale.join(", ")
+        );
+
+        if !config.force() {
+            let term = Term::stderr();
+            if !term.is_term() {
+                bail!("Conflicting interfaces found, use --force to remove them")
+            }
+            term.write_str("Remove the interfaces? [y/N] ")?;
+            match term.read_line()?.trim() {
+                "y" | "Y" | "yes" => {}
+                _ => bail!("Removal of conflicting interfaces declined"),
+            }
+        }
+        for interface in stale {
+            info!("Removing stale interface {}", interface);
+            Self::remove_interface(&interface)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the host network state left behind by the CRI runtimes of
+    /// the cluster, whereas all steps are tried even if some of them fail.
+    /// The rules of kube-proxy are shared by all clusters and host services
+    /// within the host network namespace, which is why they are kept.
+    pub fn cleanup(&self, config: &Config) -> Result<()> {
+        let names = (0..config.nodes())
+            .map(|x| Cni::network_name(config, &Node::name(config, self, x)))
+            .collect::<Vec<_>>();
+        let mut failed = false;
+        for result in [
+            Self::remove_interfaces(config).context("Unable to remove interfaces"),
+            Self::remove_cni_rules(&names).context("Unable to remove CNI iptables rules"),
+        ] {
+            if let Err(e) = result {
+                debug!("{:#}", e);
+                failed = true;
+            }
+        }
+        if failed {
+            bail!("Unable to cleanup the host network")
+        }
+        Ok(())
+    }
+
+    /// Remove the bridge interfaces of all nodes including their attached
+    /// veth pairs, if existing
+    fn remove_interfaces(config: &Config) -> Result<()> {
+        for node in 0..config.nodes() {
+            let interface = Self::interface(config, node);
+            if !Path::new("/sys/class/net").join(&interface).exists() {
+                continue;
+            }
+            let output = Command::new("ip")
+                .args(["-o", "link", "show", "master", &interface])
+                .recorded_output()?;
+            for veth in Self::link_names(&String::from_utf8(output.stdout)?) {
+                debug!("Removing interface {}", veth);
+                Self::remove_interface(&veth)?;
+            }
+            debug!("Removing interface {}", interface);
+            Self::remove_interface(&interface)?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve the link names of the `ip -o link` output
+    fn link_names(output: &str) -> Vec<String> {
+        output
+            .lines()
+            .filter_map(|x| x.split(": ").nth(1))
+            .filter_map(|x| x.split('@').next())
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Remove the CNI masquerading rules and chains of the provided networks,
+    /// which remain if the pod sandboxes were not removed gracefully
+    fn remove_cni_rules(names: &[String]) -> Result<()> {
+        let output = Command::new("iptables")
+            .args(["-t", "nat", "-L", "POSTROUTING", "--line-numbers", "-n"])
+            .recorded_output()?;
+        if !output.status.success() {
+            bail!("Unable to list iptables rules")
+        }
+        let (numbers, chains) = Self::cni_rules(&String::from_utf8(output.stdout)?, names);
+        for number in numbers.iter().rev() {
+            Self::iptables(&["-t", "nat", "-D", "POSTROUTING", &number.to_string()])?;
+        }
+        for chain in chains {
+            debug!("Removing iptables chain {}", chain);
+            Self::iptables(&["-t", "nat", "-F", &chain])?;
+            Self::iptables(&["-t", "nat", "-X", &chain])?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve the rule numbers and target chains of the POSTROUTING rules
+    /// which belong to one of the provided CNI networks
+    fn cni_rules(listing: &str, names: &[String]) -> (Vec<u32>, Vec<String>) {
+        let mut numbers = vec![];
+        let mut chains = vec![];
+        for line in listing.lines().filter(|x| {
+            names
+                .iter()
+                .any(|name| x.contains(&format!("name: \"{}\" ", name)))
+        }) {
+            let mut parts = line.split_whitespace();
+            if let (Some(Ok(number)), Some(target)) = (parts.next().map(str::parse), parts.next()) {
+                numbers.push(number);
+                if target.starts_with("CNI-") && !chains.iter().any(|x| x == target) {
+                    chains.push(target.into());
+                }
+            }
+        }
+        (numbers, chains)
+    }
+
+    fn iptables(args: &[&str]) -> Result<()> {
+        let status = Command::new("iptables").args(args).recorded_status()?;
+        if !status.success() {
+            bail!("iptables {} failed ({})", args.join(" "), status)
+        }
+        Ok(())
+    }
+
+    fn remove_interface(name: &str) -> Result<()> {
+        let status = Command::new("ip")
+            .args(["link", "delete", name])
+            .recorded_status()?;
+        if !status.success() {
+            bail!("Unable to remove interface {}", name)
+        }
+        Ok(())
+    }
+
+    /// Retrieve all kubernix interfaces which route into the CIDR
+    fn conflicting_interfaces(routes: &str, cidr: Ipv4Network) -> Vec<String> {
+        let mut interfaces = routes
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let destination = parts.next()?.parse::<Ipv4Network>().ok()?;
+                let device = parts.skip_while(|x| *x != "dev").nth(1)?;
+                if device.starts_with(Self::INTERFACE_PREFIX) && destination.overlaps(cidr) {
+                    Some(device.to_owned())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        interfaces.sort();
+        interfaces.dedup();
+        interfaces
+    }
+
+    /// Retrieve the output of `ip route`
+    fn routes() -> Result<String> {
+        let cmd = Command::new("ip").arg("route").recorded_output()?;
         if !cmd.status.success() {
             bail!("Unable to obtain `ip` routes")
         }
-        String::from_utf8(cmd.stdout)?
+        Ok(String::from_utf8(cmd.stdout)?)
+    }
+
+    /// Check if there are overlapping routes and warn
+    fn warn_overlapping_route(cidr: Ipv4Network) -> Result<()> {
+        Self::routes()?
             .lines()
             .filter(|x| !x.contains(Self::INTERFACE_PREFIX))
             .filter_map(|x| x.split_whitespace().next())
@@ -123,6 +375,58 @@ impl Network {
         })
     }
 
+    /// Retrieve the hosts and CIDRs which must never be accessed via the
+    /// proxy, like the API server, the pods and the services
+    pub fn no_proxy(&self, config: &Config) -> String {
+        let mut hosts = vec![
+            "localhost".into(),
+            Ipv4Addr::LOCALHOST.to_string(),
+            self.hostname().clone(),
+            self.cluster_cidr().to_string(),
+            self.service_cidr().to_string(),
+        ];
+        hosts.extend(self.crio_cidrs().iter().map(ToString::to_string));
+        hosts.extend(vec![".svc".into(), ".cluster.local".into()]);
+        hosts.extend(config.proxy_exclusions().iter().cloned());
+        hosts.join(",")
+    }
+
+    /// Retrieve the proxy environment variables, which are empty if no proxy
+    /// has been configured
+    pub fn proxy_env(&self, config: &Config) -> BTreeMap<String, String> {
+        let mut env = BTreeMap::<String, String>::new();
+        let https_proxy = config
+            .https_proxy()
+            .as_ref()
+            .or_else(|| config.http_proxy().as_ref());
+        for (name, value) in &[
+            ("HTTP_PROXY", config.http_proxy().as_ref()),
+            ("HTTPS_PROXY", https_proxy),
+        ] {
+            if let Some(value) = value {
+                env.insert((*name).into(), (*value).clone());
+            }
+        }
+        if !env.is_empty() {
+            env.insert("NO_PROXY".into(), self.no_proxy(config));
+        }
+
+        // Not every tool respects the upper case variables
+        for (name, value) in env.clone() {
+            env.insert(name.to_lowercase(), value);
+        }
+        env
+    }
+
+    /// Export the proxy environment variables to be inherited by all spawned
+    /// processes, which includes nix and the CRI runtimes
+    pub fn export_proxy_env(&self, config: &Config) {
+        for (name, value) in self.proxy_env(config) {
+            debug!("Setting {}={}", name, value);
+            env::set_var(name, value);
+        }
+    }
+
     /// Retrieve the DNS address from the service CIDR
     pub fn dns(&self) -> Result<Ipv4Addr> {
         self.service_cidr().nth(2).with_context(|| {
@@ -137,7 +441,11 @@ impl Network {
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::config::tests::{test_config, test_config_wrong_cidr};
+    use crate::{
+        config::tests::{test_config, test_config_with, test_config_wrong_cidr},
+        controllermanager::ControllerManager,
+        scheduler::Scheduler,
+    };
 
     pub fn test_network() -> Result<Network> {
         let c = test_config()?;
@@ -158,6 +466,112 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn proxy_env_success() -> Result<()> {
+        let mut c = test_config()?;
+        let n = Network::new(&c)?;
+        assert!(n.proxy_env(&c).is_empty());
+
+        c = test_config_with(|c| {
+            c.set_http_proxy(Some("http://proxy:3128".into()))
+                .set_proxy_exclusions(vec!["registry.local".into()]);
+        })?;
+        let env = n.proxy_env(&c);
+        assert_eq!(env.len(), 6);
+        assert_eq!(env["HTTP_PROXY"], "http://proxy:3128");
+        assert_eq!(env["https_proxy"], "http://proxy:3128");
+
+        let no_proxy = env["NO_PROXY"].split(',').collect::<Vec<_>>();
+        assert!(no_proxy.contains(&"127.0.0.1"));
+        assert!(no_proxy.contains(&"10.10.0.0/24"));
+        assert!(no_proxy.contains(&"10.10.1.0/24"));
+        assert!(no_proxy.contains(&"10.10.2.0/24"));
+        assert!(no_proxy.contains(&"registry.local"));
+        Ok(())
+    }
+
+    #[test]
+    fn new_success_prefixes() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_nodes(3)
+                .set_cluster_prefix(20)
+                .set_service_prefix(24)
+                .set_node_prefix(26);
+        })?;
+        let n = Network::new(&c)?;
+        assert_eq!(n.cluster_cidr().to_string(), "10.10.0.0/20");
+        assert_eq!(n.service_cidr().to_string(), "10.10.16.0/24");
+        assert_eq!(
+            n.crio_cidrs()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["10.10.17.0/26", "10.10.17.64/26", "10.10.17.128/26"]
+        );
+
+        // The subnets get aligned to their size
+        let c = test_config_with(|c| {
+            c.set_cluster_prefix(24)
+                .set_service_prefix(20)
+                .set_node_prefix(24);
+        })?;
+        let n = Network::new(&c)?;
+        assert_eq!(n.service_cidr().to_string(), "10.10.16.0/20");
+        assert_eq!(n.crio_cidrs()[0].to_string(), "10.10.32.0/24");
+        Ok(())
+    }
+
+    #[test]
+    fn new_failure_prefixes() -> Result<()> {
+        for (nodes, cluster, service, node) in &[(1, 15, 24, 24), (1, 24, 29, 24), (1, 17, 17, 24)]
+        {
+            let c = test_config_with(|c| {
+                c.set_nodes(*nodes)
+                    .set_cluster_prefix(*cluster)
+                    .set_service_prefix(*service)
+                    .set_node_prefix(*node);
+            })?;
+            assert!(Network::new(&c).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn new_success_ports() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_nodes(2)
+                .set_api_port(7443)
+                .set_etcd_port(3379)
+                .set_kubelet_port(21250);
+        })?;
+        let n = Network::new(&c)?;
+        assert_eq!(n.etcd_client().port(), 3379);
+        assert_eq!(n.etcd_peer().port(), 3380);
+        assert_eq!(Kubelet::port(&c, 1), 21251);
+        assert_eq!(Kubelet::healthz_port(&c, 1), 22251);
+        assert_eq!(ControllerManager::port(&c), 11257);
+        assert_eq!(Scheduler::port(&c), 11259);
+        Ok(())
+    }
+
+    #[test]
+    fn new_failure_ports() -> Result<()> {
+        for (api, etcd, kubelet) in &[
+            (6443, u16::MAX, 11250),
+            (6443, 2379, u16::MAX - 1000),
+            (u16::MAX - 1000, 2379, 11250),
+        ] {
+            let c = test_config_with(|c| {
+                c.set_nodes(2)
+                    .set_api_port(*api)
+                    .set_etcd_port(*etcd)
+                    .set_kubelet_port(*kubelet);
+            })?;
+            assert!(Network::new(&c).is_err());
+        }
+        Ok(())
+    }
+
     #[test]
     fn dns_success() -> Result<()> {
         let c = test_config()?;
@@ -165,4 +579,56 @@ pub mod tests {
         assert_eq!(n.dns()?, Ipv4Addr::new(10, 10, 1, 2));
         Ok(())
     }
+
+    #[test]
+    fn interface_success() -> Result<()> {
+        let c = test_config()?;
+        let interface = Network::interface(&c, 255);
+        assert!(interface.starts_with(Network::INTERFACE_PREFIX));
+        assert!(interface.ends_with(".255"));
+        assert!(interface.len() <= 15);
+        assert_eq!(interface, Network::interface(&c, 255));
+        Ok(())
+    }
+
+    #[test]
+    fn link_names_success() {
+        assert_eq!(
+            Network::link_names(
+                "12: veth1a2b3c@if3: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500
+13: vethd4e5f6@if3: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500"
+            ),
+            vec!["veth1a2b3c".to_owned(), "vethd4e5f6".into()]
+        );
+    }
+
+    #[test]
+    fn cni_rules_success() {
+        let listing = r#"Chain POSTROUTING (policy ACCEPT)
+num  target     prot opt source               destination
+1    KUBE-POSTROUTING  all  --  0.0.0.0/0            0.0.0.0/0            /* kubernetes postrouting rules */
+2    CNI-1a2b3c  all  --  10.10.2.5            0.0.0.0/0            /* name: "kubernix-node-0" id: "abc" */
+3    CNI-4d5e6f  all  --  10.88.0.2            0.0.0.0/0            /* name: "podman" id: "def" */
+4    CNI-1a2b3c  all  --  10.10.2.6            0.0.0.0/0            /* name: "kubernix-node-0" id: "ghi" */
+5    CNI-7a8b9c  all  --  10.20.2.5            0.0.0.0/0            /* name: "kubernix-node-01" id: "jkl" */
+6    CNI-0d1e2f  all  --  10.20.2.6            0.0.0.0/0            /* name: "kubernix-dev-node-0" id: "mno" */"#;
+        assert_eq!(
+            Network::cni_rules(listing, &["kubernix-node-0".into()]),
+            (vec![2, 4], vec!["CNI-1a2b3c".to_owned()])
+        );
+    }
+
+    #[test]
+    fn conflicting_interfaces_success() -> Result<()> {
+        let routes = "default via 192.168.0.1 dev eth0 proto dhcp metric 100
+10.10.2.0/24 dev kubernix.0 proto kernel scope link src 10.10.2.1
+10.10.3.0/24 dev kubernix3fa.1 proto kernel scope link src 10.10.3.1
+10.10.4.0/24 dev cni0 proto kernel scope link src 10.10.4.1
+10.20.2.0/24 dev kubernix1b2.0 proto kernel scope link src 10.20.2.1";
+        assert_eq!(
+            Network::conflicting_interfaces(routes, "10.10.0.0/16".parse()?),
+            vec!["kubernix.0".to_owned(), "kubernix3fa.1".into()]
+        );
+        Ok(())
+    }
 }
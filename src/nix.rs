@@ -1,10 +1,21 @@
-use crate::{system::System, Config};
-use anyhow::Result;
+use crate::{
+    cni::Cni,
+    error::{ErrorKind, Reported},
+    fakenodes::FakeNodes,
+    kine::KINE,
+    logging::Logging,
+    proxy::Proxy,
+    runtimeclass::RuntimeClasses,
+    system::System,
+    Config,
+};
+use anyhow::{anyhow, bail, Context, Result};
 use log::{debug, info};
 use std::{
     env::{current_exe, var},
-    fs::{self, create_dir_all},
-    process::Command,
+    fs::{self, create_dir_all, read_dir},
+    path::PathBuf,
+    process::{Command, ExitStatus},
 };
 
 pub struct Nix;
@@ -29,52 +40,163 @@ impl Nix {
             )?;
             fs::write(dir.join("nixpkgs.nix"), include_str!("../nix/nixpkgs.nix"))?;
 
-            let packages = &config.packages().join(" ");
-            debug!("Adding additional packages: {:?}", config.packages());
+            let mut packages = config.packages().clone();
+            if config.single_binary() {
+                packages.push(KINE.into());
+            }
+            if config.fake_nodes() > 0 {
+                packages.push(FakeNodes::PACKAGE.into());
+            }
+            if config.addons().iter().any(|x| x == Logging::NAME) {
+                packages.push(Logging::PACKAGE.into());
+            }
+            packages.extend(
+                config
+                    .oci_runtimes()
+                    .into_iter()
+                    .filter_map(RuntimeClasses::package)
+                    .map(Into::into),
+            );
+            packages.extend(Cni::package(config.cni()).map(Into::into));
+            if !config.no_proxy() {
+                packages.extend(Proxy::package(config.proxy_mode()).map(Into::into));
+            }
+            debug!("Adding additional packages: {:?}", packages);
             fs::write(
                 dir.join("default.nix"),
-                include_str!("../nix/default.nix").replace("/* PACKAGES */", packages),
+                include_str!("../nix/default.nix").replace("/* PACKAGES */", &packages.join(" ")),
             )?;
 
-            // Apply the overlay if existing
-            let target_overlay = dir.join("overlay.nix");
-            match config.overlay() {
-                // User defined overlay
-                Some(overlay) => {
-                    info!("Using custom overlay '{}'", overlay.display());
-                    fs::copy(overlay, target_overlay)?;
-                }
-
-                // The default overlay
-                None => {
-                    debug!("Using default overlay");
-                    fs::write(target_overlay, include_str!("../nix/overlay.nix"))?;
-                }
+            // Apply the overlays in order, whereas their file names ensure
+            // the ordering within the nix environment
+            let overlays_dir = dir.join("overlays");
+            create_dir_all(&overlays_dir)?;
+            let overlays = Self::overlays(&config)?;
+            if overlays.is_empty() {
+                debug!("Using default overlay");
+                fs::write(
+                    overlays_dir.join("00-default.nix"),
+                    include_str!("../nix/overlay.nix"),
+                )?;
+            }
+            for (i, overlay) in overlays.iter().enumerate() {
+                info!("Using custom overlay '{}'", overlay.display());
+                let name = overlay
+                    .file_name()
+                    .with_context(|| format!("Invalid overlay '{}'", overlay.display()))?;
+                fs::copy(
+                    overlay,
+                    overlays_dir.join(format!("{:02}-{}", i, name.to_string_lossy())),
+                )
+                .with_context(|| format!("Unable to copy overlay '{}'", overlay.display()))?;
             }
         }
 
-        // Run the shell
-        Self::run(
+        // Run the shell, whereas the confirmation flags and the color policy
+        // are not persisted
+        let args: &[&str] = &[
+            &format!("{}", current_exe()?.display()),
+            "--root",
+            &format!("{}", config.root().display()),
+            &format!("--color={}", config.color()),
+        ];
+        let force = if config.force() {
+            Some("--force")
+        } else {
+            None
+        };
+        let status = Self::run(
             &config,
-            &[
-                &format!("{}", current_exe()?.display()),
-                "--root",
-                &format!("{}", config.root().display()),
-            ],
+            &args.iter().copied().chain(force).collect::<Vec<_>>(),
         )
+        .context(ErrorKind::Nix)?;
+
+        // The nested kubernix process already reported its own failures
+        match status.code().and_then(ErrorKind::from_exit_code) {
+            _ if status.success() => Ok(()),
+            Some(kind) => Err(Reported(kind).into()),
+            None => Err(anyhow!("Nix run failed ({})", status).context(ErrorKind::Nix)),
+        }
+    }
+
+    /// Retrieve all configured overlay files in order, whereas directories get
+    /// expanded to their contained `.nix` files sorted by name
+    fn overlays(config: &Config) -> Result<Vec<PathBuf>> {
+        let mut overlays = vec![];
+        for overlay in config.overlays() {
+            if overlay.is_dir() {
+                let mut files = read_dir(overlay)
+                    .with_context(|| {
+                        format!("Unable to read overlay directory '{}'", overlay.display())
+                    })?
+                    .map(|x| x.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into_iter()
+                    .filter(|x| x.is_file() && x.extension().map_or(false, |e| e == "nix"))
+                    .collect::<Vec<_>>();
+                if files.is_empty() {
+                    bail!(
+                        "Overlay directory '{}' contains no .nix files",
+                        overlay.display()
+                    )
+                }
+                files.sort();
+                overlays.extend(files);
+            } else if overlay.is_file() {
+                overlays.push(overlay.clone());
+            } else {
+                bail!("Overlay '{}' does not exist", overlay.display())
+            }
+        }
+        Ok(overlays)
     }
 
     /// Run a pure nix command
-    pub fn run(config: &Config, args: &[&str]) -> Result<()> {
-        Command::new(System::find_executable("nix")?)
-            .env(Self::NIX_ENV, "true")
+    pub fn run(config: &Config, args: &[&str]) -> Result<ExitStatus> {
+        Ok(Self::command(config.nix_wrapper().as_deref())?
             .arg("run")
             .arg("-f")
             .arg(config.root().join(Self::DIR))
             .arg("-c")
             .args(args)
-            .status()?;
-        Ok(())
+            .status()?)
+    }
+
+    /// Retrieve the nix command, which gets executed through the whitespace
+    /// separated wrapper command if provided
+    fn command(wrapper: Option<&str>) -> Result<Command> {
+        let wrapper = match wrapper {
+            Some(wrapper) => wrapper,
+            None => {
+                let mut command = Command::new(System::find_executable("nix")?);
+                command.env(Self::NIX_ENV, "true");
+                return Ok(command);
+            }
+        };
+        let mut parts = wrapper.split_whitespace();
+        let program = parts.next().context("Nix wrapper is empty")?;
+        debug!("Running nix through wrapper: {}", wrapper);
+
+        // The environment is not necessarily passed through the wrapper
+        let mut command = Command::new(program);
+        command
+            .args(parts)
+            .arg("env")
+            .arg(format!("{}=true", Self::NIX_ENV))
+            .arg("nix");
+        Ok(command)
+    }
+
+    /// Run a command inside the nix environment, whereas an already active
+    /// environment will be reused
+    pub fn execute(config: &Config, args: &[&str]) -> Result<ExitStatus> {
+        debug!("Running: {}", args.join(" "));
+        if Self::is_active() {
+            let (command, args) = args.split_first().context("No command provided")?;
+            Ok(Command::new(command).args(args).status()?)
+        } else {
+            Self::run(config, args)
+        }
     }
 
     /// Returns true if running in nix environment
@@ -82,3 +204,62 @@ impl Nix {
         var(Nix::NIX_ENV).is_ok()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_config_with;
+    use tempfile::tempdir;
+
+    #[test]
+    fn command_success_wrapper() -> Result<()> {
+        let command = Nix::command(Some("nsenter -t 1 -m --"))?;
+        assert_eq!(command.get_program(), "nsenter");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec!["-t", "1", "-m", "--", "env", "IN_NIX=true", "nix"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn command_failure_wrapper() {
+        assert!(Nix::command(Some(" ")).is_err());
+    }
+
+    #[test]
+    fn overlays_success() -> Result<()> {
+        let d = tempdir()?;
+        let base = d.path().join("base");
+        create_dir_all(&base)?;
+        fs::write(base.join("b.nix"), "")?;
+        fs::write(base.join("a.nix"), "")?;
+        fs::write(base.join("README"), "")?;
+        let project = d.path().join("project.nix");
+        fs::write(&project, "")?;
+
+        let c = test_config_with(|c| {
+            c.set_overlays(vec![base.clone(), project.clone()]);
+        })?;
+        assert_eq!(
+            Nix::overlays(&c)?,
+            vec![base.join("a.nix"), base.join("b.nix"), project]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn overlays_failure() -> Result<()> {
+        let d = tempdir()?;
+        let c = test_config_with(|c| {
+            c.set_overlays(vec![d.path().to_path_buf()]);
+        })?;
+        assert!(Nix::overlays(&c).is_err());
+
+        let c = test_config_with(|c| {
+            c.set_overlays(vec![d.path().join("invalid.nix")]);
+        })?;
+        assert!(Nix::overlays(&c).is_err());
+        Ok(())
+    }
+}
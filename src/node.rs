@@ -1,4 +1,7 @@
 use crate::{network::Network, Config};
+use log::warn;
+use nix::{ifaddrs::getifaddrs, sys::socket::SockAddr};
+use std::net::{IpAddr, Ipv4Addr};
 
 pub struct Node;
 
@@ -12,9 +15,74 @@ impl Node {
         }
     }
 
+    /// Retrieve the IP address of the node, which is currently the same for
+    /// all nodes since they share the host network
+    pub fn ip(_number: u8) -> Ipv4Addr {
+        Ipv4Addr::LOCALHOST
+    }
+
+    /// Retrieve all addresses the kubelet of the node is reachable on, which
+    /// includes the host addresses for a single node, because its kubelet
+    /// detects the node IP on its own
+    pub fn addresses(config: &Config, number: u8) -> Vec<Ipv4Addr> {
+        let mut addresses = vec![Self::ip(number)];
+        if !config.multi_node() {
+            addresses.extend(Self::host_addresses());
+        }
+        addresses
+    }
+
+    /// Retrieve the IPv4 addresses of all non loopback host interfaces
+    fn host_addresses() -> Vec<Ipv4Addr> {
+        let interfaces = match getifaddrs() {
+            Ok(interfaces) => interfaces,
+            Err(e) => {
+                warn!("Unable to retrieve host addresses: {}", e);
+                return vec![];
+            }
+        };
+        let mut addresses = interfaces
+            .filter_map(|x| match x.address? {
+                SockAddr::Inet(inet) => match inet.ip().to_std() {
+                    IpAddr::V4(ip) if !ip.is_loopback() => Some(ip),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        addresses.sort();
+        addresses.dedup();
+        addresses
+    }
+
     /// Retrieve the raw node name
     pub fn raw(number: u8) -> String {
         const PREFIX: &str = "node";
         format!("{}-{}", PREFIX, number)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{test_config, test_config_with};
+    use anyhow::Result;
+
+    #[test]
+    fn addresses_success() -> Result<()> {
+        let addresses = Node::addresses(&test_config()?, 0);
+        assert_eq!(addresses[0], Ipv4Addr::LOCALHOST);
+        assert!(addresses[1..].iter().all(|x| !x.is_loopback()));
+
+        assert_eq!(
+            Node::addresses(
+                &test_config_with(|c| {
+                    c.set_nodes(2);
+                })?,
+                1
+            ),
+            vec![Ipv4Addr::LOCALHOST]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+use crate::{
+    addon::Addon,
+    config::Config,
+    controllermanager::ControllerManager,
+    kubectl::Kubectl,
+    kubelet::Kubelet,
+    logging::Logging,
+    network::Network,
+    node::Node,
+    pki::{Pki, PROMETHEUS_USER},
+    scheduler::Scheduler,
+};
+use anyhow::{Context, Result};
+use base64::encode;
+use log::info;
+use serde_json::{json, Value};
+use std::{
+    fs::{self, create_dir_all, read},
+    net::Ipv4Addr,
+    path::Path,
+};
+
+/// The observability addon, which deploys Prometheus scraping the control
+/// plane and kubelets as well as Grafana serving a cluster dashboard on the
+/// host
+pub struct Observability;
+
+impl Observability {
+    /// The name of the addon
+    pub const NAME: &'static str = "observability";
+
+    const PROMETHEUS_IMAGE: &'static str = "quay.io/prometheus/prometheus:v2.45.0";
+    const GRAFANA_IMAGE: &'static str = "docker.io/grafana/grafana:10.0.3";
+
+    /// The distance of the Prometheus host port to the API server port, which
+    /// results in the default port 9090
+    const PROMETHEUS_PORT_OFFSET: u16 = 2647;
+
+    /// The distance of the Grafana host port below the API server port, which
+    /// results in the default port 3000
+    pub const GRAFANA_PORT_DISTANCE: u16 = 3443;
+
+    /// The directory of the scrape certificates within the Prometheus
+    /// container
+    const CERTS_DIR: &'static str = "/etc/prometheus/certs";
+
+    /// The host port of Prometheus, which listens on the loopback interface
+    fn prometheus_port(config: &Config) -> u16 {
+        config.api_port() + Self::PROMETHEUS_PORT_OFFSET
+    }
+
+    /// The host port serving Grafana
+    fn grafana_port(config: &Config) -> u16 {
+        config.api_port() - Self::GRAFANA_PORT_DISTANCE
+    }
+
+    /// The URL of Grafana on the host
+    pub fn url(config: &Config) -> String {
+        format!(
+            "http://{}:{}",
+            Ipv4Addr::LOCALHOST,
+            Self::grafana_port(config)
+        )
+    }
+
+    /// Render the certificates used by Prometheus as secret data, whereas
+    /// its dedicated client identity is only allowed to read metrics
+    fn certs(pki: &Pki) -> Result<String> {
+        let files: &[(&str, &Path)] = &[
+            ("ca.pem", pki.ca().cert()),
+            ("prometheus.pem", pki.prometheus().cert()),
+            ("prometheus-key.pem", pki.prometheus().key()),
+        ];
+        let mut out = String::new();
+        for (name, path) in files {
+            let content =
+                read(path).with_context(|| format!("Unable to read '{}'", path.display()))?;
+            out.push_str(&format!("  {}: {}\n", name, encode(content)));
+        }
+        Ok(out)
+    }
+
+    /// Create the Prometheus configuration scraping the control plane and
+    /// kubelets of all nodes. etcd is not scraped, because every client
+    /// certificate of its CA has full access to the stored data, whereas the
+    /// API server exposes the database size as well.
+    fn prometheus_config(config: &Config) -> Value {
+        let job = |name: &str, path: &str, targets: Vec<String>| {
+            json!({
+                "job_name": name,
+                "scheme": "https",
+                "metrics_path": path,
+                "static_configs": [{ "targets": targets }],
+                "tls_config": {
+                    "ca_file": format!("{}/ca.pem", Self::CERTS_DIR),
+                    "cert_file": format!("{}/prometheus.pem", Self::CERTS_DIR),
+                    "key_file": format!("{}/prometheus-key.pem", Self::CERTS_DIR)
+                }
+            })
+        };
+        let local = |port: u16| vec![format!("{}:{}", Ipv4Addr::LOCALHOST, port)];
+        let kubelets = (0..config.nodes())
+            .map(|n| format!("{}:{}", Node::ip(n), Kubelet::port(config, n)))
+            .collect::<Vec<_>>();
+
+        let jobs = vec![
+            job("apiserver", "/metrics", local(config.api_port())),
+            job(
+                "controller-manager",
+                "/metrics",
+                local(ControllerManager::port(config)),
+            ),
+            job("scheduler", "/metrics", local(Scheduler::port(config))),
+            job("kubelet", "/metrics", kubelets.clone()),
+            job("cadvisor", "/metrics/cadvisor", kubelets),
+        ];
+        json!({
+            "global": { "scrape_interval": "15s" },
+            "scrape_configs": jobs
+        })
+    }
+
+    /// Create the Grafana dashboard showing the most important metrics of
+    /// the cluster
+    fn dashboard() -> Value {
+        let panels = [
+            (
+                "API server requests per second",
+                "sum(rate(apiserver_request_total[5m])) by (verb)",
+            ),
+            (
+                "API server request latency (p99)",
+                "histogram_quantile(0.99, sum(rate(apiserver_request_duration_seconds_bucket{verb!=\"WATCH\"}[5m])) by (le, verb))",
+            ),
+            (
+                "etcd database size",
+                "apiserver_storage_db_total_size_in_bytes or etcd_db_total_size_in_bytes",
+            ),
+            ("Pending pods", "sum(scheduler_pending_pods) by (queue)"),
+            ("Running pods per node", "kubelet_running_pods"),
+            ("Scrape targets up", "up"),
+            (
+                "Container CPU usage per namespace",
+                "sum(rate(container_cpu_usage_seconds_total{container!=\"\"}[5m])) by (namespace)",
+            ),
+            (
+                "Container memory usage per namespace",
+                "sum(container_memory_working_set_bytes{container!=\"\"}) by (namespace)",
+            ),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, (title, expr))| {
+            json!({
+                "id": i + 1,
+                "type": "timeseries",
+                "title": title,
+                "datasource": "Prometheus",
+                "gridPos": { "h": 8, "w": 12, "x": (i % 2) * 12, "y": (i / 2) * 8 },
+                "targets": [{ "expr": expr, "refId": "A" }]
+            })
+        })
+        .collect::<Vec<_>>();
+        json!({
+            "uid": "kubernix",
+            "title": "KuberNix",
+            "refresh": "30s",
+            "time": { "from": "now-1h", "to": "now" },
+            "schemaVersion": 38,
+            "panels": panels
+        })
+    }
+
+    /// Create the Grafana datasources, which include Loki if the logging
+    /// addon is enabled as well
+    fn datasources(config: &Config) -> Value {
+        let mut datasources = vec![json!({
+            "name": "Prometheus",
+            "type": "prometheus",
+            "access": "proxy",
+            "url": format!("http://{}:{}", Ipv4Addr::LOCALHOST, Self::prometheus_port(config)),
+            "isDefault": true
+        })];
+        if config.addons().iter().any(|x| x == Logging::NAME) {
+            datasources.push(Logging::datasource(config));
+        }
+        json!({ "apiVersion": 1, "datasources": datasources })
+    }
+
+    /// Render the manifest of the addon, which contains the provided
+    /// certificate secret data
+    fn render(config: &Config, certs: &str) -> Result<String> {
+        Ok(format!(
+            include_str!("assets/observability.yml"),
+            user = PROMETHEUS_USER,
+            certs = certs,
+            prometheus_config = Self::quote(&Self::prometheus_config(config))?,
+            prometheus_image = config.image("prometheus", Self::PROMETHEUS_IMAGE)?,
+            prometheus_port = Self::prometheus_port(config),
+            certs_dir = Self::CERTS_DIR,
+            datasources = Self::quote(&Self::datasources(config))?,
+            dashboards = Self::quote(&json!({
+                "apiVersion": 1,
+                "providers": [{
+                    "name": "kubernix",
+                    "type": "file",
+                    "options": { "path": "/etc/grafana/dashboards" }
+                }]
+            }))?,
+            dashboard = Self::quote(&Self::dashboard())?,
+            grafana_image = config.image("grafana", Self::GRAFANA_IMAGE)?,
+            grafana_port = Self::grafana_port(config),
+        ))
+    }
+
+    /// Quote the provided value as single line YAML string
+    fn quote(value: &Value) -> Result<String> {
+        Ok(serde_json::to_string(&value.to_string())?)
+    }
+}
+
+impl Addon for Observability {
+    fn name(&self) -> String {
+        Observability::NAME.into()
+    }
+
+    fn apply(&self, config: &Config, network: &Network, kubectl: &Kubectl) -> Result<()> {
+        info!("Deploying Prometheus and Grafana and waiting to be ready");
+
+        let dir = config.root().join(Observability::NAME);
+        create_dir_all(&dir)?;
+
+        // The manifest embeds the current certificates, which is why it gets
+        // always rendered again
+        let pki = Pki::new(config, network)?;
+        let yml = Observability::render(config, &Observability::certs(&pki)?)?;
+        let file = dir.join("observability.yml");
+        fs::write(&file, yml)?;
+
+        kubectl
+            .apply(&file)
+            .context("Unable to deploy Prometheus and Grafana")?;
+        kubectl.wait_ready("prometheus")?;
+        kubectl.wait_ready("grafana")?;
+        info!(
+            "Prometheus and Grafana deployed, the dashboards are available on {}",
+            Observability::url(config)
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{test_config, test_config_with};
+
+    #[test]
+    fn url_success() -> Result<()> {
+        assert_eq!(Observability::url(&test_config()?), "http://127.0.0.1:3000");
+        let c = test_config_with(|c| {
+            c.set_api_port(7443)
+                .set_etcd_port(2379)
+                .set_kubelet_port(11250);
+        })?;
+        assert_eq!(Observability::url(&c), "http://127.0.0.1:4000");
+        assert_eq!(Observability::prometheus_port(&c), 10090);
+        Ok(())
+    }
+
+    #[test]
+    fn prometheus_config_success() -> Result<()> {
+        let c = test_config_with(|c| {
+            c.set_nodes(2);
+        })?;
+        let value = Observability::prometheus_config(&c);
+        let jobs = value["scrape_configs"]
+            .as_array()
+            .context("no scrape configs")?;
+        assert_eq!(jobs.len(), 5);
+        assert_eq!(
+            jobs[3]["static_configs"][0]["targets"],
+            json!(["127.0.0.1:11250", "127.0.0.1:11251"])
+        );
+        assert!(jobs
+            .iter()
+            .all(|x| x["tls_config"]["cert_file"] == "/etc/prometheus/certs/prometheus.pem"));
+        Ok(())
+    }
+
+    #[test]
+    fn datasources_success() -> Result<()> {
+        let value = Observability::datasources(&test_config()?);
+        assert_eq!(value["datasources"].as_array().map(Vec::len), Some(1));
+
+        let c = test_config_with(|c| {
+            c.set_addons(vec!["observability".into(), "logging".into()]);
+        })?;
+        let value = Observability::datasources(&c);
+        assert_eq!(value["datasources"][1]["type"], "loki");
+        Ok(())
+    }
+
+    #[test]
+    fn render_success() -> Result<()> {
+        let c = test_config()?;
+        let yml = Observability::render(&c, "  ca.pem: YWJj\n")?;
+        let docs = yml
+            .split("\n---\n")
+            .map(serde_yaml::from_str::<serde_yaml::Value>)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(docs.len(), 7);
+        assert_eq!(
+            docs[1]["subjects"][0]["name"].as_str(),
+            Some(PROMETHEUS_USER)
+        );
+        let prometheus: serde_yaml::Value = serde_yaml::from_str(
+            docs[3]["data"]["prometheus.yml"]
+                .as_str()
+                .context("no prometheus config")?,
+        )?;
+        assert_eq!(prometheus["scrape_configs"][0]["job_name"], "apiserver");
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::{bail, Result};
+use getset::Getters;
+use std::{env::consts, fs::read_to_string, path::PathBuf};
+
+/// The host platform kubernix is running on
+#[derive(Getters)]
+pub struct Platform {
+    #[get = "pub"]
+    /// The operating system, like `linux`
+    os: String,
+
+    #[get = "pub"]
+    /// The CPU architecture, like `x86_64`
+    arch: String,
+
+    #[get = "pub"]
+    /// The kernel version string of `/proc/version`
+    kernel: String,
+}
+
+impl Platform {
+    /// The architectures for which nixpkgs provides all cluster binaries
+    const ARCHITECTURES: &'static [&'static str] = &["x86_64", "aarch64"];
+
+    /// Detect the platform of the current host
+    pub fn detect() -> Platform {
+        let kernel = read_to_string(PathBuf::from("/").join("proc").join("version"))
+            .unwrap_or_default()
+            .trim()
+            .into();
+        Platform {
+            os: consts::OS.into(),
+            arch: consts::ARCH.into(),
+            kernel,
+        }
+    }
+
+    /// Verify that the platform is able to run a cluster before anything gets
+    /// bootstrapped, whereas unsupported platforms fail with a suggestion
+    pub fn check(&self) -> Result<()> {
+        match self.os.as_str() {
+            "linux" => {}
+            "windows" => {
+                bail!("Windows is not supported, please run kubernix inside WSL2 or a Linux VM")
+            }
+            "macos" => {
+                bail!("macOS is not supported, please run kubernix inside a Linux VM or container")
+            }
+            os => bail!(
+                "Operating system {} is not supported, kubernix requires Linux",
+                os
+            ),
+        }
+
+        // WSL1 translates syscalls and lacks namespaces, cgroups and netfilter
+        if self.kernel.contains("Microsoft") {
+            bail!("WSL1 is not supported, please convert the distribution to WSL2 via `wsl --set-version <distro> 2`")
+        }
+
+        if !Self::ARCHITECTURES.contains(&self.arch.as_str()) {
+            let suggestion = if self.arch.starts_with("arm") {
+                "please use a 64 bit (aarch64) operating system"
+            } else {
+                "please use a Linux VM or container on one of them"
+            };
+            bail!(
+                "Architecture {} is not supported, only {} provide all cluster binaries, {}",
+                self.arch,
+                Self::ARCHITECTURES.join(" and "),
+                suggestion
+            )
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn platform(os: &str, arch: &str, kernel: &str) -> Platform {
+        Platform {
+            os: os.into(),
+            arch: arch.into(),
+            kernel: kernel.into(),
+        }
+    }
+
+    #[test]
+    fn detect_success() -> Result<()> {
+        let p = Platform::detect();
+        assert_eq!(p.os(), consts::OS);
+        assert_eq!(p.arch(), consts::ARCH);
+        p.check()
+    }
+
+    #[test]
+    fn check_success() -> Result<()> {
+        platform("linux", "x86_64", "Linux version 5.8.0").check()?;
+        platform("linux", "aarch64", "Linux version 5.8.0").check()?;
+        platform(
+            "linux",
+            "x86_64",
+            "Linux version 5.4.72-microsoft-standard-WSL2",
+        )
+        .check()
+    }
+
+    #[test]
+    fn check_failure() {
+        for (os, arch, kernel) in &[
+            ("windows", "x86_64", ""),
+            ("macos", "aarch64", ""),
+            ("freebsd", "x86_64", ""),
+            ("linux", "x86_64", "Linux version 4.4.0-19041-Microsoft"),
+            ("linux", "arm", "Linux version 5.8.0"),
+            ("linux", "riscv64", "Linux version 5.8.0"),
+        ] {
+            assert!(platform(os, arch, kernel).check().is_err());
+        }
+    }
+}
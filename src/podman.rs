@@ -12,6 +12,9 @@ impl Podman {
     /// The executable name
     pub const EXECUTABLE: &'static str = "podman";
 
+    /// The default storage root of podman
+    const GRAPH_ROOT: &'static str = "/var/lib/containers/storage";
+
     /// Returns true if podman is configured as container runtime
     pub fn is_configured(config: &Config) -> bool {
         config.container_runtime() == Self::EXECUTABLE
@@ -32,6 +35,9 @@ impl Podman {
             "build".into(),
             format!("--signature-policy={}", policy_json.display()),
         ]);
+        if let Some(auth_file) = config.auth_file() {
+            args.push(format!("--authfile={}", auth_file.display()));
+        }
 
         Ok(args)
     }
@@ -53,6 +59,8 @@ impl Podman {
         ];
         if System::in_container()? {
             args.push("--storage-driver=vfs".into());
+        } else if let Some(driver) = System::storage_driver(Path::new(Self::GRAPH_ROOT))? {
+            args.push(format!("--storage-driver={}", driver));
         }
         Ok(args)
     }
@@ -1,30 +1,139 @@
-use crate::system::System;
-use anyhow::{bail, Context, Result};
-use crossbeam_channel::{bounded, Receiver, Sender};
-use log::{debug, error, info};
+use crate::{
+    config::{Config, RestartPolicy},
+    error::ErrorKind,
+    graph::Unit,
+    pki::Pki,
+    record::Recorder,
+    status::Status,
+    system::System,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
 use nix::{
-    sys::signal::{kill, Signal},
-    unistd::Pid,
+    errno::Errno,
+    sys::{
+        inotify::{AddWatchFlags, InitFlags, Inotify},
+        signal::{killpg, Signal},
+    },
+    unistd::{close, setpgid, Pid},
 };
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
-    fs::{self, create_dir_all, File},
-    io::{BufRead, BufReader},
+    collections::BTreeMap,
+    fs::{self, create_dir_all, File, OpenOptions},
+    io::{self, SeekFrom, Write},
+    net::Ipv4Addr,
+    os::unix::io::AsRawFd,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
-    thread::{spawn, JoinHandle},
-    time::Instant,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    fs::File as AsyncFile,
+    io::{unix::AsyncFd, AsyncBufReadExt, AsyncSeekExt, BufReader},
+    process::Command,
+    runtime::{Builder, Runtime},
+    select,
+    sync::{oneshot, watch},
+    task::JoinHandle,
+    time::{sleep, timeout},
 };
 
+lazy_static! {
+    /// The async runtime supervising all processes
+    pub static ref RUNTIME: Runtime = Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("kubernix-runtime")
+        .worker_threads(2)
+        .build()
+        .expect("Unable to create async runtime");
+}
+
+/// The automatic restart, readiness and stopping behavior of a process
+#[derive(Clone)]
+pub struct ProcessOptions {
+    /// The restart policy of crashed processes
+    restart_policy: RestartPolicy,
+
+    /// The maximum number of restarts of crashed processes
+    max_restarts: u32,
+
+    /// The readiness timeouts by component
+    readiness_timeouts: BTreeMap<String, u64>,
+
+    /// The default readiness timeout
+    readiness_timeout: u64,
+
+    /// The number of retries of processes which do not become ready in time
+    readiness_retries: u32,
+
+    /// The seconds between SIGTERM and SIGKILL when stopping a process
+    stop_grace_period: u64,
+
+    /// The file recording the process groups of all spawned processes
+    pid_file: Option<PathBuf>,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            restart_policy: RestartPolicy::Never,
+            max_restarts: 0,
+            readiness_timeouts: BTreeMap::new(),
+            readiness_timeout: 120,
+            readiness_retries: 0,
+            stop_grace_period: 10,
+            pid_file: None,
+        }
+    }
+}
+
+impl ProcessOptions {
+    /// Create the process options from the provided config
+    pub fn new(config: &Config) -> Self {
+        Self {
+            restart_policy: config.restart_policy(),
+            max_restarts: config.max_restarts(),
+            readiness_timeouts: config.component_timeout_overrides().unwrap_or_default(),
+            readiness_timeout: config.readiness_timeout(),
+            readiness_retries: config.readiness_retries(),
+            stop_grace_period: config.stop_grace_period(),
+            pid_file: Some(Process::pid_file(config)),
+        }
+    }
+
+    /// The readiness timeout and retries of the process identifier, whereas
+    /// the timeout of its component takes precedence over the default one
+    fn readiness(&self, identifier: &str) -> (u64, u32) {
+        let timeout = Unit::component(identifier)
+            .and_then(|x| self.readiness_timeouts.get(x))
+            .copied()
+            .unwrap_or(self.readiness_timeout);
+        (timeout, self.readiness_retries)
+    }
+}
+
 /// A general process abstraction
 pub struct Process {
     command: String,
-    died: Receiver<()>,
-    kill: Sender<()>,
+    died: watch::Receiver<bool>,
+    dir: PathBuf,
+    kill: Option<oneshot::Sender<()>>,
     log_file: PathBuf,
     name: String,
-    pid: u32,
+    options: ProcessOptions,
+    pid: Arc<AtomicU32>,
+    readiness: Arc<Mutex<Option<Readiness>>>,
     readyness_timeout: u64,
+    readiness_retries: u32,
+    stop_grace_period: u64,
     watch: Option<JoinHandle<Result<()>>>,
 }
 
@@ -32,6 +141,11 @@ pub struct Process {
 pub trait Stoppable {
     /// Stop the process
     fn stop(&mut self) -> Result<()>;
+
+    /// Start the stopped process again, which is not supported by default
+    fn restart(&mut self) -> Result<()> {
+        bail!("Restarting is not supported")
+    }
 }
 
 /// A started process
@@ -43,16 +157,200 @@ pub type Stoppables = Vec<Started>;
 /// The process state as result
 pub type ProcessState = Result<Started>;
 
-#[derive(Deserialize, Serialize)]
+/// The available ways to detect that a process is ready
+#[derive(Clone)]
+pub enum Readiness {
+    /// Search for the pattern in every line of the process output
+    LogPattern(String),
+
+    /// Poll the CRI runtime status via crictl on the provided endpoint
+    Cri(String),
+
+    /// Poll the HTTP endpoint until it responds with a successful status
+    HttpGet {
+        /// The URL of the endpoint
+        url: String,
+
+        /// The CA to verify the serving certificate of HTTPS endpoints
+        ca: Option<PathBuf>,
+
+        /// The client certificate and key used for authentication
+        client: Option<(PathBuf, PathBuf)>,
+    },
+}
+
+impl Readiness {
+    /// Probe the `healthz` endpoint on the provided port, whereas the admin
+    /// identity is used to authenticate against the endpoint
+    pub fn healthz(port: u16, pki: &Pki) -> Readiness {
+        Self::https(port, "healthz", pki)
+    }
+
+    /// Probe the `readyz` endpoint on the provided port, whereas the admin
+    /// identity is used to authenticate against the endpoint
+    pub fn readyz(port: u16, pki: &Pki) -> Readiness {
+        Self::https(port, "readyz", pki)
+    }
+
+    /// Probe the unauthenticated `healthz` endpoint on the provided port
+    pub fn http_healthz(port: u16) -> Readiness {
+        Readiness::HttpGet {
+            url: format!("http://{}:{}/healthz", Ipv4Addr::LOCALHOST, port),
+            ca: None,
+            client: None,
+        }
+    }
+
+    fn https(port: u16, path: &str, pki: &Pki) -> Readiness {
+        Readiness::HttpGet {
+            url: format!("https://{}:{}/{}", Ipv4Addr::LOCALHOST, port, path),
+            ca: Some(pki.ca().cert().into()),
+            client: Some((pki.admin().cert().into(), pki.admin().key().into())),
+        }
+    }
+}
+
+impl From<&str> for Readiness {
+    fn from(pattern: &str) -> Self {
+        Readiness::LogPattern(pattern.into())
+    }
+}
+
+/// An inotify based watch for file modifications
+pub struct FileWatch(Option<AsyncFd<Inotify>>);
+
+impl FileWatch {
+    /// Fallback interval in case modifications are not reported, for example
+    /// if the file gets replaced
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(path: &Path) -> Result<FileWatch> {
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)?;
+        let watch = FileWatch(Some(AsyncFd::new(inotify)?));
+        inotify.add_watch(path, AddWatchFlags::IN_MODIFY)?;
+        Ok(watch)
+    }
+
+    /// Wait until the file got modified, or at least for the fallback interval
+    pub async fn modified(&self) -> Result<()> {
+        let fd = self.0.as_ref().context("No inotify instance available")?;
+        if let Ok(guard) = timeout(Self::INTERVAL, fd.readable()).await {
+            let mut guard = guard?;
+            match fd.get_ref().read_events() {
+                Ok(_) | Err(Errno::EAGAIN) => guard.clear_ready(),
+                Err(e) => bail!("Unable to read inotify events: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FileWatch {
+    fn drop(&mut self) {
+        // The inotify instance does not close its file descriptor on drop
+        if let Some(fd) = self.0.take() {
+            if let Err(e) = close(fd.into_inner().as_raw_fd()) {
+                debug!("Unable to close inotify instance: {}", e)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 struct Run {
     command: PathBuf,
     args: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<String, String>,
 }
 
 impl Process {
-    /// Creates a new `Process` instance by spawning the provided `command` and `args`.
+    /// The maximum delay before restarting a crashed process
+    const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+
+    /// The time to probe a died process once more, which may have become
+    /// ready right before exiting
+    const DIED_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Retrieve the path to the file recording the spawned process groups
+    fn pid_file(config: &Config) -> PathBuf {
+        config.root().join("processes.pid")
+    }
+
+    /// Record the process group of the spawned process together with its
+    /// start time, which allows to detect reused pids later on
+    fn record_pid(pid_file: Option<&Path>, pid: u32) -> Result<()> {
+        if let Some(file) = pid_file {
+            let mut f = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)
+                .with_context(|| format!("Unable to open pid file '{}'", file.display()))?;
+            writeln!(f, "{} {}", pid, Self::start_time(pid).unwrap_or_default())?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve the start time of the process in clock ticks after boot
+    pub fn start_time(pid: u32) -> Option<u64> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // The command name in parentheses may contain whitespace, whereas the
+        // start time is the 22nd field
+        stat.get(stat.rfind(')')? + 1..)?
+            .split_whitespace()
+            .nth(19)?
+            .parse()
+            .ok()
+    }
+
+    /// Kill all process groups recorded by a crashed kubernix instance and
+    /// remove the pid file afterwards. Process groups whose leader pid has
+    /// been reused by another process are skipped.
+    pub fn kill_orphans(config: &Config) -> Result<()> {
+        let file = Self::pid_file(config);
+        if !file.exists() {
+            return Ok(());
+        }
+        let content = fs::read_to_string(&file)
+            .with_context(|| format!("Unable to read pid file '{}'", file.display()))?;
+        for (pid, start_time) in Self::orphans(&content) {
+            if matches!(Self::start_time(pid), Some(x) if x != start_time) {
+                debug!("Skipping reused pid {}", pid);
+                continue;
+            }
+            match killpg(Pid::from_raw(pid as i32), Signal::SIGKILL) {
+                Ok(()) => info!("Killed orphaned process group {}", pid),
+                Err(Errno::ESRCH) => {}
+                Err(e) => warn!("Unable to kill process group {}: {}", pid, e),
+            }
+        }
+        fs::remove_file(&file)
+            .with_context(|| format!("Unable to remove pid file '{}'", file.display()))
+    }
+
+    /// Parse the process groups and start times of the pid file content
+    fn orphans(content: &str) -> Vec<(u32, u64)> {
+        content
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+            })
+            .collect()
+    }
+
+    /// Creates a new `Process` instance by spawning the provided `command` and `args`
+    /// together with the additional environment variables `env`.
     /// If the process creation fails, an `Error` will be returned.
-    pub fn start(dir: &Path, identifier: &str, command: &str, args: &[&str]) -> Result<Process> {
+    pub fn start(
+        options: &ProcessOptions,
+        dir: &Path,
+        identifier: &str,
+        command: &str,
+        args: &[&str],
+        env: &BTreeMap<String, String>,
+    ) -> Result<Process> {
         // Prepare the commands
         if command.is_empty() {
             bail!("No valid command provided")
@@ -73,6 +371,7 @@ impl Process {
             let f = Run {
                 command: System::find_executable(command)?,
                 args: args.iter().map(|x| (*x).to_string()).collect(),
+                env: env.clone(),
             };
             fs::write(run_file, serde_yaml::to_string(&f)?)?;
             f
@@ -86,82 +385,365 @@ impl Process {
         let mut log_file = dir.join(command);
         log_file.set_extension("log");
         let out_file = File::create(&log_file)?;
-        let err_file = out_file.try_clone()?;
 
-        // Spawn the process child
-        let mut child = Command::new(run.command)
-            .args(run.args)
-            .stderr(Stdio::from(err_file))
-            .stdout(Stdio::from(out_file))
+        // Spawn the process child, which has to happen inside the runtime
+        let _guard = RUNTIME.enter();
+        let mut process = Self::command(&run, out_file)?;
+        Recorder::record(process.as_std(), None, None, Duration::default(), true);
+        let mut child = process
             .spawn()
             .with_context(|| format!("Unable to start process '{}' ({})", identifier, command,))?;
+        let pid = Arc::new(AtomicU32::new(
+            child.id().context("Unable to retrieve process ID")?,
+        ));
+        Self::record_pid(options.pid_file.as_deref(), pid.load(Ordering::SeqCst))?;
 
-        // Start the watcher thread
-        let (kill, killed) = bounded(1);
-        let (dead, died) = bounded(1);
+        // Start the watcher task, which restarts the process if it died
+        // unexpectedly and the restart policy allows it
+        let (kill, mut killed) = oneshot::channel();
+        let (dead, died) = watch::channel(false);
+        let readiness = Arc::new(Mutex::new(None));
+        let (readyness_timeout, readiness_retries) = options.readiness(identifier);
+        let (policy, max_restarts) = (options.restart_policy, options.max_restarts);
+        let pid_file = options.pid_file.clone();
         let c = command.to_owned();
         let n = identifier.to_owned();
-        let pid = child.id();
-        let watch = spawn(move || {
-            // Wait for the process to exit
-            let status = child.wait()?;
+        let l = log_file.clone();
+        let p = pid.clone();
+        let r = readiness.clone();
+        Status::set_component(identifier, pid.load(Ordering::SeqCst), false, &log_file);
+        let watch = RUNTIME.spawn(async move {
+            let mut restarts = 0;
+            let mut stopping = false;
+            loop {
+                // Wait for the process to exit
+                let status = child.wait().await?;
+                Status::set_component(&n, p.load(Ordering::SeqCst), false, &l);
+                debug!("{} ({}) {}", n, c, status);
 
-            // No kill send, we assume that the process died
-            if killed.try_recv().is_err() {
+                // No kill send, we assume that the process died
+                stopping |= killed.try_recv().is_ok();
+                if stopping {
+                    info!("{} stopped", n);
+                    return Ok(());
+                }
                 error!("{} ({}) died unexpectedly", n, c);
-                dead.send(())?;
-            } else {
-                info!("{} stopped", n);
+                if !Self::restartable(policy, status.success()) {
+                    dead.send(true)?;
+                    return Ok(());
+                }
+                if restarts >= max_restarts {
+                    error!("Giving up on {} after {} restarts", n, restarts);
+                    dead.send(true)?;
+                    return Ok(());
+                }
+
+                // Restart from the run file, whereas the output gets appended
+                // to the previous log
+                restarts += 1;
+                select! {
+                    _ = &mut killed => {
+                        info!("{} stopped", n);
+                        return Ok(());
+                    }
+                    _ = sleep(Self::restart_backoff(restarts)) => {}
+                }
+                info!("Restarting {} ({}/{})", n, restarts, max_restarts);
+                let out_file = OpenOptions::new().append(true).open(&l)?;
+                let offset = out_file.metadata()?.len();
+                child = Self::command(&run, out_file)?
+                    .spawn()
+                    .with_context(|| format!("Unable to restart process '{}' ({})", n, c))?;
+                p.store(
+                    child.id().context("Unable to retrieve process ID")?,
+                    Ordering::SeqCst,
+                );
+                Self::record_pid(pid_file.as_deref(), p.load(Ordering::SeqCst))?;
+                Status::set_component(&n, p.load(Ordering::SeqCst), false, &l);
+
+                // Wait for the same readiness as on the initial start
+                let readiness = r.lock().clone();
+                if let Some(readiness) = readiness {
+                    let duration = Duration::from_secs(readyness_timeout);
+                    select! {
+                        _ = &mut killed => stopping = true,
+                        result = timeout(duration, Self::probe(&n, &c, &l, offset, &readiness)) => {
+                            match result {
+                                Ok(Ok(())) => {
+                                    info!("{} is ready again", n);
+                                    Status::set_component(&n, p.load(Ordering::SeqCst), true, &l);
+                                }
+                                Ok(Err(e)) => error!("{} did not become ready: {:#}", n, e),
+                                Err(_) => error!("Timed out waiting for {} to become ready", n),
+                            }
+                        }
+                    }
+                }
             }
-            debug!("{} ({}) {}", n, c, status);
-            Ok(())
         });
 
         Ok(Process {
             command: command.into(),
             died,
-            kill,
+            dir: dir.into(),
+            kill: Some(kill),
             log_file,
             name: identifier.into(),
+            options: options.clone(),
             pid,
-            readyness_timeout: 120,
+            readiness,
+            readyness_timeout,
+            readiness_retries,
+            stop_grace_period: options.stop_grace_period,
             watch: Some(watch),
         })
     }
 
-    /// Wait for the process to become ready, by searching for the pattern in
-    /// every line of its output.
-    pub fn wait_ready(&mut self, pattern: &str) -> Result<()> {
+    /// Create the command of the run file, whose output gets written to the
+    /// provided file. The process leads its own process group, which allows
+    /// to kill it together with all of its children.
+    fn command(run: &Run, out_file: File) -> Result<Command> {
+        let err_file = out_file.try_clone()?;
+        let mut command = Command::new(&run.command);
+        command
+            .args(&run.args)
+            .envs(&run.env)
+            .stderr(Stdio::from(err_file))
+            .stdout(Stdio::from(out_file));
+        // setpgid is async-signal-safe and can therefore be called between
+        // fork and exec
+        unsafe {
+            command
+                .pre_exec(|| setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(io::Error::from));
+        }
+        Ok(command)
+    }
+
+    /// Send the signal to the process group, which may be already gone while
+    /// being restarted
+    fn signal(&self, signal: Signal) -> Result<()> {
+        match killpg(
+            Pid::from_raw(self.pid.load(Ordering::SeqCst) as i32),
+            signal,
+        ) {
+            Err(Errno::ESRCH) => debug!("Process {} already exited", self.name),
+            result => result?,
+        }
+        Ok(())
+    }
+
+    /// Returns true if the restart policy applies to a process which exited
+    /// unexpectedly with the provided success state
+    fn restartable(policy: RestartPolicy, success: bool) -> bool {
+        match policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !success,
+            RestartPolicy::Always => true,
+        }
+    }
+
+    /// The exponential delay before the provided restart attempt
+    fn restart_backoff(restart: u32) -> Duration {
+        Duration::from_secs(1 << restart.saturating_sub(1).min(5)).min(Self::MAX_RESTART_BACKOFF)
+    }
+
+    /// Wait for the process to become ready, either by searching for a log
+    /// pattern or by probing the process directly. Processes which do not
+    /// become ready in time are started again for the configured retries.
+    pub fn wait_ready<R: Into<Readiness>>(&mut self, readiness: R) -> Result<()> {
+        let readiness = readiness.into();
+        let mut retry = 0;
+        loop {
+            let duration = Duration::from_secs(self.readyness_timeout);
+            let result =
+                RUNTIME.block_on(async { timeout(duration, self.ready(&readiness)).await });
+            if let Ok(ready) = result {
+                ready?;
+                info!("{} is ready", self.name);
+                Status::set_component(
+                    &self.name,
+                    self.pid.load(Ordering::SeqCst),
+                    true,
+                    &self.log_file,
+                );
+                *self.readiness.lock() = Some(readiness);
+                return Ok(());
+            }
+
+            // Cleanup since process is not ready
+            self.stop()?;
+            if retry >= self.readiness_retries {
+                break;
+            }
+            retry += 1;
+            warn!(
+                "Process '{}' ({}) did not become ready within {}s, retrying ({}/{})",
+                self.name, self.command, self.readyness_timeout, retry, self.readiness_retries
+            );
+            let mut process = Process::start(
+                &self.options,
+                &self.dir,
+                &self.name,
+                &self.command,
+                &[],
+                &BTreeMap::new(),
+            )?;
+            process.readyness_timeout = self.readyness_timeout;
+            process.readiness_retries = self.readiness_retries;
+            *self = process;
+        }
+        error!(
+            "Timed out waiting for process '{}' ({}) to become ready",
+            self.name, self.command
+        );
+        Err(anyhow!("Process timeout").context(ErrorKind::ReadinessTimeout))
+    }
+
+    /// Describe the process for the cluster summary
+    pub fn describe(&self, binary: &str, endpoint: &str, kubeconfig: Option<&Path>) {
+        Status::describe_component(&self.name, binary, endpoint, kubeconfig)
+    }
+
+    /// Probe the readiness until it succeeds or the process dies, whereas a
+    /// died process gets probed once more to not miss its final output
+    async fn ready(&self, readiness: &Readiness) -> Result<()> {
+        let mut died = self.died.clone();
+        let probe = || Self::probe(&self.name, &self.command, &self.log_file, 0, readiness);
+        select! {
+            biased;
+            ready = probe() => ready,
+            _ = died.changed() => match timeout(Self::DIED_PROBE_TIMEOUT, probe()).await {
+                Ok(Ok(())) => Ok(()),
+                _ => bail!("{} ({}) died", self.command, self.name),
+            },
+        }
+    }
+
+    /// Probe the readiness of the process until it succeeds, whereas only
+    /// the output after the offset of the log file is considered
+    async fn probe(
+        name: &str,
+        command: &str,
+        log_file: &Path,
+        offset: u64,
+        readiness: &Readiness,
+    ) -> Result<()> {
+        match readiness {
+            Readiness::LogPattern(pattern) => {
+                Self::wait_log_pattern(name, command, log_file, offset, pattern).await
+            }
+            Readiness::Cri(endpoint) => Self::wait_cri(name, command, endpoint).await,
+            Readiness::HttpGet { url, ca, client } => {
+                Self::wait_http(name, command, url, ca.as_deref(), client.as_ref()).await
+            }
+        }
+    }
+
+    /// Search for the pattern in every line of the process output
+    async fn wait_log_pattern(
+        name: &str,
+        command: &str,
+        log_file: &Path,
+        offset: u64,
+        pattern: &str,
+    ) -> Result<()> {
         debug!(
             "Waiting for process '{}' ({}) to become ready with pattern: '{}'",
-            self.name, self.command, pattern
+            name, command, pattern
         );
-        let now = Instant::now();
-        let file = File::open(&self.log_file)?;
+        let watch = FileWatch::new(log_file)?;
+        let mut file = AsyncFile::open(log_file).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
         let mut reader = BufReader::new(file);
 
-        while now.elapsed().as_secs() < self.readyness_timeout {
-            let mut line = String::new();
-            reader.read_line(&mut line)?;
-
+        let mut line = String::new();
+        loop {
+            let read = reader.read_line(&mut line).await?;
             if line.contains(pattern) {
-                info!("{} is ready", self.name);
                 debug!("Found pattern '{}' in line '{}'", pattern, line.trim());
                 return Ok(());
             }
 
-            if self.died.try_recv().is_ok() {
-                bail!("{} ({}) died", self.command, self.name)
+            // End of file reached, keep incomplete lines and wait for new output
+            if read == 0 || !line.ends_with('\n') {
+                watch.modified().await?;
+                continue;
             }
+            line.clear();
         }
+    }
 
-        // Cleanup since process is not ready
-        self.stop()?;
-        error!(
-            "Timed out waiting for process '{}' ({}) to become ready",
-            self.name, self.command
+    /// Poll `crictl info` until the runtime reports the `RuntimeReady` condition
+    async fn wait_cri(name: &str, command: &str, endpoint: &str) -> Result<()> {
+        debug!(
+            "Waiting for process '{}' ({}) to become ready on CRI endpoint: '{}'",
+            name, command, endpoint
+        );
+        loop {
+            let output = Command::new("crictl")
+                .arg(format!("--runtime-endpoint={}", endpoint))
+                .arg("info")
+                .output()
+                .await
+                .context("Unable to run crictl")?;
+            if output.status.success() && Self::cri_runtime_ready(&output.stdout) {
+                debug!("CRI runtime on '{}' is ready", endpoint);
+                return Ok(());
+            }
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    /// Poll the HTTP endpoint via curl until it responds with a successful
+    /// status, like the HTTP probes of the kubelet
+    async fn wait_http(
+        name: &str,
+        command: &str,
+        url: &str,
+        ca: Option<&Path>,
+        client: Option<&(PathBuf, PathBuf)>,
+    ) -> Result<()> {
+        debug!(
+            "Waiting for process '{}' ({}) to become ready on health endpoint: '{}'",
+            name, command, url
         );
-        bail!("Process timeout")
+        loop {
+            let output = Command::new("curl")
+                .args(Self::curl_args(url, ca, client))
+                .output()
+                .await?;
+            if output.status.success() {
+                debug!("Health endpoint '{}' reports ok", url);
+                return Ok(());
+            }
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    /// The curl arguments to probe the HTTP endpoint, whereas responses with
+    /// a status code of 400 or higher result in a failure
+    fn curl_args(url: &str, ca: Option<&Path>, client: Option<&(PathBuf, PathBuf)>) -> Vec<String> {
+        let mut args = vec!["--silent".into(), "--fail".into(), "--max-time=5".into()];
+        if let Some(ca) = ca {
+            args.push(format!("--cacert={}", ca.display()));
+        }
+        if let Some((cert, key)) = client {
+            args.push(format!("--cert={}", cert.display()));
+            args.push(format!("--key={}", key.display()));
+        }
+        args.push(url.into());
+        args
+    }
+
+    /// Returns true if the `crictl info` output contains a true `RuntimeReady`
+    /// condition
+    fn cri_runtime_ready(info: &[u8]) -> bool {
+        serde_json::from_slice::<Value>(info)
+            .ok()
+            .and_then(|x| x["status"]["conditions"].as_array().cloned())
+            .unwrap_or_default()
+            .iter()
+            .any(|x| x["type"] == "RuntimeReady" && x["status"] == true)
     }
 
     /// Retrieve a pseudo state for stopped processes
@@ -176,19 +758,36 @@ impl Stoppable for Process {
         debug!("Stopping process {} (via {})", self.name, self.command);
 
         // Indicate that this shutdown is intended
-        self.kill.send(()).with_context(|| {
-            format!(
-                "Unable to send kill signal to process {} (via {})",
-                self.name, self.command,
-            )
-        })?;
+        self.kill
+            .take()
+            .and_then(|x| x.send(()).ok())
+            .with_context(|| {
+                format!(
+                    "Unable to send kill signal to process {} (via {})",
+                    self.name, self.command,
+                )
+            })?;
 
-        // Send SIGTERM to the process
-        kill(Pid::from_raw(self.pid as i32), Signal::SIGTERM)?;
+        self.signal(Signal::SIGTERM)?;
 
-        // Join the waiting thread
-        if let Some(handle) = self.watch.take() {
-            if handle.join().is_err() {
+        // Join the watcher task, whereas the process group gets killed if it
+        // does not exit within the grace period
+        if let Some(mut handle) = self.watch.take() {
+            let grace_period = Duration::from_secs(self.stop_grace_period);
+            let joined = RUNTIME.block_on(async {
+                match timeout(grace_period, &mut handle).await {
+                    Ok(joined) => Ok(joined),
+                    Err(_) => {
+                        warn!(
+                            "Process {} (via {}) did not stop within {}s, killing it",
+                            self.name, self.command, self.stop_grace_period
+                        );
+                        self.signal(Signal::SIGKILL)?;
+                        Ok::<_, anyhow::Error>(handle.await)
+                    }
+                }
+            })?;
+            if joined.is_err() {
                 bail!(
                     "Unable to stop process {} (via {})",
                     self.name,
@@ -199,11 +798,42 @@ impl Stoppable for Process {
         debug!("Process {} (via {}) stopped", self.name, self.command);
         Ok(())
     }
+
+    /// Restarting the process from its run file and waiting for the same
+    /// readiness as on its initial start
+    fn restart(&mut self) -> Result<()> {
+        if self.kill.is_some() {
+            bail!(
+                "Process {} (via {}) is still running",
+                self.name,
+                self.command
+            )
+        }
+        let mut process = Process::start(
+            &self.options,
+            &self.dir,
+            &self.name,
+            &self.command,
+            &[],
+            &BTreeMap::new(),
+        )?;
+        process.readyness_timeout = self.readyness_timeout;
+        process.readiness_retries = self.readiness_retries;
+        let readiness = self.readiness.lock().clone();
+        if let Some(readiness) = readiness {
+            process.wait_ready(readiness)?;
+        }
+        *self = process;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::tests::test_config;
+    use nix::sys::signal::kill;
+    use std::time::Instant;
     use tempfile::tempdir;
 
     #[test]
@@ -214,45 +844,330 @@ mod tests {
     #[test]
     fn start_success() -> Result<()> {
         let d = tempdir()?;
-        Process::start(d.path(), "", "echo", &[])?;
+        Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "echo",
+            &[],
+            &BTreeMap::new(),
+        )?;
         Ok(())
     }
 
     #[test]
     fn start_failure_no_command() -> Result<()> {
         let d = tempdir()?;
-        assert!(Process::start(d.path(), "", "", &[]).is_err());
+        assert!(Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "",
+            &[],
+            &BTreeMap::new()
+        )
+        .is_err());
         Ok(())
     }
 
     #[test]
     fn start_failure_invalid_command() -> Result<()> {
         let d = tempdir()?;
-        assert!(Process::start(d.path(), "", "invalid_command", &[]).is_err());
+        assert!(Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "invalid_command",
+            &[],
+            &BTreeMap::new()
+        )
+        .is_err());
         Ok(())
     }
 
     #[test]
     fn wait_ready_success() -> Result<()> {
         let d = tempdir()?;
-        let mut p = Process::start(d.path(), "", "echo", &["test"])?;
+        let mut p = Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "echo",
+            &["test"],
+            &BTreeMap::new(),
+        )?;
         p.wait_ready("test")?;
         Ok(())
     }
 
+    #[test]
+    fn file_watch_modified_success() -> Result<()> {
+        let d = tempdir()?;
+        let file = d.path().join("log");
+        fs::write(&file, "")?;
+        let now = Instant::now();
+        RUNTIME.block_on(async {
+            let watch = FileWatch::new(&file)?;
+            let writer = RUNTIME.spawn(async move {
+                sleep(Duration::from_millis(100)).await;
+                tokio::fs::write(file, "test").await
+            });
+            watch.modified().await?;
+            writer.await??;
+            Ok::<_, anyhow::Error>(())
+        })?;
+        assert!(now.elapsed() < FileWatch::INTERVAL);
+        Ok(())
+    }
+
+    #[test]
+    fn wait_ready_failure_cri() -> Result<()> {
+        let d = tempdir()?;
+        let mut p = Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "sleep",
+            &["10"],
+            &BTreeMap::new(),
+        )?;
+        p.readyness_timeout = 1;
+        let endpoint = format!("unix://{}", d.path().join("invalid.sock").display());
+        assert!(p.wait_ready(Readiness::Cri(endpoint)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cri_runtime_ready_success() {
+        assert!(Process::cri_runtime_ready(
+            br#"{"status":{"conditions":[
+                {"type":"RuntimeReady","status":true},
+                {"type":"NetworkReady","status":false}
+            ]}}"#
+        ));
+    }
+
+    #[test]
+    fn cri_runtime_ready_failure() {
+        assert!(!Process::cri_runtime_ready(b"invalid"));
+        assert!(!Process::cri_runtime_ready(
+            br#"{"status":{"conditions":[{"type":"RuntimeReady","status":false}]}}"#
+        ));
+    }
+
+    #[test]
+    fn curl_args_success() {
+        assert_eq!(
+            Process::curl_args("http://127.0.0.1:12250/healthz", None, None),
+            vec![
+                "--silent",
+                "--fail",
+                "--max-time=5",
+                "http://127.0.0.1:12250/healthz"
+            ]
+        );
+        let client = (PathBuf::from("admin.pem"), PathBuf::from("admin-key.pem"));
+        let args = Process::curl_args(
+            "https://127.0.0.1:6443/readyz",
+            Some(Path::new("ca.pem")),
+            Some(&client),
+        );
+        assert_eq!(
+            &args[3..],
+            &[
+                "--cacert=ca.pem",
+                "--cert=admin.pem",
+                "--key=admin-key.pem",
+                "https://127.0.0.1:6443/readyz"
+            ]
+        );
+    }
+
+    #[test]
+    fn wait_ready_failure_http() -> Result<()> {
+        let d = tempdir()?;
+        let mut p = Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "sleep",
+            &["10"],
+            &BTreeMap::new(),
+        )?;
+        p.readyness_timeout = 1;
+        assert!(p.wait_ready(Readiness::http_healthz(1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn wait_ready_failure_retries() -> Result<()> {
+        let d = tempdir()?;
+        let mut p = Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "sleep",
+            &["10"],
+            &BTreeMap::new(),
+        )?;
+        p.readyness_timeout = 1;
+        p.readiness_retries = 1;
+        let now = Instant::now();
+        assert!(p.wait_ready("invalid").is_err());
+        assert!(now.elapsed() >= Duration::from_secs(2));
+        Ok(())
+    }
+
+    #[test]
+    fn stop_success_kill() -> Result<()> {
+        let d = tempdir()?;
+        let mut p = Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "sh",
+            &["-c", "trap '' TERM; sleep 30"],
+            &BTreeMap::new(),
+        )?;
+        p.stop_grace_period = 1;
+        std::thread::sleep(Duration::from_millis(200));
+        let now = Instant::now();
+        p.stop()?;
+        assert!(now.elapsed() >= Duration::from_secs(1));
+        assert!(now.elapsed() < Duration::from_secs(10));
+        Ok(())
+    }
+
+    #[test]
+    fn orphans_success() {
+        assert_eq!(
+            Process::orphans("123 456\ninvalid\n789 0\n"),
+            vec![(123, 456), (789, 0)]
+        );
+    }
+
+    #[test]
+    fn options_readiness_success() {
+        let mut o = ProcessOptions::default();
+        o.readiness_timeouts.insert("apiserver".into(), 300);
+        o.readiness_retries = 2;
+        assert_eq!(o.readiness("API Server"), (300, 2));
+        assert_eq!(o.readiness("Kubelet node-1"), (120, 2));
+    }
+
+    #[test]
+    fn kill_orphans_success() -> Result<()> {
+        let c = test_config()?;
+        let d = tempdir()?;
+        let p = Process::start(
+            &ProcessOptions::new(&c),
+            d.path(),
+            "",
+            "sleep",
+            &["30"],
+            &BTreeMap::new(),
+        )?;
+        let pid = p.pid.load(Ordering::SeqCst);
+        assert_eq!(
+            fs::read_to_string(Process::pid_file(&c))?,
+            format!(
+                "{} {}\n",
+                pid,
+                Process::start_time(pid).context("no start time")?
+            ),
+        );
+        Process::kill_orphans(&c)?;
+        assert!(!Process::pid_file(&c).exists());
+        let now = Instant::now();
+        while kill(Pid::from_raw(pid as i32), None).is_ok() {
+            assert!(now.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        Ok(())
+    }
+
     #[test]
     fn wait_ready_failure() -> Result<()> {
         let d = tempdir()?;
-        let mut p = Process::start(d.path(), "", "echo", &["test"])?;
+        let mut p = Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "echo",
+            &["test"],
+            &BTreeMap::new(),
+        )?;
         p.readyness_timeout = 1;
         assert!(p.wait_ready("invalid").is_err());
         Ok(())
     }
 
+    #[test]
+    fn start_success_env() -> Result<()> {
+        let d = tempdir()?;
+        let env = vec![("KUBERNIX_TEST".to_owned(), "value".to_owned())]
+            .into_iter()
+            .collect();
+        let mut p = Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "sh",
+            &["-c", "echo $KUBERNIX_TEST && sleep 10"],
+            &env,
+        )?;
+        p.wait_ready("value")?;
+        p.stop()?;
+
+        // The environment is persisted in the run file
+        p.restart()?;
+        p.stop()
+    }
+
+    #[test]
+    fn restart_success() -> Result<()> {
+        let d = tempdir()?;
+        let mut p = Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "sh",
+            &["-c", "echo test && sleep 10"],
+            &BTreeMap::new(),
+        )?;
+        p.wait_ready("test")?;
+        assert!(p.restart().is_err());
+        p.stop()?;
+        p.restart()?;
+        p.stop()
+    }
+
+    #[test]
+    fn restartable_success() {
+        assert!(!Process::restartable(RestartPolicy::Never, false));
+        assert!(Process::restartable(RestartPolicy::OnFailure, false));
+        assert!(!Process::restartable(RestartPolicy::OnFailure, true));
+        assert!(Process::restartable(RestartPolicy::Always, true));
+    }
+
+    #[test]
+    fn restart_backoff_success() {
+        assert_eq!(Process::restart_backoff(1), Duration::from_secs(1));
+        assert_eq!(Process::restart_backoff(3), Duration::from_secs(4));
+        assert_eq!(Process::restart_backoff(10), Process::MAX_RESTART_BACKOFF);
+    }
+
     #[test]
     fn stop_success() -> Result<()> {
         let d = tempdir()?;
-        let mut p = Process::start(d.path(), "", "sleep", &["500"])?;
+        let mut p = Process::start(
+            &ProcessOptions::default(),
+            d.path(),
+            "",
+            "sleep",
+            &["500"],
+            &BTreeMap::new(),
+        )?;
         p.stop()?;
         Ok(())
     }
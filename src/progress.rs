@@ -1,65 +1,270 @@
+use anyhow::Result;
 use console::style;
 use indicatif::{ProgressBar, ProgressStyle};
 use lazy_static::lazy_static;
-use log::LevelFilter;
-use parking_lot::RwLock;
-use std::sync::{Arc, Weak};
+use log::{debug, LevelFilter};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+/// A named phase of the progress with its relative weight
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    name: &'static str,
+    weight: u64,
+}
+
+impl Step {
+    /// Create a new step, whereas the weight is at least one
+    pub fn new(name: &'static str, weight: u64) -> Step {
+        Step {
+            name,
+            weight: weight.max(1),
+        }
+    }
+}
 
 pub struct Progress {
-    inner: Option<Arc<ProgressBar>>,
+    inner: Option<Arc<Bar>>,
+}
+
+/// The shared progress bar including its step state
+pub struct Bar {
+    bar: ProgressBar,
+    steps: Vec<Step>,
+    history: Option<PathBuf>,
+    estimated: bool,
+    state: Mutex<State>,
+}
+
+struct State {
+    current: Option<usize>,
+    started: Instant,
+    durations: BTreeMap<String, u64>,
 }
 
 lazy_static! {
-    static ref PROGRESS_BAR: RwLock<Option<Weak<ProgressBar>>> = RwLock::new(None);
+    static ref PROGRESS_BAR: RwLock<Option<Weak<Bar>>> = RwLock::new(None);
 }
 
 impl Progress {
-    // Create a new global progress bar
-    pub fn new(items: u64, level: LevelFilter) -> Progress {
-        if level < LevelFilter::Info {
+    /// The file within the run root containing the step durations of the
+    /// previous runs in milliseconds
+    const HISTORY: &'static str = "progress.json";
+
+    // Create a new global progress bar for the provided steps, whereas the
+    // step durations of previous runs are used as weights if the history
+    // directory contains all of them. Without animation all messages are
+    // logged line by line.
+    pub fn new(
+        steps: &[Step],
+        level: LevelFilter,
+        animated: bool,
+        history_dir: Option<&Path>,
+    ) -> Progress {
+        if level < LevelFilter::Info || !animated {
             return Progress { inner: None };
         }
 
+        let history = history_dir.map(|x| x.join(Self::HISTORY));
+        let (steps, estimated) = match history.as_ref().map(|x| Self::estimate(steps, x)) {
+            Some(Some(estimated)) => (estimated, true),
+            _ => (steps.to_vec(), false),
+        };
+
         // Create the progress bar
-        let p = Arc::new(ProgressBar::new(items));
+        let total = steps.iter().map(|x| x.weight).sum();
+        let p = ProgressBar::new(total);
         p.set_style(ProgressStyle::default_bar().template(&format!(
             "{}{}{} {}",
-            style("[").white().dim(),
+            style("[").white().dim().for_stderr(),
             "{spinner:.green} {elapsed:>3}",
-            style("]").white().dim(),
-            "{bar:25.green/blue} {pos:>2}/{len} {msg}",
+            style("]").white().dim().for_stderr(),
+            "{bar:25.green/blue} {percent:>3}% {prefix} {msg}",
         )));
         p.enable_steady_tick(100);
 
+        let bar = Arc::new(Bar {
+            bar: p,
+            steps,
+            history,
+            estimated,
+            state: Mutex::new(State {
+                current: None,
+                started: Instant::now(),
+                durations: BTreeMap::new(),
+            }),
+        });
+
         // Set the global instance
-        *PROGRESS_BAR.write() = Some(Arc::downgrade(&p));
+        *PROGRESS_BAR.write() = Some(Arc::downgrade(&bar));
 
-        Progress { inner: Some(p) }
+        Progress { inner: Some(bar) }
     }
 
     // Get the progress bar
-    pub fn get() -> Option<Arc<ProgressBar>> {
+    pub fn get() -> Option<Arc<Bar>> {
         PROGRESS_BAR.read().as_ref()?.upgrade()
     }
 
+    // Enter the named step, which completes the current one
+    pub fn step(&self, name: &str) {
+        if let Some(p) = &self.inner {
+            p.step(name)
+        }
+    }
+
+    // Complete the current step, persist the durations and consume the
+    // progress bar
+    pub fn finish(self) {
+        if let Some(p) = &self.inner {
+            p.complete_current();
+            if let Err(e) = p.persist() {
+                debug!("Unable to persist progress history: {:#}", e)
+            }
+        }
+        self.reset()
+    }
+
     // Reset and consume the progress bar
     pub fn reset(self) {
         if let Some(p) = self.inner {
-            p.finish()
+            p.bar.finish()
         }
         *PROGRESS_BAR.write() = None;
     }
+
+    /// Retrieve the steps weighted by their previous durations, if all of
+    /// them are part of the history
+    fn estimate(steps: &[Step], history: &Path) -> Option<Vec<Step>> {
+        let durations = Self::load(history).ok()?;
+        steps
+            .iter()
+            .map(|x| durations.get(x.name).map(|d| Step::new(x.name, *d)))
+            .collect()
+    }
+
+    /// Load the step durations from the history file
+    fn load(history: &Path) -> Result<BTreeMap<String, u64>> {
+        Ok(serde_json::from_slice(&fs::read(history)?)?)
+    }
+}
+
+impl Bar {
+    // Print a message above the progress bar
+    pub fn println(&self, msg: &str) {
+        self.bar.println(msg)
+    }
+
+    // Set the message of the current step and refresh the ETA
+    pub fn set_message(&self, msg: &str) {
+        self.bar.set_message(msg);
+        self.update_prefix();
+    }
+
+    fn step(&self, name: &str) {
+        self.complete_current();
+        let mut state = self.state.lock();
+        match self.steps.iter().position(|x| x.name == name) {
+            Some(i) => {
+                state.current = Some(i);
+                state.started = Instant::now();
+                self.bar.set_position(self.offset(i));
+            }
+            None => debug!("Skipping unknown progress step '{}'", name),
+        }
+        drop(state);
+        self.update_prefix();
+    }
+
+    /// Record the duration of the current step
+    fn complete_current(&self) {
+        let mut state = self.state.lock();
+        if let Some(i) = state.current.take() {
+            let millis = state.started.elapsed().as_millis() as u64;
+            state
+                .durations
+                .insert(self.steps[i].name.into(), millis.max(1));
+            self.bar.set_position(self.offset(i + 1));
+        }
+    }
+
+    /// Merge the recorded durations into the history file
+    fn persist(&self) -> Result<()> {
+        let history = match &self.history {
+            Some(history) => history,
+            None => return Ok(()),
+        };
+        let mut durations = Progress::load(history).unwrap_or_default();
+        durations.extend(self.state.lock().durations.clone());
+        fs::write(history, serde_json::to_string_pretty(&durations)?)?;
+        Ok(())
+    }
+
+    /// The summed weight of all steps before the provided index
+    fn offset(&self, index: usize) -> u64 {
+        self.steps.iter().take(index).map(|x| x.weight).sum()
+    }
+
+    /// Display the current step name and the remaining time, if estimated
+    fn update_prefix(&self) {
+        let state = self.state.lock();
+        let i = match state.current {
+            Some(i) => i,
+            None => return,
+        };
+        let name = style(self.steps[i].name).bold().for_stderr().to_string();
+        if !self.estimated {
+            self.bar.set_prefix(&name);
+            return;
+        }
+        let done =
+            self.offset(i) + (state.started.elapsed().as_millis() as u64).min(self.steps[i].weight);
+        let remaining = Duration::from_millis(self.bar.length().saturating_sub(done));
+        self.bar.set_prefix(&format!(
+            "{} {}",
+            name,
+            style(format!("(ETA {}s)", remaining.as_secs()))
+                .dim()
+                .for_stderr()
+        ));
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use tempfile::tempdir;
 
     #[test]
     fn progress_success() {
-        let p = Progress::new(10, LevelFilter::Info);
+        let p = Progress::new(&[Step::new("test", 10)], LevelFilter::Info, true, None);
         assert!(Progress::get().is_some());
+        p.step("test");
         p.reset();
         assert!(Progress::get().is_none());
     }
+
+    #[test]
+    fn estimate_success() -> Result<()> {
+        let d = tempdir()?;
+        let history = d.path().join(Progress::HISTORY);
+        let steps = [Step::new("first", 1), Step::new("second", 5)];
+        assert!(Progress::estimate(&steps, &history).is_none());
+
+        fs::write(&history, r#"{"first":1000}"#)?;
+        assert!(Progress::estimate(&steps, &history).is_none());
+
+        fs::write(&history, r#"{"first":1000,"second":0}"#)?;
+        assert_eq!(
+            Progress::estimate(&steps, &history),
+            Some(vec![Step::new("first", 1000), Step::new("second", 1)])
+        );
+        Ok(())
+    }
 }
@@ -1,57 +1,213 @@
 use crate::{
-    config::Config,
+    config::{Config, ProxyMode},
     kubeconfig::KubeConfig,
+    kubectl::Kubectl,
+    kubelet::Kubelet,
     network::Network,
     node::Node,
-    process::{Process, ProcessState, Stoppable},
+    process::{Process, ProcessOptions, ProcessState, Stoppable},
+    spec::Spec,
+    system::System,
+};
+use anyhow::{bail, Context, Result};
+use log::info;
+use std::{
+    fs::{self, create_dir_all},
+    net::Ipv4Addr,
+    path::PathBuf,
 };
-use anyhow::Result;
-use std::fs::{self, create_dir_all};
 
 pub struct Proxy {
     process: Process,
 }
 
 impl Proxy {
+    const IMAGE: &'static str = "k8s.gcr.io/kube-proxy";
+
+    /// The first kube-proxy minor version supporting the nftables mode
+    const NFTABLES_MINOR: u32 = 29;
+
+    /// The kernel modules required by the proxy mode
+    pub fn kernel_modules(mode: ProxyMode) -> &'static [&'static str] {
+        match mode {
+            ProxyMode::Iptables => &[],
+            ProxyMode::Ipvs => &["ip_vs", "ip_vs_rr", "ip_vs_wrr", "ip_vs_sh"],
+            ProxyMode::Nftables => &["nf_tables"],
+        }
+    }
+
+    /// The nix package providing the userspace tools of the proxy mode
+    pub fn package(mode: ProxyMode) -> Option<&'static str> {
+        match mode {
+            ProxyMode::Iptables => None,
+            ProxyMode::Ipvs => Some("ipset"),
+            ProxyMode::Nftables => Some("nftables"),
+        }
+    }
+
+    /// Verify that the kernel and kube-proxy support the configured mode
+    pub fn preflight(config: &Config) -> Result<()> {
+        if config.no_proxy() {
+            return Ok(());
+        }
+        let mode = config.proxy_mode();
+        for module in Self::kernel_modules(mode) {
+            if !System::kernel_module_available(module)? {
+                bail!(
+                    "Kernel module '{}' required by the {} proxy mode is not available",
+                    module,
+                    mode
+                )
+            }
+        }
+        if mode == ProxyMode::Nftables {
+            let version = System::version("kube-proxy")?;
+            if !Self::supports_nftables(&version) {
+                bail!(
+                    "The nftables proxy mode requires kube-proxy v1.{} or later, found {}",
+                    Self::NFTABLES_MINOR,
+                    version
+                )
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the kube-proxy version supports the nftables mode
+    fn supports_nftables(version: &str) -> bool {
+        let mut parts = version.trim_start_matches('v').split('.');
+        match (
+            parts.next().and_then(|x| x.parse::<u32>().ok()),
+            parts.next().and_then(|x| x.parse::<u32>().ok()),
+        ) {
+            (Some(major), Some(minor)) => major > 1 || minor >= Self::NFTABLES_MINOR,
+            _ => false,
+        }
+    }
+
+    /// Start kube-proxy on the host. All nodes share the host network, which
+    /// is why a single instance programs the rules for the whole cluster and
+    /// represents the first node.
     pub fn start(config: &Config, network: &Network, kubeconfig: &KubeConfig) -> ProcessState {
-        let dir = config.root().join("proxy");
-        create_dir_all(&dir)?;
+        const PROXY: &str = "kube-proxy";
+        let node = 0;
+        let node_name = Node::name(config, network, node);
+        let cfg = Self::config_file(config, network, node);
+        let dir = cfg.parent().context("Unable to find proxy dir")?;
+        create_dir_all(dir)?;
 
+        let healthz_port = Kubelet::port(config, node) + Kubelet::PROXY_PORT_OFFSET;
         let yml = format!(
             include_str!("assets/proxy.yml"),
-            kubeconfig.proxy().display(),
-            network.cluster_cidr(),
+            kubeconfig = kubeconfig.proxy().display(),
+            cidr = network.cluster_cidr(),
+            mode = config.proxy_mode(),
+            healthz_port = healthz_port,
+            metrics_port = healthz_port + 500,
+            feature_gates = config.feature_gates_yml(&[])?,
         );
-        let cfg = dir.join("config.yml");
-
         if !cfg.exists() {
-            fs::write(&cfg, yml)?;
+            fs::write(&cfg, Spec::patch_config(config, "proxy", node, &yml)?)?;
         }
 
+        let args: &[&str] = &[
+            &format!("--config={}", cfg.display()),
+            &format!("--hostname-override={}", node_name),
+        ];
+
+        let identifier = if config.multi_node() {
+            format!("Proxy {}", node_name)
+        } else {
+            "Proxy".into()
+        };
         let mut process = Process::start(
-            &dir,
-            "Proxy",
-            "kube-proxy",
-            &[
-                &format!("--config={}", cfg.display()),
-                &format!(
-                    "--hostname-override={}",
-                    if config.multi_node() {
-                        Node::name(config, network, 0)
-                    } else {
-                        network.hostname().into()
-                    }
-                ),
-            ],
+            &ProcessOptions::new(config),
+            dir,
+            &identifier,
+            PROXY,
+            args,
+            &config.component_env_vars("proxy"),
         )?;
 
         process.wait_ready("Caches are synced")?;
+        process.describe(
+            PROXY,
+            &format!("http://{}:{}/healthz", Ipv4Addr::LOCALHOST, healthz_port),
+            Some(kubeconfig.proxy()),
+        );
         Ok(Box::new(Proxy { process }))
     }
+
+    /// Retrieve the configuration file of the provided node
+    pub fn config_file(config: &Config, network: &Network, node: u8) -> PathBuf {
+        config
+            .root()
+            .join("proxy")
+            .join(Node::name(config, network, node))
+            .join("config.yml")
+    }
+
+    /// Deploy kube-proxy as DaemonSet and wait until it runs on the first
+    /// node, which is the only one since all nodes share the host network
+    pub fn apply(config: &Config, network: &Network, kubectl: &Kubectl) -> Result<()> {
+        info!("Deploying kube-proxy DaemonSet and waiting to be ready");
+
+        let dir = config.root().join("proxy");
+        create_dir_all(&dir)?;
+
+        let file = dir.join("kube-proxy.yml");
+        if !file.exists() {
+            // The image has to match the version of the control plane
+            let version = System::version("kube-proxy")?;
+            let yml = format!(
+                include_str!("assets/kube-proxy.yml"),
+                api_host = Ipv4Addr::LOCALHOST,
+                api_port = config.api_port(),
+                cidr = network.cluster_cidr(),
+                mode = config.proxy_mode(),
+                node = Node::name(config, network, 0),
+                image = config.image("kube-proxy", &format!("{}:{}", Self::IMAGE, version))?,
+                feature_gates = config.feature_gates_yml(&[])?,
+            );
+            fs::write(&file, yml)?;
+        }
+
+        kubectl
+            .apply(&file)
+            .context("Unable to deploy kube-proxy")?;
+        kubectl
+            .execute(&[
+                "rollout",
+                "status",
+                "daemonset/kube-proxy",
+                "-n=kube-system",
+                "--timeout=120s",
+            ])
+            .context("Unable to wait for kube-proxy DaemonSet")?;
+        info!("kube-proxy deployed");
+        Ok(())
+    }
 }
 
 impl Stoppable for Proxy {
     fn stop(&mut self) -> Result<()> {
         self.process.stop()
     }
+
+    fn restart(&mut self) -> Result<()> {
+        self.process.restart()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supports_nftables_success() {
+        assert!(Proxy::supports_nftables("v1.29.0"));
+        assert!(Proxy::supports_nftables("v1.30.2"));
+        assert!(!Proxy::supports_nftables("v1.19.5"));
+        assert!(!Proxy::supports_nftables("unknown"));
+    }
 }
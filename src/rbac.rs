@@ -0,0 +1,184 @@
+use crate::{
+    config::Config, kubeconfig::KubeConfig, kubectl::Kubectl, network::Network, node::Node,
+};
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use std::{
+    fs::{self, create_dir_all},
+    path::{Path, PathBuf},
+};
+
+/// A smoke check of the RBAC permissions of the generated cluster identities
+pub struct Rbac;
+
+/// A cluster identity and the permissions it requires
+struct Identity {
+    name: String,
+    kubeconfig: PathBuf,
+    impersonate: Option<&'static str>,
+    permissions: &'static [(&'static str, &'static str)],
+}
+
+impl Rbac {
+    const DIR: &'static str = "rbac";
+
+    /// The admin has to be allowed to do anything
+    const ADMIN: &'static [(&'static str, &'static str)] = &[("*", "*")];
+
+    /// The permissions the kubelets need to register and run pods
+    const NODE: &'static [(&'static str, &'static str)] = &[
+        ("get", "nodes"),
+        ("list", "pods"),
+        ("watch", "services"),
+        ("create", "events"),
+    ];
+
+    /// The permissions the API server needs to access the kubelets, for
+    /// example for `kubectl logs` and `kubectl exec`
+    const APISERVER: &'static [(&'static str, &'static str)] = &[
+        ("get", "nodes/proxy"),
+        ("create", "nodes/proxy"),
+        ("get", "nodes/log"),
+        ("get", "nodes/stats"),
+    ];
+
+    /// The user of the API server client certificate for the kubelets
+    const APISERVER_USER: &'static str = "kubernetes";
+
+    /// List the permissions of the admin, node and API server identities into
+    /// the run root and warn about every missing one. The node identities use
+    /// their own kubeconfigs, which verifies their certificates, too.
+    pub fn verify(config: &Config, network: &Network, kubectl: &Kubectl) -> Result<()> {
+        info!("Verifying RBAC permissions");
+        let dir = config.root().join(Self::DIR);
+        create_dir_all(&dir)?;
+
+        let mut identities = vec![Identity {
+            name: "admin".into(),
+            kubeconfig: kubectl.kubeconfig().clone(),
+            impersonate: None,
+            permissions: Self::ADMIN,
+        }];
+        for n in 0..config.nodes() {
+            let node = Node::name(config, network, n);
+            identities.push(Identity {
+                kubeconfig: KubeConfig::kubelet_path(config.root(), &node),
+                name: node,
+                impersonate: None,
+                permissions: Self::NODE,
+            });
+        }
+        identities.push(Identity {
+            name: "apiserver".into(),
+            kubeconfig: kubectl.kubeconfig().clone(),
+            impersonate: Some(Self::APISERVER_USER),
+            permissions: Self::APISERVER,
+        });
+
+        let mut gaps = 0;
+        for identity in &identities {
+            match Self::check(&dir, identity) {
+                Ok(missing) => gaps += missing,
+                Err(e) => {
+                    warn!(
+                        "Unable to verify RBAC permissions of {}: {:#}",
+                        identity.name, e
+                    );
+                    gaps += 1;
+                }
+            }
+        }
+        if gaps == 0 {
+            info!("RBAC permissions verified");
+        }
+        Ok(())
+    }
+
+    /// Write the permission report of the identity and return the number of
+    /// missing permissions
+    fn check(dir: &Path, identity: &Identity) -> Result<usize> {
+        let kubectl = Kubectl::new(&identity.kubeconfig);
+        let impersonate = identity.impersonate.map(|x| format!("--as={}", x));
+
+        let output = kubectl
+            .execute(&Self::can_i(impersonate.as_deref(), &["--list"]))
+            .context("Unable to list permissions")?;
+        let report = dir.join(format!("{}.txt", identity.name));
+        fs::write(&report, &output.stdout)?;
+        debug!("Wrote permission report '{}'", report.display());
+
+        // The permission list does not contain the decisions of the node
+        // authorizer, which is why every permission gets checked on its own
+        let mut missing = 0;
+        for (verb, resource) in identity.permissions {
+            let (name, subresource) = Self::split_resource(resource);
+            let subresource = subresource.map(|x| format!("--subresource={}", x));
+            let mut args = vec![*verb, name];
+            args.extend(subresource.as_deref());
+
+            let output = kubectl.execute_unchecked(&Self::can_i(impersonate.as_deref(), &args))?;
+            match Self::allowed(&output.stdout) {
+                Some(true) => {}
+                Some(false) => {
+                    warn!(
+                        "RBAC smoke check: {} is not allowed to {} {}",
+                        identity.name, verb, resource
+                    );
+                    missing += 1;
+                }
+                None => bail!(
+                    "Unexpected can-i output: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Build the arguments of a `kubectl auth can-i` invocation
+    fn can_i<'a>(impersonate: Option<&'a str>, args: &[&'a str]) -> Vec<&'a str> {
+        let mut result = vec!["auth", "can-i"];
+        result.extend(impersonate);
+        result.extend(args);
+        result
+    }
+
+    /// Split the resource into the resource and its optional subresource
+    fn split_resource(resource: &str) -> (&str, Option<&str>) {
+        match resource.split_once('/') {
+            Some((resource, subresource)) => (resource, Some(subresource)),
+            None => (resource, None),
+        }
+    }
+
+    /// Retrieve the decision of a `kubectl auth can-i` invocation
+    fn allowed(stdout: &[u8]) -> Option<bool> {
+        match String::from_utf8_lossy(stdout).trim() {
+            x if x.starts_with("yes") => Some(true),
+            x if x.starts_with("no") => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_resource_success() {
+        assert_eq!(Rbac::split_resource("pods"), ("pods", None));
+        assert_eq!(
+            Rbac::split_resource("nodes/proxy"),
+            ("nodes", Some("proxy"))
+        );
+    }
+
+    #[test]
+    fn allowed_success() {
+        assert_eq!(Rbac::allowed(b"yes\n"), Some(true));
+        assert_eq!(Rbac::allowed(b"no\n"), Some(false));
+        assert_eq!(Rbac::allowed(b"no - no RBAC policy matched\n"), Some(false));
+        assert_eq!(Rbac::allowed(b""), None);
+    }
+}
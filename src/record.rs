@@ -0,0 +1,344 @@
+use crate::Config;
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env::current_dir,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    time::{Duration, Instant},
+};
+
+/// A single executed external command
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Record {
+    /// The bootstrap phase in which the command got executed
+    phase: String,
+
+    /// The program and its arguments
+    argv: Vec<String>,
+
+    /// The command whose output got piped into the standard input
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stdin: Option<Vec<String>>,
+
+    /// The environment variables explicitly set for the command
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+
+    /// The working directory of the command
+    cwd: Option<PathBuf>,
+
+    /// The exit code, which is not available for background processes or
+    /// failed spawns
+    exit_code: Option<i32>,
+
+    /// The execution duration in milliseconds
+    duration_ms: u64,
+
+    /// Indicates a long running process which is not waited for
+    #[serde(default)]
+    background: bool,
+}
+
+#[derive(Default)]
+struct State {
+    file: Option<PathBuf>,
+    phase: String,
+}
+
+lazy_static! {
+    static ref STATE: RwLock<State> = RwLock::new(State::default());
+}
+
+/// The recorder of all external commands executed during the bootstrap
+pub struct Recorder;
+
+impl Recorder {
+    pub const FILENAME: &'static str = "commands.jsonl";
+
+    /// The phase of the cleanup, whose destructive commands never get
+    /// replayed
+    pub const CLEANUP_PHASE: &'static str = "cleanup";
+
+    /// Start a new recording inside the run root
+    pub fn init(config: &Config) -> Result<()> {
+        let file = config.root().join(Self::FILENAME);
+        fs::write(&file, "").with_context(|| format!("Unable to create '{}'", file.display()))?;
+        STATE.write().file = Some(file);
+        Ok(())
+    }
+
+    /// Set the phase of all subsequently recorded commands
+    pub fn set_phase(phase: &str) {
+        STATE.write().phase = phase.into();
+    }
+
+    /// Record the executed command, if a recording is active
+    pub fn record(
+        command: &Command,
+        stdin: Option<&Command>,
+        exit_code: Option<i32>,
+        duration: Duration,
+        background: bool,
+    ) {
+        // The write lock serializes the appends of parallel commands
+        let state = STATE.write();
+        let file = match &state.file {
+            Some(file) => file,
+            None => return,
+        };
+        let record = Record {
+            phase: state.phase.clone(),
+            argv: Self::argv(command),
+            stdin: stdin.map(Self::argv),
+            env: command
+                .get_envs()
+                .filter_map(|(k, v)| {
+                    Some((k.to_string_lossy().into(), v?.to_string_lossy().into()))
+                })
+                .collect(),
+            cwd: command
+                .get_current_dir()
+                .map(Into::into)
+                .or_else(|| current_dir().ok()),
+            exit_code,
+            duration_ms: duration.as_millis() as u64,
+            background,
+        };
+        if let Err(e) = Self::append(file, &record) {
+            debug!("Unable to record command: {:#}", e)
+        }
+    }
+
+    /// Run the producer and pipe its output into the consumer, whereas both
+    /// get recorded as a single command
+    pub fn piped_output(producer: &mut Command, consumer: &mut Command) -> Result<Output> {
+        let now = Instant::now();
+        let mut child = producer
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let pipe = child.stdout.take().context("Unable to get stdout")?;
+        let output = consumer.stdin(pipe).output();
+        if let Err(e) = child.wait() {
+            debug!("Unable to wait for piped process: {}", e)
+        }
+        Self::record(
+            consumer,
+            Some(producer),
+            output.as_ref().ok().and_then(|x| x.status.code()),
+            now.elapsed(),
+            false,
+        );
+        Ok(output?)
+    }
+
+    /// Load all records from the provided file
+    pub fn load(file: &Path) -> Result<Vec<Record>> {
+        let content = fs::read_to_string(file)
+            .with_context(|| format!("Unable to read '{}'", file.display()))?;
+        content
+            .lines()
+            .filter(|x| !x.trim().is_empty())
+            .enumerate()
+            .map(|(i, x)| {
+                serde_json::from_str(x)
+                    .with_context(|| format!("Unable to parse record in line {}", i + 1))
+            })
+            .collect()
+    }
+
+    /// Retrieve all records up to the last one of the provided phase,
+    /// whereas the records of the cleanup phase are always skipped
+    pub fn until(records: &[Record], phase: Option<&str>) -> Result<Vec<Record>> {
+        let end = match phase {
+            Some(Self::CLEANUP_PHASE) => bail!("The cleanup phase cannot be replayed"),
+            Some(phase) => match records.iter().rposition(|x| x.phase == phase) {
+                Some(i) => i + 1,
+                None => bail!("No recorded commands found for phase '{}'", phase),
+            },
+            None => records.len(),
+        };
+        Ok(records[..end]
+            .iter()
+            .filter(|x| x.phase != Self::CLEANUP_PHASE)
+            .cloned()
+            .collect())
+    }
+
+    /// Execute the records in order, whereas background processes get
+    /// terminated at the end
+    pub fn replay(records: &[Record]) -> Result<()> {
+        let mut background = vec![];
+        let result = Self::execute_all(records, &mut background);
+        for mut child in background {
+            debug!("Stopping background process {}", child.id());
+            if kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).is_ok() {
+                child.wait().ok();
+            }
+        }
+        result
+    }
+
+    fn execute_all(records: &[Record], background: &mut Vec<Child>) -> Result<()> {
+        for record in records {
+            let (program, args) = record.argv.split_first().context("Empty command")?;
+            info!("[{}] {}", record.phase, record.argv.join(" "));
+
+            let mut command = Command::new(program);
+            command.args(args).envs(&record.env);
+            if let Some(cwd) = &record.cwd {
+                command.current_dir(cwd);
+            }
+
+            if record.background {
+                background.push(
+                    command
+                        .stdout(Stdio::null())
+                        .stderr(Stdio::null())
+                        .spawn()?,
+                );
+                continue;
+            }
+            let code = match &record.stdin {
+                Some(stdin) => {
+                    let (program, args) = stdin.split_first().context("Empty command")?;
+                    let mut producer = Command::new(program);
+                    producer.args(args);
+                    Self::piped_output(&mut producer, &mut command)?
+                        .status
+                        .code()
+                }
+                None => command.status()?.code(),
+            };
+            if code != record.exit_code {
+                warn!(
+                    "Exit code {:?} differs from the recorded one {:?}",
+                    code, record.exit_code
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn append(file: &Path, record: &Record) -> Result<()> {
+        let mut f = OpenOptions::new().append(true).open(file)?;
+        writeln!(f, "{}", serde_json::to_string(record)?)?;
+        Ok(())
+    }
+
+    fn argv(command: &Command) -> Vec<String> {
+        let mut argv = vec![command.get_program().to_string_lossy().into()];
+        argv.extend(command.get_args().map(|x| x.to_string_lossy().into()));
+        argv
+    }
+}
+
+/// Command executions which get recorded
+pub trait Recorded {
+    /// Execute the command and collect its output
+    fn recorded_output(&mut self) -> io::Result<Output>;
+
+    /// Execute the command and wait for its exit status
+    fn recorded_status(&mut self) -> io::Result<ExitStatus>;
+}
+
+impl Recorded for Command {
+    fn recorded_output(&mut self) -> io::Result<Output> {
+        let now = Instant::now();
+        let output = self.output();
+        let code = output.as_ref().ok().and_then(|x| x.status.code());
+        Recorder::record(self, None, code, now.elapsed(), false);
+        output
+    }
+
+    fn recorded_status(&mut self) -> io::Result<ExitStatus> {
+        let now = Instant::now();
+        let status = self.status();
+        let code = status.as_ref().ok().and_then(|x| x.code());
+        Recorder::record(self, None, code, now.elapsed(), false);
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_config;
+
+    fn record(phase: &str) -> Record {
+        Record {
+            phase: phase.into(),
+            argv: vec!["true".into()],
+            stdin: None,
+            env: BTreeMap::new(),
+            cwd: None,
+            exit_code: Some(0),
+            duration_ms: 0,
+            background: false,
+        }
+    }
+
+    #[test]
+    fn until_success() -> Result<()> {
+        let records = [
+            record("a"),
+            record("b"),
+            record("b"),
+            record("c"),
+            record(Recorder::CLEANUP_PHASE),
+        ];
+        assert_eq!(Recorder::until(&records, None)?.len(), 4);
+        assert_eq!(Recorder::until(&records, Some("b"))?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn until_failure() {
+        assert!(Recorder::until(&[record("a")], Some("b")).is_err());
+        assert!(Recorder::until(
+            &[record(Recorder::CLEANUP_PHASE)],
+            Some(Recorder::CLEANUP_PHASE)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn load_success() -> Result<()> {
+        let c = test_config()?;
+        let file = c.root().join(Recorder::FILENAME);
+        fs::write(
+            &file,
+            format!(
+                "{}\n\n{}\n",
+                serde_json::to_string(&record("a"))?,
+                r#"{"phase":"b","argv":["sh"],"cwd":null,"exit_code":null,"duration_ms":1}"#
+            ),
+        )?;
+        let records = Recorder::load(&file)?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], record("a"));
+        Ok(())
+    }
+
+    #[test]
+    fn replay_success() -> Result<()> {
+        let mut background = record("a");
+        background.argv = vec!["sleep".into(), "10".into()];
+        background.background = true;
+        let mut piped = record("a");
+        piped.argv = vec!["cat".into()];
+        piped.stdin = Some(vec!["echo".into(), "test".into()]);
+        Recorder::replay(&[record("a"), background, piped])
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{kubectl::Kubectl, Config};
+use anyhow::{bail, Context, Result};
+use base64::encode;
+use getset::Getters;
+use log::{debug, info};
+use serde_json::{json, Map, Value};
+use std::{
+    fs::{self, create_dir_all, read_to_string, set_permissions, Permissions},
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+};
+
+#[derive(Clone, Getters)]
+pub struct RegistryAuth {
+    #[get = "pub"]
+    path: Option<PathBuf>,
+}
+
+impl RegistryAuth {
+    /// The name of the image pull secret inside the default namespace
+    pub const SECRET_NAME: &'static str = "kubernix-registry-auth";
+
+    pub fn new(config: &Config) -> Result<RegistryAuth> {
+        let dir = &config.root().join("registryauth");
+        let path = dir.join("auth.json");
+
+        // The single credentials are not part of the stored configuration,
+        // which means that they are only kept within the auth file
+        if config.auth_file().is_none() && config.registry_logins().is_empty() {
+            if path.exists() {
+                debug!("Reusing existing registry credentials");
+                return Ok(RegistryAuth { path: Some(path) });
+            }
+            debug!("No registry credentials provided");
+            return Ok(RegistryAuth { path: None });
+        }
+
+        info!("Writing registry credentials");
+        create_dir_all(dir)?;
+
+        // Merge the provided or existing auth file with the single
+        // credentials
+        let base = config
+            .auth_file()
+            .clone()
+            .or_else(|| Some(path.clone()).filter(|x| x.exists()));
+        let mut auth = match base {
+            Some(file) => serde_json::from_str(&read_to_string(&file).with_context(|| {
+                format!("Unable to read registry auth file '{}'", file.display())
+            })?)
+            .with_context(|| format!("Unable to parse registry auth file '{}'", file.display()))?,
+            None => json!({ "auths": {} }),
+        };
+        let auths = auth
+            .as_object_mut()
+            .context("Registry auth file is not a JSON object")?
+            .entry("auths")
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .context("Registry auth file 'auths' is not a JSON object")?;
+        for login in config.registry_logins() {
+            let (registry, credentials) = Self::parse_login(login)?;
+            auths.insert(registry.into(), json!({ "auth": encode(credentials) }));
+        }
+
+        fs::write(&path, serde_json::to_string_pretty(&auth)?)?;
+        set_permissions(&path, Permissions::from_mode(0o600))?;
+
+        Ok(RegistryAuth { path: Some(path) })
+    }
+
+    /// Apply the credentials as image pull secret for the default service account
+    pub fn apply(&self, config: &Config, kubectl: &Kubectl) -> Result<()> {
+        let path = match &self.path {
+            Some(path) if config.pull_secret() => path,
+            _ => return Ok(()),
+        };
+        info!("Creating registry image pull secret");
+
+        let yml = format!(
+            include_str!("assets/registryauth.yml"),
+            name = Self::SECRET_NAME,
+            auth = encode(fs::read(path)?),
+        );
+        let file = path.with_file_name("registryauth.yml");
+        fs::write(&file, yml)?;
+        set_permissions(&file, Permissions::from_mode(0o600))?;
+
+        kubectl
+            .apply(&file)
+            .context("Unable to deploy registry image pull secret")?;
+        debug!("Registry image pull secret created");
+        Ok(())
+    }
+
+    /// Split a `REGISTRY=USER:PASSWORD` login into registry and credentials
+    fn parse_login(login: &str) -> Result<(&str, &str)> {
+        let mut split = login.splitn(2, '=');
+        match (split.next(), split.next()) {
+            (Some(registry), Some(credentials))
+                if !registry.is_empty() && credentials.contains(':') =>
+            {
+                Ok((registry, credentials))
+            }
+            _ => bail!(
+                "Invalid registry login, expected format REGISTRY=USER:PASSWORD: {}",
+                login
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_config;
+
+    #[test]
+    fn registryauth_success_no_credentials() -> Result<()> {
+        let c = test_config()?;
+        let r = RegistryAuth::new(&c)?;
+        assert!(r.path().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_login_success() -> Result<()> {
+        assert_eq!(
+            RegistryAuth::parse_login("localhost:5000=user:pass")?,
+            ("localhost:5000", "user:pass")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_login_failure() {
+        assert!(RegistryAuth::parse_login("localhost:5000").is_err());
+        assert!(RegistryAuth::parse_login("=user:pass").is_err());
+        assert!(RegistryAuth::parse_login("localhost=user").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::{
+    error::{ErrorKind, Reported},
+    kubeconfig::KubeConfig,
+    status::{Phase, Status},
+    Config,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info, warn};
+use std::{
+    env::{args, current_exe, vars},
+    fs::{self, create_dir_all, File},
+    net::Ipv4Addr,
+    path::Path,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{sleep, spawn},
+    time::Duration,
+};
+
+/// A remote machine which hosts the cluster and is accessed via SSH
+#[derive(Clone)]
+pub struct Remote {
+    destination: String,
+    port: Option<u16>,
+}
+
+impl Remote {
+    const SCHEME: &'static str = "ssh://";
+
+    /// Create a new remote from the provided `ssh://[user@]host[:port]` target
+    pub fn new(target: &str) -> Result<Remote> {
+        let address = target
+            .strip_prefix(Self::SCHEME)
+            .with_context(|| format!("Target '{}' has to start with {}", target, Self::SCHEME))?
+            .trim_end_matches('/');
+        let (destination, port) = match address.rsplit_once(':') {
+            Some((destination, port)) => (
+                destination,
+                Some(
+                    port.parse()
+                        .with_context(|| format!("Invalid port in target '{}'", target))?,
+                ),
+            ),
+            None => (address, None),
+        };
+        if destination.is_empty() || destination.ends_with('@') {
+            bail!("Target '{}' contains no host", target)
+        }
+        Ok(Remote {
+            destination: destination.into(),
+            port,
+        })
+    }
+
+    /// Bootstrap the cluster on the remote machine by running the current
+    /// executable with the same arguments, whereas the API server is
+    /// forwarded to localhost and the admin kubeconfig fetched into the local
+    /// run root
+    pub fn bootstrap(&self, config: &Config) -> Result<()> {
+        let binary = self
+            .copy_binary(config.root())
+            .context(ErrorKind::Preflight)?;
+
+        // The environment is not passed through SSH
+        let mut command = vec!["sudo".to_owned(), "env".into()];
+        command.extend(
+            vars()
+                .filter(|(k, _)| k.starts_with("KUBERNIX_") && k != "KUBERNIX_TARGET")
+                .map(|(k, v)| format!("{}={}", k, v)),
+        );
+        command.push(binary.clone());
+        command.extend(Self::forwarded_args(&args().skip(1).collect::<Vec<_>>()));
+
+        info!("Bootstrapping cluster on {}", self.destination);
+        let remote = self.clone();
+        let root = config.root().to_path_buf();
+        let finished = Arc::new(AtomicBool::new(false));
+        let done = finished.clone();
+        let fetcher = spawn(move || {
+            if let Err(e) = remote.fetch_kubeconfig(&root, &done) {
+                warn!("Unable to fetch admin kubeconfig: {:#}", e)
+            }
+        });
+
+        // The API server gets forwarded to the same port on localhost
+        let forward = format!(
+            "-L{}:{}:{}",
+            config.api_port(),
+            Ipv4Addr::LOCALHOST,
+            config.api_port()
+        );
+        let status = self
+            .ssh(&["-t", &forward])
+            .arg(Self::quote_all(&command))
+            .status()
+            .context("Unable to run ssh")?;
+        finished.store(true, Ordering::SeqCst);
+        if fetcher.join().is_err() {
+            debug!("Kubeconfig fetcher panicked")
+        }
+        self.remove_binary(&binary);
+
+        // The remote kubernix process already reported its own failures
+        match status.code().and_then(ErrorKind::from_exit_code) {
+            _ if status.success() => Ok(()),
+            Some(kind) => Err(Reported(kind).into()),
+            None => Err(anyhow!("Remote bootstrap failed ({})", status)),
+        }
+    }
+
+    /// Copy the current executable into a new file within the remote run
+    /// root and return its path. The file is only accessible by root, since
+    /// it gets executed via sudo.
+    fn copy_binary(&self, root: &Path) -> Result<String> {
+        info!("Copying kubernix to {}", self.destination);
+        let exe = current_exe()?;
+        let output = self
+            .ssh(&[])
+            .arg(Self::copy_command(root))
+            .stdin(File::open(&exe).with_context(|| format!("Unable to open {}", exe.display()))?)
+            .output()?;
+        let path = String::from_utf8(output.stdout)?.trim().to_owned();
+        if !output.status.success() || path.is_empty() {
+            bail!(
+                "Unable to copy kubernix to {} ({})",
+                self.destination,
+                output.status
+            )
+        }
+        debug!("Copied kubernix to {}:{}", self.destination, path);
+        Ok(path)
+    }
+
+    /// The remote shell command writing its input into a new executable
+    /// within the run root, which prints the path of the file
+    fn copy_command(root: &Path) -> String {
+        let root = Self::quote(&root.display().to_string());
+        format!(
+            "sudo mkdir -p {root} && \
+             f=$(sudo mktemp {root}/.kubernix.XXXXXX) && \
+             sudo chmod 0700 \"$f\" && \
+             sudo tee \"$f\" >/dev/null && \
+             echo \"$f\"",
+            root = root
+        )
+    }
+
+    /// Remove the copied executable from the remote machine
+    fn remove_binary(&self, path: &str) {
+        let removed = self
+            .ssh(&[])
+            .arg(format!("sudo rm -f {}", Self::quote(path)))
+            .stdin(Stdio::null())
+            .status();
+        if !removed.map(|x| x.success()).unwrap_or(false) {
+            warn!("Unable to remove {}:{}", self.destination, path)
+        }
+    }
+
+    /// Wait for the remote cluster to run and copy its admin kubeconfig into
+    /// the local run root, which has the same path as the remote one
+    fn fetch_kubeconfig(&self, root: &Path, finished: &AtomicBool) -> Result<()> {
+        let status_file = root.join("status.json");
+        loop {
+            if finished.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            let output = self.read(&status_file)?;
+            let running = serde_json::from_slice::<Status>(&output)
+                .map(|x| x.phase() == Phase::Running)
+                .unwrap_or(false);
+            if running {
+                break;
+            }
+            sleep(Duration::from_secs(2));
+        }
+
+        let kubeconfig = self.read(&KubeConfig::admin_path(root))?;
+        if kubeconfig.is_empty() {
+            bail!("Remote admin kubeconfig is empty")
+        }
+        let target = KubeConfig::admin_path(root);
+        if let Some(dir) = target.parent() {
+            create_dir_all(dir)?;
+        }
+        fs::write(&target, kubeconfig)
+            .with_context(|| format!("Unable to write '{}'", target.display()))?;
+        info!(
+            "Admin kubeconfig available at '{}' while the cluster is running",
+            target.display()
+        );
+        Ok(())
+    }
+
+    /// Read a file from the remote machine, whereas missing files result in
+    /// an empty output
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let output = self
+            .ssh(&[])
+            .arg(format!(
+                "sudo cat {} 2>/dev/null || true",
+                Self::quote(&path.display().to_string())
+            ))
+            .stdin(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            bail!("Unable to read {} on {}", path.display(), self.destination)
+        }
+        Ok(output.stdout)
+    }
+
+    /// The base SSH command for the remote machine including the provided
+    /// options, whereas the remote command has to be appended
+    fn ssh(&self, options: &[&str]) -> Command {
+        let mut command = Command::new("ssh");
+        if let Some(port) = self.port {
+            command.arg(format!("-p{}", port));
+        }
+        command.args(options).arg(&self.destination).arg("--");
+        command
+    }
+
+    /// Remove the target argument, which must not be passed to the remote
+    fn forwarded_args(args: &[String]) -> Vec<String> {
+        let mut forwarded = vec![];
+        let mut skip = false;
+        for arg in args {
+            if skip {
+                skip = false;
+            } else if arg == "--target" {
+                skip = true;
+            } else if !arg.starts_with("--target=") {
+                forwarded.push(arg.clone());
+            }
+        }
+        forwarded
+    }
+
+    /// Quote all arguments for the remote shell
+    fn quote_all(args: &[String]) -> String {
+        args.iter()
+            .map(|x| Self::quote(x))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Single quote the argument for the remote shell
+    fn quote(arg: &str) -> String {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_success() -> Result<()> {
+        let r = Remote::new("ssh://user@host:2222")?;
+        assert_eq!(r.destination, "user@host");
+        assert_eq!(r.port, Some(2222));
+
+        let r = Remote::new("ssh://host/")?;
+        assert_eq!(r.destination, "host");
+        assert_eq!(r.port, None);
+        Ok(())
+    }
+
+    #[test]
+    fn new_failure() {
+        assert!(Remote::new("user@host").is_err());
+        assert!(Remote::new("ssh://").is_err());
+        assert!(Remote::new("ssh://user@").is_err());
+        assert!(Remote::new("ssh://host:port").is_err());
+    }
+
+    #[test]
+    fn copy_command_success() {
+        let command = Remote::copy_command(Path::new("/kubernix-run"));
+        assert!(command.starts_with("sudo mkdir -p '/kubernix-run' && "));
+        assert!(command.contains("sudo mktemp '/kubernix-run'/.kubernix.XXXXXX"));
+        assert!(command.contains("sudo chmod 0700 \"$f\""));
+        assert!(!command.contains("/tmp"));
+    }
+
+    #[test]
+    fn forwarded_args_success() {
+        let args = [
+            "--target",
+            "ssh://host",
+            "--nodes=2",
+            "--target=ssh://other",
+            "-s",
+            "zsh",
+        ]
+        .iter()
+        .map(|x| (*x).to_owned())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            Remote::forwarded_args(&args),
+            vec!["--nodes=2", "-s", "zsh"]
+        );
+    }
+
+    #[test]
+    fn quote_all_success() {
+        assert_eq!(
+            Remote::quote_all(&["a b".into(), "it's".into()]),
+            r"'a b' 'it'\''s'"
+        );
+    }
+}
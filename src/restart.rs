@@ -0,0 +1,171 @@
+use crate::{graph::Unit, Config};
+use anyhow::{bail, Context, Result};
+use getset::CopyGetters;
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// A restart of a unit and its dependents requested by the CLI and handled by
+/// the main kubernix process, whereas both communicate via a file inside the
+/// run root
+#[derive(CopyGetters, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Restart {
+    #[get_copy = "pub"]
+    /// The unit to be restarted
+    unit: Unit,
+
+    #[get_copy = "pub"]
+    /// The seconds to wait between stopping and starting the units
+    downtime: u64,
+
+    #[get_copy = "pub"]
+    #[serde(default)]
+    /// Validate the configuration file of the unit before restarting it
+    reload: bool,
+
+    #[serde(default)]
+    /// Indicates that the main process handled the request
+    done: bool,
+
+    #[serde(default)]
+    /// The error if the restart failed
+    error: Option<String>,
+}
+
+impl Restart {
+    const FILENAME: &'static str = "restart.json";
+
+    /// The additional time the restarted processes have to become ready
+    const TIMEOUT: Duration = Duration::from_secs(600);
+
+    /// The signal which notifies the main process about the request
+    pub const SIGNAL: Signal = Signal::SIGUSR2;
+
+    /// Request the restart of the unit from the main process with the
+    /// provided pid and wait until it is done
+    pub fn request(
+        config: &Config,
+        pid: u32,
+        unit: Unit,
+        downtime: u64,
+        reload: bool,
+    ) -> Result<()> {
+        let path = Self::path(config);
+        if let Ok(previous) = Self::load(config) {
+            if !previous.done {
+                bail!(
+                    "Restart of {} is already in progress ({})",
+                    previous.unit,
+                    path.display()
+                )
+            }
+        }
+
+        let request = Restart {
+            unit,
+            downtime,
+            reload,
+            done: false,
+            error: None,
+        };
+        request.write(config)?;
+        kill(Pid::from_raw(pid as i32), Self::SIGNAL)
+            .with_context(|| format!("Unable to notify kubernix process {}", pid))?;
+
+        // Wait for the main process to finish the request
+        let timeout = Duration::from_secs(downtime) + Self::TIMEOUT;
+        let now = Instant::now();
+        let result = loop {
+            sleep(Duration::from_secs(1));
+            let current = Self::load(config)?;
+            if current.done {
+                break current;
+            }
+            if now.elapsed() > timeout {
+                bail!("Timed out waiting for the restart of {}", unit)
+            }
+        };
+        fs::remove_file(&path).with_context(|| format!("Unable to remove '{}'", path.display()))?;
+        if let Some(e) = result.error {
+            bail!("Unable to restart {}: {}", unit, e)
+        }
+        Ok(())
+    }
+
+    /// Load the pending request
+    pub fn load(config: &Config) -> Result<Restart> {
+        let path = Self::path(config);
+        serde_json::from_slice(
+            &fs::read(&path).with_context(|| format!("Unable to read '{}'", path.display()))?,
+        )
+        .with_context(|| format!("Unable to parse '{}'", path.display()))
+    }
+
+    /// Mark the request as done by recording its result
+    pub fn finish(mut self, config: &Config, result: &Result<()>) -> Result<()> {
+        self.done = true;
+        self.error = result.as_ref().err().map(|e| format!("{:#}", e));
+        self.write(config)
+    }
+
+    fn write(&self, config: &Config) -> Result<()> {
+        let path = Self::path(config);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Unable to write '{}'", path.display()))
+    }
+
+    fn path(config: &Config) -> PathBuf {
+        config.root().join(Self::FILENAME)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_config;
+    use anyhow::anyhow;
+
+    #[test]
+    fn finish_success() -> Result<()> {
+        let c = test_config()?;
+        let request = Restart {
+            unit: Unit::Kubelet(1),
+            downtime: 10,
+            reload: false,
+            done: false,
+            error: None,
+        };
+        request.write(&c)?;
+        assert!(!Restart::load(&c)?.done);
+
+        Restart::load(&c)?.finish(&c, &Err(anyhow!("failure")))?;
+        let restart = Restart::load(&c)?;
+        assert!(restart.done);
+        assert_eq!(restart.unit, Unit::Kubelet(1));
+        assert_eq!(restart.error, Some("failure".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn request_failure_in_progress() -> Result<()> {
+        let c = test_config()?;
+        let request = Restart {
+            unit: Unit::Crio(0),
+            downtime: 0,
+            reload: true,
+            done: false,
+            error: None,
+        };
+        request.write(&c)?;
+        assert!(Restart::request(&c, 0, Unit::ApiServer, 0, false).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    addon::Addon,
+    config::{Config, OciRuntime},
+    kubectl::Kubectl,
+    network::Network,
+    system::System,
+};
+use anyhow::{Context, Result};
+use log::info;
+use std::{
+    fs::{self, create_dir_all},
+    path::Path,
+};
+
+/// The RuntimeClass objects of all configured OCI runtimes
+pub struct RuntimeClasses;
+
+impl RuntimeClasses {
+    /// The name of the addon
+    pub const NAME: &'static str = "runtime-classes";
+
+    /// The table of the containerd runtimes within the configuration
+    const CONTAINERD_RUNTIMES: &'static str =
+        "plugins.\"io.containerd.grpc.v1.cri\".containerd.runtimes";
+
+    /// The CRI handler name of the runtime
+    pub fn handler(runtime: OciRuntime) -> &'static str {
+        match runtime {
+            OciRuntime::Gvisor => "runsc",
+            _ => runtime.as_str(),
+        }
+    }
+
+    /// The nix package which provides the runtime, whereas Kata Containers
+    /// have to be installed on the host
+    pub fn package(runtime: OciRuntime) -> Option<&'static str> {
+        match runtime {
+            OciRuntime::Runc | OciRuntime::Kata => None,
+            OciRuntime::Crun => Some("crun"),
+            OciRuntime::Gvisor => Some("gvisor"),
+        }
+    }
+
+    /// Render the CRI-O runtime tables of all configured runtimes, whereas
+    /// every runtime stores its state below the provided directory
+    pub fn crio_runtimes(config: &Config, dir: &Path) -> Result<String> {
+        let mut out = String::new();
+        for runtime in config.oci_runtimes() {
+            let (executable, runtime_type) = match runtime {
+                OciRuntime::Kata => ("containerd-shim-kata-v2", "vm"),
+                OciRuntime::Gvisor => ("runsc", "oci"),
+                _ => (runtime.as_str(), "oci"),
+            };
+            let handler = Self::handler(runtime);
+            out.push_str(&format!(
+                "\n[crio.runtime.runtimes.{}]\nruntime_path = {}\n\
+                 runtime_root = {}\nruntime_type = {}\n",
+                handler,
+                Self::quote(&System::find_executable(executable)?.display().to_string()),
+                Self::quote(&dir.join(handler).display().to_string()),
+                Self::quote(runtime_type),
+            ));
+        }
+        Ok(out)
+    }
+
+    /// Render the containerd runtime tables of all configured runtimes,
+    /// whereas every runtime stores its state below the provided directory
+    pub fn containerd_runtimes(config: &Config, dir: &Path) -> Result<String> {
+        let mut out = String::new();
+        for runtime in config.oci_runtimes() {
+            let handler = Self::handler(runtime);
+            let table = format!("{}.{}", Self::CONTAINERD_RUNTIMES, handler);
+            match runtime {
+                OciRuntime::Runc | OciRuntime::Crun => out.push_str(&format!(
+                    "\n[{table}]\n  runtime_type = \"io.containerd.runc.v2\"\n\n\
+                     [{table}.options]\n  BinaryName = {}\n  Root = {}\n",
+                    Self::quote(
+                        &System::find_executable(runtime.as_str())?
+                            .display()
+                            .to_string()
+                    ),
+                    Self::quote(&dir.join(handler).display().to_string()),
+                    table = table,
+                )),
+                OciRuntime::Kata => {
+                    System::find_executable("containerd-shim-kata-v2")?;
+                    out.push_str(&format!(
+                        "\n[{}]\n  runtime_type = \"io.containerd.kata.v2\"\n",
+                        table
+                    ))
+                }
+                OciRuntime::Gvisor => {
+                    System::find_executable("containerd-shim-runsc-v1")?;
+                    out.push_str(&format!(
+                        "\n[{}]\n  runtime_type = \"io.containerd.runsc.v1\"\n",
+                        table
+                    ))
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn quote(value: &str) -> String {
+        toml::Value::String(value.into()).to_string()
+    }
+}
+
+impl Addon for RuntimeClasses {
+    fn name(&self) -> String {
+        RuntimeClasses::NAME.into()
+    }
+
+    fn apply(&self, config: &Config, _: &Network, kubectl: &Kubectl) -> Result<()> {
+        info!("Creating RuntimeClasses");
+
+        let dir = config.root().join(RuntimeClasses::NAME);
+        create_dir_all(&dir)?;
+
+        let file = dir.join("runtimeclasses.yml");
+        let yml = config
+            .oci_runtimes()
+            .into_iter()
+            .map(|x| {
+                format!(
+                    include_str!("assets/runtimeclass.yml"),
+                    name = x,
+                    handler = RuntimeClasses::handler(x),
+                )
+            })
+            .collect::<String>();
+        fs::write(&file, yml)?;
+
+        kubectl
+            .apply(&file)
+            .context("Unable to create RuntimeClasses")?;
+        info!("RuntimeClasses created");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handler_success() {
+        assert_eq!(RuntimeClasses::handler(OciRuntime::Runc), "runc");
+        assert_eq!(RuntimeClasses::handler(OciRuntime::Kata), "kata");
+        assert_eq!(RuntimeClasses::handler(OciRuntime::Gvisor), "runsc");
+    }
+}
@@ -1,40 +1,202 @@
 use crate::{
     config::Config,
+    controlplane::ControlPlane,
     kubeconfig::KubeConfig,
-    process::{Process, ProcessState, Stoppable},
+    pki::Pki,
+    process::{Process, ProcessState, Readiness, Stoppable},
+    spec::Spec,
+};
+use anyhow::{bail, Result};
+use std::{
+    fmt::Write,
+    fs::{self, create_dir_all},
+    net::Ipv4Addr,
+    path::PathBuf,
 };
-use anyhow::Result;
-use std::fs::{self, create_dir_all};
 
 pub struct Scheduler {
     process: Process,
 }
 
 impl Scheduler {
-    pub fn start(config: &Config, kubeconfig: &KubeConfig) -> ProcessState {
-        let dir = config.root().join("scheduler");
+    /// The distance of the secure port to the one of the API server, which
+    /// results in the default port 10259
+    const PORT_OFFSET: u16 = 3816;
+
+    /// The secure port serving the health endpoint
+    pub fn port(config: &Config) -> u16 {
+        config.api_port() + Self::PORT_OFFSET
+    }
+
+    /// The secure port of the secondary scheduler
+    fn secondary_port(config: &Config) -> u16 {
+        Self::port(config) + 1
+    }
+
+    const IDENTIFIER: &'static str = "Scheduler";
+    const SECONDARY_IDENTIFIER: &'static str = "Secondary Scheduler";
+
+    pub fn start(config: &Config, pki: &Pki, kubeconfig: &KubeConfig) -> ProcessState {
+        let extra = Self::extenders(config.scheduler_extenders())?;
+        Self::run(
+            config,
+            pki,
+            kubeconfig,
+            Self::IDENTIFIER,
+            "kube-scheduler",
+            Self::port(config),
+            &extra,
+        )
+    }
+
+    /// Start the secondary scheduler binary from the nix environment, which
+    /// has to accept the same arguments as the kube-scheduler and schedules
+    /// all pods requesting its binary name as `schedulerName`
+    pub fn start_secondary(
+        config: &Config,
+        pki: &Pki,
+        kubeconfig: &KubeConfig,
+        binary: &str,
+    ) -> ProcessState {
+        if binary.is_empty() || binary.contains('/') {
+            bail!(
+                "Secondary scheduler '{}' has to be a binary name within the nix environment",
+                binary
+            )
+        }
+
+        // The insecure endpoints are already served by the default scheduler
+        let extra = format!(
+            "healthzBindAddress: \"\"\nmetricsBindAddress: \"\"\nprofiles:\n- schedulerName: \"{}\"\n",
+            binary
+        );
+        Self::run(
+            config,
+            pki,
+            kubeconfig,
+            Self::SECONDARY_IDENTIFIER,
+            binary,
+            Self::secondary_port(config),
+            &extra,
+        )
+    }
+
+    /// Run the scheduler within the directory named after its identifier
+    fn run(
+        config: &Config,
+        pki: &Pki,
+        kubeconfig: &KubeConfig,
+        identifier: &str,
+        binary: &str,
+        port: u16,
+        extra: &str,
+    ) -> ProcessState {
+        let dir = Self::dir(config, identifier);
         create_dir_all(&dir)?;
 
         let yml = format!(
             include_str!("assets/scheduler.yml"),
-            kubeconfig.scheduler().display()
+            kubeconfig = kubeconfig.scheduler().display(),
+            extra = extra,
         );
         let cfg = &dir.join("config.yml");
+        let component = if identifier == Self::SECONDARY_IDENTIFIER {
+            "secondary-scheduler"
+        } else {
+            "scheduler"
+        };
 
         if !cfg.exists() {
-            fs::write(cfg, yml)?;
+            fs::write(cfg, Spec::patch_config(config, component, 0, &yml)?)?;
         }
 
-        let mut process = Process::start(
+        let args = &[
+            &format!(
+                "--authentication-kubeconfig={}",
+                kubeconfig.scheduler().display()
+            ),
+            &format!(
+                "--authorization-kubeconfig={}",
+                kubeconfig.scheduler().display()
+            ),
+            &format!("--client-ca-file={}", pki.ca().cert().display()),
+            &format!("--config={}", cfg.display()),
+            &format!("--secure-port={}", port),
+            &format!("--tls-cert-file={}", pki.scheduler().cert().display()),
+            &format!("--tls-private-key-file={}", pki.scheduler().key().display()),
+            "--v=2",
+        ];
+
+        // The feature gates and additional arguments belong to
+        // kube-scheduler, whereas the secondary scheduler may be any other
+        // binary
+        let (feature_gates, extra_args): (_, &[String]) = if identifier == Self::IDENTIFIER {
+            (config.feature_gates_arg()?, config.scheduler_args())
+        } else {
+            (None, &[])
+        };
+        let mut process = ControlPlane::exec(
+            config,
             &dir,
-            "Scheduler",
-            "kube-scheduler",
-            &[&format!("--config={}", cfg.display()), "--v=2"],
+            identifier,
+            binary,
+            &args
+                .iter()
+                .copied()
+                .chain(feature_gates.as_deref())
+                .chain(extra_args.iter().map(String::as_str))
+                .collect::<Vec<_>>(),
+            &config.component_env_vars(component),
         )?;
 
-        process.wait_ready("Serving securely")?;
+        process.wait_ready(Readiness::healthz(port, pki))?;
+        process.describe(
+            binary,
+            &format!("https://{}:{}", Ipv4Addr::LOCALHOST, port),
+            Some(kubeconfig.scheduler()),
+        );
         Ok(Box::new(Self { process }))
     }
+
+    /// Retrieve the configuration file of the default or secondary scheduler
+    pub fn config_file(config: &Config, secondary: bool) -> PathBuf {
+        let identifier = if secondary {
+            Self::SECONDARY_IDENTIFIER
+        } else {
+            Self::IDENTIFIER
+        };
+        Self::dir(config, identifier).join("config.yml")
+    }
+
+    /// Retrieve the directory named after the identifier
+    fn dir(config: &Config, identifier: &str) -> PathBuf {
+        config
+            .root()
+            .join(identifier.to_lowercase().replace(' ', "-"))
+    }
+
+    /// Render the scheduler extenders configuration for the provided URLs
+    fn extenders(urls: &[String]) -> Result<String> {
+        if urls.is_empty() {
+            return Ok(String::new());
+        }
+        let mut extenders = "extenders:\n".to_owned();
+        for url in urls {
+            let https = if url.starts_with("https://") {
+                true
+            } else if url.starts_with("http://") {
+                false
+            } else {
+                bail!("Scheduler extender URL '{}' is not HTTP(S)", url)
+            };
+            write!(
+                extenders,
+                "- urlPrefix: \"{}\"\n  filterVerb: filter\n  prioritizeVerb: prioritize\n  weight: 1\n  enableHTTPS: {}\n",
+                url, https
+            )?;
+        }
+        Ok(extenders)
+    }
 }
 
 impl Stoppable for Scheduler {
@@ -42,3 +204,27 @@ impl Stoppable for Scheduler {
         self.process.stop()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extenders_success() -> Result<()> {
+        assert!(Scheduler::extenders(&[])?.is_empty());
+
+        let extenders = Scheduler::extenders(&[
+            "http://127.0.0.1:8888/scheduler".into(),
+            "https://extender".into(),
+        ])?;
+        assert!(extenders.contains("urlPrefix: \"http://127.0.0.1:8888/scheduler\""));
+        assert!(extenders.contains("enableHTTPS: false"));
+        assert!(extenders.contains("enableHTTPS: true"));
+        Ok(())
+    }
+
+    #[test]
+    fn extenders_failure() {
+        assert!(Scheduler::extenders(&["127.0.0.1:8888".into()]).is_err());
+    }
+}
@@ -0,0 +1,387 @@
+use crate::{
+    addon::Addon,
+    config::{Config, NodeRuntime},
+    kubectl::Kubectl,
+    network::Network,
+    node::Node,
+};
+use anyhow::{bail, Context, Result};
+use getset::Getters;
+use log::info;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::{
+    collections::BTreeMap,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+#[derive(Default, Deserialize, Getters)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+/// A declarative cluster specification, which gets translated into the
+/// configuration and applied to the cluster after the bootstrap
+pub struct Spec {
+    #[get = "pub"]
+    #[serde(default)]
+    /// All nodes of the cluster
+    nodes: Vec<NodeSpec>,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// The optional addons to be deployed
+    addons: Vec<AddonSpec>,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// Additional manifests to be applied, relative to the spec file
+    manifests: Vec<PathBuf>,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// Patches of the generated component configuration files
+    patches: Vec<Patch>,
+}
+
+#[derive(Default, Deserialize, Getters)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+/// A single node of the cluster
+pub struct NodeSpec {
+    #[get = "pub"]
+    #[serde(default)]
+    /// The role of the node, which becomes a `node-role.kubernetes.io` label
+    role: Option<String>,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// Additional labels of the node
+    labels: BTreeMap<String, String>,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// The CRI runtime of the node
+    runtime: Option<NodeRuntime>,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// The resource limits of the node container
+    resources: Resources,
+}
+
+#[derive(Default, Deserialize, Getters)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+/// The resource limits of a node container
+pub struct Resources {
+    #[get = "pub"]
+    #[serde(default)]
+    /// The number of CPUs, for example `1.5`
+    cpus: Option<String>,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// The memory limit, for example `4g`
+    memory: Option<String>,
+}
+
+#[derive(Deserialize, Getters)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+/// An optional cluster addon
+pub struct AddonSpec {
+    #[get = "pub"]
+    /// The name of the addon
+    name: String,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// The values of the addon, whereas only `image` is supported
+    values: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Getters)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+/// A JSON merge patch of a component configuration file
+pub struct Patch {
+    #[get = "pub"]
+    /// The component whose configuration gets patched
+    component: String,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// The node of the component, whereas all nodes get patched if not set
+    node: Option<u8>,
+
+    #[get = "pub"]
+    /// The merge patch to be applied
+    patch: Value,
+}
+
+impl Spec {
+    /// The components whose configuration files can be patched
+    pub const COMPONENTS: &'static [&'static str] =
+        &["kubelet", "proxy", "scheduler", "secondary-scheduler"];
+
+    /// The name of the addon labeling the nodes
+    const NAME: &'static str = "node-labels";
+
+    /// Read the spec file of the configuration, if set
+    pub fn load(config: &Config) -> Result<Option<Spec>> {
+        match config.spec() {
+            Some(path) => Self::from_file(path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read and validate the provided spec file, whereas the manifest paths
+    /// get resolved relative to it
+    pub fn from_file(path: &Path) -> Result<Spec> {
+        let content = read_to_string(path)
+            .with_context(|| format!("Unable to read spec file '{}'", path.display()))?;
+        let mut spec: Spec = serde_yaml::from_str(&content)
+            .with_context(|| format!("Unable to parse spec file '{}'", path.display()))?;
+        spec.validate()?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        for manifest in &mut spec.manifests {
+            *manifest = dir.join(&manifest);
+        }
+        Ok(spec)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.nodes.len() > usize::from(u8::MAX) {
+            bail!("Spec contains more than {} nodes", u8::MAX)
+        }
+        for addon in &self.addons {
+            if !Config::ADDONS.contains(&addon.name.as_str()) {
+                bail!("Unknown addon in spec: {}", addon.name)
+            }
+            if let Some(key) = addon.values.keys().find(|x| *x != "image") {
+                bail!("Unsupported value '{}' of addon {}", key, addon.name)
+            }
+        }
+        for patch in &self.patches {
+            if !Self::COMPONENTS.contains(&patch.component.as_str()) {
+                bail!(
+                    "Unable to patch component {}, supported are: {}",
+                    patch.component,
+                    Self::COMPONENTS.join(", ")
+                )
+            }
+            if !patch.patch.is_mapping() {
+                bail!("Patch of component {} is no mapping", patch.component)
+            }
+        }
+        Ok(())
+    }
+
+    /// Retrieve the image overrides of the addons
+    pub fn images(&self) -> Vec<String> {
+        self.addons
+            .iter()
+            .filter_map(|x| x.values.get("image").map(|i| format!("{}={}", x.name, i)))
+            .collect()
+    }
+
+    /// Retrieve the node runtime overrides of the nodes
+    pub fn node_runtimes(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, x)| x.runtime.map(|r| format!("{}={}", i, r)))
+            .collect()
+    }
+
+    /// Apply all patches of the component to the rendered YAML configuration
+    pub fn patch_config(config: &Config, component: &str, node: u8, yml: &str) -> Result<String> {
+        let spec = match Self::load(config)? {
+            Some(spec) => spec,
+            None => return Ok(yml.into()),
+        };
+        let patches = spec
+            .patches
+            .iter()
+            .filter(|x| x.component == component && x.node.map_or(true, |n| n == node))
+            .collect::<Vec<_>>();
+        if patches.is_empty() {
+            return Ok(yml.into());
+        }
+
+        let mut value: Value = serde_yaml::from_str(yml)
+            .with_context(|| format!("Unable to parse {} configuration", component))?;
+        for patch in patches {
+            Self::merge(&mut value, &patch.patch);
+        }
+        info!("Patched {} configuration", component);
+        Ok(serde_yaml::to_string(&value)?)
+    }
+
+    /// Apply the JSON merge patch (RFC 7386) to the target value
+    fn merge(target: &mut Value, patch: &Value) {
+        let patch = match patch.as_mapping() {
+            Some(patch) => patch,
+            None => {
+                *target = patch.clone();
+                return;
+            }
+        };
+        if !target.is_mapping() {
+            *target = Value::Mapping(Default::default());
+        }
+        if let Value::Mapping(target) = target {
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(key);
+                } else {
+                    match target.get_mut(key) {
+                        Some(x) => Self::merge(x, value),
+                        None => {
+                            let mut x = Value::Null;
+                            Self::merge(&mut x, value);
+                            target.insert(key.clone(), x);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Retrieve the container runtime arguments limiting the resources of the
+    /// node container
+    pub fn resource_args(config: &Config, node_name: &str) -> Result<Vec<String>> {
+        let spec = match Self::load(config)? {
+            Some(spec) => spec,
+            None => return Ok(vec![]),
+        };
+        let mut args = vec![];
+        for (i, node) in spec.nodes.iter().enumerate() {
+            if Node::raw(i as u8) != node_name {
+                continue;
+            }
+            if let Some(cpus) = &node.resources.cpus {
+                args.push(format!("--cpus={}", cpus));
+            }
+            if let Some(memory) = &node.resources.memory {
+                args.push(format!("--memory={}", memory));
+            }
+        }
+        Ok(args)
+    }
+
+    /// Retrieve all labels of the node, including its role
+    fn labels(node: &NodeSpec) -> Vec<String> {
+        node.role
+            .iter()
+            .map(|x| format!("node-role.kubernetes.io/{}=", x))
+            .chain(node.labels.iter().map(|(k, v)| format!("{}={}", k, v)))
+            .collect()
+    }
+
+    /// Returns true if any node has labels to be applied
+    pub fn has_labels(&self) -> bool {
+        self.nodes.iter().any(|x| !Self::labels(x).is_empty())
+    }
+}
+
+impl Addon for Spec {
+    fn name(&self) -> String {
+        Spec::NAME.into()
+    }
+
+    fn apply(&self, config: &Config, network: &Network, kubectl: &Kubectl) -> Result<()> {
+        for (i, node) in self.nodes.iter().enumerate() {
+            let labels = Spec::labels(node);
+            if labels.is_empty() {
+                continue;
+            }
+            let name = Node::name(config, network, i as u8);
+            info!("Labeling node {}", name);
+            let mut args = vec!["label", "node", &name, "--overwrite"];
+            args.extend(labels.iter().map(String::as_str));
+            kubectl
+                .execute(&args)
+                .with_context(|| format!("Unable to label node {}", name))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::write;
+    use tempfile::tempdir;
+
+    const SPEC: &str = r#"
+nodes:
+  - role: control-plane
+  - runtime: containerd
+    labels:
+      disk: ssd
+    resources:
+      cpus: "2"
+      memory: 4g
+addons:
+  - name: dashboard
+    values:
+      image: registry.local/dashboard:v2.0.4
+  - name: ingress
+manifests:
+  - workloads/app.yml
+patches:
+  - component: kubelet
+    node: 1
+    patch:
+      maxPods: 50
+"#;
+
+    #[test]
+    fn from_file_success() -> Result<()> {
+        let d = tempdir()?;
+        let path = d.path().join("kubernix.yaml");
+        write(&path, SPEC)?;
+
+        let spec = Spec::from_file(&path)?;
+        assert_eq!(spec.nodes().len(), 2);
+        assert_eq!(spec.node_runtimes(), vec!["1=containerd"]);
+        assert_eq!(
+            spec.images(),
+            vec!["dashboard=registry.local/dashboard:v2.0.4"]
+        );
+        assert_eq!(spec.manifests(), &[d.path().join("workloads/app.yml")]);
+        assert_eq!(
+            Spec::labels(&spec.nodes()[0]),
+            vec!["node-role.kubernetes.io/control-plane="]
+        );
+        assert_eq!(Spec::labels(&spec.nodes()[1]), vec!["disk=ssd"]);
+        assert!(spec.has_labels());
+        Ok(())
+    }
+
+    #[test]
+    fn from_file_failure() -> Result<()> {
+        let d = tempdir()?;
+        let path = d.path().join("kubernix.yaml");
+        assert!(Spec::from_file(&path).is_err());
+
+        for content in &[
+            "unknown: true",
+            "addons: [{name: unknown}]",
+            "addons: [{name: ingress, values: {replicas: '2'}}]",
+            "patches: [{component: apiserver, patch: {}}]",
+            "patches: [{component: kubelet, patch: []}]",
+        ] {
+            write(&path, content)?;
+            assert!(Spec::from_file(&path).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn merge_success() -> Result<()> {
+        let mut target: Value = serde_yaml::from_str("a: 1\nb:\n  c: 2\n  d: 3\n")?;
+        let patch: Value = serde_yaml::from_str("a: 4\nb:\n  c: null\n  e: 5\nf: [1]\n")?;
+        Spec::merge(&mut target, &patch);
+        let expected: Value = serde_yaml::from_str("a: 4\nb:\n  d: 3\n  e: 5\nf: [1]\n")?;
+        assert_eq!(target, expected);
+        Ok(())
+    }
+}
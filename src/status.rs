@@ -0,0 +1,427 @@
+use crate::{
+    clusterinfo::NodeInfo,
+    events::{Event, Events},
+    warnings::{Warning, Warnings},
+    Config,
+};
+use anyhow::{Context, Result};
+use getset::{CopyGetters, Getters};
+use lazy_static::lazy_static;
+use log::{debug, info};
+use nix::{sys::signal::kill, unistd::Pid};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    fs::{self, read_to_string, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process::id,
+};
+
+/// The overall phase of the cluster
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// The cluster is being bootstrapped
+    Bootstrapping,
+
+    /// All processes are up and running
+    Running,
+
+    /// The cluster is being cleaned up
+    Stopping,
+
+    /// The cluster has been cleaned up
+    Stopped,
+
+    /// The cluster failed to bootstrap or its main process died
+    Failed,
+}
+
+/// The state of a cluster addon
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddonState {
+    /// The addon has not been applied yet
+    Pending,
+
+    /// The addon has been applied successfully
+    Applied,
+
+    /// The addon could not be applied
+    Failed,
+}
+
+/// The state of a single process
+#[derive(Clone, CopyGetters, Debug, Default, Deserialize, Getters, Serialize)]
+pub struct Component {
+    #[get_copy = "pub"]
+    /// The process ID
+    pid: u32,
+
+    #[get_copy = "pub"]
+    /// True if the process passed its readiness check
+    ready: bool,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// The log file of the process
+    log_file: PathBuf,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// The executable providing the component
+    binary: String,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// The endpoint the component serves on
+    endpoint: String,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// The kubeconfig used by the component
+    kubeconfig: Option<PathBuf>,
+}
+
+/// The persisted cluster status, written by the main kubernix process
+#[derive(Clone, CopyGetters, Debug, Deserialize, Getters, Serialize)]
+pub struct Status {
+    #[get = "pub"]
+    /// The name of the cluster, which is the name of its root directory
+    cluster: String,
+
+    #[get_copy = "pub"]
+    /// The process ID of the main kubernix process
+    pid: u32,
+
+    #[get_copy = "pub"]
+    /// The overall cluster phase
+    phase: Phase,
+
+    #[get = "pub"]
+    /// All processes by their name
+    components: BTreeMap<String, Component>,
+
+    #[get = "pub"]
+    /// All cluster addons by their name
+    addons: BTreeMap<String, AddonState>,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// All deduplicated warnings which occurred during the bootstrap
+    warnings: Vec<Warning>,
+
+    #[get = "pub"]
+    #[serde(default)]
+    /// All nodes of the cluster
+    nodes: Vec<NodeInfo>,
+}
+
+lazy_static! {
+    static ref STATUS: RwLock<Option<(PathBuf, Status)>> = RwLock::new(None);
+}
+
+impl Status {
+    const FILENAME: &'static str = "status.json";
+    const DUMP_LINES: usize = 5;
+
+    /// Initialize the global status of the current process
+    pub fn init(config: &Config) -> Result<()> {
+        let status = Self::new(config);
+        let path = Self::path(config);
+        status.write(&path)?;
+        *STATUS.write() = Some((path, status));
+        Ok(())
+    }
+
+    /// Retrieve the global cluster phase, if initialized
+    pub fn current_phase() -> Option<Phase> {
+        STATUS.read().as_ref().map(|(_, s)| s.phase)
+    }
+
+    /// Update the global cluster phase
+    pub fn set_phase(phase: Phase) {
+        let changed = Self::update(|s| {
+            let changed = s.phase != phase;
+            s.phase = phase;
+            changed
+        });
+        if changed == Some(true) {
+            Events::emit(&Event::Cluster { phase })
+        }
+    }
+
+    /// Update the global state of a process
+    pub fn set_component(name: &str, pid: u32, ready: bool, log_file: &Path) {
+        let became_ready = Self::update(|s| {
+            let c = s.components.entry(name.into()).or_default();
+            let became_ready = ready && (!c.ready || c.pid != pid);
+            c.pid = pid;
+            c.ready = ready;
+            c.log_file = log_file.into();
+            became_ready
+        });
+        if became_ready == Some(true) {
+            Events::emit(&Event::ComponentReady {
+                component: name.into(),
+            })
+        }
+    }
+
+    /// Update the global description of a process
+    pub fn describe_component(name: &str, binary: &str, endpoint: &str, kubeconfig: Option<&Path>) {
+        Self::update(|s| {
+            let c = s.components.entry(name.into()).or_default();
+            c.binary = binary.into();
+            c.endpoint = endpoint.into();
+            c.kubeconfig = kubeconfig.map(Into::into);
+        });
+    }
+
+    /// Update the global state of an addon
+    pub fn set_addon(name: &str, state: AddonState) {
+        if Self::update(|s| s.addons.insert(name.into(), state)).is_some() {
+            Events::emit(&Event::Addon {
+                addon: name.into(),
+                state,
+            })
+        }
+    }
+
+    /// Update the global warnings
+    pub fn set_warnings(warnings: Vec<Warning>) {
+        Self::update(|s| s.warnings = warnings);
+    }
+
+    /// Update the nodes of the cluster
+    pub fn set_nodes(nodes: Vec<NodeInfo>) {
+        Self::update(|s| s.nodes = nodes);
+    }
+
+    /// Retrieve a copy of the global status, if initialized
+    pub fn current() -> Option<Status> {
+        STATUS.read().as_ref().map(|(_, s)| s.clone())
+    }
+
+    /// Log the global status including the recent output of every process
+    pub fn dump() {
+        let status = match Self::current() {
+            Some(status) => status,
+            None => return,
+        };
+        info!(
+            "Cluster {} is {:?} (pid {})",
+            status.cluster, status.phase, status.pid
+        );
+        for (name, c) in &status.components {
+            info!(
+                "{} (pid {}) is {}",
+                name,
+                c.pid,
+                if c.ready { "ready" } else { "not ready" }
+            );
+            match Self::tail(&c.log_file, Self::DUMP_LINES) {
+                Ok(lines) => lines.iter().for_each(|x| info!("  {}", x)),
+                Err(e) => debug!("Unable to read log of {}: {:#}", name, e),
+            }
+        }
+        for (name, state) in &status.addons {
+            info!("Addon {} is {:?}", name, state);
+        }
+    }
+
+    /// Retrieve the last lines of a file, whereas only its end will be read
+    fn tail(path: &Path, lines: usize) -> Result<Vec<String>> {
+        const MAX_BYTES: u64 = 8192;
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        file.seek(SeekFrom::Start(len.saturating_sub(MAX_BYTES)))?;
+        let mut content = vec![];
+        file.read_to_end(&mut content)?;
+
+        let content = String::from_utf8_lossy(&content);
+        let all = content.lines().collect::<Vec<_>>();
+        Ok(all[all.len().saturating_sub(lines)..]
+            .iter()
+            .map(|x| (*x).to_string())
+            .collect())
+    }
+
+    /// Load the status of the cluster from its root directory
+    pub fn load(config: &Config) -> Result<Status> {
+        Self::load_root(config.root())
+    }
+
+    /// Load the status of the cluster from the provided root directory
+    pub fn load_root(root: &Path) -> Result<Status> {
+        let path = root.join(Self::FILENAME);
+        let mut status: Status =
+            serde_json::from_str(&read_to_string(&path).with_context(|| {
+                format!(
+                    "Unable to read status file '{}', is the cluster running?",
+                    path.display()
+                )
+            })?)
+            .with_context(|| format!("Unable to parse status file '{}'", path.display()))?;
+
+        // The main process may have been killed without cleaning up
+        if status.phase != Phase::Stopped && kill(Pid::from_raw(status.pid as i32), None).is_err() {
+            status.phase = Phase::Failed;
+            status.components.values_mut().for_each(|x| x.ready = false);
+        }
+        Ok(status)
+    }
+
+    /// Remove a possibly stale status file from the provided root directory
+    #[cfg(feature = "testing")]
+    pub fn remove_root(root: &Path) -> Result<()> {
+        let path = root.join(Self::FILENAME);
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Unable to remove status file '{}'", path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn new(config: &Config) -> Status {
+        Status {
+            cluster: config
+                .root()
+                .file_name()
+                .map(|x| x.to_string_lossy().into())
+                .unwrap_or_default(),
+            pid: id(),
+            phase: Phase::Bootstrapping,
+            components: BTreeMap::new(),
+            addons: BTreeMap::new(),
+            warnings: Warnings::all(),
+            nodes: vec![],
+        }
+    }
+
+    fn path(config: &Config) -> PathBuf {
+        config.root().join(Self::FILENAME)
+    }
+
+    fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Unable to write status file '{}'", path.display()))
+    }
+
+    /// Update the global status if initialized and return the result of the
+    /// update function
+    fn update<F, R>(f: F) -> Option<R>
+    where
+        F: FnOnce(&mut Status) -> R,
+    {
+        let mut global = STATUS.write();
+        let (path, status) = global.as_mut()?;
+        let result = f(status);
+        if let Err(e) = status.write(path) {
+            debug!("{:#}", e)
+        }
+        Some(result)
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Cluster: {}", self.cluster)?;
+        writeln!(f, "Phase:   {:?}", self.phase)?;
+        writeln!(f, "PID:     {}", self.pid)?;
+        writeln!(f, "Components:")?;
+        for (name, c) in &self.components {
+            writeln!(
+                f,
+                "  {:<20} pid={:<8} {}",
+                name,
+                c.pid,
+                if c.ready { "ready" } else { "not ready" }
+            )?;
+        }
+        if !self.nodes.is_empty() {
+            writeln!(f, "Nodes:")?;
+            for n in &self.nodes {
+                writeln!(
+                    f,
+                    "  {:<20} kubelet={:<6} healthz={:<6} {}",
+                    n.name(),
+                    n.kubelet_port(),
+                    n.healthz_port(),
+                    n.cri_socket().display()
+                )?;
+            }
+        }
+        writeln!(f, "Addons:")?;
+        for (name, state) in &self.addons {
+            writeln!(f, "  {:<20} {:?}", name, state)?;
+        }
+        if !self.warnings.is_empty() {
+            writeln!(f, "Warnings:")?;
+            for w in &self.warnings {
+                writeln!(f, "  {} (x{})", w.message(), w.count())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_config;
+
+    #[test]
+    fn load_success() -> Result<()> {
+        let c = test_config()?;
+        let mut s = Status::new(&c);
+        s.components.insert(
+            "etcd".into(),
+            Component {
+                pid: 1,
+                ready: true,
+                log_file: "etcd.log".into(),
+                ..Default::default()
+            },
+        );
+        s.addons.insert("coredns".into(), AddonState::Applied);
+        s.write(&Status::path(&c))?;
+
+        let l = Status::load(&c)?;
+        assert_eq!(l.cluster(), s.cluster());
+        assert_eq!(l.phase(), Phase::Bootstrapping);
+        assert!(l.components()["etcd"].ready());
+        assert_eq!(l.addons()["coredns"], AddonState::Applied);
+        Ok(())
+    }
+
+    #[test]
+    fn load_success_dead_process() -> Result<()> {
+        let c = test_config()?;
+        let mut s = Status::new(&c);
+        s.pid = i32::MAX as u32;
+        s.write(&Status::path(&c))?;
+        assert_eq!(Status::load(&c)?.phase(), Phase::Failed);
+        Ok(())
+    }
+
+    #[test]
+    fn tail_success() -> Result<()> {
+        let c = test_config()?;
+        let file = c.root().join("test.log");
+        fs::write(&file, "1\n2\n3\n")?;
+        assert_eq!(Status::tail(&file, 2)?, vec!["2", "3"]);
+        assert_eq!(Status::tail(&file, 5)?, vec!["1", "2", "3"]);
+        Ok(())
+    }
+
+    #[test]
+    fn load_failure() -> Result<()> {
+        let c = test_config()?;
+        assert!(Status::load(&c).is_err());
+        Ok(())
+    }
+}
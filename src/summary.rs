@@ -0,0 +1,130 @@
+use crate::{
+    status::{AddonState, Status},
+    system::System,
+};
+use console::style;
+use std::{collections::BTreeMap, path::Path};
+
+/// The cluster summary printed after a successful bootstrap
+pub struct Summary;
+
+impl Summary {
+    const UNKNOWN: &'static str = "unknown";
+
+    /// Print the summary of the provided status to stderr
+    pub fn print(status: &Status, kubeconfig: &Path) {
+        eprint!(
+            "\n{}\n{}  {}\n",
+            Self::render(status),
+            style("KUBECONFIG").bold(),
+            kubeconfig.display()
+        );
+    }
+
+    /// Render the summary table of all components and addons
+    fn render(status: &Status) -> String {
+        let mut versions = BTreeMap::new();
+        let mut rows = vec![[
+            "COMPONENT".to_owned(),
+            "VERSION".into(),
+            "ENDPOINT".into(),
+            "LOG".into(),
+            "KUBECONFIG".into(),
+        ]];
+        for (name, c) in status.components() {
+            let version = versions
+                .entry(c.binary().clone())
+                .or_insert_with(|| Self::version(c.binary()))
+                .clone();
+            rows.push([
+                name.clone(),
+                version,
+                Self::or_unknown(c.endpoint()),
+                c.log_file().display().to_string(),
+                c.kubeconfig()
+                    .as_ref()
+                    .map(|x| x.display().to_string())
+                    .unwrap_or_else(|| "-".into()),
+            ]);
+        }
+
+        let mut widths = [0; 5];
+        for row in &rows {
+            for (width, column) in widths.iter_mut().zip(row) {
+                *width = (*width).max(column.len());
+            }
+        }
+
+        let mut out = String::new();
+        for (i, row) in rows.iter().enumerate() {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(column, width)| format!("{:<w$}", column, w = width))
+                .collect::<Vec<_>>()
+                .join("  ");
+            let line = line.trim_end();
+            out.push_str(&if i == 0 {
+                style(line).bold().to_string()
+            } else {
+                let (name, rest) = line.split_at(widths[0]);
+                format!("{}{}", style(name).green(), rest)
+            });
+            out.push('\n');
+        }
+
+        if !status.addons().is_empty() {
+            out.push_str(&format!("\n{}\n", style("ADDON").bold()));
+            let width = status.addons().keys().map(String::len).max().unwrap_or(0);
+            for (name, state) in status.addons() {
+                let text = format!("{:?}", state).to_lowercase();
+                let text = match state {
+                    AddonState::Applied => style(text).green(),
+                    AddonState::Pending => style(text).yellow(),
+                    AddonState::Failed => style(text).red(),
+                };
+                out.push_str(&format!("{:<w$}  {}\n", name, text, w = width));
+            }
+        }
+
+        if !status.warnings().is_empty() {
+            out.push_str(&format!("\n{}\n", style("WARNING").bold()));
+            for w in status.warnings() {
+                let count = if w.count() > 1 {
+                    format!(" (x{})", w.count())
+                } else {
+                    String::new()
+                };
+                out.push_str(&format!("{}{}\n", style(w.message()).yellow(), count));
+            }
+        }
+        out
+    }
+
+    /// Retrieve the version of the provided binary, if available
+    fn version(binary: &str) -> String {
+        if binary.is_empty() {
+            return Self::UNKNOWN.into();
+        }
+        System::version(binary).unwrap_or_else(|_| Self::UNKNOWN.into())
+    }
+
+    fn or_unknown(value: &str) -> String {
+        if value.is_empty() {
+            Self::UNKNOWN.into()
+        } else {
+            value.into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_failure() {
+        assert_eq!(Summary::version(""), Summary::UNKNOWN);
+        assert_eq!(Summary::version("/invalid/binary"), Summary::UNKNOWN);
+    }
+}
@@ -1,17 +1,31 @@
-use crate::{node::Node, Config};
+use crate::{nix::Nix, node::Node, proxy::Proxy, record::Recorded, Config};
 use anyhow::{bail, Context, Result};
 use log::{debug, info, warn};
+use nix::{
+    fcntl::{open, OFlag},
+    mount::{mount, MsFlags},
+    sys::{
+        stat::Mode,
+        statfs::{statfs, FsType},
+    },
+    unistd::close,
+};
 use std::{
     env::{split_paths, var, var_os},
     fmt::Display,
-    fs::{self, read_to_string},
+    fs::{self, create_dir_all, read_to_string, File, OpenOptions, Permissions},
+    io::Write,
     net::Ipv4Addr,
+    os::unix::{
+        self,
+        fs::{OpenOptionsExt, PermissionsExt},
+    },
     path::{Path, PathBuf},
     process::Command,
 };
 
 pub struct System {
-    hosts: Option<String>,
+    hosts: Option<Vec<String>>,
 }
 
 impl System {
@@ -20,7 +34,15 @@ impl System {
         if Self::in_container()? {
             info!("Skipping modprobe and sysctl for sake of containerization")
         } else {
-            for module in &["overlay", "br_netfilter", "ip_conntrack"] {
+            for module in
+                ["overlay", "br_netfilter", "ip_conntrack"]
+                    .iter()
+                    .chain(if config.no_proxy() {
+                        &[]
+                    } else {
+                        Proxy::kernel_modules(config.proxy_mode())
+                    })
+            {
                 Self::modprobe(module)?;
             }
             for sysctl in &[
@@ -37,18 +59,11 @@ impl System {
             // Try to write the hostnames, which does not work on every system
             let hosts_file = Self::hosts();
             let hosts = read_to_string(&hosts_file)?;
-            let local_hosts = (0..config.nodes())
-                .map(|x| format!("{} {}", Ipv4Addr::LOCALHOST, Node::raw(x)))
-                .collect::<Vec<_>>();
-
-            let mut new_hosts = hosts
-                .lines()
-                .filter(|x| !local_hosts.iter().any(|y| x == y))
-                .map(|x| x.into())
-                .collect::<Vec<_>>();
+            let local_hosts = Self::local_hosts(config);
+            let mut new_hosts = Self::without_hosts(&hosts, &local_hosts);
             new_hosts.extend(local_hosts);
 
-            match fs::write(&hosts_file, new_hosts.join("\n")) {
+            match fs::write(&hosts_file, new_hosts.join("\n") + "\n") {
                 Err(e) => {
                     warn!(
                         "Unable to write hosts file '{}'. The nodes may be not reachable: {}",
@@ -57,7 +72,7 @@ impl System {
                     );
                     None
                 }
-                _ => Some(hosts),
+                _ => Some(Self::local_hosts(config)),
             }
         } else {
             None
@@ -76,18 +91,195 @@ impl System {
         )
     }
 
+    /// Mount a size limited tmpfs at the run root. The nix environment and the
+    /// configuration file are bind mounted from the disk to keep them cached
+    /// between runs.
+    pub fn mount_ephemeral_root(config: &Config) -> Result<()> {
+        let root = config.root();
+
+        // Keep a reference to the files which will be hidden by the tmpfs
+        let mut kept = vec![];
+        for path in [root.join(Nix::DIR), root.join(Config::FILENAME)] {
+            if path.exists() {
+                let fd = open(&path, OFlag::O_PATH | OFlag::O_CLOEXEC, Mode::empty())
+                    .with_context(|| format!("Unable to open '{}'", path.display()))?;
+                let is_dir = path.is_dir();
+                kept.push((path, is_dir, fd));
+            }
+        }
+
+        info!(
+            "Mounting ephemeral run root '{}' (size {})",
+            root.display(),
+            config.ephemeral_size()
+        );
+        mount(
+            Some("tmpfs"),
+            root,
+            Some("tmpfs"),
+            MsFlags::empty(),
+            Some(format!("size={},mode=0755", config.ephemeral_size()).as_str()),
+        )
+        .with_context(|| format!("Unable to mount tmpfs at '{}'", root.display()))?;
+
+        for (path, is_dir, fd) in kept {
+            debug!("Keeping '{}' on disk", path.display());
+            if is_dir {
+                create_dir_all(&path)?;
+            } else {
+                File::create(&path)?;
+            }
+            let source = Path::new("/")
+                .join("proc")
+                .join("self")
+                .join("fd")
+                .join(fd.to_string());
+            let result = mount(
+                Some(&source),
+                &path,
+                None::<&str>,
+                MsFlags::MS_BIND,
+                None::<&str>,
+            );
+            close(fd)?;
+            result.with_context(|| format!("Unable to bind mount '{}'", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Create a symlink to the target, whereas an existing link will be replaced
+    pub fn symlink(target: &Path, link: &Path) -> Result<()> {
+        if let Ok(metadata) = link.symlink_metadata() {
+            if !metadata.file_type().is_symlink() {
+                warn!(
+                    "Not replacing '{}' with a link to '{}'",
+                    link.display(),
+                    target.display()
+                );
+                return Ok(());
+            }
+            fs::remove_file(link)?;
+        }
+        debug!("Linking '{}' to '{}'", link.display(), target.display());
+        unix::fs::symlink(target, link)
+            .with_context(|| format!("Unable to create symlink '{}'", link.display()))
+    }
+
+    /// Write the file containing credentials, which is only readable by the
+    /// current user, even if it has been created before
+    pub fn write_private(path: &Path, content: &str) -> Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .and_then(|mut f| {
+                f.set_permissions(Permissions::from_mode(0o600))?;
+                f.write_all(content.as_bytes())
+            })
+            .with_context(|| format!("Unable to write '{}'", path.display()))
+    }
+
+    /// Retrieve the container storage driver required by the filesystem of
+    /// the provided path, or `None` if the default driver can be used
+    pub fn storage_driver(path: &Path) -> Result<Option<&'static str>> {
+        // The path may not exist yet, so use its nearest existing ancestor
+        let existing = path
+            .ancestors()
+            .find(|x| x.exists())
+            .with_context(|| format!("Unable to find existing parent of '{}'", path.display()))?;
+        let fs_type = statfs(existing)
+            .with_context(|| format!("Unable to retrieve filesystem of '{}'", existing.display()))?
+            .filesystem_type();
+        let driver = Self::storage_driver_for(fs_type);
+        if let Some(driver) = driver {
+            info!(
+                "Using {} storage driver for '{}'",
+                driver,
+                existing.display()
+            );
+        }
+        Ok(driver)
+    }
+
+    fn storage_driver_for(fs_type: FsType) -> Option<&'static str> {
+        const BTRFS_SUPER_MAGIC: FsType = FsType(0x9123_683e);
+        const ZFS_SUPER_MAGIC: FsType = FsType(0x2fc1_2fc1);
+
+        if fs_type == BTRFS_SUPER_MAGIC {
+            Some("btrfs")
+        } else if fs_type == ZFS_SUPER_MAGIC {
+            // The native driver requires the ZFS userspace tools
+            if Self::find_executable("zfs").is_ok() {
+                Some("zfs")
+            } else {
+                warn!("No 'zfs' executable found, falling back to the vfs storage driver");
+                Some("vfs")
+            }
+        } else {
+            None
+        }
+    }
+
     /// Restore the initial system state
     pub fn cleanup(&self) {
-        if let Some(hosts) = &self.hosts {
-            if let Err(e) = fs::write(Self::hosts(), hosts) {
+        if let Some(local_hosts) = &self.hosts {
+            if let Err(e) = Self::remove_host_entries(local_hosts) {
                 warn!(
-                    "Unable to restore hosts file, may need manual cleanup: {}",
+                    "Unable to remove node entries from hosts file, may need manual cleanup: {:#}",
                     e
                 )
             }
         }
     }
 
+    /// Remove the node entries of the cluster from the hosts file, which
+    /// remain if kubernix got killed before restoring it
+    pub fn remove_hosts(config: &Config) -> Result<()> {
+        Self::remove_host_entries(&Self::local_hosts(config))
+    }
+
+    /// Remove the provided entries from the hosts file, whereas the entries
+    /// of other clusters are kept
+    fn remove_host_entries(local_hosts: &[String]) -> Result<()> {
+        let hosts_file = Self::hosts();
+        let hosts = read_to_string(&hosts_file)
+            .with_context(|| format!("Unable to read hosts file '{}'", hosts_file.display()))?;
+        let new_hosts = Self::without_hosts(&hosts, local_hosts);
+        if new_hosts.len() != hosts.lines().count() {
+            info!("Removing node entries from hosts file");
+            fs::write(&hosts_file, new_hosts.join("\n") + "\n").with_context(|| {
+                format!("Unable to write hosts file '{}'", hosts_file.display())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The hosts file entries of all nodes, which are marked by the cluster
+    /// name since all clusters share the node names
+    fn local_hosts(config: &Config) -> Vec<String> {
+        (0..config.nodes())
+            .map(|x| {
+                format!(
+                    "{} {} # {}",
+                    Ipv4Addr::LOCALHOST,
+                    Node::raw(x),
+                    config.cluster_name()
+                )
+            })
+            .collect()
+    }
+
+    /// Retrieve all lines of the hosts file which are no local hosts entry
+    fn without_hosts(hosts: &str, local_hosts: &[String]) -> Vec<String> {
+        hosts
+            .lines()
+            .filter(|x| !local_hosts.iter().any(|y| x == y))
+            .map(|x| x.into())
+            .collect()
+    }
+
     /// Find an executable inside the current $PATH environment
     pub fn find_executable<P>(name: P) -> Result<PathBuf>
     where
@@ -109,6 +301,29 @@ impl System {
             .with_context(|| format!("Unable to find executable '{}' in $PATH", name))
     }
 
+    /// Retrieve the version of the provided binary, which is the last word of
+    /// the first line of its `--version` output
+    pub fn version(binary: &str) -> Result<String> {
+        let output = Command::new(binary)
+            .arg("--version")
+            .recorded_output()
+            .with_context(|| format!("Unable to run '{} --version'", binary))?;
+        if !output.status.success() {
+            bail!("Unable to retrieve version of '{}'", binary)
+        }
+        Self::parse_version(&String::from_utf8_lossy(&output.stdout))
+            .with_context(|| format!("Unable to parse version of '{}'", binary))
+    }
+
+    fn parse_version(output: &str) -> Option<String> {
+        output
+            .lines()
+            .next()?
+            .split_whitespace()
+            .last()
+            .map(Into::into)
+    }
+
     /// Return the full path to the default system shell
     pub fn shell() -> Result<String> {
         let shell = var("SHELL").unwrap_or_else(|_| "sh".into());
@@ -120,10 +335,27 @@ impl System {
         ))
     }
 
+    /// Returns true if the kernel module is either loaded or able to be
+    /// loaded, whereas containers are only able to use already loaded ones
+    pub fn kernel_module_available(module: &str) -> Result<bool> {
+        if PathBuf::from("/sys/module").join(module).exists() {
+            return Ok(true);
+        }
+        if Self::in_container()? {
+            return Ok(false);
+        }
+        Ok(Command::new("modprobe")
+            .arg("--dry-run")
+            .arg(module)
+            .recorded_output()?
+            .status
+            .success())
+    }
+
     /// Load a single kernel module via 'modprobe'
     fn modprobe(module: &str) -> Result<()> {
         debug!("Loading kernel module '{}'", module);
-        let output = Command::new("modprobe").arg(module).output()?;
+        let output = Command::new("modprobe").arg(module).recorded_output()?;
         if !output.status.success() {
             bail!(
                 "Unable to load '{}' kernel module: {}",
@@ -138,7 +370,10 @@ impl System {
     fn sysctl_enable(key: &str) -> Result<()> {
         debug!("Enabling sysctl '{}'", key);
         let enable_arg = format!("{}=1", key);
-        let output = Command::new("sysctl").arg("-w").arg(&enable_arg).output()?;
+        let output = Command::new("sysctl")
+            .arg("-w")
+            .arg(&enable_arg)
+            .recorded_output()?;
         let stderr = String::from_utf8(output.stderr)?;
         if !stderr.is_empty() {
             bail!("Unable to set sysctl '{}': {}", enable_arg, stderr);
@@ -154,7 +389,9 @@ impl System {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::tests::test_config_named;
     use std::env::set_var;
+    use tempfile::tempdir;
 
     const VALID_EXECUTABLE: &str = "runc";
     const INVALID_EXECUTABLE: &str = "should-not-exist";
@@ -169,6 +406,53 @@ mod tests {
         assert!(System::sysctl_enable("invalid").is_err());
     }
 
+    #[test]
+    fn symlink_success() -> Result<()> {
+        let d = tempdir()?;
+        let link = d.path().join("link");
+        System::symlink(&d.path().join("a"), &link)?;
+        System::symlink(&d.path().join("b"), &link)?;
+        assert_eq!(fs::read_link(&link)?, d.path().join("b"));
+        Ok(())
+    }
+
+    #[test]
+    fn storage_driver_success() -> Result<()> {
+        System::storage_driver(&Path::new("/").join("does").join("not").join("exist"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn storage_driver_for_success() {
+        assert_eq!(
+            System::storage_driver_for(FsType(0x9123_683e)),
+            Some("btrfs")
+        );
+        assert_eq!(System::storage_driver_for(FsType(0xef53)), None);
+    }
+
+    #[test]
+    fn local_hosts_success() -> Result<()> {
+        let c = test_config_named("dev")?;
+        assert_eq!(
+            System::local_hosts(&c),
+            vec!["127.0.0.1 node-0 # kubernix-dev"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn without_hosts_success() {
+        let local_hosts = vec!["127.0.0.1 node-0".to_owned(), "127.0.0.1 node-1".to_owned()];
+        assert_eq!(
+            System::without_hosts(
+                "127.0.0.1 localhost\n127.0.0.1 node-0\n::1 localhost\n127.0.0.1 node-1",
+                &local_hosts
+            ),
+            vec!["127.0.0.1 localhost", "::1 localhost"]
+        );
+    }
+
     #[test]
     fn find_executable_success() {
         assert!(System::find_executable(VALID_EXECUTABLE).is_ok());
@@ -184,4 +468,21 @@ mod tests {
         set_var("SHELL", VALID_EXECUTABLE);
         assert!(System::shell().is_ok());
     }
+
+    #[test]
+    fn parse_version_success() {
+        assert_eq!(
+            System::parse_version("Kubernetes v1.19.3\n"),
+            Some("v1.19.3".into())
+        );
+        assert_eq!(
+            System::parse_version("etcd Version: 3.4.13\nGit SHA: Not provided\n"),
+            Some("3.4.13".into())
+        );
+    }
+
+    #[test]
+    fn parse_version_failure() {
+        assert_eq!(System::parse_version(""), None);
+    }
 }
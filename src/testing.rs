@@ -0,0 +1,221 @@
+//! Support for using kubernix as a test fixture
+//!
+//! A [`TestCluster`] spawns the `kubernix` binary in a dedicated run root and
+//! waits until the cluster is ready to be used. The cluster gets destroyed
+//! when the fixture goes out of scope. The `KUBERNIX_*` environment of the
+//! caller is ignored, so that the cluster only depends on the provided
+//! arguments.
+use crate::{
+    clusterinfo::ClusterInfo,
+    kubeconfig::KubeConfig,
+    status::{Phase, Status},
+    system::System,
+};
+use anyhow::{bail, Context, Result};
+use log::debug;
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::{getuid, Pid},
+};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::{
+    env::{temp_dir, var, var_os, vars_os},
+    fs::{self, create_dir_all, File},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// An ephemeral cluster for testing purposes
+pub struct TestCluster {
+    child: Option<Child>,
+    root: PathBuf,
+    ephemeral: bool,
+    log_file: PathBuf,
+}
+
+impl TestCluster {
+    /// The environment variable to point to the `kubernix` binary. If unset,
+    /// the binary will be looked up in `$PATH`.
+    pub const BINARY_ENV: &'static str = "KUBERNIX_BINARY";
+
+    /// The default time to wait for the cluster to become ready
+    pub const TIMEOUT: Duration = Duration::from_secs(2000);
+
+    /// Start a new cluster inside a temporary directory, which will be removed
+    /// on teardown
+    pub fn start(args: &[&str]) -> Result<TestCluster> {
+        let binary = match var_os(Self::BINARY_ENV) {
+            Some(binary) => binary.into(),
+            None => System::find_executable("kubernix")?,
+        };
+        let suffix: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect();
+        let root = temp_dir().join(format!("kubernix-test-{}", suffix));
+
+        let mut cluster = Self::start_with(&binary, &root, args)?;
+        cluster.ephemeral = true;
+        Ok(cluster)
+    }
+
+    /// Start a new cluster by using the provided binary and run root, which
+    /// will be kept on teardown
+    pub fn start_with(binary: &Path, root: &Path, args: &[&str]) -> Result<TestCluster> {
+        create_dir_all(root)?;
+        let root_arg = format!("--root={}", root.display());
+        Status::remove_root(root)?;
+
+        let log_file = root.join("kubernix.log");
+        let out_file = File::create(&log_file)?;
+        let err_file = out_file.try_clone()?;
+
+        let mut command = if getuid().is_root() {
+            Command::new(binary)
+        } else {
+            let mut command = Command::new("sudo");
+            command
+                .arg("env")
+                .arg(format!("PATH={}", var("PATH")?))
+                .arg(binary);
+            command
+        };
+        for (key, _) in vars_os().filter(|(x, _)| x.to_string_lossy().starts_with("KUBERNIX_")) {
+            command.env_remove(key);
+        }
+        command
+            .arg(&root_arg)
+            .args(args)
+            .arg("--no-shell")
+            .stderr(Stdio::from(err_file))
+            .stdout(Stdio::from(out_file));
+        debug!("Starting test cluster: {:?}", command);
+
+        let mut cluster = TestCluster {
+            child: Some(command.spawn().context("Unable to spawn kubernix")?),
+            root: root.into(),
+            ephemeral: false,
+            log_file,
+        };
+        cluster.wait_ready(Self::TIMEOUT)?;
+        Ok(cluster)
+    }
+
+    /// Wait until the cluster is running and all of its components are ready
+    pub fn wait_ready(&mut self, timeout: Duration) -> Result<()> {
+        let now = Instant::now();
+        while now.elapsed() < timeout {
+            if let Some(child) = self.child.as_mut() {
+                if let Some(status) = child.try_wait()? {
+                    bail!(
+                        "kubernix exited before becoming ready ({}), see '{}'",
+                        status,
+                        self.log_file.display()
+                    )
+                }
+            }
+            if let Ok(status) = Status::load_root(&self.root) {
+                match status.phase() {
+                    Phase::Running if status.components().values().all(|x| x.ready()) => {
+                        return Ok(());
+                    }
+                    Phase::Failed => {
+                        bail!("Cluster failed to start, see '{}'", self.log_file.display())
+                    }
+                    _ => {}
+                }
+            }
+            sleep(Duration::from_secs(1));
+        }
+        bail!("Timed out waiting for the cluster to become ready")
+    }
+
+    /// Retrieve the run root of the cluster
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Retrieve the log file of the kubernix process
+    pub fn log_file(&self) -> &Path {
+        &self.log_file
+    }
+
+    /// Retrieve the admin kubeconfig path of the cluster
+    pub fn kubeconfig(&self) -> Result<PathBuf> {
+        KubeConfig::existing_admin_root(&self.root)
+    }
+
+    /// Retrieve the facts about the running cluster
+    pub fn cluster_info(&self) -> Result<ClusterInfo> {
+        ClusterInfo::load_root(&self.root)
+    }
+
+    /// Retrieve a kubectl command using the admin kubeconfig of the cluster
+    pub fn kubectl(&self) -> Result<Command> {
+        let mut command = Command::new("kubectl");
+        command.env("KUBECONFIG", self.kubeconfig()?);
+        Ok(command)
+    }
+
+    /// Stop the cluster and return an error if it did not exit cleanly
+    pub fn stop(mut self) -> Result<()> {
+        self.teardown()
+    }
+
+    fn teardown(&mut self) -> Result<()> {
+        let mut child = match self.child.take() {
+            Some(child) => child,
+            None => return Ok(()),
+        };
+
+        // The main process may be nested inside the nix environment
+        let pid = Status::load_root(&self.root)
+            .map(|x| x.pid())
+            .unwrap_or_else(|_| child.id());
+        debug!("Stopping test cluster with pid {}", pid);
+        if getuid().is_root() {
+            kill(Pid::from_raw(pid as i32), Signal::SIGTERM)?;
+        } else {
+            Command::new("sudo")
+                .arg("kill")
+                .arg("-TERM")
+                .arg(pid.to_string())
+                .status()?;
+        }
+        let status = child.wait()?;
+
+        // Keep the logs for further investigation on failure
+        if self.ephemeral && status.success() {
+            debug!("Removing test cluster root {}", self.root().display());
+            if getuid().is_root() {
+                fs::remove_dir_all(self.root())?;
+            } else {
+                Command::new("sudo")
+                    .arg("rm")
+                    .arg("-rf")
+                    .arg(self.root())
+                    .status()?;
+            }
+        }
+
+        if !status.success() {
+            bail!(
+                "kubernix did not exit cleanly ({}), see '{}'",
+                status,
+                self.log_file.display()
+            )
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TestCluster {
+    fn drop(&mut self) {
+        if let Err(e) = self.teardown() {
+            debug!("Unable to teardown test cluster: {:#}", e)
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{config::Config, kubectl::Kubectl};
+use anyhow::{bail, Context, Result};
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::{
+    fs::{self, create_dir_all},
+    path::Path,
+};
+
+/// Short-lived service account credentials to share access to the cluster
+pub struct Token<'a> {
+    service_account: &'a str,
+    namespace: &'a str,
+    cluster_role: &'a str,
+    duration: u64,
+}
+
+impl<'a> Token<'a> {
+    /// The minimum expiration the API server accepts for requested tokens
+    pub const MIN_DURATION: u64 = 600;
+
+    /// Create a new token for the service account, which gets bound to the
+    /// cluster role and expires after the duration in seconds
+    pub fn new(
+        service_account: &'a str,
+        namespace: &'a str,
+        cluster_role: &'a str,
+        duration: u64,
+    ) -> Self {
+        Self {
+            service_account,
+            namespace,
+            cluster_role,
+            duration,
+        }
+    }
+
+    /// Create the service account and render a kubeconfig, which
+    /// authenticates via the requested token
+    pub fn kubeconfig(
+        &self,
+        config: &Config,
+        kubectl: &Kubectl,
+        ca: &Path,
+        server: &str,
+    ) -> Result<String> {
+        let token = self.request(config, kubectl)?;
+        let ca = fs::read(ca)
+            .with_context(|| format!("Unable to read CA certificate '{}'", ca.display()))?;
+        info!("Token kubeconfig created, expiring in {}s", self.duration);
+        Self::render(
+            &ca,
+            server,
+            &config.cluster_name(),
+            self.service_account,
+            &token,
+        )
+    }
+
+    /// Create the service account, bind it to the cluster role and request a
+    /// new bearer token for it
+    pub fn request(&self, config: &Config, kubectl: &Kubectl) -> Result<String> {
+        if self.duration < Self::MIN_DURATION {
+            bail!(
+                "Token duration has to be at least {} seconds",
+                Self::MIN_DURATION
+            )
+        }
+
+        let dir = config.root().join("token");
+        create_dir_all(&dir)?;
+
+        info!(
+            "Binding service account {}/{} to cluster role {}",
+            self.namespace, self.service_account, self.cluster_role
+        );
+        let file = dir.join(format!("{}-{}.yml", self.namespace, self.service_account));
+        fs::write(
+            &file,
+            format!(
+                include_str!("assets/token.yml"),
+                name = self.service_account,
+                namespace = self.namespace,
+                role = self.cluster_role,
+            ),
+        )?;
+        kubectl
+            .apply(&file)
+            .context("Unable to create service account")?;
+
+        debug!("Requesting token valid for {}s", self.duration);
+        let request = dir.join("tokenrequest.json");
+        fs::write(
+            &request,
+            json!({
+                "apiVersion": "authentication.k8s.io/v1",
+                "kind": "TokenRequest",
+                "spec": { "expirationSeconds": self.duration },
+            })
+            .to_string(),
+        )?;
+        let output = kubectl
+            .execute(&[
+                "create",
+                "--raw",
+                &format!(
+                    "/api/v1/namespaces/{}/serviceaccounts/{}/token",
+                    self.namespace, self.service_account
+                ),
+                "-f",
+                &request.display().to_string(),
+            ])
+            .context("Unable to request service account token")?;
+        Self::response_token(&output.stdout)
+    }
+
+    /// Retrieve the token of the TokenRequest response
+    fn response_token(response: &[u8]) -> Result<String> {
+        let response: Value =
+            serde_json::from_slice(response).context("Unable to parse TokenRequest response")?;
+        response["status"]["token"]
+            .as_str()
+            .map(Into::into)
+            .context("TokenRequest response contains no token")
+    }
+
+    /// Render the kubeconfig of the token user
+    fn render(ca: &[u8], server: &str, context: &str, user: &str, token: &str) -> Result<String> {
+        let kubeconfig = json!({
+            "apiVersion": "v1",
+            "kind": "Config",
+            "clusters": [{
+                "name": "kubernetes",
+                "cluster": {
+                    "certificate-authority-data": base64::encode(ca),
+                    "server": server,
+                },
+            }],
+            "users": [{
+                "name": user,
+                "user": { "token": token },
+            }],
+            "contexts": [{
+                "name": context,
+                "context": { "cluster": "kubernetes", "user": user },
+            }],
+            "current-context": context,
+        });
+        Ok(serde_yaml::to_string(&kubeconfig)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_token_success() -> Result<()> {
+        let response = br#"{"kind":"TokenRequest","status":{"token":"abc"}}"#;
+        assert_eq!(Token::response_token(response)?, "abc");
+        Ok(())
+    }
+
+    #[test]
+    fn response_token_failure() {
+        assert!(Token::response_token(b"{}").is_err());
+        assert!(Token::response_token(b"not json").is_err());
+    }
+
+    #[test]
+    fn render_success() -> Result<()> {
+        let kubeconfig = Token::render(b"ca", "https://127.0.0.1:6443", "kubernix", "user", "abc")?;
+        let value: serde_yaml::Value = serde_yaml::from_str(&kubeconfig)?;
+        assert_eq!(value["current-context"].as_str(), Some("kubernix"));
+        assert_eq!(value["users"][0]["user"]["token"].as_str(), Some("abc"));
+        assert_eq!(
+            value["clusters"][0]["cluster"]["certificate-authority-data"].as_str(),
+            Some("Y2E=")
+        );
+        Ok(())
+    }
+}
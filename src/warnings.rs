@@ -0,0 +1,126 @@
+use crate::status::Status;
+use getset::{CopyGetters, Getters};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    mem,
+    time::{Duration, Instant},
+};
+
+/// A deduplicated warning message
+#[derive(Clone, CopyGetters, Debug, Deserialize, Getters, PartialEq, Eq, Serialize)]
+pub struct Warning {
+    #[get = "pub"]
+    /// The message of the warning
+    message: String,
+
+    #[get_copy = "pub"]
+    /// The number of occurrences of the message
+    count: u32,
+
+    #[serde(skip)]
+    /// The last time the message got printed
+    printed: Option<Instant>,
+
+    #[serde(skip)]
+    /// The number of occurrences since the message got printed
+    suppressed: u32,
+}
+
+lazy_static! {
+    static ref WARNINGS: Mutex<Vec<Warning>> = Mutex::new(vec![]);
+}
+
+/// The registry of all warnings which occurred during the lifetime of the
+/// current process
+pub struct Warnings;
+
+impl Warnings {
+    /// The period in which repeated messages are not printed again
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    /// Record a warning message and return the number of suppressed
+    /// occurrences if it should be printed, which is the case for its first
+    /// occurrence and once the suppression window elapsed
+    pub fn record(message: &str) -> Option<u32> {
+        Self::record_at(message, Instant::now())
+    }
+
+    fn record_at(message: &str, now: Instant) -> Option<u32> {
+        let (result, all) = {
+            let mut warnings = WARNINGS.lock();
+            let result = match warnings.iter_mut().find(|x| x.message == message) {
+                Some(warning) => {
+                    warning.count = warning.count.saturating_add(1);
+                    match warning.printed {
+                        Some(printed) if now.saturating_duration_since(printed) < Self::WINDOW => {
+                            warning.suppressed = warning.suppressed.saturating_add(1);
+                            None
+                        }
+                        _ => {
+                            warning.printed = Some(now);
+                            Some(mem::take(&mut warning.suppressed))
+                        }
+                    }
+                }
+                None => {
+                    warnings.push(Warning {
+                        message: message.into(),
+                        count: 1,
+                        printed: Some(now),
+                        suppressed: 0,
+                    });
+                    Some(0)
+                }
+            };
+            (result, warnings.clone())
+        };
+        Status::set_warnings(all);
+        result
+    }
+
+    /// Retrieve all recorded warnings in the order of their first occurrence
+    pub fn all() -> Vec<Warning> {
+        WARNINGS.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, Result};
+
+    #[test]
+    fn record_success() -> Result<()> {
+        let message = "Route overlaps with the cluster CIDR (record_success)";
+        assert_eq!(Warnings::record(message), Some(0));
+        assert_eq!(Warnings::record(message), None);
+        assert_eq!(Warnings::record(message), None);
+        assert_eq!(
+            Warnings::record("Unable to update hosts file (record_success)"),
+            Some(0)
+        );
+
+        let all = Warnings::all();
+        let warning = all
+            .iter()
+            .find(|x| x.message() == message)
+            .context("no warning")?;
+        assert_eq!(warning.count(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn record_success_window_elapsed() {
+        let message = "Component crashed (record_success_window_elapsed)";
+        let now = Instant::now();
+        assert_eq!(Warnings::record_at(message, now), Some(0));
+        assert_eq!(Warnings::record_at(message, now), None);
+        assert_eq!(Warnings::record_at(message, now), None);
+
+        let later = now + Warnings::WINDOW;
+        assert_eq!(Warnings::record_at(message, later), Some(2));
+        assert_eq!(Warnings::record_at(message, later), None);
+    }
+}
@@ -1,10 +1,11 @@
 #![allow(dead_code)]
 use anyhow::{bail, Result};
+use kubernix::testing::TestCluster;
 use std::{
     env::{current_dir, var},
     fs::{canonicalize, create_dir_all, File},
     io::{BufRead, BufReader},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
     time::Instant,
 };
@@ -76,28 +77,29 @@ where
     let binary = current_dir()?
         .join("target")
         .join("release")
-        .join("kubernix")
-        .display()
-        .to_string();
-    let root = format!("--root={}", run_root(test).display());
-    let mut full_args: Vec<&str> = vec![&binary, &root, "--log-level=debug"];
+        .join("kubernix");
+    let mut full_args = vec!["--log-level=debug"];
     if let Some(a) = args {
         full_args.extend(a);
     }
-    let success = run_test(test, &full_args, hook)?;
 
-    // Kill the kubernix pid
-    let pid_file = run_root(test).join("kubernix.pid");
-    println!("Killing pid: {}", pid_file.display());
+    // Prepare the test dir
     Command::new(SUDO)
-        .arg("pkill")
-        .arg("-F")
-        .arg(&pid_file)
+        .arg("rm")
+        .arg("-rf")
+        .arg(test_dir(test))
         .status()?;
-    let cleanup_success = check_file_for_output(test, "All done", "died unexpectedly")?;
+    let cluster = TestCluster::start_with(&binary, &run_root(test), &full_args)?;
+    let log_file = cluster.log_file().to_path_buf();
+    println!("Cluster ready, logs in: {}", log_file.display());
+
+    // Run the test hook and stop the cluster in any case
+    let hook_result = hook();
+    let stop_result = cluster.stop();
+    hook_result.and(stop_result)?;
 
     // Results evaluation
-    if !success || !cleanup_success {
+    if !check_file_for_output(&log_file, "All done", "died unexpectedly")? {
         bail!("Test failed")
     }
     Ok(())
@@ -107,6 +109,29 @@ pub fn run_root(test: &str) -> PathBuf {
     test_dir(test).join("run")
 }
 
+pub fn kubectl(test: &str) -> Command {
+    // The admin kubeconfig is world readable, so no need to use sudo here
+    let kubeconfig = run_root(test).join("kubeconfig").join("admin.kubeconfig");
+    let mut cmd = Command::new("kubectl");
+    cmd.env("KUBECONFIG", kubeconfig);
+    cmd
+}
+
+pub fn run_kubectl(test: &str, args: &[&str]) -> Result<String> {
+    println!("running: kubectl {}", args.join(" "));
+    let output = kubectl(test).args(args).output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    if !output.status.success() {
+        bail!(
+            "kubectl {} failed: {}{}",
+            args.join(" "),
+            stdout,
+            String::from_utf8(output.stderr)?
+        )
+    }
+    Ok(stdout)
+}
+
 pub fn none_hook() -> Result<()> {
     Ok(())
 }
@@ -142,7 +167,7 @@ where
     // Check the expected output
     println!("Waiting for process to be ready");
     let success_ready = check_file_for_output(
-        test,
+        &log_file,
         "Waiting for interrupt",
         "Unable to start all processes",
     )?;
@@ -164,10 +189,14 @@ where
     Ok(success_ready && success_hook)
 }
 
-fn check_file_for_output(test: &str, success_pattern: &str, failure_pattern: &str) -> Result<bool> {
+fn check_file_for_output(
+    log_file: &Path,
+    success_pattern: &str,
+    failure_pattern: &str,
+) -> Result<bool> {
     let mut success = false;
     let now = Instant::now();
-    let mut reader = BufReader::new(File::open(log_file(test))?);
+    let mut reader = BufReader::new(File::open(log_file)?);
 
     while now.elapsed().as_secs() < TIMEOUT {
         let mut line = String::new();
@@ -1,7 +1,13 @@
 mod common;
 
-use anyhow::Result;
-use common::{none_hook, run_docker_test, run_local_test, run_podman_test};
+use anyhow::{bail, Result};
+use common::{kubectl, none_hook, run_docker_test, run_kubectl, run_local_test, run_podman_test};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread::sleep,
+    time::Duration,
+};
 
 #[test]
 fn local_single_node() -> Result<()> {
@@ -13,6 +19,59 @@ fn local_multi_node() -> Result<()> {
     run_local_test("integration-local-multi", Some(&["--nodes=2"]), none_hook)
 }
 
+#[test]
+fn local_multi_node_streaming() -> Result<()> {
+    let test = "integration-local-multi-streaming";
+    run_local_test(test, Some(&["--nodes=2"]), || {
+        for (i, node) in ["node-0", "node-1"].iter().enumerate() {
+            let pod = format!("streaming-{}", node);
+            run_kubectl(
+                test,
+                &[
+                    "run",
+                    &pod,
+                    "--image=docker.io/library/nginx:alpine",
+                    "--restart=Never",
+                    &format!(r#"--overrides={{"spec":{{"nodeName":"{}"}}}}"#, node),
+                ],
+            )?;
+            run_kubectl(
+                test,
+                &[
+                    "wait",
+                    "--for=condition=Ready",
+                    &format!("pod/{}", pod),
+                    "--timeout=300s",
+                ],
+            )?;
+
+            // Exec and logs
+            run_kubectl(test, &["exec", &pod, "--", "nginx", "-v"])?;
+            run_kubectl(test, &["logs", &pod])?;
+
+            // Port forward
+            let port = 18080 + i;
+            let mut port_forward = kubectl(test)
+                .arg("port-forward")
+                .arg(&pod)
+                .arg(format!("{}:80", port))
+                .spawn()?;
+            sleep(Duration::from_secs(5));
+            let response = TcpStream::connect(("127.0.0.1", port as u16)).and_then(|mut s| {
+                s.write_all(b"GET / HTTP/1.0\r\n\r\n")?;
+                let mut response = String::new();
+                s.read_to_string(&mut response)?;
+                Ok(response)
+            });
+            port_forward.kill()?;
+            if !response?.contains("200 OK") {
+                bail!("port-forward to {} failed", pod)
+            }
+        }
+        Ok(())
+    })
+}
+
 #[test]
 fn docker_single_node() -> Result<()> {
     run_docker_test("integration-docker-single", None)